@@ -11,6 +11,21 @@ pub struct WardenConfig {
     pub c2_auth: C2AuthConfig,
     pub features: FeaturesConfig,
     pub mqtt: Option<MqttConfig>,
+    /// PostgreSQL databases the daemon backs up
+    #[serde(default)]
+    pub databases: Vec<DatabaseConfig>,
+    /// Backup jobs run by the daemon's scheduler
+    #[serde(default)]
+    pub schedules: Vec<BackupScheduleConfig>,
+    /// Bandwidth and process priority of every backup, unless a job overrides them
+    #[serde(default)]
+    pub throttle: ThrottleConfig,
+    /// Background backup and restore jobs started by commands
+    #[serde(default)]
+    pub jobs: JobsConfig,
+    /// Keys encrypting uploads of commands that name none of their own
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -21,6 +36,15 @@ pub struct MqttConfig {
     pub username: Option<String>,
     pub password: Option<String>,
     pub topics: Option<Vec<String>>,
+    // MQTT specific fields
+    /// Quality of service of subscriptions and publishes: 0, 1 (default) or 2
+    pub qos: Option<u8>,
+    /// PEM certificates trusted for an `mqtts://` broker instead of the system ones
+    pub ca_file: Option<String>,
+    /// PEM certificate the daemon authenticates with to an `mqtts://` broker
+    pub client_cert_file: Option<String>,
+    /// PEM private key of `client_cert_file`
+    pub client_key_file: Option<String>,
     // AMQP specific fields
     pub vhost: Option<String>,
     pub exchange: Option<String>,
@@ -28,6 +52,170 @@ pub struct MqttConfig {
     pub protocol: Option<String>, // "mqtt" or "amqp"
 }
 
+/// A PostgreSQL database that scheduled backups can target
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DatabaseConfig {
+    /// Name schedules use to refer to this database
+    pub name: String,
+    pub host: String,
+    #[serde(default = "default_postgres_port")]
+    pub port: u16,
+    pub database: String,
+    pub user: String,
+    pub password: Option<String>,
+    pub ssl_mode: Option<String>,
+    /// Local directory holding the backups and their catalog
+    pub backup_dir: String,
+    /// Keep WAL on the server in the `warden_wal` replication slot until
+    /// incremental backups stream it. The server holds on to that WAL for as
+    /// long as the slot exists, so once this is turned off the slot must be
+    /// dropped with `SELECT pg_drop_replication_slot('warden_wal')`.
+    #[serde(default)]
+    pub replication_slot: bool,
+}
+
+fn default_postgres_port() -> u16 {
+    5432
+}
+
+/// Kind of backup a schedule creates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduledBackupType {
+    Full,
+    Incremental,
+    Snapshot,
+}
+
+/// A cron-style backup job
+///
+/// ```toml
+/// [[schedules]]
+/// name = "main-hourly"
+/// database = "main"
+/// backup_type = "incremental"
+/// cron = "0 * * * *"
+/// jitter_secs = 120
+/// compression = "zstd:3"
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BackupScheduleConfig {
+    /// Unique job name, used in state, logs and published messages
+    pub name: String,
+    /// Name of the entry in `databases` to back up
+    pub database: String,
+    pub backup_type: ScheduledBackupType,
+    /// Cron expression, either the classic 5 fields or with leading seconds
+    pub cron: String,
+    /// Delay each run by a random amount up to this many seconds
+    #[serde(default)]
+    pub jitter_secs: u64,
+    /// Run once after downtime if runs were missed while the daemon was down
+    #[serde(default = "default_catch_up")]
+    pub catch_up: bool,
+    /// Codec for archived WAL and uploads, `algorithm[:level]`, none when unset
+    #[serde(default)]
+    pub compression: Option<String>,
+    /// Settings replacing those of the global `throttle` section for this job
+    #[serde(default)]
+    pub throttle: ThrottleConfig,
+}
+
+fn default_catch_up() -> bool {
+    true
+}
+
+/// Limits keeping backups from starving the database host
+///
+/// ```toml
+/// [throttle]
+/// bandwidth_limit = "20M"
+/// nice = 10
+/// ionice_class = 3
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ThrottleConfig {
+    /// Bytes per second shared by the transfers, with an optional K, M or G suffix
+    pub bandwidth_limit: Option<String>,
+    /// Niceness of pg_dump and pg_basebackup
+    pub nice: Option<i32>,
+    /// ionice class of pg_dump and pg_basebackup: 1 realtime, 2 best-effort, 3 idle
+    pub ionice_class: Option<u8>,
+    /// Priority within the ionice class, from 0 to 7
+    pub ionice_level: Option<u8>,
+}
+
+impl ThrottleConfig {
+    /// These settings, falling back to `global` for the ones left unset
+    pub fn or(&self, global: &ThrottleConfig) -> ThrottleConfig {
+        ThrottleConfig {
+            bandwidth_limit: self
+                .bandwidth_limit
+                .clone()
+                .or_else(|| global.bandwidth_limit.clone()),
+            nice: self.nice.or(global.nice),
+            ionice_class: self.ionice_class.or(global.ionice_class),
+            ionice_level: self.ionice_level.or(global.ionice_level),
+        }
+    }
+}
+
+/// Master keys of client-side encryption
+///
+/// ```toml
+/// [encryption]
+/// key_file = "/etc/warden/keys"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    /// File with one `[key-id:]base64-key` per line, the first encrypts new uploads
+    pub key_file: Option<String>,
+}
+
+/// Where jobs are recorded and how many run at once
+///
+/// ```toml
+/// [jobs]
+/// state_file = "/var/lib/warden/jobs.json"
+/// max_concurrent_backups = 2
+/// max_concurrent_restores = 1
+/// history = 100
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct JobsConfig {
+    /// File the state of the jobs is kept in, so it survives restarts
+    pub state_file: String,
+    /// Backups running at once, later ones wait in the queue
+    pub max_concurrent_backups: usize,
+    /// Restores running at once, later ones wait in the queue
+    pub max_concurrent_restores: usize,
+    /// Finished jobs kept in the state file
+    pub history: usize,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            state_file: "~/.local/state/warden/jobs.json".to_string(),
+            max_concurrent_backups: 1,
+            max_concurrent_restores: 1,
+            history: 100,
+        }
+    }
+}
+
+impl JobsConfig {
+    /// `state_file` with `~` and environment variables expanded
+    pub fn state_path(&self) -> std::path::PathBuf {
+        shellexpand::full(&self.state_file)
+            .map(|p| p.into_owned())
+            .unwrap_or_else(|_| self.state_file.clone())
+            .into()
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct C2AuthConfig {
     pub id: String,
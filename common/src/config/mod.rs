@@ -1,3 +1,7 @@
 mod file;
 
-pub use file::{load_config, update_config, C2AuthConfig, FeaturesConfig, WardenConfig};
+pub use file::{
+    load_config, update_config, BackupScheduleConfig, C2AuthConfig, DatabaseConfig,
+    EncryptionConfig, FeaturesConfig, JobsConfig, MqttConfig, ScheduledBackupType, ThrottleConfig,
+    WardenConfig,
+};
@@ -50,9 +50,9 @@ pub async fn execute() -> Result<()> {
     // Create daemon instance
     let mut daemon = Daemon::new(config);
 
-    // Initialize AMQP client
-    if let Err(e) = daemon.init_amqp().await {
-        error!("Failed to initialize AMQP client: {e}");
+    // Initialize the AMQP or MQTT client
+    if let Err(e) = daemon.connect().await {
+        error!("Failed to initialize broker client: {e}");
         std::fs::remove_file(pid_file).ok(); // Clean up PID file on error
         return Err(e);
     }
@@ -1,10 +1,18 @@
-use crate::amqp::{AmqpClient, MessageType};
+use crate::amqp::MessageType;
+use crate::handlers::postgres::{postgres_config, start_backup, start_restore, ConnectionArgs};
+use crate::jobs::{JobContext, JobKind, JobManager, JobRecord, JobReporter};
+use crate::transport::{exchange, Namespace, Transport};
 use anyhow::{anyhow, Result};
-use common::config::WardenConfig;
+use common::config::{DatabaseConfig, WardenConfig};
 use log::{error, info, warn};
+use postgres::cli::commands::{prune_backups, PruneOutcome, StorageOptions};
+use postgres::retention::RetentionPolicy;
+use postgres::PostgresManager;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use uuid::Uuid;
 
 /// Helper function to extract subtopic from routing key
 fn get_subtopic(routing_key: &str, prefix: &str) -> Option<String> {
@@ -22,6 +30,10 @@ pub enum CommandType {
     ConfigSet,
     PostgresBackup,
     PostgresRestore,
+    PostgresPrune,
+    JobStatus,
+    JobCancel,
+    JobList,
     OverwatchStatus,
     OverwatchStart,
     OverwatchStop,
@@ -35,6 +47,25 @@ pub struct CommandPayload {
     pub args: Option<HashMap<String, serde_json::Value>>,
 }
 
+/// Arguments of a `PostgresPrune` command
+#[derive(Debug, Deserialize)]
+struct PruneArgs {
+    #[serde(flatten)]
+    connection: ConnectionArgs,
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(flatten)]
+    policy: RetentionPolicy,
+    /// Remote storage holding copies of the backups, pruned along with them
+    storage: Option<StorageOptions>,
+}
+
+/// Arguments of the `JobStatus` and `JobCancel` commands
+#[derive(Debug, Deserialize)]
+struct JobArgs {
+    job_id: Uuid,
+}
+
 /// Response payload structure
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ResponsePayload {
@@ -43,16 +74,90 @@ pub struct ResponsePayload {
     pub data: Option<serde_json::Value>,
 }
 
+/// Check the arguments of a `PostgresPrune` command and queue its job
+fn start_prune(
+    args: serde_json::Value,
+    config: &WardenConfig,
+    jobs: &Arc<JobManager>,
+    reporter: Option<JobReporter>,
+) -> Result<JobRecord> {
+    let mut args: PruneArgs =
+        serde_json::from_value(args).map_err(|e| anyhow!("Invalid prune arguments: {e}"))?;
+    let database = std::mem::take(&mut args.connection).resolve(&config.databases)?;
+    if args.policy.is_empty() {
+        return Err(anyhow!("No retention rules provided"));
+    }
+
+    Ok(
+        jobs.submit(JobKind::Prune, reporter, move |ctx| async move {
+            let outcome = prune(args, database, &ctx).await?;
+            if !outcome.failed.is_empty() {
+                let failed: Vec<String> = outcome
+                    .failed
+                    .iter()
+                    .map(|f| format!("{} ({})", f.backup_id, f.error))
+                    .collect();
+                return Err(anyhow!(
+                    "{} backups pruned, {} kept, {} could not be pruned: {}",
+                    outcome.pruned.len(),
+                    outcome.plan.keep.len(),
+                    failed.len(),
+                    failed.join(", ")
+                ));
+            }
+            info!(
+                "{} backups pruned, {} kept",
+                outcome.pruned.len(),
+                outcome.plan.keep.len()
+            );
+            Ok(serde_json::to_value(&outcome)?)
+        }),
+    )
+}
+
+/// Apply a retention policy to a database's backups and their remote copies
+///
+/// Waits for the backups writing to the directory, so it never prunes the
+/// catalog under them.
+async fn prune(
+    args: PruneArgs,
+    database: DatabaseConfig,
+    ctx: &JobContext,
+) -> Result<PruneOutcome> {
+    let backup_dir = PathBuf::from(&database.backup_dir);
+    let _guard = ctx.lock_dir(&backup_dir).await;
+    let mut manager = PostgresManager::new(postgres_config(&database), backup_dir)?;
+    let storage = match args.storage {
+        Some(mut storage) => {
+            storage.remote_storage = true;
+            storage
+        }
+        None => StorageOptions::default(),
+    };
+    prune_backups(&mut manager, &args.policy, args.dry_run, &storage).await
+}
+
 /// Handle command messages
 pub async fn handle_command(
     routing_key: &str,
     payload: &str,
-    client: &Arc<AmqpClient>,
+    client: &Transport,
     config: &Arc<Mutex<WardenConfig>>,
+    jobs: &Arc<JobManager>,
 ) -> Result<()> {
+    // Get exchange name and routing key namespace from config
+    let (exchange, namespace) = {
+        let config_guard = config.lock().unwrap();
+        (
+            exchange(&config_guard),
+            Namespace::from_config(&config_guard),
+        )
+    };
+
     // Extract command subtopic from routing key
-    let subtopic = get_subtopic(routing_key, "warden.commands.")
+    let subtopic = get_subtopic(routing_key, &namespace.key("commands."))
         .ok_or_else(|| anyhow!("Invalid command routing key format"))?;
+    let response_routing_key = namespace.key(&format!("responses.{subtopic}"));
 
     // Parse command payload
     let command = match serde_json::from_str::<CommandPayload>(payload) {
@@ -68,23 +173,10 @@ pub async fn handle_command(
                 data: None,
             };
 
-            // Get exchange name from config
-            let exchange = {
-                let config_guard = config.lock().unwrap();
-                if let Some(mqtt_config) = &config_guard.mqtt {
-                    mqtt_config
-                        .exchange
-                        .clone()
-                        .unwrap_or_else(|| "warden".to_string())
-                } else {
-                    "warden".to_string()
-                }
-            };
-
             client
                 .publish(
                     &exchange,
-                    &format!("warden.responses.{subtopic}"),
+                    &response_routing_key,
                     MessageType::Response,
                     &serde_json::to_string(&response)?,
                 )
@@ -96,19 +188,6 @@ pub async fn handle_command(
 
     info!("Received command: {command:?}");
 
-    // Get exchange name from config
-    let exchange = {
-        let config_guard = config.lock().unwrap();
-        if let Some(mqtt_config) = &config_guard.mqtt {
-            mqtt_config
-                .exchange
-                .clone()
-                .unwrap_or_else(|| "warden".to_string())
-        } else {
-            "warden".to_string()
-        }
-    };
-
     // Process command based on type
     let response = match command.command_type {
         CommandType::Status => {
@@ -193,31 +272,58 @@ pub async fn handle_command(
                     data: None,
                 }
             } else {
-                // Call PostgreSQL backup functionality
-                // match postgres::backup::full::create_backup().await {
-                //     Ok(backup_info) => {
-                //         ResponsePayload {
-                //             success: true,
-                //             message: "Backup created successfully".to_string(),
-                //             data: Some(serde_json::to_value(backup_info).unwrap_or_default()),
-                //         }
-                //     },
-                //     Err(e) => {
-                //         ResponsePayload {
-                //             success: false,
-                //             message: format!("Failed to create backup: {}", e),
-                //             data: None,
-                //         }
-                //     }
-                // }
+                let args = serde_json::to_value(command.args.clone().unwrap_or_default())?;
+                let reporter = JobReporter::new(
+                    client.clone(),
+                    exchange.clone(),
+                    response_routing_key.clone(),
+                );
+                let started = start_backup(args, &config.lock().unwrap(), jobs, Some(reporter));
+                match started {
+                    Ok(record) => ResponsePayload {
+                        success: true,
+                        message: record.message.clone(),
+                        data: serde_json::to_value(&record).ok(),
+                    },
+                    Err(e) => ResponsePayload {
+                        success: false,
+                        message: format!("Failed to start backup: {e}"),
+                        data: None,
+                    },
+                }
+            }
+        }
+        CommandType::PostgresRestore => {
+            // Check if PostgreSQL backup feature is enabled
+            if !config.lock().unwrap().features.postgres_backup {
                 ResponsePayload {
                     success: false,
-                    message: "PostgreSQL backup feature is not implemented yet".to_string(),
+                    message: "PostgreSQL backup feature is not enabled".to_string(),
                     data: None,
                 }
+            } else {
+                let args = serde_json::to_value(command.args.clone().unwrap_or_default())?;
+                let reporter = JobReporter::new(
+                    client.clone(),
+                    exchange.clone(),
+                    response_routing_key.clone(),
+                );
+                let started = start_restore(args, &config.lock().unwrap(), jobs, Some(reporter));
+                match started {
+                    Ok(record) => ResponsePayload {
+                        success: true,
+                        message: record.message.clone(),
+                        data: serde_json::to_value(&record).ok(),
+                    },
+                    Err(e) => ResponsePayload {
+                        success: false,
+                        message: format!("Failed to start restore: {e}"),
+                        data: None,
+                    },
+                }
             }
         }
-        CommandType::PostgresRestore => {
+        CommandType::PostgresPrune => {
             // Check if PostgreSQL backup feature is enabled
             if !config.lock().unwrap().features.postgres_backup {
                 ResponsePayload {
@@ -225,52 +331,64 @@ pub async fn handle_command(
                     message: "PostgreSQL backup feature is not enabled".to_string(),
                     data: None,
                 }
-            } else if let Some(args) = &command.args {
-                if let Some(backup_id) = args.get("backup_id") {
-                    if let Some(_backup_id) = backup_id.as_str() {
-                        // Call PostgreSQL restore functionality
-                        // match postgres::backup::restore_backup(backup_id).await {
-                        //     Ok(_) => {
-                        //         ResponsePayload {
-                        //             success: true,
-                        //             message: "Backup restored successfully".to_string(),
-                        //             data: None,
-                        //         }
-                        //     },
-                        //     Err(e) => {
-                        //         ResponsePayload {
-                        //             success: false,
-                        //             message: format!("Failed to restore backup: {}", e),
-                        //             data: None,
-                        //         }
-                        //     }
-                        // }
-                        ResponsePayload {
-                            success: false,
-                            message: "PostgreSQL restore feature is not implemented yet"
-                                .to_string(),
-                            data: None,
-                        }
-                    } else {
-                        ResponsePayload {
-                            success: false,
-                            message: "Invalid backup_id format".to_string(),
-                            data: None,
-                        }
-                    }
-                } else {
-                    ResponsePayload {
+            } else {
+                let args = serde_json::to_value(command.args.clone().unwrap_or_default())?;
+                let reporter = JobReporter::new(
+                    client.clone(),
+                    exchange.clone(),
+                    response_routing_key.clone(),
+                );
+                let started = start_prune(args, &config.lock().unwrap(), jobs, Some(reporter));
+                match started {
+                    Ok(record) => ResponsePayload {
+                        success: true,
+                        message: record.message.clone(),
+                        data: serde_json::to_value(&record).ok(),
+                    },
+                    Err(e) => ResponsePayload {
                         success: false,
-                        message: "Missing 'backup_id' parameter".to_string(),
+                        message: format!("Failed to start prune: {e}"),
                         data: None,
+                    },
+                }
+            }
+        }
+        CommandType::JobStatus | CommandType::JobCancel => {
+            let args = serde_json::to_value(command.args.clone().unwrap_or_default())?;
+            match serde_json::from_value::<JobArgs>(args) {
+                Ok(args) => {
+                    let record = match command.command_type {
+                        CommandType::JobCancel => jobs.cancel(args.job_id).await,
+                        _ => jobs
+                            .get(args.job_id)
+                            .ok_or_else(|| anyhow!("Unknown job {}", args.job_id)),
+                    };
+                    match record {
+                        Ok(record) => ResponsePayload {
+                            success: true,
+                            message: record.message.clone(),
+                            data: serde_json::to_value(&record).ok(),
+                        },
+                        Err(e) => ResponsePayload {
+                            success: false,
+                            message: e.to_string(),
+                            data: None,
+                        },
                     }
                 }
-            } else {
-                ResponsePayload {
+                Err(e) => ResponsePayload {
                     success: false,
-                    message: "No arguments provided".to_string(),
+                    message: format!("Invalid job arguments: {e}"),
                     data: None,
-                }
+                },
+            }
+        }
+        CommandType::JobList => {
+            let records = jobs.list();
+            ResponsePayload {
+                success: true,
+                message: format!("{} jobs", records.len()),
+                data: Some(serde_json::to_value(&records).unwrap_or_default()),
             }
         }
         CommandType::OverwatchStatus => {
@@ -378,7 +496,7 @@ pub async fn handle_command(
     client
         .publish(
             &exchange,
-            &format!("warden.responses.{subtopic}"),
+            &response_routing_key,
             MessageType::Response,
             &serde_json::to_string(&response)?,
         )
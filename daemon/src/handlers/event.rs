@@ -1,4 +1,5 @@
-use crate::amqp::{AmqpClient, MessageType};
+use crate::amqp::MessageType;
+use crate::transport::{exchange, Namespace, Transport};
 use anyhow::{anyhow, Result};
 use common::config::WardenConfig;
 use log::{debug, error, info, warn};
@@ -17,6 +18,7 @@ fn get_subtopic(routing_key: &str, prefix: &str) -> Option<String> {
 #[derive(Debug, Serialize, Deserialize)]
 pub enum EventType {
     PostgresAlert,
+    PostgresBackup,
     OverwatchAlert,
     SystemAlert,
     Custom(String),
@@ -56,11 +58,20 @@ impl std::fmt::Display for EventSeverity {
 pub async fn handle_event(
     routing_key: &str,
     payload: &str,
-    client: &Arc<AmqpClient>,
+    client: &Transport,
     config: &Arc<Mutex<WardenConfig>>,
 ) -> Result<()> {
+    // Get exchange name and routing key namespace from config
+    let (exchange, namespace) = {
+        let config_guard = config.lock().unwrap();
+        (
+            exchange(&config_guard),
+            Namespace::from_config(&config_guard),
+        )
+    };
+
     // Extract event subtopic from routing key
-    let subtopic = get_subtopic(routing_key, "warden.events.")
+    let subtopic = get_subtopic(routing_key, &namespace.key("events."))
         .ok_or_else(|| anyhow!("Invalid event routing key format"))?;
 
     // Parse event payload
@@ -79,19 +90,6 @@ pub async fn handle_event(
     );
     debug!("Event details: {event:?}");
 
-    // Get exchange name from config
-    let exchange = {
-        let config_guard = config.lock().unwrap();
-        if let Some(mqtt_config) = &config_guard.mqtt {
-            mqtt_config
-                .exchange
-                .clone()
-                .unwrap_or_else(|| "warden".to_string())
-        } else {
-            "warden".to_string()
-        }
-    };
-
     // Process event based on type
     match event.event_type {
         EventType::PostgresAlert => {
@@ -107,6 +105,10 @@ pub async fn handle_event(
                 Err(e) => error!("Failed to handle PostgreSQL event: {e}"),
             }
         }
+        EventType::PostgresBackup => {
+            // Results of scheduled backups, only worth a log line here
+            info!("Backup event: {} - {}", event.severity, event.message);
+        }
         EventType::OverwatchAlert => {
             // Check if Overwatch feature is enabled
             if !config.lock().unwrap().features.overwatch {
@@ -115,7 +117,7 @@ pub async fn handle_event(
             }
 
             // Process Overwatch alert
-            match handle_overwatch_event(&event, client).await {
+            match handle_overwatch_event(&event, client, &exchange, &namespace).await {
                 Ok(_) => info!("Overwatch event handled successfully"),
                 Err(e) => error!("Failed to handle Overwatch event: {e}"),
             }
@@ -135,7 +137,7 @@ pub async fn handle_event(
     }
 
     // Acknowledge event receipt
-    let ack_routing_key = format!("warden.events.{subtopic}.ack");
+    let ack_routing_key = namespace.key(&format!("events.{subtopic}.ack"));
     let ack_payload = serde_json::json!({
         "event_id": event.data.as_ref().and_then(|d| d.get("id")).unwrap_or(&serde_json::Value::Null),
         "status": "received",
@@ -162,7 +164,7 @@ pub async fn handle_event(
 }
 
 /// Handle PostgreSQL events
-async fn handle_postgres_event(event: &EventPayload, _client: &Arc<AmqpClient>) -> Result<()> {
+async fn handle_postgres_event(event: &EventPayload, _client: &Transport) -> Result<()> {
     match event.severity {
         EventSeverity::Critical => {
             // For critical PostgreSQL events, we might want to take immediate action
@@ -227,7 +229,12 @@ async fn handle_postgres_event(event: &EventPayload, _client: &Arc<AmqpClient>)
 }
 
 /// Handle Overwatch events
-async fn handle_overwatch_event(event: &EventPayload, client: &Arc<AmqpClient>) -> Result<()> {
+async fn handle_overwatch_event(
+    event: &EventPayload,
+    client: &Transport,
+    exchange: &str,
+    namespace: &Namespace,
+) -> Result<()> {
     match event.severity {
         EventSeverity::Error | EventSeverity::Critical => {
             // For error or critical Overwatch events, we might want to restart the service
@@ -253,7 +260,7 @@ async fn handle_overwatch_event(event: &EventPayload, client: &Arc<AmqpClient>)
                             info!("Overwatch restarted successfully");
 
                             // Notify about the restart
-                            let routing_key = "warden.notifications.overwatch";
+                            let routing_key = namespace.key("notifications.overwatch");
                             let response_payload = serde_json::json!({
                                 "action": "restart",
                                 "status": "success",
@@ -262,8 +269,8 @@ async fn handle_overwatch_event(event: &EventPayload, client: &Arc<AmqpClient>)
 
                             client
                                 .publish(
-                                    "warden", // Using default exchange
-                                    routing_key,
+                                    exchange,
+                                    &routing_key,
                                     MessageType::Notification,
                                     &serde_json::to_string(&response_payload)?,
                                 )
@@ -273,7 +280,7 @@ async fn handle_overwatch_event(event: &EventPayload, client: &Arc<AmqpClient>)
                             error!("Failed to start Overwatch: {e}");
 
                             // Notify about the failure
-                            let routing_key = "warden.notifications.overwatch";
+                            let routing_key = namespace.key("notifications.overwatch");
                             let response_payload = serde_json::json!({
                                 "action": "restart",
                                 "status": "failed",
@@ -283,8 +290,8 @@ async fn handle_overwatch_event(event: &EventPayload, client: &Arc<AmqpClient>)
 
                             client
                                 .publish(
-                                    "warden", // Using default exchange
-                                    routing_key,
+                                    exchange,
+                                    &routing_key,
                                     MessageType::Notification,
                                     &serde_json::to_string(&response_payload)?,
                                 )
@@ -296,7 +303,7 @@ async fn handle_overwatch_event(event: &EventPayload, client: &Arc<AmqpClient>)
                     error!("Failed to stop Overwatch: {e}");
 
                     // Notify about the failure
-                    let routing_key = "warden.notifications.overwatch";
+                    let routing_key = namespace.key("notifications.overwatch");
                     let response_payload = serde_json::json!({
                         "action": "restart",
                         "status": "failed",
@@ -306,8 +313,8 @@ async fn handle_overwatch_event(event: &EventPayload, client: &Arc<AmqpClient>)
 
                     client
                         .publish(
-                            "warden", // Using default exchange
-                            routing_key,
+                            exchange,
+                            &routing_key,
                             MessageType::Notification,
                             &serde_json::to_string(&response_payload)?,
                         )
@@ -325,7 +332,7 @@ async fn handle_overwatch_event(event: &EventPayload, client: &Arc<AmqpClient>)
 }
 
 /// Handle system events
-async fn handle_system_event(event: &EventPayload, _client: &Arc<AmqpClient>) -> Result<()> {
+async fn handle_system_event(event: &EventPayload, _client: &Transport) -> Result<()> {
     // Log all system events
     info!("System event: {} - {}", event.severity, event.message);
 
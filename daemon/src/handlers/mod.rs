@@ -1,3 +1,4 @@
 pub mod command;
 pub mod config;
 pub mod event;
+pub mod postgres;
@@ -0,0 +1,413 @@
+//! Backups and restores requested through `PostgresBackup` and
+//! `PostgresRestore` commands.
+//!
+//! A command is answered as soon as its arguments check out, with the
+//! record of a job queued in the [`JobManager`]. The job then publishes its
+//! progress and outcome on the response routing key of the command.
+
+use crate::jobs::{JobContext, JobKind, JobManager, JobRecord, JobReporter};
+use anyhow::{anyhow, Result};
+use common::config::{
+    DatabaseConfig, EncryptionConfig, ScheduledBackupType, ThrottleConfig, WardenConfig,
+};
+use log::info;
+use postgres::cli::commands::{
+    streaming_source, upload_to_destinations, RecoveryTargetOptions, StorageOptions,
+};
+use postgres::wrapper::ProcessPriority;
+use postgres::{Backup, PostgresConfig, PostgresManager, Restore};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use storage::{Compression, RateLimiter};
+use uuid::Uuid;
+
+/// Database a command works on
+///
+/// `name` picks an entry of `databases` in the configuration, the other
+/// fields override it. Without a name, `host`, `database`, `user` and
+/// `backup_dir` are required.
+#[derive(Debug, Default, Deserialize)]
+pub struct ConnectionArgs {
+    pub name: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub database: Option<String>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub ssl_mode: Option<String>,
+    pub backup_dir: Option<String>,
+}
+
+impl ConnectionArgs {
+    /// Settings of the database, from the configured `databases` and the arguments
+    pub fn resolve(self, databases: &[DatabaseConfig]) -> Result<DatabaseConfig> {
+        let base = match &self.name {
+            Some(name) => Some(
+                databases
+                    .iter()
+                    .find(|d| &d.name == name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Unknown database {name}"))?,
+            ),
+            None => None,
+        };
+        let missing = |field: &str| anyhow!("Missing '{field}' parameter");
+        Ok(DatabaseConfig {
+            name: self.name.unwrap_or_default(),
+            host: self
+                .host
+                .or_else(|| base.as_ref().map(|d| d.host.clone()))
+                .ok_or_else(|| missing("host"))?,
+            port: self
+                .port
+                .or_else(|| base.as_ref().map(|d| d.port))
+                .unwrap_or(5432),
+            database: self
+                .database
+                .or_else(|| base.as_ref().map(|d| d.database.clone()))
+                .ok_or_else(|| missing("database"))?,
+            user: self
+                .user
+                .or_else(|| base.as_ref().map(|d| d.user.clone()))
+                .ok_or_else(|| missing("user"))?,
+            password: self
+                .password
+                .or_else(|| base.as_ref().and_then(|d| d.password.clone())),
+            ssl_mode: self
+                .ssl_mode
+                .or_else(|| base.as_ref().and_then(|d| d.ssl_mode.clone())),
+            backup_dir: self
+                .backup_dir
+                .or_else(|| base.as_ref().map(|d| d.backup_dir.clone()))
+                .ok_or_else(|| missing("backup_dir"))?,
+            // Only a configured database opts in to the slot
+            replication_slot: base.as_ref().is_some_and(|d| d.replication_slot),
+        })
+    }
+}
+
+/// Arguments of a `PostgresBackup` command
+#[derive(Debug, Deserialize)]
+pub struct BackupArgs {
+    #[serde(flatten)]
+    pub connection: ConnectionArgs,
+    #[serde(default = "default_backup_type")]
+    pub backup_type: ScheduledBackupType,
+    /// Codec such as `zstd:3`, none when unset
+    pub compression: Option<String>,
+    /// Remote storage to upload the backup to once taken
+    pub storage: Option<StorageOptions>,
+    /// Overrides of the global throttle settings
+    #[serde(default)]
+    pub throttle: ThrottleConfig,
+}
+
+fn default_backup_type() -> ScheduledBackupType {
+    ScheduledBackupType::Full
+}
+
+/// How a `PostgresRestore` command restores its backup
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreType {
+    #[default]
+    Full,
+    Incremental,
+    PointInTime,
+    Snapshot,
+}
+
+/// Arguments of a `PostgresRestore` command
+#[derive(Debug, Deserialize)]
+pub struct RestoreArgs {
+    #[serde(flatten)]
+    pub connection: ConnectionArgs,
+    /// Backup to restore, the full backup for incremental and point-in-time restores
+    pub backup_id: Uuid,
+    pub target_dir: PathBuf,
+    #[serde(default)]
+    pub restore_type: RestoreType,
+    /// Recovery target of a point-in-time restore
+    pub target: Option<RecoveryTargetOptions>,
+    /// Remote storage to stream the backup from, instead of the backup directory
+    pub storage: Option<StorageOptions>,
+}
+
+/// Connection settings of a configured database
+pub fn postgres_config(database: &DatabaseConfig) -> PostgresConfig {
+    PostgresConfig {
+        host: database.host.clone(),
+        port: database.port,
+        database: database.database.clone(),
+        user: database.user.clone(),
+        password: database.password.clone(),
+        ssl_mode: database.ssl_mode.clone(),
+        ssh_host: None,
+        ssh_user: None,
+        ssh_port: None,
+        ssh_password: None,
+        ssh_key_path: None,
+        ssh_local_port: None,
+        ssh_remote_port: None,
+        ssh_known_hosts: None,
+        ssh_host_key_fingerprint: None,
+    }
+}
+
+/// Use the configured key file for storage that names no encryption key of its own
+fn use_configured_keys(storage: &mut Option<StorageOptions>, encryption: &EncryptionConfig) {
+    if let Some(storage) = storage {
+        if storage.encryption_key.is_none() && storage.encryption_key_file.is_none() {
+            storage.encryption_key_file = encryption.key_file.clone();
+        }
+    }
+}
+
+/// Check the arguments of a `PostgresBackup` command and queue its job
+pub fn start_backup(
+    args: serde_json::Value,
+    config: &WardenConfig,
+    jobs: &Arc<JobManager>,
+    reporter: Option<JobReporter>,
+) -> Result<JobRecord> {
+    let mut args: BackupArgs =
+        serde_json::from_value(args).map_err(|e| anyhow!("Invalid backup arguments: {e}"))?;
+    let database = std::mem::take(&mut args.connection).resolve(&config.databases)?;
+    let throttle = args.throttle.or(&config.throttle);
+    use_configured_keys(&mut args.storage, &config.encryption);
+
+    Ok(
+        jobs.submit(JobKind::Backup, reporter, move |ctx| async move {
+            let backup = run_backup(args, database, throttle, &ctx).await?;
+            Ok(serde_json::to_value(&backup)?)
+        }),
+    )
+}
+
+/// Check the arguments of a `PostgresRestore` command and queue its job
+pub fn start_restore(
+    args: serde_json::Value,
+    config: &WardenConfig,
+    jobs: &Arc<JobManager>,
+    reporter: Option<JobReporter>,
+) -> Result<JobRecord> {
+    let mut args: RestoreArgs =
+        serde_json::from_value(args).map_err(|e| anyhow!("Invalid restore arguments: {e}"))?;
+    let database = std::mem::take(&mut args.connection).resolve(&config.databases)?;
+    if matches!(args.restore_type, RestoreType::PointInTime) && args.target.is_none() {
+        return Err(anyhow!("A point-in-time restore needs a 'target'"));
+    }
+    use_configured_keys(&mut args.storage, &config.encryption);
+
+    Ok(
+        jobs.submit(JobKind::Restore, reporter, move |ctx| async move {
+            let restore = run_restore(args, database, &ctx).await?;
+            Ok(serde_json::to_value(&restore)?)
+        }),
+    )
+}
+
+/// Take a backup, then upload it if the command asked for storage
+async fn run_backup(
+    args: BackupArgs,
+    database: DatabaseConfig,
+    throttle: ThrottleConfig,
+    ctx: &JobContext,
+) -> Result<Backup> {
+    let compression = match &args.compression {
+        Some(spec) => spec
+            .parse::<Compression>()
+            .map_err(|e| anyhow!("Invalid compression: {e}"))?,
+        None => Compression::default(),
+    };
+    let limiter = throttle
+        .bandwidth_limit
+        .as_deref()
+        .map(RateLimiter::parse)
+        .transpose()?;
+    let backup_dir = PathBuf::from(&database.backup_dir);
+
+    // Scheduled backups of the same directory wait for this one, and the other
+    // way round. The manager reads the catalog, so it is built under the lock.
+    let _guard = ctx.lock_dir(&backup_dir).await;
+    let mut manager = PostgresManager::new(postgres_config(&database), backup_dir.clone())?
+        .with_compression(compression)
+        .with_priority(ProcessPriority {
+            nice: throttle.nice,
+            ionice_class: throttle.ionice_class,
+            ionice_level: throttle.ionice_level,
+        })
+        .with_cancellation(ctx.cancellation())
+        .with_replication_slot(database.replication_slot);
+    if let Some(limiter) = &limiter {
+        manager = manager.with_rate_limiter(limiter.clone());
+    }
+
+    info!(
+        "Running {:?} backup of {} for job {}",
+        args.backup_type,
+        database.database,
+        ctx.job_id()
+    );
+    let backup = match args.backup_type {
+        ScheduledBackupType::Full => manager.full_backup().await?,
+        ScheduledBackupType::Incremental => manager.incremental_backup().await?,
+        ScheduledBackupType::Snapshot => manager.snapshot_backup().await?,
+    };
+
+    if let Some(mut storage) = args.storage {
+        // Uploads cannot be interrupted, so do not start one for a cancelled job
+        if ctx.cancellation().is_cancelled() {
+            return Err(anyhow!("Cancelled before uploading backup {}", backup.id));
+        }
+        ctx.progress(format!("Uploading backup {}", backup.id))
+            .await;
+        storage.remote_storage = true;
+        upload_to_destinations(
+            &storage,
+            &backup_dir,
+            &backup,
+            &database.database,
+            limiter.as_ref(),
+        )
+        .await?;
+    }
+    Ok(backup)
+}
+
+/// Restore a backup into the target directory of the command
+async fn run_restore(
+    args: RestoreArgs,
+    database: DatabaseConfig,
+    ctx: &JobContext,
+) -> Result<Restore> {
+    let mut manager = PostgresManager::new(
+        postgres_config(&database),
+        PathBuf::from(&database.backup_dir),
+    )?
+    .with_cancellation(ctx.cancellation());
+    if let Some(mut storage) = args.storage {
+        storage.remote_storage = true;
+        manager = manager.with_remote_storage(streaming_source(&storage).await?);
+    }
+
+    info!(
+        "Running {:?} restore of backup {} into {} for job {}",
+        args.restore_type,
+        args.backup_id,
+        args.target_dir.display(),
+        ctx.job_id()
+    );
+    let restore = match args.restore_type {
+        RestoreType::Full => {
+            manager
+                .restore_full_backup(&args.backup_id, args.target_dir)
+                .await?
+        }
+        RestoreType::Incremental => {
+            manager
+                .restore_incremental_backup(&args.backup_id, args.target_dir)
+                .await?
+        }
+        RestoreType::PointInTime => {
+            let (target, action) = args
+                .target
+                .ok_or_else(|| anyhow!("A point-in-time restore needs a 'target'"))?
+                .parse()?;
+            manager
+                .restore_point_in_time(&args.backup_id, args.target_dir, target, action)
+                .await?
+        }
+        RestoreType::Snapshot => {
+            manager
+                .restore_snapshot_backup(&args.backup_id, args.target_dir)
+                .await?
+        }
+    };
+    Ok(restore)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configured() -> Vec<DatabaseConfig> {
+        vec![DatabaseConfig {
+            name: "main".to_string(),
+            host: "db.internal".to_string(),
+            port: 5433,
+            database: "app".to_string(),
+            user: "backup".to_string(),
+            password: Some("secret".to_string()),
+            ssl_mode: None,
+            backup_dir: "/var/backups/main".to_string(),
+            replication_slot: false,
+        }]
+    }
+
+    #[test]
+    fn connection_overrides_the_configured_database() {
+        let args: BackupArgs = serde_json::from_value(serde_json::json!({
+            "name": "main",
+            "backup_dir": "/tmp/adhoc",
+            "backup_type": "snapshot",
+            "storage": { "provider_type": "s3", "bucket": "backups" },
+        }))
+        .unwrap();
+        assert_eq!(args.backup_type, ScheduledBackupType::Snapshot);
+        assert_eq!(
+            args.storage.as_ref().and_then(|s| s.bucket.as_deref()),
+            Some("backups")
+        );
+
+        let database = args.connection.resolve(&configured()).unwrap();
+        assert_eq!(database.host, "db.internal");
+        assert_eq!(database.port, 5433);
+        assert_eq!(database.password.as_deref(), Some("secret"));
+        assert_eq!(database.backup_dir, "/tmp/adhoc");
+    }
+
+    #[test]
+    fn connection_without_a_name_needs_every_setting() {
+        let args = ConnectionArgs {
+            host: Some("localhost".to_string()),
+            database: Some("postgres".to_string()),
+            user: Some("postgres".to_string()),
+            ..Default::default()
+        };
+        let err = args.resolve(&[]).unwrap_err();
+        assert!(err.to_string().contains("backup_dir"), "{err}");
+
+        let unknown = ConnectionArgs {
+            name: Some("other".to_string()),
+            ..Default::default()
+        };
+        assert!(unknown.resolve(&configured()).is_err());
+    }
+
+    #[test]
+    fn storage_falls_back_to_the_configured_key_file() {
+        let encryption = EncryptionConfig {
+            key_file: Some("/etc/warden/keys".to_string()),
+        };
+        let mut storage = Some(StorageOptions::default());
+        use_configured_keys(&mut storage, &encryption);
+        assert_eq!(
+            storage.unwrap().encryption_key_file.as_deref(),
+            Some("/etc/warden/keys")
+        );
+
+        // A key sent with the command wins
+        let mut storage = Some(StorageOptions {
+            encryption_key: Some("AAAA".to_string()),
+            ..Default::default()
+        });
+        use_configured_keys(&mut storage, &encryption);
+        assert!(storage.unwrap().encryption_key_file.is_none());
+
+        let mut storage = None;
+        use_configured_keys(&mut storage, &encryption);
+        assert!(storage.is_none());
+    }
+}
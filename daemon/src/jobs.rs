@@ -0,0 +1,638 @@
+//! Background jobs: backups, restores and prunes started by commands.
+//!
+//! Every job gets an ID and moves from `queued` to `running`, then to
+//! `succeeded`, `failed` or `cancelled`. Its record is saved to the state file
+//! on every change, so `JobStatus` and `JobList` still answer for jobs of an
+//! earlier daemon run. Jobs that were unfinished when the daemon stopped are
+//! marked failed when it starts again.
+//!
+//! The number of jobs of a kind running at once is capped; jobs over the cap
+//! wait in the `queued` state. Jobs writing to the same backup directory,
+//! whether started by a command or a schedule, take turns through its lock.
+
+use crate::amqp::MessageType;
+use crate::handlers::command::ResponsePayload;
+use crate::transport::Transport;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use common::config::JobsConfig;
+use log::{error, info, warn};
+use postgres::wrapper::Cancellation;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::{OwnedMutexGuard, Semaphore};
+use tokio::task::AbortHandle;
+use uuid::Uuid;
+
+/// Where a job stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    /// Whether the job reached its final state
+    pub fn is_finished(self) -> bool {
+        matches!(self, Self::Succeeded | Self::Failed | Self::Cancelled)
+    }
+}
+
+/// What a job does, each kind having its own concurrency limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Backup,
+    Restore,
+    Prune,
+}
+
+impl fmt::Display for JobKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Backup => write!(f, "Backup"),
+            Self::Restore => write!(f, "Restore"),
+            Self::Prune => write!(f, "Prune"),
+        }
+    }
+}
+
+/// A job as reported by `JobStatus` and saved in the state file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub job_id: Uuid,
+    pub kind: JobKind,
+    pub state: JobState,
+    /// Latest progress, or why the job ended
+    pub message: String,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Backup or restore produced by a successful job
+    pub result: Option<serde_json::Value>,
+}
+
+/// Publishes the changes of a job on the response routing key of its command
+#[derive(Clone)]
+pub struct JobReporter {
+    client: Transport,
+    exchange: String,
+    routing_key: String,
+}
+
+impl JobReporter {
+    pub fn new(client: Transport, exchange: String, routing_key: String) -> Self {
+        Self {
+            client,
+            exchange,
+            routing_key,
+        }
+    }
+
+    pub async fn report(&self, record: &JobRecord) {
+        let response = ResponsePayload {
+            success: record.state != JobState::Failed,
+            message: record.message.clone(),
+            data: serde_json::to_value(record).ok(),
+        };
+        let payload = match serde_json::to_string(&response) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize job {}: {e}", record.job_id);
+                return;
+            }
+        };
+        if let Err(e) = self
+            .client
+            .publish(
+                &self.exchange,
+                &self.routing_key,
+                MessageType::Response,
+                &payload,
+            )
+            .await
+        {
+            error!("Failed to publish progress of job {}: {e}", record.job_id);
+        }
+    }
+}
+
+/// Handle on its own job given to the work of a job
+pub struct JobContext {
+    job_id: Uuid,
+    cancellation: Cancellation,
+    jobs: Arc<JobManager>,
+}
+
+impl JobContext {
+    pub fn job_id(&self) -> Uuid {
+        self.job_id
+    }
+
+    /// Cancelled when the job is, to pass on to the tools the job runs
+    pub fn cancellation(&self) -> Cancellation {
+        self.cancellation.clone()
+    }
+
+    /// Wait for the other jobs using the backup directory `dir` to finish
+    ///
+    /// The directory is the job's until the returned guard is dropped.
+    pub async fn lock_dir(&self, dir: &Path) -> OwnedMutexGuard<()> {
+        let lock = self.jobs.dir_lock(dir);
+        match lock.clone().try_lock_owned() {
+            Ok(guard) => guard,
+            Err(_) => {
+                self.progress(format!("Waiting for another job using {}", dir.display()))
+                    .await;
+                lock.lock_owned().await
+            }
+        }
+    }
+
+    /// Record and publish what the job is doing
+    pub async fn progress(&self, message: String) {
+        let changed = self
+            .jobs
+            .update(self.job_id, |record| record.message = message);
+        if let Some((record, Some(reporter))) = changed {
+            reporter.report(&record).await;
+        }
+    }
+}
+
+/// What is kept of an unfinished job besides its record
+struct ActiveJob {
+    cancellation: Cancellation,
+    reporter: Option<JobReporter>,
+    task: Option<AbortHandle>,
+}
+
+#[derive(Default)]
+struct JobTable {
+    /// Every known job, oldest first
+    records: Vec<JobRecord>,
+    active: HashMap<Uuid, ActiveJob>,
+}
+
+/// Runs, tracks and persists the jobs of the daemon
+pub struct JobManager {
+    state_file: PathBuf,
+    history: usize,
+    limits: HashMap<JobKind, Arc<Semaphore>>,
+    table: Mutex<JobTable>,
+    /// One lock per backup directory, so jobs sharing a catalog never write it at once
+    dir_locks: Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl JobManager {
+    /// Load the jobs of earlier runs from the configured state file
+    pub fn new(config: &JobsConfig) -> Self {
+        let limit = |n: usize| Arc::new(Semaphore::new(n.max(1)));
+        let manager = Self {
+            state_file: config.state_path(),
+            history: config.history,
+            limits: HashMap::from([
+                (JobKind::Backup, limit(config.max_concurrent_backups)),
+                (JobKind::Restore, limit(config.max_concurrent_restores)),
+                // Prunes only wait for the directory lock of their backups
+                (
+                    JobKind::Prune,
+                    Arc::new(Semaphore::new(Semaphore::MAX_PERMITS)),
+                ),
+            ]),
+            table: Mutex::new(JobTable::default()),
+            dir_locks: Mutex::new(HashMap::new()),
+        };
+
+        let mut records = load_records(&manager.state_file);
+        let now = Utc::now();
+        let mut interrupted = 0;
+        for record in records.iter_mut().filter(|r| !r.state.is_finished()) {
+            record.state = JobState::Failed;
+            record.message = format!("{} job interrupted by a daemon restart", record.kind);
+            record.finished_at = Some(now);
+            interrupted += 1;
+        }
+        if interrupted > 0 {
+            warn!("{interrupted} jobs were interrupted by the last daemon stop");
+        }
+        let mut table = manager.table();
+        table.records = records;
+        manager.save(&mut table);
+        drop(table);
+        manager
+    }
+
+    fn table(&self) -> MutexGuard<'_, JobTable> {
+        self.table.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Lock of the backup directory `dir`
+    pub fn dir_lock(&self, dir: &Path) -> Arc<tokio::sync::Mutex<()>> {
+        self.dir_locks
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(dir.to_path_buf())
+            .or_default()
+            .clone()
+    }
+
+    /// Queue a job running `work`, returning its record
+    ///
+    /// The job starts once fewer jobs of its kind than the limit run. Its
+    /// changes are published through `reporter`, when given.
+    pub fn submit<F, Fut>(
+        self: &Arc<Self>,
+        kind: JobKind,
+        reporter: Option<JobReporter>,
+        work: F,
+    ) -> JobRecord
+    where
+        F: FnOnce(JobContext) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<serde_json::Value>> + Send + 'static,
+    {
+        let job_id = Uuid::new_v4();
+        let record = JobRecord {
+            job_id,
+            kind,
+            state: JobState::Queued,
+            message: format!("{kind} job {job_id} queued"),
+            created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+            result: None,
+        };
+        let cancellation = Cancellation::new();
+        let context = JobContext {
+            job_id,
+            cancellation: cancellation.clone(),
+            jobs: Arc::clone(self),
+        };
+
+        let mut table = self.table();
+        table.records.push(record.clone());
+        self.save(&mut table);
+
+        let jobs = Arc::clone(self);
+        let limit = Arc::clone(&self.limits[&kind]);
+        // Spawned with the table locked, so the job cannot change before it is listed
+        let task = tokio::spawn(async move {
+            let Ok(_permit) = limit.acquire_owned().await else {
+                return;
+            };
+            let started = jobs.update(job_id, |record| {
+                record.state = JobState::Running;
+                record.started_at = Some(Utc::now());
+                record.message = format!("{kind} job {job_id} running");
+            });
+            let Some((record, reporter)) = started else {
+                return;
+            };
+            info!("{}", record.message);
+            if let Some(reporter) = reporter {
+                reporter.report(&record).await;
+            }
+
+            let outcome = work(context).await;
+            jobs.finish(job_id, outcome).await;
+        });
+        table.active.insert(
+            job_id,
+            ActiveJob {
+                cancellation,
+                reporter,
+                task: Some(task.abort_handle()),
+            },
+        );
+        record
+    }
+
+    /// Record of a job
+    pub fn get(&self, job_id: Uuid) -> Option<JobRecord> {
+        self.table()
+            .records
+            .iter()
+            .find(|r| r.job_id == job_id)
+            .cloned()
+    }
+
+    /// Records of every known job, oldest first
+    pub fn list(&self) -> Vec<JobRecord> {
+        self.table().records.clone()
+    }
+
+    /// Cancel a queued or running job
+    ///
+    /// A queued job never starts. The PostgreSQL tools of a running job are
+    /// terminated, and the job stops at its next step.
+    pub async fn cancel(&self, job_id: Uuid) -> Result<JobRecord> {
+        let (record, active) = {
+            let mut table = self.table();
+            let record = table
+                .records
+                .iter_mut()
+                .find(|r| r.job_id == job_id)
+                .ok_or_else(|| anyhow!("Unknown job {job_id}"))?;
+            if record.state.is_finished() {
+                return Err(anyhow!("Job {job_id} already {:?}", record.state));
+            }
+            let queued = record.state == JobState::Queued;
+            record.state = JobState::Cancelled;
+            record.finished_at = Some(Utc::now());
+            record.message = format!("{} job {job_id} cancelled", record.kind);
+            let record = record.clone();
+            let active = table.active.remove(&job_id);
+            if let Some(active) = &active {
+                active.cancellation.cancel();
+                // A running job is left to stop on its own, so it can clean up
+                if queued {
+                    if let Some(task) = &active.task {
+                        task.abort();
+                    }
+                }
+            }
+            self.save(&mut table);
+            (record, active)
+        };
+
+        info!("{}", record.message);
+        if let Some(reporter) = active.and_then(|a| a.reporter) {
+            reporter.report(&record).await;
+        }
+        Ok(record)
+    }
+
+    /// Record the outcome of a job, unless it was cancelled meanwhile
+    async fn finish(&self, job_id: Uuid, outcome: Result<serde_json::Value>) {
+        let reporter = {
+            let mut table = self.table();
+            let Some(record) = table
+                .records
+                .iter_mut()
+                .find(|r| r.job_id == job_id && !r.state.is_finished())
+            else {
+                return;
+            };
+            match outcome {
+                Ok(result) => {
+                    record.state = JobState::Succeeded;
+                    record.message = format!("{} job {job_id} succeeded", record.kind);
+                    record.result = Some(result);
+                    info!("{}", record.message);
+                }
+                Err(e) => {
+                    record.state = JobState::Failed;
+                    record.message = format!("{} job {job_id} failed: {e}", record.kind);
+                    error!("{}", record.message);
+                }
+            }
+            record.finished_at = Some(Utc::now());
+            let record = record.clone();
+            let reporter = table.active.remove(&job_id).and_then(|a| a.reporter);
+            self.save(&mut table);
+            reporter.map(|reporter| (reporter, record))
+        };
+        if let Some((reporter, record)) = reporter {
+            reporter.report(&record).await;
+        }
+    }
+
+    /// Change an unfinished job, returning its new record and reporter
+    fn update(
+        &self,
+        job_id: Uuid,
+        change: impl FnOnce(&mut JobRecord),
+    ) -> Option<(JobRecord, Option<JobReporter>)> {
+        let mut table = self.table();
+        let record = table
+            .records
+            .iter_mut()
+            .find(|r| r.job_id == job_id && !r.state.is_finished())?;
+        change(record);
+        let record = record.clone();
+        let reporter = table.active.get(&job_id).and_then(|a| a.reporter.clone());
+        self.save(&mut table);
+        Some((record, reporter))
+    }
+
+    /// Drop the oldest finished jobs beyond the history, then write the state file
+    fn save(&self, table: &mut JobTable) {
+        let finished = table
+            .records
+            .iter()
+            .filter(|r| r.state.is_finished())
+            .count();
+        let mut excess = finished.saturating_sub(self.history);
+        table.records.retain(|r| {
+            let trimmed = excess > 0 && r.state.is_finished();
+            if trimmed {
+                excess -= 1;
+            }
+            !trimmed
+        });
+
+        if let Err(e) = save_records(&self.state_file, &table.records) {
+            warn!(
+                "Failed to save job state to {}: {e}",
+                self.state_file.display()
+            );
+        }
+    }
+}
+
+fn load_records(path: &Path) -> Vec<JobRecord> {
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+            warn!("Ignoring invalid job state {}: {e}", path.display());
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn save_records(path: &Path, records: &[JobRecord]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Write then rename so a crash never leaves a truncated state behind
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(records)?)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+
+    fn config(dir: &Path) -> JobsConfig {
+        JobsConfig {
+            state_file: dir.join("jobs.json").to_string_lossy().into_owned(),
+            max_concurrent_backups: 1,
+            max_concurrent_restores: 1,
+            history: 2,
+        }
+    }
+
+    async fn wait_for(jobs: &JobManager, job_id: Uuid, state: JobState) -> JobRecord {
+        for _ in 0..500 {
+            let record = jobs.get(job_id).unwrap();
+            if record.state == state {
+                return record;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("Job {job_id} never reached {state:?}");
+    }
+
+    #[tokio::test]
+    async fn jobs_queue_behind_the_limit_and_record_their_outcome() {
+        let dir = tempfile::tempdir().unwrap();
+        let jobs = Arc::new(JobManager::new(&config(dir.path())));
+
+        let (release, released) = oneshot::channel::<()>();
+        let first = jobs.submit(JobKind::Backup, None, |ctx| async move {
+            ctx.progress("Copying".to_string()).await;
+            released.await?;
+            Ok(serde_json::json!({ "backup_id": "b1" }))
+        });
+        let second = jobs.submit(JobKind::Backup, None, |_| async {
+            Err(anyhow!("server unreachable"))
+        });
+        assert_eq!(first.state, JobState::Queued);
+
+        let running = wait_for(&jobs, first.job_id, JobState::Running).await;
+        assert!(running.started_at.is_some());
+        assert_eq!(
+            jobs.get(second.job_id).unwrap().state,
+            JobState::Queued,
+            "only one backup runs at once"
+        );
+
+        release.send(()).unwrap();
+        let done = wait_for(&jobs, first.job_id, JobState::Succeeded).await;
+        assert_eq!(done.result.unwrap()["backup_id"], "b1");
+        let failed = wait_for(&jobs, second.job_id, JobState::Failed).await;
+        assert!(failed.message.contains("server unreachable"));
+        assert!(failed.finished_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_running_job_terminates_its_tools() {
+        let dir = tempfile::tempdir().unwrap();
+        let jobs = Arc::new(JobManager::new(&config(dir.path())));
+
+        let running = jobs.submit(JobKind::Restore, None, |ctx| async move {
+            let cancellation = ctx.cancellation();
+            tokio::task::spawn_blocking(move || {
+                cancellation.status(Command::new("sleep").arg("30"))
+            })
+            .await??;
+            Ok(serde_json::Value::Null)
+        });
+        let queued = jobs.submit(JobKind::Restore, None, |_| async {
+            panic!("a cancelled job must not start")
+        });
+        wait_for(&jobs, running.job_id, JobState::Running).await;
+        // Let the tool start
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let cancelled = jobs.cancel(running.job_id).await.unwrap();
+        assert_eq!(cancelled.state, JobState::Cancelled);
+        assert_eq!(
+            jobs.cancel(queued.job_id).await.unwrap().state,
+            JobState::Cancelled
+        );
+        assert!(jobs.cancel(queued.job_id).await.is_err());
+
+        // The job ends as soon as its tool is gone, and stays cancelled
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while Arc::strong_count(&jobs) > 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the job did not stop");
+        assert_eq!(jobs.get(running.job_id).unwrap().state, JobState::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn jobs_sharing_a_backup_directory_take_turns() {
+        let dir = tempfile::tempdir().unwrap();
+        let jobs = Arc::new(JobManager::new(&config(dir.path())));
+        let backup_dir = dir.path().join("backups");
+
+        let (release, released) = oneshot::channel::<()>();
+        let locked = backup_dir.clone();
+        let first = jobs.submit(JobKind::Backup, None, |ctx| async move {
+            let _guard = ctx.lock_dir(&locked).await;
+            released.await?;
+            Ok(serde_json::Value::Null)
+        });
+        wait_for(&jobs, first.job_id, JobState::Running).await;
+        let locked = backup_dir.clone();
+        let second = jobs.submit(JobKind::Restore, None, |ctx| async move {
+            let _guard = ctx.lock_dir(&locked).await;
+            Ok(serde_json::Value::Null)
+        });
+
+        wait_for(&jobs, second.job_id, JobState::Running).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let waiting = jobs.get(second.job_id).unwrap();
+        assert_eq!(waiting.state, JobState::Running);
+        assert!(waiting.message.contains("Waiting for another job"));
+
+        release.send(()).unwrap();
+        wait_for(&jobs, second.job_id, JobState::Succeeded).await;
+    }
+
+    #[tokio::test]
+    async fn state_survives_restarts_and_history_is_trimmed() {
+        let dir = tempfile::tempdir().unwrap();
+        let jobs = Arc::new(JobManager::new(&config(dir.path())));
+        let mut finished = Vec::new();
+        for i in 0..3 {
+            let job = jobs.submit(JobKind::Backup, None, move |_| async move {
+                Ok(serde_json::json!(i))
+            });
+            wait_for(&jobs, job.job_id, JobState::Succeeded).await;
+            finished.push(job.job_id);
+        }
+        let (_hold, held) = oneshot::channel::<()>();
+        let unfinished = jobs.submit(JobKind::Backup, None, |_| async move {
+            held.await?;
+            Ok(serde_json::Value::Null)
+        });
+        wait_for(&jobs, unfinished.job_id, JobState::Running).await;
+
+        let ids: Vec<Uuid> = jobs.list().iter().map(|r| r.job_id).collect();
+        assert_eq!(ids, [finished[1], finished[2], unfinished.job_id]);
+
+        // A new daemon reads the same file
+        let restarted = JobManager::new(&config(dir.path()));
+        assert_eq!(
+            restarted.get(finished[2]).unwrap().result,
+            Some(serde_json::json!(2))
+        );
+        let interrupted = restarted.get(unfinished.job_id).unwrap();
+        assert_eq!(interrupted.state, JobState::Failed);
+        assert!(interrupted.message.contains("restart"));
+        assert_eq!(
+            restarted.list().len(),
+            2,
+            "the interrupted job counts as finished"
+        );
+    }
+}
@@ -1,97 +1,68 @@
 pub mod amqp;
 pub mod cli;
 pub mod handlers;
+pub mod jobs;
+pub mod mqtt;
+pub mod scheduler;
+pub mod transport;
 
 use amqp::AmqpClient;
 use anyhow::{anyhow, Context, Result};
 use common::config::WardenConfig;
 use futures::StreamExt;
-use lapin::message::Delivery;
 use lapin::options::BasicAckOptions;
 use log::{debug, error, info, warn};
 use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc;
 use tokio::task;
+use transport::{Protocol, Transport};
 
-/// Main daemon struct that handles AMQP communication
+/// Main daemon struct that handles AMQP or MQTT communication
 #[derive()]
 pub struct Daemon {
     config: Arc<Mutex<WardenConfig>>,
-    amqp_client: Option<Arc<amqp::AmqpClient>>,
+    transport: Option<Transport>,
+    /// Connection of the MQTT client, until `start` drives it
+    mqtt_eventloop: Option<rumqttc::EventLoop>,
+    jobs: Arc<jobs::JobManager>,
 }
 
 impl Daemon {
     /// Create a new daemon instance with the given configuration
     pub fn new(config: WardenConfig) -> Self {
+        let jobs = Arc::new(jobs::JobManager::new(&config.jobs));
         Daemon {
             config: Arc::new(Mutex::new(config)),
-            amqp_client: None,
+            transport: None,
+            mqtt_eventloop: None,
+            jobs,
         }
     }
 
-    /// Initialize the AMQP client with the current configuration
-    pub async fn init_amqp(&mut self) -> Result<()> {
-        // Extract config fields up front to avoid holding the MutexGuard across await
-        let (c2_server, c2_auth_id, c2_auth_secret) = {
-            let config_guard = self.config.lock().unwrap();
-            (
-                config_guard.c2_server.clone(),
-                config_guard.c2_auth.id.clone(),
-                config_guard.c2_auth.secret.clone(),
-            )
-        };
-        // MutexGuard is dropped here before any await points
-
-        // Create AMQP config from configuration
-        // Use C2 server as default broker
-        let _default_broker = format!("amqp://{c2_server}");
-
-        // Create AMQP config based on available configuration
-        let amqp_config = {
-            // Extract host from C2 server URL
-            let amqp_host = c2_server.clone();
-            let amqp_host = amqp_host.replace("http://", "").replace("https://", "");
-
-            // Split host and port
-            let parts: Vec<&str> = amqp_host.split(':').collect();
-            let host = parts[0].to_string();
-            let port = if parts.len() > 1 {
-                parts[1].parse::<u16>().unwrap_or(5672)
-            } else {
-                5672 // Default AMQP port
-            };
+    /// Create the client of the protocol chosen by `mqtt.protocol`
+    pub async fn connect(&mut self) -> Result<()> {
+        let protocol = Protocol::from_config(&self.config.lock().unwrap())?;
+        match protocol {
+            Protocol::Amqp => self.init_amqp().await,
+            Protocol::Mqtt => self.init_mqtt(),
+        }
+    }
 
-            let username = if !c2_auth_id.is_empty() {
-                Some(c2_auth_id.clone())
-            } else {
-                None
-            };
-            let password = if !c2_auth_secret.is_empty() {
-                Some(c2_auth_secret.clone())
-            } else {
-                None
-            };
+    /// Initialize the MQTT client with the current configuration
+    pub fn init_mqtt(&mut self) -> Result<()> {
+        let settings = mqtt::MqttSettings::from_config(&self.config.lock().unwrap())?;
+        let (client, eventloop) = mqtt::MqttClient::new(&settings)?;
+        info!("Subscribing to MQTT topics for {:?}", settings.routing_keys);
 
-            amqp::AmqpConfig {
-                host,
-                port,
-                username,
-                password,
-                client_id: format!("warden-{c2_auth_id}"),
-                vhost: Some("/".to_string()),
-                exchange: "warden".to_string(),
-                queues: vec![
-                    "warden.commands".to_string(),
-                    "warden.config".to_string(),
-                    "warden.events".to_string(),
-                ],
-                routing_keys: vec![
-                    "warden.commands.#".to_string(),
-                    "warden.config".to_string(),
-                    "warden.events.#".to_string(),
-                ],
-            }
-        };
+        self.transport = Some(Transport::Mqtt(client));
+        self.mqtt_eventloop = Some(eventloop);
+        Ok(())
+    }
+
+    /// Initialize the AMQP client with the current configuration
+    pub async fn init_amqp(&mut self) -> Result<()> {
+        // Built before connecting, not to hold the MutexGuard across await
+        let amqp_config = amqp::AmqpConfig::from_config(&self.config.lock().unwrap())?;
 
         // Create AMQP client
         let client = amqp::AmqpClient::new(&amqp_config).await?;
@@ -104,67 +75,85 @@ impl Daemon {
         info!("Using exchange: {}", amqp_config.exchange);
         info!("Routing keys: {:?}", amqp_config.routing_keys);
 
-        self.amqp_client = Some(Arc::new(client));
+        self.transport = Some(Transport::Amqp(client));
 
         Ok(())
     }
 
-    /// Start the daemon and begin processing AMQP messages
+    /// Start the daemon and begin processing messages
     pub async fn start(&mut self) -> Result<()> {
-        // Initialize AMQP client if not already done
-        if self.amqp_client.is_none() {
-            self.init_amqp().await?;
+        // Initialize the client if not already done
+        if self.transport.is_none() {
+            self.connect().await?;
         }
 
-        let client = self
-            .amqp_client
-            .as_ref()
-            .ok_or_else(|| anyhow!("AMQP client not initialized"))?
-            .clone();
+        let transport = self
+            .transport
+            .clone()
+            .ok_or_else(|| anyhow!("Broker client not initialized"))?;
         let config = Arc::clone(&self.config);
 
-        // Get configuration for exchange, queues, and routing keys
-        let (exchange, queue_bindings) = self.get_queue_configuration();
-
-        // Set up AMQP infrastructure
-        self.setup_amqp_infrastructure(&client, &exchange, &queue_bindings)
-            .await?;
+        let exchange = transport::exchange(&config.lock().unwrap());
 
         // Create message processing channel
-        let (tx, rx) = mpsc::channel::<(String, String, Delivery)>(100);
+        let (tx, rx) = mpsc::channel::<(String, String, String)>(100);
 
-        // Spawn consumer tasks for each queue
-        let consumer_tasks = self
-            .spawn_consumer_tasks(&client, &queue_bindings, tx.clone())
-            .await?;
+        let (consumer_tasks, supervisor) = match &transport {
+            Transport::Amqp(client) => {
+                // Queues and the routing keys they are bound with
+                let queue_bindings = self.get_queue_configuration()?;
+
+                // Set up AMQP infrastructure
+                self.setup_amqp_infrastructure(client, &exchange, &queue_bindings)
+                    .await?;
+
+                // Reconnect and declare everything again whenever the broker goes away
+                let supervisor = client.supervise();
+
+                // Spawn consumer tasks for each queue
+                let consumer_tasks = self
+                    .spawn_consumer_tasks(client, &queue_bindings, tx.clone())
+                    .await?;
+                (consumer_tasks, Some(supervisor))
+            }
+            Transport::Mqtt(client) => {
+                let eventloop = self
+                    .mqtt_eventloop
+                    .take()
+                    .ok_or_else(|| anyhow!("MQTT client not initialized"))?;
+                // The event loop subscribes, and resubscribes after reconnecting
+                (vec![client.run(eventloop, tx.clone())], None)
+            }
+        };
+
+        // Run scheduled backups next to message processing
+        let scheduler = scheduler::Scheduler::new(
+            Arc::clone(&self.config),
+            transport.clone(),
+            exchange.clone(),
+            Arc::clone(&self.jobs),
+        );
+        let scheduler_task = task::spawn(scheduler.run());
 
         // Spawn message processing task
         let process_task = self
-            .spawn_message_processor(rx, client.clone(), config)
+            .spawn_message_processor(rx, transport, config, Arc::clone(&self.jobs))
             .await?;
 
         // Monitor tasks and handle unexpected termination
         self.monitor_tasks(consumer_tasks, process_task).await;
+        scheduler_task.abort();
+        if let Some(supervisor) = supervisor {
+            supervisor.abort();
+        }
 
         Ok(())
     }
 
-    /// Get queue configuration from config or use defaults
-    fn get_queue_configuration(&self) -> (String, Vec<(String, String)>) {
-        // Default values
-        let default_exchange = "warden".to_string();
-        let default_bindings = vec![
-            (
-                "warden.commands".to_string(),
-                "warden.commands.#".to_string(),
-            ),
-            ("warden.config".to_string(), "warden.config".to_string()),
-            ("warden.events".to_string(), "warden.events.#".to_string()),
-        ];
-
-        // TODO: In the future, we could extract these from self.config if custom values are needed
-
-        (default_exchange, default_bindings)
+    /// Get the queues and the routing keys binding them from config
+    fn get_queue_configuration(&self) -> Result<Vec<(String, String)>> {
+        let amqp_config = amqp::AmqpConfig::from_config(&self.config.lock().unwrap())?;
+        Ok(amqp_config.bindings())
     }
 
     /// Set up AMQP infrastructure (exchange, queues, bindings)
@@ -207,7 +196,7 @@ impl Daemon {
         &self,
         client: &Arc<AmqpClient>,
         queue_bindings: &[(String, String)],
-        tx: mpsc::Sender<(String, String, Delivery)>,
+        tx: mpsc::Sender<(String, String, String)>,
     ) -> Result<Vec<task::JoinHandle<()>>> {
         let mut consumer_tasks = Vec::new();
 
@@ -217,7 +206,10 @@ impl Daemon {
             let tx_clone = tx.clone();
 
             let task = task::spawn(async move {
+                let mut generation = 0;
                 loop {
+                    // Consume again from every new connection
+                    generation = client_clone.wait_connected(generation).await;
                     match client_clone.consume(&queue_name).await {
                         Ok(mut consumer) => {
                             info!("Started consuming from queue: {queue_name}");
@@ -233,12 +225,9 @@ impl Daemon {
                                             "Received message on routing key {routing_key}: {payload}"
                                         );
 
-                                        // Get delivery tag before moving the delivery
-                                        let delivery_tag = delivery.delivery_tag;
-
                                         // Send to processing channel
                                         if let Err(e) = tx_clone
-                                            .send((queue_name.clone(), routing_key, delivery))
+                                            .send((queue_name.clone(), routing_key, payload))
                                             .await
                                         {
                                             error!(
@@ -247,11 +236,8 @@ impl Daemon {
                                         }
 
                                         // Acknowledge the message
-                                        let channel = client_clone.channel();
-                                        let channel_guard = channel.lock().await;
-                                        if let Err(e) = channel_guard
-                                            .basic_ack(delivery_tag, BasicAckOptions::default())
-                                            .await
+                                        if let Err(e) =
+                                            delivery.acker.ack(BasicAckOptions::default()).await
                                         {
                                             error!("Failed to acknowledge message: {e}");
                                         }
@@ -260,20 +246,18 @@ impl Daemon {
                                         error!(
                                             "Error receiving message from queue {queue_name}: {e}"
                                         );
+                                        break;
                                     }
                                 }
                             }
 
-                            // If we get here, the consumer has ended - we'll try to reconnect
-                            warn!("Consumer for queue {queue_name} ended unexpectedly, reconnecting in 5 seconds...");
-                            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                            warn!("Consumer for queue {queue_name} ended, waiting for the connection to come back");
                         }
                         Err(e) => {
                             error!("Failed to consume from queue {queue_name}: {e}");
-                            // Wait before retrying
-                            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
                         }
                     }
+                    client_clone.report_lost(generation);
                 }
             });
 
@@ -286,19 +270,24 @@ impl Daemon {
     /// Spawn message processor task
     async fn spawn_message_processor(
         &self,
-        mut rx: mpsc::Receiver<(String, String, Delivery)>,
-        client: Arc<AmqpClient>,
+        mut rx: mpsc::Receiver<(String, String, String)>,
+        client: Transport,
         config: Arc<Mutex<WardenConfig>>,
+        jobs: Arc<jobs::JobManager>,
     ) -> Result<task::JoinHandle<()>> {
         let process_task = task::spawn(async move {
-            while let Some((queue, routing_key, delivery)) = rx.recv().await {
-                let payload = String::from_utf8_lossy(&delivery.data).to_string();
-
+            while let Some((queue, routing_key, payload)) = rx.recv().await {
                 let result = match true {
                     // Command handling
                     _ if queue.contains("commands") || routing_key.contains("commands") => {
-                        handlers::command::handle_command(&routing_key, &payload, &client, &config)
-                            .await
+                        handlers::command::handle_command(
+                            &routing_key,
+                            &payload,
+                            &client,
+                            &config,
+                            &jobs,
+                        )
+                        .await
                     }
                     // Config update handling
                     _ if queue.contains("config") || routing_key.contains("config") => {
@@ -364,56 +353,35 @@ impl Daemon {
 
     /// Stop the daemon and clean up resources
     pub async fn stop(&self) -> Result<()> {
-        if let Some(client) = &self.amqp_client {
+        if let Some(client) = &self.transport {
             // Determine status exchange and routing key from config
             let (exchange, status_routing_key) = {
                 let config_guard = self.config.lock().unwrap();
-                // Default values
-                let default_exchange = "warden".to_string();
-                let default_status_key = "warden.status".to_string();
-
-                if let Some(mqtt_config) = &config_guard.mqtt {
-                    let exchange = mqtt_config
-                        .exchange
-                        .clone()
-                        .unwrap_or_else(|| "warden".to_string());
-
-                    // Check if a status topic is defined in the topics list
-                    let status_key = mqtt_config
-                        .topics
-                        .as_ref()
-                        .and_then(|topics| {
-                            topics
-                                .iter()
-                                .find(|t| t.contains("status"))
-                                .cloned()
-                                .or(Some("warden.status".to_string()))
-                        })
-                        .unwrap_or_else(|| "warden.status".to_string());
-
-                    (exchange, status_key.to_string())
-                } else {
-                    (default_exchange, default_status_key)
-                }
+                (
+                    transport::exchange(&config_guard),
+                    transport::status_routing_key(&config_guard),
+                )
             };
 
-            // Publish a last will message
-            match client
-                .publish(
-                    &exchange,
-                    &status_routing_key,
-                    amqp::MessageType::Response,
-                    "offline",
-                )
-                .await
-            {
-                Ok(_) => info!("Published offline status"),
-                Err(e) => error!("Failed to publish offline status: {e}"),
+            // The MQTT client retains its offline status when closing
+            if let Transport::Amqp(_) = client {
+                match client
+                    .publish(
+                        &exchange,
+                        &status_routing_key,
+                        amqp::MessageType::Response,
+                        "offline",
+                    )
+                    .await
+                {
+                    Ok(_) => info!("Published offline status"),
+                    Err(e) => error!("Failed to publish offline status: {e}"),
+                }
             }
 
             // Close connection gracefully
             if let Err(e) = client.close().await {
-                error!("Error closing AMQP connection: {e}");
+                error!("Error closing broker connection: {e}");
             }
         }
 
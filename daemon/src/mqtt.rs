@@ -1,187 +1,446 @@
-use anyhow::{Result, Context, anyhow};
-use lapin::{options::*, types::FieldTable, BasicProperties, Connection, ConnectionProperties, Channel, ExchangeKind, publisher_confirm::Confirmation};
-use serde::{Serialize, Deserialize};
-use std::sync::Arc;
-use tokio::sync::Mutex;
+//! MQTT transport, for brokers such as Mosquitto that do not speak AMQP.
+//!
+//! Routing keys map to topics by turning their dots into slashes, and the
+//! `*` wildcard into `+`: `warden.commands.#` is subscribed to as
+//! `warden/commands/#`, and a message on `warden/commands/status` reaches
+//! the handlers as `warden.commands.status`, exactly as over AMQP.
+//!
+//! The daemon's status is retained on the status topic: `online` on every
+//! connection, and `offline` as the last will the broker publishes when the
+//! daemon disappears without closing its connection.
+//!
+//! A broker written `mqtts://host[:port]` is reached over TLS, checked
+//! against the system certificates or those of `ca_file`.
+
+use crate::amqp::{Message, MessageType};
+use crate::transport::{status_routing_key, subscribed_routing_keys};
+use anyhow::{anyhow, Context, Result};
 use common::config::WardenConfig;
-use log::{info, debug, error};
-use std::time::{SystemTime, UNIX_EPOCH};
+use log::{debug, error, info, warn};
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, QoS, Transport};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task;
 
-/// Re-export MessageType from amqp module to maintain compatibility
-pub use crate::amqp::MessageType;
+const DEFAULT_MQTT_PORT: u16 = 1883;
+const DEFAULT_MQTTS_PORT: u16 = 8883;
 
-/// Re-export Message from amqp module to maintain compatibility
-pub use crate::amqp::Message;
+/// Requests waiting to be sent, rumqttc buffers them while disconnected
+const REQUEST_CAPACITY: usize = 100;
 
-/// MQTT client wrapper using lapin
-pub struct MqttClient {
-    connection: Connection,
-    channel: Arc<Mutex<Channel>>,
+/// Incoming messages held while their processing catches up, later ones are dropped
+const INCOMING_CAPACITY: usize = 1000;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// Delays between reconnection attempts, doubled after every failure
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Connection details of an MQTT broker
+#[derive(Debug, Clone)]
+pub struct MqttSettings {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub qos: QoS,
+    /// TLS of an `mqtts://` broker, `None` for plain TCP
+    pub tls: Option<MqttTls>,
+    /// Routing keys to subscribe to
+    pub routing_keys: Vec<String>,
+    /// Routing key of the retained online and offline status
+    pub status_routing_key: String,
 }
 
-impl MqttClient {
-    /// Create a new MQTT client using lapin
-    pub async fn new(config: &MqttConfig) -> Result<Self> {
-        let uri = format!(
-            "amqp://{}:{}@{}:{}/",
-            config.username.as_deref().unwrap_or("guest"),
-            config.password.as_deref().unwrap_or("guest"),
-            config.host,
-            config.port
-        );
-        
-        info!("Connecting to MQTT broker at {}", uri);
-        
-        let connection = Connection::connect(
-            &uri,
-            ConnectionProperties::default(),
-        ).await.context("Failed to connect to MQTT broker")?;
-        
-        let channel = connection.create_channel().await
-            .context("Failed to create MQTT channel")?;
-        
+/// How the broker and the daemon prove who they are over TLS
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MqttTls {
+    /// PEM certificates trusted instead of the system ones
+    pub ca_file: Option<String>,
+    /// PEM certificate and private key files the daemon authenticates with
+    pub client_auth: Option<(String, String)>,
+}
+
+impl MqttTls {
+    /// Transport reaching the broker with these settings
+    fn transport(&self) -> Result<Transport> {
+        let read = |path: &str| {
+            std::fs::read(path).with_context(|| format!("Failed to read MQTT TLS file {path}"))
+        };
+        let client_auth = match &self.client_auth {
+            Some((cert, key)) => Some((read(cert)?, read(key)?)),
+            None => None,
+        };
+        match (&self.ca_file, client_auth) {
+            (Some(ca), client_auth) => Ok(Transport::tls(read(ca)?, client_auth, None)),
+            (None, None) => Ok(Transport::tls_with_default_config()),
+            (None, Some(_)) => Err(anyhow!(
+                "An MQTT client certificate needs the ca_file of the broker"
+            )),
+        }
+    }
+}
+
+/// Host, port and whether TLS is on, of a `broker` written `host`, `mqtt://host[:port]` or `mqtts://host[:port]`
+fn parse_broker(broker: &str) -> Result<(String, Option<u16>, bool)> {
+    let (address, tls) = match broker.split_once("://") {
+        None => (broker, false),
+        Some(("mqtt" | "tcp", address)) => (address, false),
+        Some(("mqtts" | "ssl", address)) => (address, true),
+        Some((scheme, _)) => {
+            return Err(anyhow!(
+                "Invalid MQTT broker {broker}, expected mqtt:// or mqtts:// rather than {scheme}://"
+            ))
+        }
+    };
+    let address = address.trim_end_matches('/');
+    match address.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port
+                .parse()
+                .map_err(|_| anyhow!("Invalid port in MQTT broker {broker}"))?;
+            Ok((host.to_string(), Some(port), tls))
+        }
+        None => Ok((address.to_string(), None, tls)),
+    }
+}
+
+impl MqttSettings {
+    /// Settings of the `mqtt` section of the configuration
+    pub fn from_config(config: &WardenConfig) -> Result<Self> {
+        let mqtt = config
+            .mqtt
+            .as_ref()
+            .ok_or_else(|| anyhow!("The MQTT protocol needs an [mqtt] section"))?;
+        let qos = match mqtt.qos.unwrap_or(1) {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            other => return Err(anyhow!("Invalid MQTT QoS {other}, expected 0, 1 or 2")),
+        };
+        let (host, broker_port, tls) = parse_broker(&mqtt.broker)?;
+        let client_auth = match (&mqtt.client_cert_file, &mqtt.client_key_file) {
+            (Some(cert), Some(key)) => Some((cert.clone(), key.clone())),
+            (None, None) => None,
+            _ => {
+                return Err(anyhow!(
+                    "mqtt.client_cert_file and mqtt.client_key_file go together"
+                ))
+            }
+        };
+        let tls = tls.then(|| MqttTls {
+            ca_file: mqtt.ca_file.clone(),
+            client_auth,
+        });
+        let default_port = if tls.is_some() {
+            DEFAULT_MQTTS_PORT
+        } else {
+            DEFAULT_MQTT_PORT
+        };
         Ok(Self {
-            connection,
-            channel: Arc::new(Mutex::new(channel)),
+            host,
+            port: mqtt.port.or(broker_port).unwrap_or(default_port),
+            client_id: mqtt
+                .client_id
+                .clone()
+                .unwrap_or_else(|| format!("warden-{}", config.c2_auth.id)),
+            username: mqtt.username.clone(),
+            password: mqtt.password.clone(),
+            qos,
+            tls,
+            routing_keys: subscribed_routing_keys(config),
+            status_routing_key: status_routing_key(config),
         })
     }
-    
-    /// Declare an exchange
-    pub async fn declare_exchange(&self, name: &str) -> Result<()> {
-        let channel = self.channel.lock().await;
-        channel.exchange_declare(
-            name,
-            ExchangeKind::Topic,
-            ExchangeDeclareOptions {
-                durable: true,
-                ..Default::default()
-            },
-            FieldTable::default(),
-        ).await.context("Failed to declare exchange")?;
-        
-        debug!("Declared exchange: {}", name);
-        Ok(())
+}
+
+/// MQTT topic, or topic filter, of a routing key
+pub fn topic(routing_key: &str) -> String {
+    routing_key
+        .split('.')
+        .map(|level| if level == "*" { "+" } else { level })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Routing key of an MQTT topic
+pub fn routing_key(topic: &str) -> String {
+    topic.replace('/', ".")
+}
+
+/// Status message in the envelope of every other message
+fn status_message(status: &str) -> Result<Vec<u8>> {
+    let message = Message {
+        message_type: MessageType::Response,
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        payload: status,
+    };
+    serde_json::to_vec(&message).context("Failed to serialize status")
+}
+
+/// MQTT client wrapper
+///
+/// The connection itself lives in the [`EventLoop`] returned by
+/// [`MqttClient::new`], which [`MqttClient::run`] drives.
+pub struct MqttClient {
+    client: AsyncClient,
+    qos: QoS,
+    status_topic: String,
+    routing_keys: Vec<String>,
+    closed: AtomicBool,
+}
+
+impl MqttClient {
+    /// Create a client, connecting once its event loop runs
+    pub fn new(settings: &MqttSettings) -> Result<(Arc<Self>, EventLoop)> {
+        let status_topic = topic(&settings.status_routing_key);
+        let mut options = MqttOptions::new(&settings.client_id, &settings.host, settings.port);
+        options
+            .set_keep_alive(KEEP_ALIVE)
+            // Let the broker keep commands sent while the daemon is away
+            .set_clean_session(false)
+            .set_last_will(LastWill::new(
+                &status_topic,
+                status_message("offline")?,
+                settings.qos,
+                true,
+            ));
+        if let Some(username) = &settings.username {
+            options.set_credentials(username, settings.password.as_deref().unwrap_or(""));
+        }
+        if let Some(tls) = &settings.tls {
+            options.set_transport(tls.transport()?);
+        }
+
+        info!(
+            "Connecting to MQTT broker at {}:{}{}",
+            settings.host,
+            settings.port,
+            if settings.tls.is_some() {
+                " over TLS"
+            } else {
+                ""
+            }
+        );
+        let (client, eventloop) = AsyncClient::new(options, REQUEST_CAPACITY);
+        let client = Arc::new(Self {
+            client,
+            qos: settings.qos,
+            status_topic,
+            routing_keys: settings.routing_keys.clone(),
+            closed: AtomicBool::new(false),
+        });
+        Ok((client, eventloop))
     }
-    
-    /// Publish a message to a topic
+
+    /// Publish a message on the topic of a routing key
     pub async fn publish<T: Serialize>(
-        &self, 
-        exchange: &str, 
-        topic: &str, 
+        &self,
+        routing_key: &str,
         message_type: MessageType,
-        payload: T
-    ) -> Result<Confirmation> {
+        payload: T,
+    ) -> Result<()> {
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs();
-        
+
         let message = Message {
             message_type,
             timestamp,
             payload,
         };
-        
-        let json = serde_json::to_string(&message)
-            .context("Failed to serialize message")?;
-        
-        let channel = self.channel.lock().await;
-        let confirm = channel.basic_publish(
-            exchange,
-            topic,
-            BasicPublishOptions::default(),
-            json.as_bytes(),
-            BasicProperties::default(),
-        ).await.context("Failed to publish message")?
-        .await.context("Failed to get publish confirmation")?;
-        
-        if confirm.is_ack() {
-            debug!("Published message to {}/{}: {}", exchange, topic, json);
-            Ok(confirm)
-        } else {
-            Err(anyhow!("Message was not acknowledged by the broker"))
-        }
+
+        let json = serde_json::to_vec(&message).context("Failed to serialize message")?;
+        let topic = topic(routing_key);
+        self.client
+            .publish(&topic, self.qos, false, json)
+            .await
+            .context("Failed to publish message")?;
+
+        debug!("Published message to {topic}");
+        Ok(())
     }
-    
-    /// Subscribe to a topic
-    pub async fn subscribe(&self, queue: &str) -> Result<lapin::Consumer> {
-        let channel = self.channel.lock().await;
-        let consumer = channel.basic_consume(
-            queue,
-            &format!("consumer-{}", uuid::Uuid::new_v4()),
-            BasicConsumeOptions::default(),
-            FieldTable::default(),
-        ).await.context("Failed to subscribe to topic")?;
-        
-        debug!("Subscribed to topic: {}", queue);
-        Ok(consumer)
+
+    /// Publish the retained status of the daemon
+    pub async fn publish_status(&self, status: &str) -> Result<()> {
+        self.client
+            .publish(&self.status_topic, self.qos, true, status_message(status)?)
+            .await
+            .context("Failed to publish status")
     }
-    
-    /// Get the channel
-    pub fn channel(&self) -> Arc<Mutex<Channel>> {
-        self.channel.clone()
+
+    /// Drive the connection, forwarding incoming messages to `tx`
+    ///
+    /// Messages are sent as their topic, routing key and payload. After a
+    /// connection error the next poll reconnects, with exponential backoff.
+    ///
+    /// A separate task hands the messages to `tx`, so slow processing never
+    /// keeps the event loop from answering the broker's pings. Up to
+    /// [`INCOMING_CAPACITY`] messages wait for it, later ones are dropped.
+    pub fn run(
+        self: &Arc<Self>,
+        mut eventloop: EventLoop,
+        tx: mpsc::Sender<(String, String, String)>,
+    ) -> task::JoinHandle<()> {
+        let client = Arc::clone(self);
+        let (incoming, mut waiting) = mpsc::channel(INCOMING_CAPACITY);
+        task::spawn(async move {
+            while let Some(message) = waiting.recv().await {
+                if tx.send(message).await.is_err() {
+                    error!("Message processing stopped, no longer consuming MQTT messages");
+                    return;
+                }
+            }
+        });
+        task::spawn(async move {
+            let mut delay = RECONNECT_MIN_DELAY;
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(ack))) => {
+                        info!("Connected to the MQTT broker");
+                        delay = RECONNECT_MIN_DELAY;
+                        // The event loop sends requests, so they must not wait on it
+                        if !ack.session_present {
+                            client.subscribe();
+                        }
+                        if let Ok(status) = status_message("online") {
+                            if let Err(e) = client.client.try_publish(
+                                &client.status_topic,
+                                client.qos,
+                                true,
+                                status,
+                            ) {
+                                warn!("Failed to publish online status: {e}");
+                            }
+                        }
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        let payload = String::from_utf8_lossy(&publish.payload).to_string();
+                        let routing_key = routing_key(&publish.topic);
+                        match incoming.try_send((publish.topic, routing_key, payload)) {
+                            Ok(()) => {}
+                            Err(TrySendError::Full((topic, _, _))) => error!(
+                                "Dropped MQTT message on {topic}, {INCOMING_CAPACITY} messages are already waiting"
+                            ),
+                            Err(TrySendError::Closed(_)) => return,
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(_) if client.closed.load(Ordering::SeqCst) => return,
+                    Err(e) => {
+                        warn!("MQTT connection error: {e}, reconnecting in {delay:?}");
+                        tokio::time::sleep(delay).await;
+                        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+                    }
+                }
+            }
+        })
+    }
+
+    fn subscribe(&self) {
+        for routing_key in &self.routing_keys {
+            let filter = topic(routing_key);
+            match self.client.try_subscribe(&filter, self.qos) {
+                Ok(()) => info!("Subscribed to MQTT topic {filter}"),
+                Err(e) => error!("Failed to subscribe to MQTT topic {filter}: {e}"),
+            }
+        }
     }
-    
-    /// Close the connection
+
+    /// Publish the offline status and disconnect
     pub async fn close(&self) -> Result<()> {
-        self.connection.close(0, "").await
-            .context("Failed to close MQTT connection")
+        self.publish_status("offline").await?;
+        self.closed.store(true, Ordering::SeqCst);
+        self.client
+            .disconnect()
+            .await
+            .context("Failed to disconnect from MQTT broker")
     }
 }
 
-/// Helper struct for MQTT operations
-pub struct MqttHelper;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl MqttHelper {
-    /// Parse a message from JSON
-    pub fn parse_message<T: for<'de> Deserialize<'de>>(payload: &str) -> Result<Message<T>> {
-        serde_json::from_str(payload)
-            .context("Failed to parse message payload")
+    #[test]
+    fn routing_keys_and_topics_map_both_ways() {
+        assert_eq!(topic("warden.commands.#"), "warden/commands/#");
+        assert_eq!(topic("warden.*.status"), "warden/+/status");
+        assert_eq!(topic("warden.config"), "warden/config");
+        assert_eq!(
+            routing_key("warden/commands/PostgresBackup"),
+            "warden.commands.PostgresBackup"
+        );
+        // A topic already written with slashes is left alone
+        assert_eq!(topic("site1/commands/#"), "site1/commands/#");
     }
-    
-    /// Extract the subtopic from a topic
-    pub fn get_subtopic(topic: &str, prefix: &str) -> Option<String> {
-        if topic.starts_with(prefix) {
-            Some(topic[prefix.len()..].to_string())
-        } else {
-            None
-        }
+
+    fn settings(mqtt: &str) -> Result<MqttSettings> {
+        let content = format!(
+            r#"
+            c2_server = "localhost:5672"
+            [c2_auth]
+            id = "site-1"
+            secret = ""
+            [features]
+            Overwatch = false
+            PostgresBackup = true
+            [mqtt]
+            {mqtt}
+            "#
+        );
+        MqttSettings::from_config(&toml::from_str(&content).unwrap())
     }
-}
 
-/// Struct to hold MQTT connection details
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct MqttConfig {
-    pub host: String,
-    pub port: u16,
-    pub username: Option<String>,
-    pub password: Option<String>,
-    pub client_id: String,
-}
+    #[test]
+    fn mqtts_brokers_use_tls() {
+        let plain = settings(r#"broker = "mosquitto.local""#).unwrap();
+        assert_eq!((plain.host.as_str(), plain.port), ("mosquitto.local", 1883));
+        assert!(plain.tls.is_none());
 
-impl MqttConfig {
-    /// Create MQTT config from WardenConfig
-    pub fn from_warden_config(config: &WardenConfig) -> Self {
-        // Extract MQTT broker details from the C2 server URL
-        let mqtt_host = config.c2_server.clone();
-        let mqtt_host = mqtt_host.replace("http://", "").replace("https://", "");
-        
-        // Split host and port
-        let parts: Vec<&str> = mqtt_host.split(':').collect();
-        let host = parts[0].to_string();
-        let port = if parts.len() > 1 {
-            parts[1].parse::<u16>().unwrap_or(1883)
-        } else {
-            1883 // Default MQTT port
-        };
-        
-        // Create MQTT config
-        MqttConfig {
-            host,
-            port,
-            username: if !config.c2_auth.id.is_empty() { Some(config.c2_auth.id.clone()) } else { None },
-            password: if !config.c2_auth.secret.is_empty() { Some(config.c2_auth.secret.clone()) } else { None },
-            client_id: format!("warden-{}", config.c2_auth.id),
-        }
+        let tls = settings(
+            r#"
+            broker = "mqtts://mosquitto.local"
+            ca_file = "/etc/warden/ca.pem"
+            client_cert_file = "/etc/warden/client.pem"
+            client_key_file = "/etc/warden/client.key"
+            "#,
+        )
+        .unwrap();
+        assert_eq!((tls.host.as_str(), tls.port), ("mosquitto.local", 8883));
+        assert_eq!(
+            tls.tls,
+            Some(MqttTls {
+                ca_file: Some("/etc/warden/ca.pem".to_string()),
+                client_auth: Some((
+                    "/etc/warden/client.pem".to_string(),
+                    "/etc/warden/client.key".to_string()
+                )),
+            })
+        );
+
+        let port = settings(r#"broker = "mqtts://10.0.0.5:9883""#).unwrap();
+        assert_eq!(port.port, 9883);
+        let overridden = settings("broker = \"mqtt://10.0.0.5:9883\"\nport = 1884").unwrap();
+        assert_eq!(overridden.port, 1884);
+        assert!(overridden.tls.is_none());
+
+        assert!(settings(r#"broker = "ws://mosquitto.local""#).is_err());
+        assert!(settings(
+            r#"
+            broker = "mqtts://mosquitto.local"
+            client_cert_file = "/etc/warden/client.pem"
+            "#
+        )
+        .is_err());
     }
 }
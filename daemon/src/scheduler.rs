@@ -0,0 +1,550 @@
+//! Cron-style scheduler for PostgreSQL backups.
+//!
+//! Jobs from `WardenConfig::schedules` are evaluated on a short tick. When one
+//! or more cron slots of a job passed since its last evaluation, the job runs
+//! once after a random jitter delay. The evaluation time of each job is saved
+//! in the backup directory, so slots missed while the daemon was down are
+//! caught up at the next start.
+//!
+//! Runs are submitted to the `JobManager`, so they are listed and cancelled
+//! like the jobs of commands, count toward the same limits and share their
+//! backup directory locks.
+
+use crate::amqp::MessageType;
+use crate::handlers::command::ResponsePayload;
+use crate::handlers::event::{EventPayload, EventSeverity, EventType};
+use crate::handlers::postgres::postgres_config;
+use crate::jobs::{JobContext, JobKind, JobManager};
+use crate::transport::{Namespace, Transport};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use common::config::{
+    BackupScheduleConfig, DatabaseConfig, ScheduledBackupType, ThrottleConfig, WardenConfig,
+};
+use cron::Schedule;
+use log::{debug, error, info, warn};
+use postgres::wrapper::ProcessPriority;
+use postgres::{Backup, PostgresManager};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use storage::{Compression, RateLimiter};
+use tokio::task;
+
+/// How often schedules are evaluated
+const TICK_SECS: u64 = 30;
+
+/// File, inside a backup directory, holding the progress of the jobs using it
+const STATE_FILE: &str = "schedule_state.json";
+
+/// Routing key of the events published for scheduled backups, within the namespace
+const BACKUP_EVENT_ROUTING_KEY: &str = "events.backup";
+
+/// Persisted progress of the jobs writing to one backup directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ScheduleState {
+    /// Time up to which the slots of each job have been handled
+    jobs: HashMap<String, DateTime<Utc>>,
+}
+
+impl ScheduleState {
+    fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring invalid scheduler state {}: {e}", path.display());
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Write then rename so a crash never leaves a truncated state behind
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Outcome of evaluating a job at a tick
+#[derive(Debug, PartialEq, Eq)]
+enum Due {
+    /// No slot passed since the last evaluation
+    No,
+    /// Run for `slot`, the latest passed slot; `missed` earlier slots are folded into it
+    Run { slot: DateTime<Utc>, missed: usize },
+    /// Slots were missed while the daemon was down and catch-up is disabled
+    Skip { missed: usize },
+}
+
+/// Parse a cron expression, accepting the classic 5 fields as well as the
+/// 6 or 7 field form with seconds (and years)
+pub fn parse_cron(expr: &str) -> Result<Schedule> {
+    let expr = expr.trim();
+    let expr = if expr.split_whitespace().count() == 5 {
+        format!("0 {expr}")
+    } else {
+        expr.to_string()
+    };
+    Schedule::from_str(&expr).with_context(|| format!("Invalid cron expression '{expr}'"))
+}
+
+/// Decide whether a job has to run, given the time it was last evaluated
+fn due(schedule: &Schedule, since: DateTime<Utc>, now: DateTime<Utc>, catch_up: bool) -> Due {
+    let (count, latest) = schedule
+        .after(&since)
+        .take_while(|slot| *slot <= now)
+        .fold((0, None), |(count, _), slot| (count + 1, Some(slot)));
+
+    let Some(slot) = latest else {
+        return Due::No;
+    };
+
+    // A slot older than a couple of ticks was missed, not just reached
+    let late = now - slot > Duration::seconds(2 * TICK_SECS as i64);
+    if late && !catch_up {
+        Due::Skip { missed: count }
+    } else {
+        Due::Run {
+            slot,
+            missed: count - 1,
+        }
+    }
+}
+
+/// Runs the configured backup schedules
+pub struct Scheduler {
+    config: Arc<Mutex<WardenConfig>>,
+    client: Transport,
+    exchange: String,
+    /// Namespace of the routing keys results and events are published under
+    namespace: Namespace,
+    /// Jobs currently running or waiting out their jitter
+    running: Arc<Mutex<HashSet<String>>>,
+    jobs: Arc<JobManager>,
+    /// Limiter shared by the jobs following the global bandwidth limit, with that limit
+    global_limiter: Option<(String, RateLimiter)>,
+}
+
+impl Scheduler {
+    pub fn new(
+        config: Arc<Mutex<WardenConfig>>,
+        client: Transport,
+        exchange: String,
+        jobs: Arc<JobManager>,
+    ) -> Self {
+        let namespace = Namespace::from_config(&config.lock().unwrap());
+        Scheduler {
+            config,
+            client,
+            exchange,
+            namespace,
+            running: Arc::new(Mutex::new(HashSet::new())),
+            jobs,
+            global_limiter: None,
+        }
+    }
+
+    /// Evaluate the schedules forever
+    pub async fn run(mut self) {
+        info!("Backup scheduler started");
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(TICK_SECS));
+        loop {
+            interval.tick().await;
+            self.tick(Utc::now());
+        }
+    }
+
+    /// Start every job that is due at `now`
+    ///
+    /// The configuration is read on every tick so schedules changed through a
+    /// `ConfigSet` command apply without a restart.
+    fn tick(&mut self, now: DateTime<Utc>) {
+        let (enabled, databases, schedules, throttle) = {
+            let config_guard = self.config.lock().unwrap();
+            (
+                config_guard.features.postgres_backup,
+                config_guard.databases.clone(),
+                config_guard.schedules.clone(),
+                config_guard.throttle.clone(),
+            )
+        };
+        if !enabled || schedules.is_empty() {
+            return;
+        }
+
+        let mut states: HashMap<PathBuf, ScheduleState> = HashMap::new();
+        for job in schedules {
+            let Some(database) = databases.iter().find(|d| d.name == job.database) else {
+                warn!(
+                    "Schedule {} refers to unknown database {}",
+                    job.name, job.database
+                );
+                continue;
+            };
+            let schedule = match parse_cron(&job.cron) {
+                Ok(schedule) => schedule,
+                Err(e) => {
+                    warn!("Skipping schedule {}: {e}", job.name);
+                    continue;
+                }
+            };
+            let limiter = match self.rate_limiter(&job, &throttle) {
+                Ok(limiter) => limiter,
+                Err(e) => {
+                    warn!("Skipping schedule {}: {e}", job.name);
+                    continue;
+                }
+            };
+
+            let state_path = PathBuf::from(&database.backup_dir).join(STATE_FILE);
+            let state = states
+                .entry(state_path.clone())
+                .or_insert_with(|| ScheduleState::load(&state_path));
+
+            // A new job starts counting from now rather than running right away
+            let Some(since) = state.jobs.insert(job.name.clone(), now) else {
+                info!("Scheduled backup job {} ({})", job.name, job.cron);
+                continue;
+            };
+
+            match due(&schedule, since, now, job.catch_up) {
+                Due::No => {}
+                Due::Skip { missed } => warn!(
+                    "Job {} missed {missed} runs while the daemon was down and catch-up is disabled",
+                    job.name
+                ),
+                Due::Run { slot, missed } => {
+                    if missed > 0 {
+                        info!("Job {} catching up after {missed} missed runs", job.name);
+                    }
+                    let throttle = job.throttle.or(&throttle);
+                    self.start_job(job, database.clone(), slot, throttle, limiter);
+                }
+            }
+        }
+
+        for (path, state) in states {
+            if let Err(e) = state.save(&path) {
+                error!("Failed to save scheduler state {}: {e}", path.display());
+            }
+        }
+    }
+
+    /// Bandwidth limiter of `job`
+    ///
+    /// Jobs without a limit of their own share a single limiter, so together
+    /// they stay within the global limit.
+    fn rate_limiter(
+        &mut self,
+        job: &BackupScheduleConfig,
+        global: &ThrottleConfig,
+    ) -> Result<Option<RateLimiter>> {
+        if let Some(limit) = &job.throttle.bandwidth_limit {
+            return Ok(Some(RateLimiter::parse(limit)?));
+        }
+        let Some(limit) = &global.bandwidth_limit else {
+            self.global_limiter = None;
+            return Ok(None);
+        };
+        match &self.global_limiter {
+            Some((current, limiter)) if current == limit => Ok(Some(limiter.clone())),
+            _ => {
+                let limiter = RateLimiter::parse(limit)?;
+                self.global_limiter = Some((limit.clone(), limiter.clone()));
+                Ok(Some(limiter))
+            }
+        }
+    }
+
+    /// Spawn a run of `job`, unless the previous one is still going
+    fn start_job(
+        &mut self,
+        job: BackupScheduleConfig,
+        database: DatabaseConfig,
+        slot: DateTime<Utc>,
+        throttle: ThrottleConfig,
+        limiter: Option<RateLimiter>,
+    ) {
+        let client = self.client.clone();
+        let exchange = self.exchange.clone();
+        let namespace = self.namespace.clone();
+
+        if !self.running.lock().unwrap().insert(job.name.clone()) {
+            warn!(
+                "Job {} is still running, skipping the run scheduled at {slot}",
+                job.name
+            );
+            task::spawn(async move {
+                let message = format!(
+                    "Skipped scheduled backup {}: previous run still in progress",
+                    job.name
+                );
+                let data = job_data(&job, slot);
+                publish_event(
+                    &client,
+                    &exchange,
+                    &namespace,
+                    EventSeverity::Warning,
+                    message,
+                    data,
+                )
+                .await;
+            });
+            return;
+        }
+
+        let running = RunningGuard {
+            running: self.running.clone(),
+            name: job.name.clone(),
+        };
+        let jobs = Arc::clone(&self.jobs);
+        let jitter = if job.jitter_secs > 0 {
+            rand::rng().random_range(0..=job.jitter_secs)
+        } else {
+            0
+        };
+
+        task::spawn(async move {
+            if jitter > 0 {
+                debug!("Delaying job {} by {jitter}s", job.name);
+                tokio::time::sleep(std::time::Duration::from_secs(jitter)).await;
+            }
+
+            let name = job.name.clone();
+            let record = jobs.submit(JobKind::Backup, None, move |ctx| async move {
+                let (started_at, result) = {
+                    let _guard = ctx.lock_dir(&PathBuf::from(&database.backup_dir)).await;
+                    info!(
+                        "Running scheduled {:?} backup {} as job {}",
+                        job.backup_type,
+                        job.name,
+                        ctx.job_id()
+                    );
+                    let started_at = Utc::now();
+                    let result = run_backup(&job, &database, &throttle, limiter, &ctx).await;
+                    (started_at, result)
+                };
+                drop(running);
+                publish_result(
+                    &client, &exchange, &namespace, &job, slot, &ctx, started_at, &result,
+                )
+                .await;
+                Ok(serde_json::to_value(result?)?)
+            });
+            debug!("Queued scheduled backup {name} as job {}", record.job_id);
+        });
+    }
+}
+
+/// Clears a job from the running set once its run ends, however it ends
+struct RunningGuard {
+    running: Arc<Mutex<HashSet<String>>>,
+    name: String,
+}
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.running
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.name);
+    }
+}
+
+/// Publish the response and event of a finished run of `job`
+#[allow(clippy::too_many_arguments)]
+async fn publish_result(
+    client: &Transport,
+    exchange: &str,
+    namespace: &Namespace,
+    job: &BackupScheduleConfig,
+    slot: DateTime<Utc>,
+    ctx: &JobContext,
+    started_at: DateTime<Utc>,
+    result: &Result<Backup>,
+) {
+    let mut data = job_data(job, slot);
+    data.insert("job_id".into(), serde_json::json!(ctx.job_id()));
+    data.insert("started_at".into(), serde_json::json!(started_at));
+    data.insert("finished_at".into(), serde_json::json!(Utc::now()));
+
+    let (success, message, severity) = match result {
+        Ok(backup) => {
+            data.insert(
+                "backup".into(),
+                serde_json::to_value(backup).unwrap_or_default(),
+            );
+            let message = format!("Scheduled backup {} completed: {}", job.name, backup.id);
+            info!("{message}");
+            (true, message, EventSeverity::Info)
+        }
+        Err(e) => {
+            let message = format!("Scheduled backup {} failed: {e}", job.name);
+            error!("{message}");
+            (false, message, EventSeverity::Error)
+        }
+    };
+
+    let response = ResponsePayload {
+        success,
+        message: message.clone(),
+        data: Some(serde_json::to_value(&data).unwrap_or_default()),
+    };
+    match serde_json::to_string(&response) {
+        Ok(payload) => {
+            let routing_key = namespace.key(&format!("responses.schedule.{}", job.name));
+            if let Err(e) = client
+                .publish(exchange, &routing_key, MessageType::Response, &payload)
+                .await
+            {
+                error!("Failed to publish result of job {}: {e}", job.name);
+            }
+        }
+        Err(e) => error!("Failed to serialize result of job {}: {e}", job.name),
+    }
+
+    publish_event(client, exchange, namespace, severity, message, data).await;
+}
+
+/// Fields describing a job run, shared by its response and event
+fn job_data(job: &BackupScheduleConfig, slot: DateTime<Utc>) -> HashMap<String, serde_json::Value> {
+    HashMap::from([
+        ("job".to_string(), serde_json::json!(job.name)),
+        ("database".to_string(), serde_json::json!(job.database)),
+        (
+            "backup_type".to_string(),
+            serde_json::json!(job.backup_type),
+        ),
+        ("scheduled_at".to_string(), serde_json::json!(slot)),
+    ])
+}
+
+async fn publish_event(
+    client: &Transport,
+    exchange: &str,
+    namespace: &Namespace,
+    severity: EventSeverity,
+    message: String,
+    data: HashMap<String, serde_json::Value>,
+) {
+    let event = EventPayload {
+        event_type: EventType::PostgresBackup,
+        severity,
+        source: "scheduler".to_string(),
+        message,
+        data: Some(data),
+    };
+    let payload = match serde_json::to_string(&event) {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!("Failed to serialize backup event: {e}");
+            return;
+        }
+    };
+    if let Err(e) = client
+        .publish(
+            exchange,
+            &namespace.key(BACKUP_EVENT_ROUTING_KEY),
+            MessageType::Event,
+            &payload,
+        )
+        .await
+    {
+        error!("Failed to publish backup event: {e}");
+    }
+}
+
+/// Create the backup a job asks for
+async fn run_backup(
+    job: &BackupScheduleConfig,
+    database: &DatabaseConfig,
+    throttle: &ThrottleConfig,
+    limiter: Option<RateLimiter>,
+    ctx: &JobContext,
+) -> Result<Backup> {
+    let compression = match &job.compression {
+        Some(spec) => spec
+            .parse::<Compression>()
+            .map_err(|e| anyhow!("Invalid compression for job {}: {e}", job.name))?,
+        None => Compression::default(),
+    };
+    let mut manager = PostgresManager::new(
+        postgres_config(database),
+        PathBuf::from(&database.backup_dir),
+    )?
+    .with_compression(compression)
+    .with_priority(ProcessPriority {
+        nice: throttle.nice,
+        ionice_class: throttle.ionice_class,
+        ionice_level: throttle.ionice_level,
+    })
+    .with_cancellation(ctx.cancellation())
+    .with_replication_slot(database.replication_slot);
+    if let Some(limiter) = limiter {
+        manager = manager.with_rate_limiter(limiter);
+    }
+    let backup = match job.backup_type {
+        ScheduledBackupType::Full => manager.full_backup().await?,
+        ScheduledBackupType::Incremental => manager.incremental_backup().await?,
+        ScheduledBackupType::Snapshot => manager.snapshot_backup().await?,
+    };
+    Ok(backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 6, 2, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn accepts_five_field_expressions() {
+        let schedule = parse_cron("0 * * * *").unwrap();
+        let next = schedule.after(&at(10, 15)).next().unwrap();
+        assert_eq!(next, at(11, 0));
+        assert!(parse_cron("not a cron").is_err());
+    }
+
+    #[test]
+    fn runs_slot_reached_since_last_tick() {
+        let schedule = parse_cron("0 * * * *").unwrap();
+        let now = at(11, 0) + Duration::seconds(10);
+        assert_eq!(
+            due(&schedule, now - Duration::seconds(30), now, false),
+            Due::Run {
+                slot: at(11, 0),
+                missed: 0
+            }
+        );
+        assert_eq!(due(&schedule, at(11, 1), at(11, 30), true), Due::No);
+    }
+
+    #[test]
+    fn catches_up_once_after_downtime() {
+        let schedule = parse_cron("0 * * * *").unwrap();
+        // Down from 08:30 to 11:30, missing the 09:00, 10:00 and 11:00 runs
+        assert_eq!(
+            due(&schedule, at(8, 30), at(11, 30), true),
+            Due::Run {
+                slot: at(11, 0),
+                missed: 2
+            }
+        );
+        assert_eq!(
+            due(&schedule, at(8, 30), at(11, 30), false),
+            Due::Skip { missed: 3 }
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Broker connection shared by the handlers, AMQP or MQTT as
+//! `mqtt.protocol` picks.
+
+use crate::amqp::{AmqpClient, MessageType};
+use crate::mqtt::MqttClient;
+use anyhow::{anyhow, Result};
+use common::config::WardenConfig;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Exchange the daemon publishes to unless `mqtt.exchange` names another
+const DEFAULT_EXCHANGE: &str = "warden";
+
+/// Namespace of the routing keys unless the configured topics use another
+const DEFAULT_NAMESPACE: &str = "warden";
+
+/// Routing keys consumed unless `mqtt.topics` lists others
+const DEFAULT_ROUTING_KEYS: [&str; 3] = ["warden.commands.#", "warden.config", "warden.events.#"];
+
+/// Protocol spoken with the broker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Amqp,
+    Mqtt,
+}
+
+impl Protocol {
+    /// Protocol of the `mqtt` section, AMQP when it does not say
+    pub fn from_config(config: &WardenConfig) -> Result<Self> {
+        let protocol = config.mqtt.as_ref().and_then(|m| m.protocol.as_deref());
+        match protocol.map(str::to_ascii_lowercase).as_deref() {
+            None | Some("amqp") => Ok(Self::Amqp),
+            Some("mqtt") => Ok(Self::Mqtt),
+            Some(other) => Err(anyhow!(
+                "Unknown broker protocol '{other}', expected 'amqp' or 'mqtt'"
+            )),
+        }
+    }
+}
+
+fn topics(config: &WardenConfig) -> Option<&Vec<String>> {
+    config.mqtt.as_ref().and_then(|mqtt| mqtt.topics.as_ref())
+}
+
+/// Exchange the daemon's messages are published to
+pub fn exchange(config: &WardenConfig) -> String {
+    config
+        .mqtt
+        .as_ref()
+        .and_then(|mqtt| mqtt.exchange.clone())
+        .unwrap_or_else(|| DEFAULT_EXCHANGE.to_string())
+}
+
+/// Routing key the daemon's status is published on
+///
+/// The first configured topic mentioning `status`, or `status` in the
+/// daemon's namespace.
+pub fn status_routing_key(config: &WardenConfig) -> String {
+    topics(config)
+        .and_then(|topics| topics.iter().find(|t| t.contains("status")).cloned())
+        .unwrap_or_else(|| Namespace::from_config(config).key("status"))
+}
+
+/// Routing keys the daemon consumes, every configured topic but the status
+pub fn subscribed_routing_keys(config: &WardenConfig) -> Vec<String> {
+    let status = status_routing_key(config);
+    match topics(config) {
+        Some(topics) => topics.iter().filter(|t| **t != status).cloned().collect(),
+        None => DEFAULT_ROUTING_KEYS.map(str::to_string).to_vec(),
+    }
+}
+
+/// Prefix of the routing keys the daemon consumes and publishes
+///
+/// Taken from the configured topic of the commands, so `site1.commands.#`
+/// has responses go to `site1.responses.*` and events to `site1.events.*`.
+/// Wardens sharing a broker each keep to their own keys this way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Namespace(String);
+
+impl Namespace {
+    pub fn from_config(config: &WardenConfig) -> Self {
+        let prefix = topics(config).and_then(|topics| {
+            topics.iter().find_map(|topic| {
+                let levels: Vec<&str> = topic.split('.').collect();
+                let commands = levels.iter().position(|level| *level == "commands")?;
+                Some(levels[..commands].join("."))
+            })
+        });
+        Self(prefix.unwrap_or_else(|| DEFAULT_NAMESPACE.to_string()))
+    }
+
+    /// Routing key `rest` within the namespace
+    pub fn key(&self, rest: &str) -> String {
+        if self.0.is_empty() {
+            rest.to_string()
+        } else {
+            format!("{}.{rest}", self.0)
+        }
+    }
+}
+
+/// Client publishing the daemon's messages
+#[derive(Clone)]
+pub enum Transport {
+    Amqp(Arc<AmqpClient>),
+    Mqtt(Arc<MqttClient>),
+}
+
+impl Transport {
+    /// Publish a message under a routing key
+    ///
+    /// MQTT has no exchanges, so `exchange` only matters to AMQP.
+    pub async fn publish<T: Serialize>(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        message_type: MessageType,
+        payload: T,
+    ) -> Result<()> {
+        match self {
+            Self::Amqp(client) => {
+                client
+                    .publish(exchange, routing_key, message_type, payload)
+                    .await
+            }
+            Self::Mqtt(client) => client.publish(routing_key, message_type, payload).await,
+        }
+    }
+
+    /// Close the connection for good
+    pub async fn close(&self) -> Result<()> {
+        match self {
+            Self::Amqp(client) => client.close().await,
+            Self::Mqtt(client) => client.close().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(mqtt: &str) -> WardenConfig {
+        let content = format!(
+            r#"
+            c2_server = "localhost:5672"
+            [c2_auth]
+            id = "site-1"
+            secret = ""
+            [features]
+            Overwatch = false
+            PostgresBackup = true
+            {mqtt}
+            "#
+        );
+        toml::from_str(&content).unwrap()
+    }
+
+    #[test]
+    fn protocol_and_status_key_come_from_the_mqtt_section() {
+        assert_eq!(Protocol::from_config(&config("")).unwrap(), Protocol::Amqp);
+        assert_eq!(status_routing_key(&config("")), "warden.status");
+        assert_eq!(exchange(&config("")), "warden");
+
+        let mqtt = config(
+            r#"
+            [mqtt]
+            broker = "mosquitto.local"
+            protocol = "MQTT"
+            topics = ["site1.commands.#", "site1.status"]
+            "#,
+        );
+        assert_eq!(Protocol::from_config(&mqtt).unwrap(), Protocol::Mqtt);
+        assert_eq!(status_routing_key(&mqtt), "site1.status");
+
+        let mqtt = config("[mqtt]\nbroker = \"b\"\ntopics = [\"acme.site1.commands.#\"]");
+        assert_eq!(status_routing_key(&mqtt), "acme.site1.status");
+
+        let stomp = config("[mqtt]\nbroker = \"b\"\nprotocol = \"stomp\"");
+        assert!(Protocol::from_config(&stomp).is_err());
+    }
+
+    #[test]
+    fn routing_keys_follow_the_namespace_of_the_command_topic() {
+        let default = config("");
+        assert_eq!(
+            Namespace::from_config(&default).key("events.backup"),
+            "warden.events.backup"
+        );
+        assert_eq!(
+            subscribed_routing_keys(&default),
+            ["warden.commands.#", "warden.config", "warden.events.#"]
+        );
+
+        let tenant = config(
+            r#"
+            [mqtt]
+            broker = "rabbitmq.local"
+            exchange = "tenant-a"
+            topics = ["tenant-a.site1.commands.#", "tenant-a.site1.events.#", "tenant-a.site1.status"]
+            "#,
+        );
+        assert_eq!(exchange(&tenant), "tenant-a");
+        assert_eq!(
+            Namespace::from_config(&tenant).key("responses.Status"),
+            "tenant-a.site1.responses.Status"
+        );
+        assert_eq!(
+            subscribed_routing_keys(&tenant),
+            ["tenant-a.site1.commands.#", "tenant-a.site1.events.#"]
+        );
+
+        let bare = config("[mqtt]\nbroker = \"b\"\ntopics = [\"commands.#\"]");
+        assert_eq!(Namespace::from_config(&bare).key("status"), "status");
+    }
+}
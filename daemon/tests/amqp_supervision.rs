@@ -0,0 +1,188 @@
+//! AMQP client against a local RabbitMQ
+//!
+//! Start the broker with
+//! `docker run -d --name warden-rabbitmq -p 5672:5672 rabbitmq:3`, then run
+//! with `AMQP_TEST_HOST=127.0.0.1 AMQP_TEST_CONTAINER=warden-rabbitmq`. The
+//! tests restart the container, or delete queues inside it, to see the
+//! client recover.
+
+use daemon::amqp::{AmqpClient, AmqpConfig, MessageType};
+use futures::StreamExt;
+use lapin::options::BasicAckOptions;
+use std::env;
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Longer than a RabbitMQ restart plus a few reconnection attempts
+const RECOVERY_TIMEOUT: Duration = Duration::from_secs(120);
+
+struct Broker {
+    config: AmqpConfig,
+    container: String,
+}
+
+fn broker() -> Option<Broker> {
+    let (Ok(host), Ok(container)) = (env::var("AMQP_TEST_HOST"), env::var("AMQP_TEST_CONTAINER"))
+    else {
+        println!("AMQP_TEST_HOST or AMQP_TEST_CONTAINER is not set, skipping");
+        return None;
+    };
+    let port = env::var("AMQP_TEST_PORT")
+        .ok()
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(5672);
+    let name = format!("warden-test-{}", uuid::Uuid::new_v4());
+    Some(Broker {
+        config: AmqpConfig {
+            host,
+            port,
+            username: None,
+            password: None,
+            client_id: name.clone(),
+            vhost: None,
+            tls: false,
+            exchange: name.clone(),
+            queues: vec![format!("{name}.commands")],
+            routing_keys: vec![format!("{name}.commands.#")],
+        },
+        container,
+    })
+}
+
+fn docker(args: &[&str]) {
+    let status = Command::new("docker").args(args).status().expect("docker");
+    assert!(status.success(), "docker {args:?} failed");
+}
+
+/// Declare the exchange, queue and binding of the test, as the daemon does
+async fn declare(client: &AmqpClient, config: &AmqpConfig) {
+    client.declare_exchange(&config.exchange).await.unwrap();
+    client.declare_queue(&config.queues[0]).await.unwrap();
+    client
+        .bind_queue(&config.queues[0], &config.exchange, &config.routing_keys[0])
+        .await
+        .unwrap();
+}
+
+/// Consume the test queue from every connection, like the daemon's consumers
+fn consume(client: Arc<AmqpClient>, queue: String) -> mpsc::UnboundedReceiver<String> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut generation = 0;
+        loop {
+            generation = client.wait_connected(generation).await;
+            if let Ok(mut consumer) = client.consume(&queue).await {
+                while let Some(Ok(delivery)) = consumer.next().await {
+                    let _ = delivery.acker.ack(BasicAckOptions::default()).await;
+                    let _ = tx.send(String::from_utf8_lossy(&delivery.data).to_string());
+                }
+            }
+            client.report_lost(generation);
+        }
+    });
+    rx
+}
+
+async fn next(rx: &mut mpsc::UnboundedReceiver<String>) -> String {
+    tokio::time::timeout(RECOVERY_TIMEOUT, rx.recv())
+        .await
+        .expect("no message received")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_messages_published_during_a_broker_restart_are_delivered() {
+    let Some(broker) = broker() else {
+        return;
+    };
+    let client = AmqpClient::new(&broker.config).await.expect("connect");
+    let supervisor = client.supervise();
+    declare(&client, &broker.config).await;
+    let mut received = consume(client.clone(), broker.config.queues[0].clone());
+    let routing_key = broker.config.routing_keys[0].replace('#', "status");
+
+    client
+        .publish(
+            &broker.config.exchange,
+            &routing_key,
+            MessageType::Event,
+            "before",
+        )
+        .await
+        .unwrap();
+    assert!(next(&mut received).await.contains("before"));
+
+    docker(&["stop", &broker.container]);
+    // Buffered, not lost, while the broker is down
+    client
+        .publish(
+            &broker.config.exchange,
+            &routing_key,
+            MessageType::Event,
+            "during",
+        )
+        .await
+        .unwrap();
+    docker(&["start", &broker.container]);
+
+    let generation = tokio::time::timeout(RECOVERY_TIMEOUT, client.wait_connected(1))
+        .await
+        .expect("the client did not reconnect");
+    assert!(generation >= 2);
+    assert!(next(&mut received).await.contains("during"));
+
+    client
+        .publish(
+            &broker.config.exchange,
+            &routing_key,
+            MessageType::Event,
+            "after",
+        )
+        .await
+        .unwrap();
+    assert!(next(&mut received).await.contains("after"));
+
+    client.close().await.unwrap();
+    supervisor.abort();
+}
+
+#[tokio::test]
+async fn test_a_deleted_queue_is_declared_and_consumed_again() {
+    let Some(broker) = broker() else {
+        return;
+    };
+    let client = AmqpClient::new(&broker.config).await.expect("connect");
+    let supervisor = client.supervise();
+    declare(&client, &broker.config).await;
+    let queue = broker.config.queues[0].clone();
+    let mut received = consume(client.clone(), queue.clone());
+    let routing_key = broker.config.routing_keys[0].replace('#', "backup");
+
+    // The broker cancels the consumer, which has the client reconnect
+    docker(&[
+        "exec",
+        &broker.container,
+        "rabbitmqctl",
+        "delete_queue",
+        &queue,
+    ]);
+    tokio::time::timeout(RECOVERY_TIMEOUT, client.wait_connected(1))
+        .await
+        .expect("the client did not reconnect");
+
+    client
+        .publish(
+            &broker.config.exchange,
+            &routing_key,
+            MessageType::Command,
+            "again",
+        )
+        .await
+        .unwrap();
+    assert!(next(&mut received).await.contains("again"));
+
+    client.close().await.unwrap();
+    supervisor.abort();
+}
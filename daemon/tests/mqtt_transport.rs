@@ -0,0 +1,134 @@
+//! MQTT transport against a local Mosquitto
+//!
+//! Start the broker with
+//! `docker run -d -p 1883:1883 eclipse-mosquitto:2 mosquitto -c /mosquitto-no-auth.conf`,
+//! then run with `MQTT_TEST_HOST=127.0.0.1`.
+
+use daemon::amqp::MessageType;
+use daemon::mqtt::{MqttClient, MqttSettings};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
+use std::env;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn settings() -> Option<MqttSettings> {
+    let Ok(host) = env::var("MQTT_TEST_HOST") else {
+        println!("MQTT_TEST_HOST is not set, skipping");
+        return None;
+    };
+    let port = env::var("MQTT_TEST_PORT")
+        .ok()
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(1883);
+    let prefix = format!("warden-test-{}", uuid::Uuid::new_v4().simple());
+    Some(MqttSettings {
+        host,
+        port,
+        client_id: prefix.clone(),
+        username: None,
+        password: None,
+        qos: QoS::AtLeastOnce,
+        tls: None,
+        routing_keys: vec![format!("{prefix}.commands.#")],
+        status_routing_key: format!("{prefix}.status"),
+    })
+}
+
+/// A second client, standing for the C2 server
+async fn peer(settings: &MqttSettings, filter: &str) -> (AsyncClient, EventLoop) {
+    let options = MqttOptions::new(
+        format!("{}-peer", settings.client_id),
+        &settings.host,
+        settings.port,
+    );
+    let (client, eventloop) = AsyncClient::new(options, 10);
+    client.subscribe(filter, QoS::AtLeastOnce).await.unwrap();
+    (client, eventloop)
+}
+
+/// Payload of the next message the peer receives
+async fn next_publish(eventloop: &mut EventLoop) -> (String, String) {
+    tokio::time::timeout(TIMEOUT, async {
+        loop {
+            if let Event::Incoming(Packet::Publish(p)) = eventloop.poll().await.unwrap() {
+                return (p.topic, String::from_utf8_lossy(&p.payload).to_string());
+            }
+        }
+    })
+    .await
+    .expect("no message received")
+}
+
+#[tokio::test]
+async fn test_commands_reach_the_handlers_under_their_routing_key() {
+    let Some(settings) = settings() else {
+        return;
+    };
+    let (client, eventloop) = MqttClient::new(&settings).unwrap();
+    let (tx, mut rx) = mpsc::channel(10);
+    let task = client.run(eventloop, tx);
+
+    let prefix = &settings.client_id;
+    let (peer, mut peer_loop) = peer(&settings, &format!("{prefix}/responses/#")).await;
+    // Keep publishing until the daemon subscribed
+    let received = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            peer.publish(
+                format!("{prefix}/commands/Status"),
+                QoS::AtLeastOnce,
+                false,
+                r#"{"command_type":"Status"}"#,
+            )
+            .await
+            .unwrap();
+            tokio::select! {
+                message = rx.recv() => return message.unwrap(),
+                _ = peer_loop.poll() => {}
+            }
+        }
+    })
+    .await
+    .expect("the command never arrived");
+    assert_eq!(received.0, format!("{prefix}/commands/Status"));
+    assert_eq!(received.1, format!("{prefix}.commands.Status"));
+
+    client
+        .publish(
+            &format!("{prefix}.responses.Status"),
+            MessageType::Response,
+            "running",
+        )
+        .await
+        .unwrap();
+    let (topic, payload) = next_publish(&mut peer_loop).await;
+    assert_eq!(topic, format!("{prefix}/responses/Status"));
+    assert!(payload.contains("running"));
+
+    client.close().await.unwrap();
+    task.abort();
+}
+
+#[tokio::test]
+async fn test_status_is_retained_and_the_last_will_says_offline() {
+    let Some(settings) = settings() else {
+        return;
+    };
+    let status_topic = settings.status_routing_key.replace('.', "/");
+    let (client, eventloop) = MqttClient::new(&settings).unwrap();
+    let (tx, _rx) = mpsc::channel(10);
+    let task = client.run(eventloop, tx);
+
+    // A subscriber arriving later still gets the retained status
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let (_peer, mut peer_loop) = peer(&settings, &status_topic).await;
+    let (_, payload) = next_publish(&mut peer_loop).await;
+    assert!(payload.contains("online"), "{payload}");
+
+    // Dropping the connection without a DISCONNECT has the broker send the will
+    task.abort();
+    drop(client);
+    let (_, payload) = next_publish(&mut peer_loop).await;
+    assert!(payload.contains("offline"), "{payload}");
+}
@@ -1,24 +1,52 @@
 use anyhow::Result;
 use chrono::Utc;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use std::fs;
 use std::path::{Path, PathBuf};
 use tokio_postgres::Client;
 
-use crate::common::{Backup, BackupType, PostgresConfig};
-use crate::wrapper::{PgBaseBackup, PgBaseBackupOptions};
+use crate::common::{dump_file_name, Backup, BackupType, PostgresConfig, WalSegmentRange};
+use crate::wal;
+use crate::wrapper::{Cancellation, PgBaseBackup, PgBaseBackupOptions, ProcessPriority};
 use crate::PostgresError;
 
 /// Full backup manager
 pub struct FullBackupManager {
     config: PostgresConfig,
     backup_dir: PathBuf,
+    priority: ProcessPriority,
+    cancellation: Cancellation,
+    replication_slot: bool,
 }
 
 impl FullBackupManager {
     /// Create a new full backup manager
     pub fn new(config: PostgresConfig, backup_dir: PathBuf) -> Self {
-        Self { config, backup_dir }
+        Self {
+            config,
+            backup_dir,
+            priority: ProcessPriority::default(),
+            cancellation: Cancellation::default(),
+            replication_slot: false,
+        }
+    }
+
+    /// Run pg_basebackup and pg_dump with `priority`
+    pub fn with_priority(mut self, priority: ProcessPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Terminate pg_basebackup and pg_dump when `cancellation` is cancelled
+    pub fn with_cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Reserve WAL from the backup on in the `warden_wal` replication slot
+    pub fn with_replication_slot(mut self, enabled: bool) -> Self {
+        self.replication_slot = enabled;
+        self
     }
 
     /// Perform a full backup
@@ -55,7 +83,17 @@ impl FullBackupManager {
 
         // Get current WAL position before backup
         let wal_start = self.get_current_wal_position(&client).await?;
-        backup.wal_start = Some(wal_start);
+        backup.wal_start = Some(wal_start.clone());
+
+        // Reserve WAL from here on so incrementals can stream it gap-free
+        if self.replication_slot {
+            if let Err(e) = wal::ensure_replication_slot(&client, wal::WAL_REPLICATION_SLOT).await {
+                warn!("Could not reserve WAL for incremental backups: {e}");
+            }
+        }
+        if let Err(e) = wal::warn_if_slot_idle(&client, wal::WAL_REPLICATION_SLOT).await {
+            debug!("Could not check replication slot: {e}");
+        }
 
         // Perform the backup using pg_basebackup
         let options = PgBaseBackupOptions {
@@ -71,18 +109,25 @@ impl FullBackupManager {
             label: Some(format!("full_backup_{timestamp}")),
             progress: true,
             verbose: true,
+            priority: self.priority.clone(),
+            cancellation: self.cancellation.clone(),
         };
 
         match PgBaseBackup::run(&options) {
             Ok(_) => {
                 info!("Physical backup completed successfully");
+                self.seed_wal_archive(&backup_path);
 
                 // Create a logical backup (SQL dump) of the database
                 if let Err(e) = self.create_logical_backup(&backup_path).await {
+                    if self.cancellation.is_cancelled() {
+                        return Err(self.cancelled(&backup_path));
+                    }
                     error!("Failed to create logical backup: {e}");
                     // Continue with the physical backup even if logical backup fails
                 } else {
                     info!("Logical backup completed successfully");
+                    backup.dump_file = Some(dump_file_name(&self.config.database));
                 }
 
                 // Get current WAL position after backup
@@ -91,11 +136,26 @@ impl FullBackupManager {
                 // Calculate backup size
                 let size_bytes = self.calculate_backup_size(&backup_path)?;
 
+                // Record the WAL segments pg_basebackup streamed alongside the data
+                let segment_size = wal::server_segment_size(&client).await?;
+                let timeline = wal::server_timeline(&client).await?;
+                if let (Some(start), Some(end)) =
+                    (wal::parse_lsn(&wal_start), wal::parse_lsn(&wal_end))
+                {
+                    backup.wal_segments = Some(WalSegmentRange::from_lsns(
+                        timeline,
+                        start,
+                        end,
+                        segment_size,
+                    ));
+                }
+
                 // Update backup metadata
                 backup.complete(wal_end, size_bytes);
 
                 Ok(backup)
             }
+            Err(_) if self.cancellation.is_cancelled() => Err(self.cancelled(&backup_path)),
             Err(e) => {
                 let error_msg = format!("Full backup failed: {e}");
                 error!("{error_msg}");
@@ -133,6 +193,40 @@ impl FullBackupManager {
         Ok(wal_position)
     }
 
+    /// Start the WAL archive of incrementals with the WAL of this backup
+    ///
+    /// Incrementals then stream from the end of this backup on instead of
+    /// from the server's current position. The full backup stands without
+    /// it, so a failure is only logged.
+    fn seed_wal_archive(&self, backup_path: &Path) {
+        let wal_tar = backup_path.join("pg_wal.tar.gz");
+        if !wal_tar.exists() {
+            warn!("No pg_wal.tar.gz in {backup_path:?}, incrementals may miss WAL");
+            return;
+        }
+        let archive_dir = self.backup_dir.join(wal::WAL_ARCHIVE_DIR);
+        match wal::seed_archive(&wal_tar, &archive_dir) {
+            Ok(seeded) => info!(
+                "Added {seeded} WAL segments of the backup to {}",
+                archive_dir.display()
+            ),
+            Err(e) => warn!(
+                "Could not add the backup's WAL to the archive, incrementals may miss WAL: {e}"
+            ),
+        }
+    }
+
+    /// Remove what a cancelled backup wrote so far
+    fn cancelled(&self, backup_path: &Path) -> PostgresError {
+        warn!("Full backup cancelled, removing {backup_path:?}");
+        if let Err(e) = fs::remove_dir_all(backup_path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove cancelled backup {backup_path:?}: {e}");
+            }
+        }
+        PostgresError::BackupError("Full backup cancelled".to_string())
+    }
+
     /// Calculate backup size in bytes
     fn calculate_backup_size(&self, backup_path: &Path) -> Result<u64, PostgresError> {
         let mut total_size = 0;
@@ -154,7 +248,7 @@ impl FullBackupManager {
 
     /// Create a logical backup (SQL dump) of the database
     async fn create_logical_backup(&self, backup_path: &Path) -> Result<(), PostgresError> {
-        use std::process::{Command, Stdio};
+        use std::process::Stdio;
 
         info!("Creating logical backup (SQL dump) of the database");
 
@@ -164,29 +258,31 @@ impl FullBackupManager {
         let user = &self.config.user;
 
         // Create dump file path
-        let dump_file = backup_path.join(format!("{db_name}.dump"));
+        let dump_file = backup_path.join(dump_file_name(db_name));
 
         // Use pg_dump to create a custom-format backup
-        let result = Command::new("pg_dump")
-            .args([
-                "-h",
-                host,
-                "-p",
-                &port.to_string(),
-                "-U",
-                user,
-                "-F",
-                "c", // custom format
-                "-f",
-                dump_file.to_str().unwrap(),
-                "-v", // verbose
-                "-Z",
-                "9", // compression level
-                db_name,
-            ])
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status()
+        let mut cmd = self.priority.command("pg_dump");
+        cmd.args([
+            "-h",
+            host,
+            "-p",
+            &port.to_string(),
+            "-U",
+            user,
+            "-F",
+            "c", // custom format
+            "-f",
+            dump_file.to_str().unwrap(),
+            "-v", // verbose
+            "-Z",
+            "9", // compression level
+            db_name,
+        ])
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+        let result = self
+            .cancellation
+            .status(&mut cmd)
             .map_err(|e| PostgresError::BackupError(format!("Failed to execute pg_dump: {e}")))?;
 
         if !result.success() {
@@ -198,26 +294,25 @@ impl FullBackupManager {
         // Also create a plain SQL backup for flexibility
         let sql_file = backup_path.join(format!("{db_name}.sql"));
 
-        let result = Command::new("pg_dump")
-            .args([
-                "-h",
-                host,
-                "-p",
-                &port.to_string(),
-                "-U",
-                user,
-                "-F",
-                "p", // plain format
-                "-f",
-                sql_file.to_str().unwrap(),
-                db_name,
-            ])
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status()
-            .map_err(|e| {
-                PostgresError::BackupError(format!("Failed to execute pg_dump for SQL: {e}"))
-            })?;
+        let mut cmd = self.priority.command("pg_dump");
+        cmd.args([
+            "-h",
+            host,
+            "-p",
+            &port.to_string(),
+            "-U",
+            user,
+            "-F",
+            "p", // plain format
+            "-f",
+            sql_file.to_str().unwrap(),
+            db_name,
+        ])
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+        let result = self.cancellation.status(&mut cmd).map_err(|e| {
+            PostgresError::BackupError(format!("Failed to execute pg_dump for SQL: {e}"))
+        })?;
 
         if !result.success() {
             return Err(PostgresError::BackupError(
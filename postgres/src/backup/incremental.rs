@@ -3,16 +3,26 @@ use chrono::Utc;
 use log::{debug, error, info, warn};
 use std::fs;
 use std::path::{Path, PathBuf};
+use storage::{Compression, RateLimiter};
 use tokio_postgres::Client;
 
-use crate::common::{Backup, BackupCatalog, BackupType, PostgresConfig};
+use crate::common::{Backup, BackupCatalog, BackupType, PostgresConfig, WalSegmentRange};
+use crate::wal;
+use crate::wrapper::{Cancellation, PgReceiveWal, PgReceiveWalOptions};
 use crate::PostgresError;
 
+/// How long to wait for a continuous WAL stream to deliver a segment
+const WAL_WAIT_SECS: u64 = 300;
+
 /// Incremental backup manager
 pub struct IncrementalBackupManager {
     config: PostgresConfig,
     backup_dir: PathBuf,
     catalog: BackupCatalog,
+    compression: Compression,
+    rate_limiter: Option<RateLimiter>,
+    replication_slot: bool,
+    cancellation: Cancellation,
 }
 
 impl IncrementalBackupManager {
@@ -22,9 +32,41 @@ impl IncrementalBackupManager {
             config,
             backup_dir,
             catalog,
+            compression: Compression::default(),
+            rate_limiter: None,
+            replication_slot: false,
+            cancellation: Cancellation::default(),
         }
     }
 
+    /// Compress the WAL segments copied into the backup with `compression`
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Copy WAL segments into the backup no faster than `limiter` allows
+    pub fn with_rate_limiter(mut self, limiter: Option<RateLimiter>) -> Self {
+        self.rate_limiter = limiter;
+        self
+    }
+
+    /// Stream WAL through the `warden_wal` replication slot
+    ///
+    /// The server then keeps WAL until it is streamed, so incrementals never
+    /// find segments recycled before they got to them. Without the slot, such
+    /// a gap fails the backup instead.
+    pub fn with_replication_slot(mut self, enabled: bool) -> Self {
+        self.replication_slot = enabled;
+        self
+    }
+
+    /// Stop pg_receivewal and any wait for WAL when `cancellation` is cancelled
+    pub fn with_cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
     /// Perform an incremental backup based on the latest full backup
     pub async fn backup(&self) -> Result<Backup, PostgresError> {
         info!("Starting incremental backup");
@@ -74,29 +116,52 @@ impl IncrementalBackupManager {
             server_version,
             Some(base_backup.id),
         );
-
-        // Get current WAL position before backup
-        let wal_start = match &base_backup.wal_end {
+        backup.compression = self.compression;
+
+        // Continue from where the previous backup in this chain left off
+        let previous = self
+            .catalog
+            .get_latest_backup_in_chain(&base_backup.id)
+            .unwrap_or(base_backup);
+        let wal_start = match &previous.wal_end {
             Some(wal) => wal.clone(),
             None => {
-                warn!("Base backup doesn't have WAL end position, using current position");
+                warn!("Previous backup doesn't have WAL end position, using current position");
                 self.get_current_wal_position(&client).await?
             }
         };
 
         backup.wal_start = Some(wal_start.clone());
 
-        // Perform WAL archiving from the last position to the current position
-        let wal_files = self
+        // Stream WAL from the last position to the current position
+        let archived = match self
             .archive_wal_files(&client, &wal_start, &backup_path)
-            .await?;
+            .await
+        {
+            Err(_) if self.cancellation.is_cancelled() => return Err(self.cancelled(&backup_path)),
+            archived => archived?,
+        };
+        let (wal_end, wal_segments) = match archived {
+            Some((wal_end, segments)) => (wal_end, Some(segments)),
+            None => {
+                warn!("No WAL was written since the previous backup, incremental backup is empty");
+                (wal_start.clone(), None)
+            }
+        };
 
-        if wal_files.is_empty() {
-            warn!("No WAL files were archived, incremental backup might be empty");
+        if let (Some(previous_segments), Some(segments)) = (&previous.wal_segments, &wal_segments) {
+            if !segments.continues(previous_segments) {
+                let error_msg = format!(
+                    "WAL gap between backup {} (ends at {}) and new incremental (starts at {}); take a new full backup",
+                    previous.id, previous_segments.last_segment, segments.first_segment
+                );
+                error!("{error_msg}");
+                return Err(PostgresError::WalError(error_msg));
+            }
         }
 
-        // Get current WAL position after backup
-        let wal_end = self.get_current_wal_position(&client).await?;
+        // An empty incremental covers the same WAL as its predecessor
+        backup.wal_segments = wal_segments.or_else(|| previous.wal_segments.clone());
 
         // Calculate backup size
         let size_bytes = self.calculate_backup_size(&backup_path)?;
@@ -134,122 +199,243 @@ impl IncrementalBackupManager {
         Ok(wal_position)
     }
 
-    /// Archive WAL files from start_lsn to current position
+    /// Stream WAL from `start_lsn` up to the current position with pg_receivewal
+    ///
+    /// WAL is streamed into a persistent archive directory shared by all
+    /// incrementals and seeded by full backups, so each run resumes exactly
+    /// where the previous backup stopped. The segments belonging to this
+    /// backup are then copied, compressed with the configured codec, into its
+    /// `pg_wal` directory. Returns the end LSN and the segment range that was
+    /// archived, or `None` when the server has not written any WAL since
+    /// `start_lsn`.
     async fn archive_wal_files(
         &self,
         client: &Client,
         start_lsn: &str,
         backup_path: &Path,
-    ) -> Result<Vec<String>, PostgresError> {
-        // Create WAL directory
+    ) -> Result<Option<(String, WalSegmentRange)>, PostgresError> {
+        let start = wal::parse_lsn(start_lsn)
+            .ok_or_else(|| PostgresError::WalError(format!("Invalid WAL position: {start_lsn}")))?;
+
+        // Create WAL directories
         let wal_dir = backup_path.join("pg_wal");
         fs::create_dir_all(&wal_dir).map_err(PostgresError::Io)?;
+        let archive_dir = self.backup_dir.join(wal::WAL_ARCHIVE_DIR);
+        fs::create_dir_all(&archive_dir).map_err(PostgresError::Io)?;
 
-        // Get the PostgreSQL data directory
-        let data_dir_rows = client
-            .query(
-                "SELECT setting FROM pg_settings WHERE name = 'data_directory'",
-                &[],
-            )
+        let segment_size = wal::server_segment_size(client).await?;
+        let timeline = wal::server_timeline(client).await?;
+
+        // Make sure the server keeps WAL around until we have streamed it
+        if self.replication_slot {
+            wal::ensure_replication_slot(client, wal::WAL_REPLICATION_SLOT).await?;
+        }
+
+        // Switch to a new WAL file so the current one is complete, then stream
+        // up to the segment boundary so no partial segment is left behind
+        let row = client
+            .query_one("SELECT pg_switch_wal()::text", &[])
             .await
             .map_err(PostgresError::Postgres)?;
+        let switch_lsn: String = row.get(0);
+        let switch_lsn = wal::parse_lsn(&switch_lsn).ok_or_else(|| {
+            PostgresError::WalError(format!("Invalid WAL position: {switch_lsn}"))
+        })?;
+        let end = wal::align_to_segment(switch_lsn.max(start), segment_size);
+        let end_lsn = wal::format_lsn(end);
+
+        if end <= start {
+            info!("No new WAL since {start_lsn}");
+            return Ok(None);
+        }
+
+        info!(
+            "Streaming WAL from {start_lsn} to {end_lsn} into {}",
+            archive_dir.display()
+        );
 
-        if data_dir_rows.is_empty() {
-            return Err(PostgresError::BackupError(
-                "Could not determine PostgreSQL data directory".to_string(),
-            ));
+        let range = WalSegmentRange::from_lsns(timeline, start, end, segment_size);
+
+        if self.is_stream_wal_running(client).await? {
+            // A continuous `stream-wal` process already owns the slot, wait for it
+            // to deliver the last segment we need instead of competing with it
+            info!(
+                "WAL is being streamed continuously, waiting for {}",
+                range.last_segment
+            );
+            self.wait_for_segment(&archive_dir.join(&range.last_segment))
+                .await?;
+        } else {
+            let options = PgReceiveWalOptions {
+                endpos: Some(end_lsn.clone()),
+                ..self.receive_wal_options(&archive_dir)
+            };
+
+            PgReceiveWal::run(&options)
+                .map_err(|e| PostgresError::WalError(format!("WAL streaming failed: {e}")))?;
         }
 
-        let data_dir: String = data_dir_rows[0].get(0);
-        let pg_wal_dir = Path::new(&data_dir).join("pg_wal");
+        let first = wal::segment_number(start, segment_size);
+        let last = wal::segment_number(end.saturating_sub(1).max(start), segment_size);
 
-        // Get current WAL position
-        let current_lsn_rows = client
-            .query("SELECT pg_current_wal_lsn()::text", &[])
-            .await
-            .map_err(PostgresError::Postgres)?;
+        // Copy the segments of this backup out of the archive, refusing to
+        // record a backup with holes in it
+        let mut archived_files = Vec::new();
+        for segno in first..=last {
+            let file_name = wal::segment_file_name(timeline, segno, segment_size);
+            let source_path = archive_dir.join(&file_name);
+            if !source_path.exists() {
+                return Err(PostgresError::WalError(format!(
+                    "WAL segment {file_name} is missing from the archive"
+                )));
+            }
 
-        let current_lsn: String = current_lsn_rows[0].get(0);
+            let target_path = wal_dir.join(format!("{file_name}{}", self.compression.extension()));
+            if self.compression.is_none() && self.rate_limiter.is_none() {
+                fs::copy(&source_path, &target_path).map_err(PostgresError::Io)?;
+            } else {
+                self.compression
+                    .compress_file_limited(&source_path, &target_path, self.rate_limiter.as_ref())
+                    .await
+                    .map_err(|e| {
+                        PostgresError::WalError(format!("Failed to copy {file_name}: {e}"))
+                    })?;
+            }
+            debug!(
+                "Copied WAL file: {} -> {}",
+                source_path.display(),
+                target_path.display()
+            );
+            archived_files.push(file_name);
+        }
 
-        // Get list of WAL files between start_lsn and current_lsn
-        let query = format!(
-            "SELECT file_name FROM pg_walfile_name_offset('{start_lsn}') 
-                            UNION 
-                            SELECT file_name FROM pg_walfile_name_offset('{current_lsn}')"
+        info!(
+            "Archived {} WAL files ({} to {})",
+            archived_files.len(),
+            range.first_segment,
+            range.last_segment
         );
 
-        let rows = client
-            .query(&query, &[])
-            .await
-            .map_err(PostgresError::Postgres)?;
+        Ok(Some((end_lsn, range)))
+    }
+
+    /// Continuously stream WAL into the archive directory
+    ///
+    /// Runs until pg_receivewal exits. Incremental backups taken meanwhile pick
+    /// their segments up from the archive instead of streaming themselves.
+    pub async fn stream_wal(&self) -> Result<(), PostgresError> {
+        let archive_dir = self.backup_dir.join(wal::WAL_ARCHIVE_DIR);
+        fs::create_dir_all(&archive_dir).map_err(PostgresError::Io)?;
 
-        // Switch to a new WAL file to ensure all changes are archived
-        client
-            .execute("SELECT pg_switch_wal()", &[])
+        // Connect to PostgreSQL to reserve WAL for the stream
+        let conn_string = self.config.connection_string();
+        let (client, connection) = tokio_postgres::connect(&conn_string, tokio_postgres::NoTls)
             .await
-            .map_err(PostgresError::Postgres)?;
+            .map_err(|e| PostgresError::ConnectionError(e.to_string()))?;
 
-        // Get all available WAL files in the pg_wal directory
-        let mut available_wal_files = Vec::new();
-        if let Ok(entries) = fs::read_dir(&pg_wal_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_file() {
-                    if let Some(file_name) = path.file_name() {
-                        if let Some(file_name_str) = file_name.to_str() {
-                            // Only include files that match the WAL file naming pattern
-                            if file_name_str.len() == 24 && file_name_str.starts_with("0000000") {
-                                available_wal_files.push(file_name_str.to_string());
-                            }
-                        }
-                    }
-                }
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                error!("Connection error: {e}");
             }
+        });
+
+        if self.replication_slot {
+            wal::ensure_replication_slot(&client, wal::WAL_REPLICATION_SLOT).await?;
+        }
+
+        let options = PgReceiveWalOptions {
+            no_loop: false,
+            synchronous: true,
+            application_name: Some(wal::WAL_STREAM_APPLICATION_NAME.to_string()),
+            ..self.receive_wal_options(&archive_dir)
+        };
+
+        info!("Streaming WAL continuously into {}", archive_dir.display());
+        let child = PgReceiveWal::spawn(&options)
+            .map_err(|e| PostgresError::WalError(format!("Failed to start WAL streaming: {e}")))?;
+
+        let cancellation = self.cancellation.clone();
+        let status = tokio::task::spawn_blocking(move || cancellation.wait(child))
+            .await
+            .map_err(|e| PostgresError::WalError(format!("WAL streaming task failed: {e}")))?
+            .map_err(PostgresError::Io)?;
+
+        if !status.success() {
+            return Err(PostgresError::WalError(format!(
+                "pg_receivewal exited with {status}"
+            )));
         }
 
-        // Collect WAL files from the query results
-        let mut needed_wal_files = Vec::new();
-        for row in rows {
-            let file_name: String = row.get(0);
-            needed_wal_files.push(file_name);
+        Ok(())
+    }
+
+    /// Base pg_receivewal options for streaming into `archive_dir`
+    fn receive_wal_options(&self, archive_dir: &Path) -> PgReceiveWalOptions {
+        PgReceiveWalOptions {
+            host: self.config.host.clone(),
+            port: self.config.port,
+            username: self.config.user.clone(),
+            password: self.config.password.clone().unwrap_or_default(),
+            directory: archive_dir.to_string_lossy().to_string(),
+            slot: self
+                .replication_slot
+                .then(|| wal::WAL_REPLICATION_SLOT.to_string()),
+            verbose: true,
+            cancellation: self.cancellation.clone(),
+            ..Default::default()
         }
+    }
 
-        // Add the current WAL file
-        let current_wal_rows = client
-            .query("SELECT pg_walfile_name(pg_current_wal_lsn())", &[])
+    /// Whether a continuous `stream-wal` is already streaming into the archive
+    ///
+    /// With the slot, that is whoever consumes it. Without it, the stream is
+    /// recognised by its application name, and the slot is warned about when
+    /// it exists but sits idle.
+    async fn is_stream_wal_running(&self, client: &Client) -> Result<bool, PostgresError> {
+        if !self.replication_slot {
+            wal::warn_if_slot_idle(client, wal::WAL_REPLICATION_SLOT).await?;
+            return wal::is_stream_wal_connected(client).await;
+        }
+        let row = client
+            .query_opt(
+                "SELECT active FROM pg_replication_slots WHERE slot_name = $1",
+                &[&wal::WAL_REPLICATION_SLOT],
+            )
             .await
             .map_err(PostgresError::Postgres)?;
 
-        if !current_wal_rows.is_empty() {
-            let current_wal: String = current_wal_rows[0].get(0);
-            needed_wal_files.push(current_wal);
-        }
+        Ok(row.map(|r| r.get::<_, bool>(0)).unwrap_or(false))
+    }
 
-        // Copy available WAL files to the backup directory
-        let mut archived_files = Vec::new();
-        for file_name in &available_wal_files {
-            let source_path = pg_wal_dir.join(file_name);
-            let target_path = wal_dir.join(file_name);
-
-            // Copy the WAL file to the backup directory
-            match fs::copy(&source_path, &target_path) {
-                Ok(_) => {
-                    debug!(
-                        "Copied WAL file: {} -> {}",
-                        source_path.display(),
-                        target_path.display()
-                    );
-                    archived_files.push(file_name.clone());
-                }
-                Err(e) => {
-                    warn!("Failed to copy WAL file {}: {}", source_path.display(), e);
-                }
+    /// Wait for a segment to be completed in the archive directory
+    async fn wait_for_segment(&self, segment: &Path) -> Result<(), PostgresError> {
+        for _ in 0..WAL_WAIT_SECS {
+            if segment.exists() {
+                return Ok(());
+            }
+            if self.cancellation.is_cancelled() {
+                return Err(PostgresError::WalError(
+                    "Cancelled while waiting for WAL".to_string(),
+                ));
             }
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
         }
 
-        info!("Archived {} WAL files", archived_files.len());
+        Err(PostgresError::WalError(format!(
+            "Timed out waiting for WAL segment {}",
+            segment.display()
+        )))
+    }
 
-        Ok(archived_files)
+    /// Remove what a cancelled backup wrote so far
+    fn cancelled(&self, backup_path: &Path) -> PostgresError {
+        warn!("Incremental backup cancelled, removing {backup_path:?}");
+        if let Err(e) = fs::remove_dir_all(backup_path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove cancelled backup {backup_path:?}: {e}");
+            }
+        }
+        PostgresError::BackupError("Incremental backup cancelled".to_string())
     }
 
     /// Calculate backup size in bytes
@@ -7,20 +7,39 @@ use std::path::{Path, PathBuf};
 use tokio::time::Duration;
 use tokio_postgres::Client;
 
-use crate::common::{Backup, BackupType, PostgresConfig};
-use crate::wrapper::{PgDump, PgDumpFormat, PgDumpOptions};
+use crate::common::{dump_file_name, Backup, BackupType, PostgresConfig};
+use crate::wrapper::{Cancellation, PgDump, PgDumpFormat, PgDumpOptions, ProcessPriority};
 use crate::PostgresError;
 
 /// Snapshot backup manager for logical backups
 pub struct SnapshotBackupManager {
     config: PostgresConfig,
     backup_dir: PathBuf,
+    priority: ProcessPriority,
+    cancellation: Cancellation,
 }
 
 impl SnapshotBackupManager {
     /// Create a new snapshot backup manager
     pub fn new(config: PostgresConfig, backup_dir: PathBuf) -> Self {
-        Self { config, backup_dir }
+        Self {
+            config,
+            backup_dir,
+            priority: ProcessPriority::default(),
+            cancellation: Cancellation::default(),
+        }
+    }
+
+    /// Run pg_dump with `priority`
+    pub fn with_priority(mut self, priority: ProcessPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Terminate pg_dump when `cancellation` is cancelled
+    pub fn with_cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = cancellation;
+        self
     }
 
     /// Perform a snapshot backup using pg_dump
@@ -75,7 +94,8 @@ impl SnapshotBackupManager {
             server_version,
             None,
         );
-        let dump_file = backup_path.join(format!("{}.dump", self.config.database));
+        let dump_name = dump_file_name(&self.config.database);
+        let dump_file = backup_path.join(&dump_name);
 
         // Temporarily use a placeholder for WAL position to bypass the pg_lsn type issue
         let wal_start = "0/0000000".to_string();
@@ -100,6 +120,8 @@ impl SnapshotBackupManager {
             schemas: Vec::new(),
             tables: Vec::new(),
             exclude_tables: Vec::new(),
+            priority: self.priority.clone(),
+            cancellation: self.cancellation.clone(),
         };
 
         match PgDump::run(&options) {
@@ -122,10 +144,12 @@ impl SnapshotBackupManager {
                 let size_bytes = self.calculate_backup_size(&backup_path)?;
 
                 // Update backup metadata
+                backup.dump_file = Some(dump_name);
                 backup.complete(wal_end, size_bytes);
 
                 Ok(backup)
             }
+            Err(_) if self.cancellation.is_cancelled() => Err(self.cancelled(&backup_path)),
             Err(e) => {
                 let error_msg = format!("Snapshot backup failed: {e}");
                 error!("{error_msg}");
@@ -256,6 +280,17 @@ impl SnapshotBackupManager {
         ))
     }
 
+    /// Remove what a cancelled backup wrote so far
+    fn cancelled(&self, backup_path: &Path) -> PostgresError {
+        warn!("Snapshot backup cancelled, removing {backup_path:?}");
+        if let Err(e) = fs::remove_dir_all(backup_path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove cancelled backup {backup_path:?}: {e}");
+            }
+        }
+        PostgresError::BackupError("Snapshot backup cancelled".to_string())
+    }
+
     /// Calculate backup size in bytes
     fn calculate_backup_size(&self, backup_path: &Path) -> Result<u64, PostgresError> {
         let mut total_size = 0;
@@ -1,14 +1,27 @@
 use anyhow::{anyhow, Result};
-use log::{error, info};
-use std::{path::PathBuf, sync::atomic::Ordering};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc},
+};
 use uuid::Uuid;
 
 // Import storage module
-use storage::{Metadata, PostgresBackupStorage, StorageProviderType};
+use storage::{
+    BackupInfo, Compression, Keyring, MasterKey, Metadata, ObjectLock, ObjectLockMode,
+    PostgresBackupStorage, RateLimiter, StorageClass, StorageError, StorageProviderType,
+    TieringRule,
+};
 
-use crate::common::PostgresConfig;
+use crate::common::{Backup, BackupCatalog, BackupType, PostgresConfig, VerificationStatus};
 use crate::manager::PostgresManager;
+use crate::restore::{RecoveryTarget, RecoveryTargetAction, RemoteSource};
+use crate::retention::{RetentionPlan, RetentionPolicy};
 use crate::tunnel_keeper::TunnelKeeper;
+use crate::verify::VerifyOptions;
+use crate::wrapper::ProcessPriority;
 use crate::PostgresError;
 
 mod restore_full_incremental;
@@ -25,8 +38,10 @@ pub async fn snapshot_backup(
     password: Option<String>,
     ssl_mode: Option<String>,
     backup_dir: PathBuf,
+    compression: Option<String>,
     ssh: SshOptions,
     storage: StorageOptions,
+    throttle: ThrottleOptions,
 ) -> Result<()> {
     info!("[CLI] Entering snapshot_backup");
     info!(
@@ -55,6 +70,8 @@ pub async fn snapshot_backup(
         ssh_key_path: ssh.key_path.clone(),
         ssh_local_port: ssh.local_port,
         ssh_remote_port: ssh.remote_port,
+        ssh_known_hosts: ssh.known_hosts.clone(),
+        ssh_host_key_fingerprint: ssh.host_key_fingerprint.clone(),
     };
     let config_clone = config.clone();
     // Setup SSH tunnel if needed
@@ -66,91 +83,19 @@ pub async fn snapshot_backup(
             return Err(anyhow!("Failed to setup SSH tunnel: {}", e));
         }
     }
-    let mut manager = PostgresManager::new(config_clone.clone(), backup_dir.clone())?;
+    let mut manager = PostgresManager::new(config_clone.clone(), backup_dir.clone())?
+        .with_compression(parse_compression(compression)?)
+        .with_priority(throttle.priority());
+    let limiter = throttle.rate_limiter()?;
+    if let Some(limiter) = &limiter {
+        manager = manager.with_rate_limiter(limiter.clone());
+    }
     info!("[CLI] Performing snapshot backup...");
     let backup_result = manager.snapshot_backup().await;
     let backup = backup_result.as_ref().map_err(|e| anyhow!(e.to_string()))?;
     info!("[CLI] Snapshot backup completed: {}", backup.id);
     if storage.remote_storage {
-        info!("[CLI] Uploading snapshot backup to remote storage...");
-        let storage_instance = create_storage_provider(&storage).await?;
-        if let Some(storage) = storage_instance {
-            let mut metadata = Metadata::new();
-            metadata.insert("backup_id".to_string(), backup.id.to_string());
-            metadata.insert(
-                "backup_type".to_string(),
-                format!("{:?}", backup.backup_type),
-            );
-            metadata.insert("database".to_string(), database.clone());
-            metadata.insert("start_time".to_string(), backup.start_time.to_string());
-            // Find the actual backup directory (timestamp format)
-            let mut actual_backup_path = PathBuf::new();
-            if let Ok(entries) = std::fs::read_dir(&backup_dir) {
-                for entry in entries.flatten() {
-                    let path = entry.path();
-                    if path.is_dir()
-                        && path
-                            .file_name()
-                            .unwrap_or_default()
-                            .to_string_lossy()
-                            .contains("snapshot_backup_")
-                    {
-                        actual_backup_path = path;
-                        break;
-                    }
-                }
-            }
-            info!(
-                "[CLI] Using backup directory: {}",
-                actual_backup_path.display()
-            );
-            storage
-                .upload_physical_backup(
-                    &backup.id.to_string(),
-                    &actual_backup_path,
-                    Some(metadata.clone()),
-                )
-                .await
-                .map_err(|e| anyhow!("Failed to upload physical backup: {}", e))?;
-            let dump_file = actual_backup_path.join(format!("{database}.dump"));
-            if dump_file.exists() {
-                info!(
-                    "[CLI] Uploading logical backup from: {}",
-                    dump_file.display()
-                );
-                storage
-                    .upload_logical_backup(
-                        &backup.id.to_string(),
-                        &dump_file,
-                        Some(metadata.clone()),
-                    )
-                    .await
-                    .map_err(|e| anyhow!("Failed to upload logical backup: {}", e))?;
-            } else {
-                info!(
-                    "[CLI] Logical backup file not found at: {}",
-                    dump_file.display()
-                );
-                let alt_dump_file = actual_backup_path.join("pg_dump.dump");
-                if alt_dump_file.exists() {
-                    info!(
-                        "[CLI] Uploading logical backup from alternative location: {}",
-                        alt_dump_file.display()
-                    );
-                    storage
-                        .upload_logical_backup(
-                            &backup.id.to_string(),
-                            &alt_dump_file,
-                            Some(metadata),
-                        )
-                        .await
-                        .map_err(|e| anyhow!("Failed to upload logical backup: {}", e))?;
-                } else {
-                    info!("[CLI] No logical backup file found to upload");
-                }
-            }
-            info!("[CLI] Snapshot backup successfully uploaded to remote storage");
-        }
+        upload_to_destinations(&storage, &backup_dir, backup, &database, limiter.as_ref()).await?;
     }
     // Close SSH tunnel after all operations
     if config.ssh_host.is_some() {
@@ -179,9 +124,41 @@ pub struct SshOptions {
     pub key_path: Option<String>,
     pub local_port: Option<u16>,
     pub remote_port: Option<u16>,
+    pub known_hosts: Option<String>,
+    pub host_key_fingerprint: Option<String>,
 }
 
+/// Limits keeping a backup from starving the database host
 #[derive(Clone, Debug, Default)]
+pub struct ThrottleOptions {
+    pub bandwidth_limit: Option<String>,
+    pub nice: Option<i32>,
+    pub ionice_class: Option<u8>,
+    pub ionice_level: Option<u8>,
+}
+
+impl ThrottleOptions {
+    /// Limiter shared by the WAL copy and every upload of the backup
+    pub fn rate_limiter(&self) -> Result<Option<RateLimiter>> {
+        Ok(self
+            .bandwidth_limit
+            .as_deref()
+            .map(RateLimiter::parse)
+            .transpose()?)
+    }
+
+    pub fn priority(&self) -> ProcessPriority {
+        ProcessPriority {
+            nice: self.nice,
+            ionice_class: self.ionice_class,
+            ionice_level: self.ionice_level,
+        }
+    }
+}
+
+/// Remote storage flags, also accepted as JSON by the daemon's commands
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
 pub struct StorageOptions {
     pub remote_storage: bool,
     pub provider_type: Option<String>,
@@ -191,6 +168,178 @@ pub struct StorageOptions {
     pub endpoint: Option<String>,
     pub access_key: Option<String>,
     pub secret_key: Option<String>,
+    /// SSH private key for SFTP, service-account JSON for GCS
+    pub key_file: Option<String>,
+    pub encryption_key: Option<String>,
+    pub encryption_key_file: Option<String>,
+    /// Further destinations each backup is uploaded to, see [`StorageOptions::parse_destination`]
+    pub replicate_to: Vec<String>,
+    pub object_lock_days: Option<u32>,
+    pub object_lock_mode: Option<String>,
+    pub storage_class: Option<String>,
+}
+
+impl StorageOptions {
+    /// Keyring from the encryption flags; `--encryption-key` comes first, then the keyfile
+    fn keyring(&self) -> Result<Option<Keyring>> {
+        let mut keys = Vec::new();
+        if let Some(key) = &self.encryption_key {
+            keys.push(MasterKey::parse(key).map_err(|e| anyhow!("{}", e))?);
+        }
+        if let Some(path) = &self.encryption_key_file {
+            let keyring = Keyring::from_keyfile(std::path::Path::new(path))
+                .map_err(|e| anyhow!("Failed to load encryption keyfile {}: {}", path, e))?;
+            keys.extend(keyring.keys().iter().cloned());
+        }
+        if keys.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Keyring::new(keys).map_err(|e| anyhow!("{}", e))?))
+    }
+
+    /// Object lock from `--object-lock-days` and `--object-lock-mode`
+    fn object_lock(&self) -> Result<Option<ObjectLock>> {
+        let Some(days) = self.object_lock_days else {
+            if self.object_lock_mode.is_some() {
+                return Err(anyhow!("--object-lock-mode requires --object-lock-days"));
+            }
+            return Ok(None);
+        };
+        let mode = match &self.object_lock_mode {
+            Some(mode) => mode.parse().map_err(|e| anyhow!("{}", e))?,
+            None => ObjectLockMode::Governance,
+        };
+        Ok(Some(ObjectLock::new(mode, days)))
+    }
+
+    /// Storage options of a destination written `provider://bucket[/prefix][?key=value&...]`
+    ///
+    /// The keys are `region`, `endpoint`, `access_key`, `secret_key`,
+    /// `key_file` and `storage_class`. The destination shares the encryption keys and object
+    /// lock of `self`.
+    fn parse_destination(&self, spec: &str) -> Result<StorageOptions> {
+        let (location, query) = spec.split_once('?').unwrap_or((spec, ""));
+        let invalid = || {
+            anyhow!(
+                "Invalid destination {}, expected provider://bucket[/prefix]",
+                location
+            )
+        };
+        let (provider, path) = location.split_once("://").ok_or_else(invalid)?;
+        let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+        if provider.is_empty() || bucket.is_empty() {
+            return Err(invalid());
+        }
+        let prefix = prefix.trim_matches('/');
+
+        let mut destination = StorageOptions {
+            remote_storage: true,
+            provider_type: Some(provider.to_string()),
+            bucket: Some(bucket.to_string()),
+            prefix: (!prefix.is_empty()).then(|| prefix.to_string()),
+            encryption_key: self.encryption_key.clone(),
+            encryption_key_file: self.encryption_key_file.clone(),
+            object_lock_days: self.object_lock_days,
+            object_lock_mode: self.object_lock_mode.clone(),
+            ..Default::default()
+        };
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = Some(value.to_string());
+            match key {
+                "region" => destination.region = value,
+                "endpoint" => destination.endpoint = value,
+                "access_key" => destination.access_key = value,
+                "secret_key" => destination.secret_key = value,
+                "key_file" => destination.key_file = value,
+                "storage_class" => destination.storage_class = value,
+                _ => {
+                    return Err(anyhow!(
+                        "Unknown option {} in destination {}",
+                        key,
+                        location
+                    ))
+                }
+            }
+        }
+        Ok(destination)
+    }
+
+    /// Name of the destination in the catalogs, `provider://bucket[/prefix]`
+    fn destination(&self) -> String {
+        let mut destination = format!(
+            "{}://{}",
+            self.provider_type.as_deref().unwrap_or("s3").to_lowercase(),
+            self.bucket.as_deref().unwrap_or_default()
+        );
+        if let Some(prefix) = self.prefix.as_deref().filter(|p| !p.is_empty()) {
+            destination.push('/');
+            destination.push_str(prefix.trim_matches('/'));
+        }
+        destination
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RecoveryTargetOptions {
+    pub time: Option<String>,
+    pub lsn: Option<String>,
+    pub xid: Option<u64>,
+    pub name: Option<String>,
+    #[serde(default = "default_target_action")]
+    pub action: String,
+}
+
+fn default_target_action() -> String {
+    "promote".to_string()
+}
+
+impl RecoveryTargetOptions {
+    /// Turn the command line flags into exactly one recovery target
+    pub fn parse(self) -> Result<(RecoveryTarget, RecoveryTargetAction)> {
+        let mut targets = Vec::new();
+        if let Some(time) = self.time {
+            let time = chrono::DateTime::parse_from_rfc3339(&time)
+                .or_else(|_| chrono::DateTime::parse_from_str(&time, "%Y-%m-%dT%H:%M:%S%z"))
+                .map_err(|e| anyhow!("Invalid target time format: {}", e))?
+                .with_timezone(&chrono::Utc);
+            targets.push(RecoveryTarget::Time(time));
+        }
+        if let Some(lsn) = self.lsn {
+            if crate::wal::parse_lsn(&lsn).is_none() {
+                return Err(anyhow!("Invalid target LSN: {}", lsn));
+            }
+            targets.push(RecoveryTarget::Lsn(lsn));
+        }
+        if let Some(xid) = self.xid {
+            targets.push(RecoveryTarget::Xid(xid));
+        }
+        if let Some(name) = self.name {
+            targets.push(RecoveryTarget::Name(name));
+        }
+
+        if targets.len() != 1 {
+            return Err(anyhow!(
+                "Specify exactly one of --target-time, --target-lsn, --target-xid or --target-name"
+            ));
+        }
+
+        let action = self
+            .action
+            .parse::<RecoveryTargetAction>()
+            .map_err(|e| anyhow!(e))?;
+        Ok((targets.remove(0), action))
+    }
+}
+
+/// Parse the `--compression` flag, defaulting to no compression
+fn parse_compression(compression: Option<String>) -> Result<Compression> {
+    match compression {
+        Some(compression) => compression
+            .parse()
+            .map_err(|e| anyhow!("Invalid compression: {}", e)),
+        None => Ok(Compression::default()),
+    }
 }
 
 async fn create_storage_provider(
@@ -210,11 +359,26 @@ async fn create_storage_provider(
     let provider_type = match &storage.provider_type {
         Some(provider) => match provider.to_lowercase().as_str() {
             "s3" => StorageProviderType::S3,
+            "gcs" => StorageProviderType::Gcs,
+            "azure" => StorageProviderType::Azure,
+            "local" => StorageProviderType::Local,
+            "sftp" => StorageProviderType::Sftp,
             _ => return Err(anyhow!("Unsupported storage provider type: {}", provider)),
         },
         None => StorageProviderType::S3,
     };
 
+    // The key file is the SSH key of SFTP, or the credentials of GCS which
+    // otherwise come from the environment
+    let credentials_path = match provider_type {
+        StorageProviderType::Gcs => storage
+            .key_file
+            .clone()
+            .or_else(|| std::env::var("GOOGLE_APPLICATION_CREDENTIALS").ok()),
+        StorageProviderType::Sftp => storage.key_file.clone(),
+        _ => None,
+    };
+
     // Create storage provider
     let storage_instance = PostgresBackupStorage::new(
         provider_type,
@@ -225,15 +389,226 @@ async fn create_storage_provider(
         storage.access_key.clone(),
         storage.secret_key.clone(),
         None, // account_id
-        None, // project_id
-        None, // credentials_path
+        std::env::var("GOOGLE_CLOUD_PROJECT").ok(),
+        credentials_path,
     )
     .await
     .map_err(|e| anyhow!("Failed to create storage provider: {}", e))?;
 
+    let storage_instance = match storage.keyring()? {
+        Some(keyring) => {
+            info!(
+                "Client-side encryption enabled with key {}",
+                keyring.primary().id()
+            );
+            storage_instance.with_encryption(keyring)
+        }
+        None => storage_instance,
+    };
+    let storage_instance = match storage.object_lock()? {
+        Some(lock) => {
+            info!(
+                "Locking uploaded backup files for {} days in {} mode",
+                lock.retain_for.num_days(),
+                lock.mode.as_str()
+            );
+            storage_instance.with_object_lock(lock)
+        }
+        None => storage_instance,
+    };
+    let storage_instance = match &storage.storage_class {
+        Some(class) => {
+            let class: StorageClass = class.parse().map_err(|e| anyhow!("{}", e))?;
+            storage_instance.with_storage_class(class)
+        }
+        None => storage_instance,
+    };
+
     Ok(Some(storage_instance))
 }
 
+/// Remote storage a `--stream` restore reads the backup from
+pub async fn streaming_source(storage: &StorageOptions) -> Result<RemoteSource> {
+    let storage = create_storage_provider(storage)
+        .await?
+        .ok_or_else(|| anyhow!("--stream requires --remote-storage"))?;
+    info!("Streaming the backup from remote storage");
+    Ok(Arc::new(storage))
+}
+
+/// The type of a backup as remote storage records it
+fn storage_backup_type(backup_type: &BackupType) -> storage::BackupType {
+    match backup_type {
+        BackupType::Full => storage::BackupType::Full,
+        BackupType::Incremental => storage::BackupType::Incremental,
+        BackupType::Snapshot => storage::BackupType::Snapshot,
+    }
+}
+
+/// Record an uploaded backup in the remote catalog so other hosts can find it
+async fn register_remote_backup(storage: &PostgresBackupStorage, backup: &Backup) -> Result<()> {
+    let info = BackupInfo {
+        id: backup.id.to_string(),
+        backup_type: storage_backup_type(&backup.backup_type),
+        timestamp: backup.start_time,
+        size: backup.size_bytes.unwrap_or(0),
+        parent_id: backup.base_backup_id.map(|id| id.to_string()),
+        details: Some(serde_json::to_value(backup)?),
+    };
+    storage
+        .register_backup(info)
+        .await
+        .map_err(|e| anyhow!("Failed to register backup in remote catalog: {}", e))
+}
+
+/// Upload the files of a backup, its logical dump among them
+///
+/// The dump keeps the name recorded in [`Backup::dump_file`], which restores
+/// look it up by.
+pub(crate) async fn upload_backup(
+    storage: &PostgresBackupStorage,
+    backup: &Backup,
+    database: &str,
+) -> Result<()> {
+    let mut metadata = Metadata::new();
+    metadata.insert("backup_id".to_string(), backup.id.to_string());
+    metadata.insert(
+        "backup_type".to_string(),
+        format!("{:?}", backup.backup_type),
+    );
+    metadata.insert("database".to_string(), database.to_string());
+    metadata.insert("start_time".to_string(), backup.start_time.to_string());
+    let actual_backup_path = &backup.backup_path;
+    info!("Using backup directory: {}", actual_backup_path.display());
+    storage
+        .upload_physical_backup(
+            &backup.id.to_string(),
+            &storage_backup_type(&backup.backup_type),
+            actual_backup_path,
+            Some(metadata),
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to upload physical backup: {}", e))?;
+    Ok(())
+}
+
+/// Upload a backup to the remote storage and to every `--replicate-to` destination
+///
+/// All destinations are tried even when one fails. The outcome for each is
+/// kept with the backup in the local catalog and in the remote catalog of
+/// every destination it reached. Uploads to all destinations draw from the
+/// same `limiter`. The error lists every destination that failed, whether
+/// the upload or the catalog update did.
+pub async fn upload_to_destinations(
+    storage: &StorageOptions,
+    backup_dir: &Path,
+    backup: &Backup,
+    database: &str,
+    limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let mut destinations = vec![storage.clone()];
+    for spec in &storage.replicate_to {
+        destinations.push(storage.parse_destination(spec)?);
+    }
+
+    let mut backup = backup.clone();
+    let mut reached = Vec::new();
+    let mut failed = Vec::new();
+    for destination in &destinations {
+        let name = destination.destination();
+        info!("Uploading backup {} to {name}...", backup.id);
+        let upload = async {
+            let mut target = create_storage_provider(destination)
+                .await?
+                .ok_or_else(|| anyhow!("Remote storage is not configured"))?
+                .with_compression(backup.compression);
+            if let Some(limiter) = limiter {
+                target = target.with_rate_limiter(limiter.clone());
+            }
+            upload_backup(&target, &backup, database).await?;
+            Ok::<_, anyhow::Error>(target)
+        };
+        match upload.await {
+            Ok(target) => {
+                info!("Backup {} uploaded to {name}", backup.id);
+                backup.record_replica(name.clone(), None);
+                reached.push((name, target));
+            }
+            Err(e) => {
+                error!("Failed to upload backup {} to {name}: {e}", backup.id);
+                backup.record_replica(name.clone(), Some(e.to_string()));
+                failed.push(name);
+            }
+        }
+    }
+
+    save_to_local_catalog(backup_dir, backup.clone())?;
+    let mut unregistered = Vec::new();
+    for (name, target) in &reached {
+        if let Err(e) = register_remote_backup(target, &backup).await {
+            error!(
+                "Failed to register backup {} in the catalog of {name}: {e}",
+                backup.id
+            );
+            unregistered.push(format!("{name} ({e})"));
+        }
+    }
+
+    let mut errors = Vec::new();
+    if !failed.is_empty() {
+        errors.push(format!("was not uploaded to {}", failed.join(", ")));
+    }
+    if !unregistered.is_empty() {
+        errors.push(format!(
+            "was not registered in the catalog of {}",
+            unregistered.join(", ")
+        ));
+    }
+    if !errors.is_empty() {
+        return Err(anyhow!("Backup {} {}", backup.id, errors.join(" and ")));
+    }
+    Ok(())
+}
+
+/// Add or replace a backup in the local catalog of `backup_dir`
+fn save_to_local_catalog(backup_dir: &Path, backup: Backup) -> Result<()> {
+    let catalog_path = backup_dir.join("backup_catalog.json");
+    let mut catalog = if catalog_path.exists() {
+        BackupCatalog::load_from_file(&catalog_path)?
+    } else {
+        BackupCatalog::new()
+    };
+    catalog.upsert_backup(backup);
+    catalog.save_to_file(&catalog_path)?;
+    Ok(())
+}
+
+/// Add a downloaded backup to the local catalog of `backup_dir`
+///
+/// The record comes from the remote catalog, so a host that never took the
+/// backup can restore it.
+async fn import_remote_backup(
+    storage: &PostgresBackupStorage,
+    backup_dir: &Path,
+    backup_id: &str,
+    local_path: &Path,
+) -> Result<()> {
+    let info = storage.get_backup_info(backup_id).await.map_err(|e| {
+        anyhow!(
+            "Failed to read remote record of backup {}: {}",
+            backup_id,
+            e
+        )
+    })?;
+    let Some(details) = info.details else {
+        log::warn!("Remote record of backup {backup_id} has no details, not adding it to the local catalog");
+        return Ok(());
+    };
+    let mut backup: Backup = serde_json::from_value(details)?;
+    backup.backup_path = local_path.to_path_buf();
+    save_to_local_catalog(backup_dir, backup)
+}
+
 #[allow(clippy::too_many_arguments)]
 pub async fn full_backup(
     host: String,
@@ -243,8 +618,11 @@ pub async fn full_backup(
     password: Option<String>,
     ssl_mode: Option<String>,
     backup_dir: PathBuf,
+    compression: Option<String>,
+    replication_slot: bool,
     ssh: SshOptions,
     storage: StorageOptions,
+    throttle: ThrottleOptions,
 ) -> Result<()> {
     let config = PostgresConfig {
         host: if ssh.host.is_some() {
@@ -268,6 +646,8 @@ pub async fn full_backup(
         ssh_key_path: ssh.key_path.clone(),
         ssh_local_port: ssh.local_port,
         ssh_remote_port: ssh.remote_port,
+        ssh_known_hosts: ssh.known_hosts.clone(),
+        ssh_host_key_fingerprint: ssh.host_key_fingerprint.clone(),
     };
     let config_clone = config.clone();
     // Setup SSH tunnel if needed
@@ -278,82 +658,20 @@ pub async fn full_backup(
             return Err(anyhow!("Failed to setup SSH tunnel: {}", e));
         }
     }
-    let mut manager = PostgresManager::new(config_clone.clone(), backup_dir.clone())?;
+    let mut manager = PostgresManager::new(config_clone.clone(), backup_dir.clone())?
+        .with_compression(parse_compression(compression)?)
+        .with_priority(throttle.priority())
+        .with_replication_slot(replication_slot);
+    let limiter = throttle.rate_limiter()?;
+    if let Some(limiter) = &limiter {
+        manager = manager.with_rate_limiter(limiter.clone());
+    }
     log::info!("Performing full backup...");
     let backup_result = manager.full_backup().await;
     let backup = backup_result.as_ref().map_err(|e| anyhow!(e.to_string()))?;
     log::info!("Full backup completed: {}", backup.id);
     if storage.remote_storage {
-        log::info!("Uploading full backup to remote storage...");
-        let storage_instance = create_storage_provider(&storage).await?;
-        if let Some(storage) = storage_instance {
-            let mut metadata = Metadata::new();
-            metadata.insert("backup_id".to_string(), backup.id.to_string());
-            metadata.insert(
-                "backup_type".to_string(),
-                format!("{:?}", backup.backup_type),
-            );
-            metadata.insert("database".to_string(), database.clone());
-            metadata.insert("start_time".to_string(), backup.start_time.to_string());
-            // Find the actual backup directory (timestamp format)
-            let mut actual_backup_path = PathBuf::new();
-            if let Ok(entries) = std::fs::read_dir(&backup_dir) {
-                for entry in entries.flatten() {
-                    let path = entry.path();
-                    if path.is_dir()
-                        && path
-                            .file_name()
-                            .unwrap_or_default()
-                            .to_string_lossy()
-                            .contains("full_backup_")
-                    {
-                        actual_backup_path = path;
-                        break;
-                    }
-                }
-            }
-            log::info!("Using backup directory: {}", actual_backup_path.display());
-            storage
-                .upload_physical_backup(
-                    &backup.id.to_string(),
-                    &actual_backup_path,
-                    Some(metadata.clone()),
-                )
-                .await
-                .map_err(|e| anyhow!("Failed to upload physical backup: {}", e))?;
-            let dump_file = actual_backup_path.join(format!("{database}.dump"));
-            if dump_file.exists() {
-                log::info!("Uploading logical backup from: {}", dump_file.display());
-                storage
-                    .upload_logical_backup(
-                        &backup.id.to_string(),
-                        &dump_file,
-                        Some(metadata.clone()),
-                    )
-                    .await
-                    .map_err(|e| anyhow!("Failed to upload logical backup: {}", e))?;
-            } else {
-                log::info!("Logical backup file not found at: {}", dump_file.display());
-                let alt_dump_file = actual_backup_path.join("pg_dump.dump");
-                if alt_dump_file.exists() {
-                    log::info!(
-                        "Uploading logical backup from alternative location: {}",
-                        alt_dump_file.display()
-                    );
-                    storage
-                        .upload_logical_backup(
-                            &backup.id.to_string(),
-                            &alt_dump_file,
-                            Some(metadata),
-                        )
-                        .await
-                        .map_err(|e| anyhow!("Failed to upload logical backup: {}", e))?;
-                } else {
-                    log::info!("No logical backup file found to upload");
-                }
-            }
-            log::info!("Full backup successfully uploaded to remote storage");
-        }
+        upload_to_destinations(&storage, &backup_dir, backup, &database, limiter.as_ref()).await?;
     }
     // Close SSH tunnel after all operations
     if config.ssh_host.is_some() {
@@ -381,8 +699,11 @@ pub async fn incremental_backup(
     password: Option<String>,
     ssl_mode: Option<String>,
     backup_dir: PathBuf,
+    compression: Option<String>,
+    replication_slot: bool,
     ssh: SshOptions,
     storage: StorageOptions,
+    throttle: ThrottleOptions,
 ) -> Result<()> {
     let config = PostgresConfig {
         host: if ssh.host.is_some() {
@@ -406,6 +727,8 @@ pub async fn incremental_backup(
         ssh_key_path: ssh.key_path.clone(),
         ssh_local_port: ssh.local_port,
         ssh_remote_port: ssh.remote_port,
+        ssh_known_hosts: ssh.known_hosts.clone(),
+        ssh_host_key_fingerprint: ssh.host_key_fingerprint.clone(),
     };
     let config_clone = config.clone();
     // Setup SSH tunnel if needed
@@ -416,78 +739,20 @@ pub async fn incremental_backup(
             return Err(anyhow!("Failed to setup SSH tunnel: {}", e));
         }
     }
-    let mut manager = PostgresManager::new(config_clone.clone(), backup_dir.clone())?;
+    let mut manager = PostgresManager::new(config_clone.clone(), backup_dir.clone())?
+        .with_compression(parse_compression(compression)?)
+        .with_priority(throttle.priority())
+        .with_replication_slot(replication_slot);
+    let limiter = throttle.rate_limiter()?;
+    if let Some(limiter) = &limiter {
+        manager = manager.with_rate_limiter(limiter.clone());
+    }
     info!("Performing incremental backup...");
     let backup_result = manager.incremental_backup().await;
     let backup = backup_result.as_ref().map_err(|e| anyhow!(e.to_string()))?;
     info!("Incremental backup completed: {}", backup.id);
     if storage.remote_storage {
-        info!("Uploading incremental backup to remote storage...");
-        let storage_instance = create_storage_provider(&storage).await?;
-        if let Some(storage) = storage_instance {
-            let mut metadata = Metadata::new();
-            metadata.insert("backup_id".to_string(), backup.id.to_string());
-            metadata.insert(
-                "backup_type".to_string(),
-                format!("{:?}", backup.backup_type),
-            );
-            metadata.insert("database".to_string(), database.clone());
-            metadata.insert("start_time".to_string(), backup.start_time.to_string());
-            // Find the actual backup directory (timestamp format)
-            let mut actual_backup_path = PathBuf::new();
-            if let Ok(entries) = std::fs::read_dir(&backup_dir) {
-                for entry in entries.flatten() {
-                    let path = entry.path();
-                    if path.is_dir()
-                        && path
-                            .file_name()
-                            .unwrap_or_default()
-                            .to_string_lossy()
-                            .contains("incremental_backup_")
-                    {
-                        actual_backup_path = path;
-                        break;
-                    }
-                }
-            }
-            info!("Using backup directory: {}", actual_backup_path.display());
-            storage
-                .upload_physical_backup(
-                    &backup.id.to_string(),
-                    &actual_backup_path,
-                    Some(metadata.clone()),
-                )
-                .await
-                .map_err(|e| anyhow!("Failed to upload physical backup: {}", e))?;
-            let dump_file = actual_backup_path.join(format!("{database}.dump"));
-            if dump_file.exists() {
-                info!("Uploading logical backup from: {}", dump_file.display());
-                storage
-                    .upload_logical_backup(&backup.id.to_string(), &dump_file, Some(metadata))
-                    .await
-                    .map_err(|e| anyhow!("Failed to upload logical backup: {}", e))?;
-            } else {
-                info!("Logical backup file not found at: {}", dump_file.display());
-                let alt_dump_file = actual_backup_path.join("pg_dump.dump");
-                if alt_dump_file.exists() {
-                    info!(
-                        "Uploading logical backup from alternative location: {}",
-                        alt_dump_file.display()
-                    );
-                    storage
-                        .upload_logical_backup(
-                            &backup.id.to_string(),
-                            &alt_dump_file,
-                            Some(metadata),
-                        )
-                        .await
-                        .map_err(|e| anyhow!("Failed to upload logical backup: {}", e))?;
-                } else {
-                    info!("No logical backup file found to upload");
-                }
-            }
-            info!("Incremental backup successfully uploaded to remote storage");
-        }
+        upload_to_destinations(&storage, &backup_dir, backup, &database, limiter.as_ref()).await?;
     }
     // Close SSH tunnel after all operations
     if config.ssh_host.is_some() {
@@ -506,6 +771,515 @@ pub async fn incremental_backup(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
+pub async fn stream_wal(
+    host: String,
+    port: u16,
+    database: String,
+    user: String,
+    password: Option<String>,
+    ssl_mode: Option<String>,
+    backup_dir: PathBuf,
+    replication_slot: bool,
+    ssh: SshOptions,
+) -> Result<()> {
+    let config = PostgresConfig {
+        host: if ssh.host.is_some() {
+            "localhost".to_string()
+        } else {
+            host
+        },
+        port: if ssh.host.is_some() {
+            ssh.local_port.unwrap_or(6969)
+        } else {
+            port
+        },
+        database,
+        user,
+        password,
+        ssl_mode,
+        ssh_host: ssh.host.clone(),
+        ssh_user: ssh.user.clone(),
+        ssh_port: ssh.port,
+        ssh_password: ssh.password.clone(),
+        ssh_key_path: ssh.key_path.clone(),
+        ssh_local_port: ssh.local_port,
+        ssh_remote_port: ssh.remote_port,
+        ssh_known_hosts: ssh.known_hosts.clone(),
+        ssh_host_key_fingerprint: ssh.host_key_fingerprint.clone(),
+    };
+    // Setup SSH tunnel if needed
+    if config.ssh_host.is_some() {
+        let keeper_instance = TunnelKeeper::instance().await;
+        let mut keeper = keeper_instance.lock().await;
+        if let Err(e) = keeper.setup(&config).await {
+            return Err(anyhow!("Failed to setup SSH tunnel: {}", e));
+        }
+    }
+    let manager =
+        PostgresManager::new(config.clone(), backup_dir)?.with_replication_slot(replication_slot);
+    info!("Streaming WAL, press Ctrl+C to stop...");
+    let result = manager.stream_wal().await;
+    // Close SSH tunnel after streaming stops
+    if config.ssh_host.is_some() {
+        let keeper_instance = TunnelKeeper::instance().await;
+        let mut keeper = keeper_instance.lock().await;
+        if let Err(e) = keeper.close().await {
+            error!("Warning: Error closing SSH tunnel: {e}");
+        }
+    }
+    result.map_err(|e| anyhow!(e.to_string()))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn prune(
+    host: String,
+    port: u16,
+    database: String,
+    user: String,
+    password: Option<String>,
+    ssl_mode: Option<String>,
+    backup_dir: PathBuf,
+    policy: RetentionPolicy,
+    dry_run: bool,
+    storage: StorageOptions,
+) -> Result<()> {
+    if policy.is_empty() {
+        return Err(anyhow!(
+            "No retention rules given, use --keep-last, --keep-daily, --keep-weekly, --keep-monthly or --max-age-days"
+        ));
+    }
+
+    let config = PostgresConfig {
+        host,
+        port,
+        database,
+        user,
+        password,
+        ssl_mode,
+        ssh_host: None,
+        ssh_user: None,
+        ssh_port: None,
+        ssh_password: None,
+        ssh_key_path: None,
+        ssh_local_port: None,
+        ssh_remote_port: None,
+        ssh_known_hosts: None,
+        ssh_host_key_fingerprint: None,
+    };
+    let mut manager = PostgresManager::new(config, backup_dir)?;
+    let outcome = prune_backups(&mut manager, &policy, dry_run, &storage).await?;
+    let plan = &outcome.plan;
+
+    for entry in &plan.keep {
+        info!(
+            "Keep   {} ({:?}, {}): {}",
+            entry.backup_id,
+            entry.backup_type,
+            entry.start_time,
+            entry.reasons.join(", ")
+        );
+    }
+    for entry in &plan.delete {
+        info!(
+            "Prune  {} ({:?}, {}): {}",
+            entry.backup_id,
+            entry.backup_type,
+            entry.start_time,
+            entry.reasons.join(", ")
+        );
+    }
+
+    if dry_run {
+        info!(
+            "Dry run: {} backups would be pruned, {} kept",
+            plan.delete.len(),
+            plan.keep.len()
+        );
+        return Ok(());
+    }
+
+    info!("Pruned {} backups", outcome.pruned.len());
+    if !outcome.failed.is_empty() {
+        for failure in &outcome.failed {
+            error!("Kept {}: {}", failure.backup_id, failure.error);
+        }
+        return Err(anyhow!(
+            "{} backups could not be pruned and stay in the catalog",
+            outcome.failed.len()
+        ));
+    }
+    Ok(())
+}
+
+/// A backup pruning left in place, with why
+#[derive(Debug, Clone, Serialize)]
+pub struct PruneFailure {
+    pub backup_id: Uuid,
+    pub error: String,
+}
+
+/// Outcome of [`prune_backups`]
+#[derive(Debug, Clone, Serialize)]
+pub struct PruneOutcome {
+    pub plan: RetentionPlan,
+    /// Backups removed, everywhere they were stored
+    pub pruned: Vec<Uuid>,
+    /// Backups a remote copy of which could not be deleted, and the backups
+    /// they need, all kept in the local catalog
+    pub failed: Vec<PruneFailure>,
+}
+
+/// Apply a retention policy to the local backups and their remote copies
+///
+/// The remote copies of each pruned backup, on the storage and on every
+/// `--replicate-to` destination, are deleted first. Only once they are gone,
+/// or were never uploaded, are the local files and catalog entry removed, so
+/// a backup a storage refused to delete, such as one still under object
+/// lock, keeps its local record. Incrementals go before their bases, and a
+/// base stays along with any backup it is needed by.
+pub async fn prune_backups(
+    manager: &mut PostgresManager,
+    policy: &RetentionPolicy,
+    dry_run: bool,
+    storage: &StorageOptions,
+) -> Result<PruneOutcome> {
+    let plan = manager.plan_prune(policy);
+    let mut outcome = PruneOutcome {
+        pruned: Vec::new(),
+        failed: Vec::new(),
+        plan: plan.clone(),
+    };
+    if dry_run {
+        return Ok(outcome);
+    }
+
+    let mut remotes = Vec::new();
+    if storage.remote_storage {
+        let mut destinations = vec![storage.clone()];
+        for spec in &storage.replicate_to {
+            destinations.push(storage.parse_destination(spec)?);
+        }
+        for destination in &destinations {
+            if let Some(remote) = create_storage_provider(destination).await? {
+                remotes.push((destination.destination(), remote));
+            }
+        }
+    }
+
+    // Backups left in place because a backup that stays needs them
+    let mut needed_by: HashMap<Uuid, Uuid> = HashMap::new();
+    for backup_id in plan.delete_ids() {
+        if let Some(dependent) = needed_by.get(&backup_id).copied() {
+            warn!("Backup {backup_id} stays, backup {dependent} still needs it");
+            outcome.failed.push(PruneFailure {
+                backup_id,
+                error: format!("needed by backup {dependent}, which could not be deleted"),
+            });
+            continue;
+        }
+
+        let mut errors = Vec::new();
+        for (name, remote) in &remotes {
+            match remote.delete_backup(&backup_id.to_string()).await {
+                Ok(()) => info!("Deleted backup {backup_id} from {name}"),
+                Err(StorageError::NotFound(_)) => {
+                    info!("Backup {backup_id} was not in {name}")
+                }
+                Err(StorageError::Locked(msg)) => {
+                    warn!("Backup {backup_id} stays in {name} until its lock expires: {msg}");
+                    errors.push(format!("{name}: locked: {msg}"));
+                }
+                Err(e) => {
+                    error!("Failed to delete backup {backup_id} from {name}: {e}");
+                    errors.push(format!("{name}: {e}"));
+                }
+            }
+        }
+        if errors.is_empty() {
+            outcome.pruned.push(backup_id);
+        } else {
+            for dependency in manager.backup_dependencies(&backup_id) {
+                needed_by.entry(dependency).or_insert(backup_id);
+            }
+            outcome.failed.push(PruneFailure {
+                backup_id,
+                error: errors.join(", "),
+            });
+        }
+    }
+
+    manager
+        .remove_backups(&outcome.pruned)
+        .map_err(|e| anyhow!(e.to_string()))?;
+    Ok(outcome)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn verify_backup(
+    host: String,
+    port: u16,
+    database: String,
+    user: String,
+    password: Option<String>,
+    ssl_mode: Option<String>,
+    backup_dir: PathBuf,
+    backup_id: String,
+    options: VerifyOptions,
+) -> Result<()> {
+    let config = PostgresConfig {
+        host,
+        port,
+        database,
+        user,
+        password,
+        ssl_mode,
+        ssh_host: None,
+        ssh_user: None,
+        ssh_port: None,
+        ssh_password: None,
+        ssh_key_path: None,
+        ssh_local_port: None,
+        ssh_remote_port: None,
+        ssh_known_hosts: None,
+        ssh_host_key_fingerprint: None,
+    };
+    let backup_id = Uuid::parse_str(&backup_id).map_err(|e: uuid::Error| anyhow!(e))?;
+    let mut manager = PostgresManager::new(config, backup_dir)?;
+    let verification = manager
+        .verify_backup(&backup_id, options)
+        .await
+        .map_err(|e| anyhow!(e.to_string()))?;
+
+    for check in &verification.checks {
+        info!("  {check}");
+    }
+    match verification.status {
+        VerificationStatus::Passed => {
+            info!(
+                "Backup {backup_id} verified at {}",
+                verification.verified_at
+            );
+            Ok(())
+        }
+        VerificationStatus::Failed => Err(anyhow!(
+            "Backup {} failed verification: {}",
+            backup_id,
+            verification.error_message.unwrap_or_default()
+        )),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn verify_integrity(
+    host: String,
+    port: u16,
+    database: String,
+    user: String,
+    password: Option<String>,
+    ssl_mode: Option<String>,
+    backup_dir: PathBuf,
+    backup_id: String,
+    storage: StorageOptions,
+) -> Result<()> {
+    let config = PostgresConfig {
+        host,
+        port,
+        database,
+        user,
+        password,
+        ssl_mode,
+        ssh_host: None,
+        ssh_user: None,
+        ssh_port: None,
+        ssh_password: None,
+        ssh_key_path: None,
+        ssh_local_port: None,
+        ssh_remote_port: None,
+        ssh_known_hosts: None,
+        ssh_host_key_fingerprint: None,
+    };
+    let backup_id = Uuid::parse_str(&backup_id).map_err(|e: uuid::Error| anyhow!(e))?;
+    let manager = PostgresManager::new(config, backup_dir)?;
+
+    let mut reports = Vec::new();
+    if manager.get_backup(&backup_id).is_some() {
+        let report = manager
+            .verify_integrity(&backup_id)
+            .await
+            .map_err(|e| anyhow!(e.to_string()))?;
+        reports.push(("local", report));
+    } else {
+        info!("Backup {backup_id} is not in the local catalog, skipping the local copy");
+    }
+
+    if storage.remote_storage {
+        if let Some(storage) = create_storage_provider(&storage).await? {
+            let report = storage
+                .verify_backup_integrity(&backup_id.to_string())
+                .await
+                .map_err(|e| anyhow!("Failed to verify remote copy: {}", e))?;
+            reports.push(("remote", report));
+        }
+    }
+
+    if reports.is_empty() {
+        return Err(anyhow!(
+            "Backup {} is neither in the local catalog nor checked remotely",
+            backup_id
+        ));
+    }
+
+    let mut damaged = Vec::new();
+    for (copy, report) in &reports {
+        if report.is_ok() {
+            info!("{copy} copy of {backup_id}: {} files OK", report.checked);
+            continue;
+        }
+        for problem in &report.problems {
+            error!("{copy} copy of {backup_id}: {problem}");
+        }
+        damaged.push(*copy);
+    }
+    if !damaged.is_empty() {
+        return Err(anyhow!(
+            "Backup {} is damaged in {} storage",
+            backup_id,
+            damaged.join(" and ")
+        ));
+    }
+    Ok(())
+}
+
+/// Rebuild the remote catalog from the metadata object of every backup in the bucket
+pub async fn rebuild_catalog(storage: StorageOptions) -> Result<()> {
+    let storage = create_storage_provider(&storage)
+        .await?
+        .ok_or_else(|| anyhow!("Remote storage is not configured"))?;
+    let catalog = storage
+        .rebuild_catalog()
+        .await
+        .map_err(|e| anyhow!("Failed to rebuild remote catalog: {}", e))?;
+    info!(
+        "Remote catalog rebuilt with {} backups",
+        catalog.backups.len()
+    );
+    for backup in catalog.backups {
+        info!(
+            "Backup ID: {}, Type: {:?}, Time: {}, Parent: {}",
+            backup.id,
+            backup.backup_type,
+            backup.timestamp,
+            backup.parent_id.as_deref().unwrap_or("-")
+        );
+    }
+    Ok(())
+}
+
+/// Copy the backups each `--to` destination is missing from the remote storage
+pub async fn sync(storage: StorageOptions, destinations: Vec<String>) -> Result<()> {
+    if destinations.is_empty() {
+        return Err(anyhow!("No destinations given, use --to"));
+    }
+    let source = create_storage_provider(&storage)
+        .await?
+        .ok_or_else(|| anyhow!("Remote storage is not configured"))?;
+
+    let mut incomplete = Vec::new();
+    for spec in &destinations {
+        let destination = storage.parse_destination(spec)?;
+        let name = destination.destination();
+        let target = create_storage_provider(&destination)
+            .await?
+            .ok_or_else(|| anyhow!("Remote storage is not configured"))?;
+        info!("Syncing {} to {name}...", storage.destination());
+        let report = source
+            .sync_to(&target)
+            .await
+            .map_err(|e| anyhow!("Failed to sync to {}: {}", name, e))?;
+        for backup_id in &report.copied {
+            info!("Copied backup {backup_id} to {name}");
+        }
+        for (backup_id, reason) in &report.failed {
+            error!("Failed to copy backup {backup_id} to {name}: {reason}");
+        }
+        info!(
+            "{name}: {} backups copied, {} already present, {} failed",
+            report.copied.len(),
+            report.up_to_date.len(),
+            report.failed.len()
+        );
+        if !report.is_ok() {
+            incomplete.push(name);
+        }
+    }
+    if !incomplete.is_empty() {
+        return Err(anyhow!(
+            "Some backups could not be copied to {}",
+            incomplete.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Check that the bucket can hold locked backups
+pub async fn check_object_lock(storage: StorageOptions) -> Result<()> {
+    let name = storage.destination();
+    let storage = create_storage_provider(&storage)
+        .await?
+        .ok_or_else(|| anyhow!("Remote storage is not configured"))?;
+    let status = storage
+        .object_lock_status()
+        .await
+        .map_err(|e| anyhow!("Failed to read the object lock status of {}: {}", name, e))?;
+
+    let enabled = |on: bool| if on { "enabled" } else { "disabled" };
+    println!("{name}");
+    println!("  Versioning:  {}", enabled(status.versioning));
+    println!("  Object lock: {}", enabled(status.object_lock));
+    if let Some(retention) = &status.default_retention {
+        println!("  Default retention: {retention}");
+    }
+    if !status.is_ready() {
+        return Err(anyhow!(
+            "{} cannot hold locked backups, enable versioning and object lock on the bucket",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Install lifecycle rules moving backups to the storage class of a tiering rule as they age
+pub async fn tier(storage: StorageOptions, rules: Vec<String>, dry_run: bool) -> Result<()> {
+    let rules = rules
+        .iter()
+        .map(|rule| rule.parse::<TieringRule>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!("{}", e))?;
+    let storage = create_storage_provider(&storage)
+        .await?
+        .ok_or_else(|| anyhow!("Remote storage is not configured"))?;
+
+    for rule in &rules {
+        info!(
+            "{} backups move to {} after {} days",
+            rule.backup_type.as_str(),
+            rule.class.as_str(),
+            rule.after_days
+        );
+    }
+    if dry_run {
+        info!("{} transitions would be installed", rules.len());
+        return Ok(());
+    }
+    storage
+        .apply_tiering(&rules)
+        .await
+        .map_err(|e| anyhow!("Failed to apply tiering rules: {}", e))?;
+    info!("{} transitions installed", rules.len());
+    Ok(())
+}
+
 #[allow(clippy::too_many_arguments)]
 pub async fn list_backups(
     host: String,
@@ -535,8 +1309,11 @@ pub async fn list_backups(
             info!("All backups in remote storage:");
             for backup in backups {
                 info!(
-                    "Backup ID: {}, Type: {:?}, Time: {}",
-                    backup.id, backup.backup_type, backup.timestamp
+                    "Backup ID: {}, Type: {:?}, Time: {}, Parent: {}",
+                    backup.id,
+                    backup.backup_type,
+                    backup.timestamp,
+                    backup.parent_id.as_deref().unwrap_or("-")
                 );
             }
 
@@ -558,14 +1335,33 @@ pub async fn list_backups(
         ssh_key_path: ssh.key_path.clone(),
         ssh_local_port: ssh.local_port,
         ssh_remote_port: ssh.remote_port,
+        ssh_known_hosts: ssh.known_hosts.clone(),
+        ssh_host_key_fingerprint: ssh.host_key_fingerprint.clone(),
     };
     let manager = PostgresManager::new(config, backup_dir)?;
     info!("All backups:");
     for backup in manager.list_backups() {
+        let verified = match &backup.verification {
+            Some(v) => format!("{:?} at {}", v.status, v.verified_at),
+            None => "never".to_string(),
+        };
         info!(
-            "Backup ID: {}, Type: {:?}, Status: {:?}, Time: {}",
-            backup.id, backup.backup_type, backup.status, backup.start_time
+            "Backup ID: {}, Type: {:?}, Status: {:?}, Time: {}, Verified: {}",
+            backup.id, backup.backup_type, backup.status, backup.start_time, verified
         );
+        for replica in &backup.replicas {
+            info!(
+                "  Replica {}: {:?} at {}{}",
+                replica.destination,
+                replica.status,
+                replica.updated_at,
+                replica
+                    .error_message
+                    .as_deref()
+                    .map(|e| format!(" ({e})"))
+                    .unwrap_or_default()
+            );
+        }
     }
     Ok(())
 }
@@ -607,6 +1403,7 @@ pub async fn restore(
                 .download_backup(&full_backup_id, &full_backup_path)
                 .await
                 .map_err(|e| anyhow!("Failed to download full backup: {}", e))?;
+            import_remote_backup(&storage, &backup_dir, &full_backup_id, &full_backup_path).await?;
 
             info!("Full backup downloaded successfully");
 
@@ -631,6 +1428,7 @@ pub async fn restore(
                     .download_backup(&backup_id, &backup_path)
                     .await
                     .map_err(|e| anyhow!("Failed to download incremental backup: {}", e))?;
+                import_remote_backup(&storage, &backup_dir, &backup_id, &backup_path).await?;
             }
 
             info!("All incremental backups downloaded successfully");
@@ -651,6 +1449,8 @@ pub async fn restore(
         ssh_key_path: ssh.key_path.clone(),
         ssh_local_port: ssh.local_port,
         ssh_remote_port: ssh.remote_port,
+        ssh_known_hosts: ssh.known_hosts.clone(),
+        ssh_host_key_fingerprint: ssh.host_key_fingerprint.clone(),
     };
     let mut manager = PostgresManager::new(config, backup_dir)?;
     info!("Restoring with incremental backups from {full_backup_id} to {target_dir:?}...");
@@ -681,15 +1481,16 @@ pub async fn restore_point_in_time(
     backup_dir: PathBuf,
     full_backup_id: String,
     target_dir: PathBuf,
-    target_time: String,
+    target: RecoveryTargetOptions,
     container_id: Option<String>,
     container_type: Option<String>,
     auto_restart: bool,
+    stream: bool,
     ssh: SshOptions,
     storage: StorageOptions,
 ) -> Result<()> {
     // If restoring from remote storage, download the backup first
-    if storage.remote_storage {
+    if storage.remote_storage && !stream {
         info!("Downloading full and incremental backups for point-in-time restore from remote storage...");
         let storage_instance = create_storage_provider(&storage).await?;
         if let Some(storage) = storage_instance {
@@ -702,6 +1503,7 @@ pub async fn restore_point_in_time(
                 .download_backup(&full_backup_id, &full_backup_path)
                 .await
                 .map_err(|e| anyhow!("Failed to download full backup: {}", e))?;
+            import_remote_backup(&storage, &backup_dir, &full_backup_id, &full_backup_path).await?;
             info!("Full backup downloaded successfully");
             let incremental_backups = storage
                 .list_backups_with_ancestor(&full_backup_id)
@@ -718,6 +1520,7 @@ pub async fn restore_point_in_time(
                     .download_backup(&backup_id, &backup_path)
                     .await
                     .map_err(|e| anyhow!("Failed to download incremental backup: {}", e))?;
+                import_remote_backup(&storage, &backup_dir, &backup_id, &backup_path).await?;
             }
         }
     }
@@ -744,6 +1547,8 @@ pub async fn restore_point_in_time(
         ssh_key_path: ssh.key_path.clone(),
         ssh_local_port: ssh.local_port,
         ssh_remote_port: ssh.remote_port,
+        ssh_known_hosts: ssh.known_hosts.clone(),
+        ssh_host_key_fingerprint: ssh.host_key_fingerprint.clone(),
     };
 
     // Setup SSH tunnel if needed
@@ -756,14 +1561,14 @@ pub async fn restore_point_in_time(
     }
 
     let mut manager = PostgresManager::new(config.clone(), backup_dir.clone())?;
-    // Parse target time
-    let target_time = chrono::DateTime::parse_from_str(&target_time, "%Y-%m-%dT%H:%M:%S%z")
-        .map_err(|e| anyhow::anyhow!("Invalid target time format: {}", e))?
-        .with_timezone(&chrono::Utc);
-    info!("Restoring to point in time {target_time} from {full_backup_id} to {target_dir:?}...");
+    if stream {
+        manager = manager.with_remote_storage(streaming_source(&storage).await?);
+    }
+    let (target, action) = target.parse()?;
+    info!("Restoring to {target} from {full_backup_id} to {target_dir:?}...");
     let full_backup_id = Uuid::parse_str(&full_backup_id).map_err(|e: uuid::Error| anyhow!(e))?;
     let restore = manager
-        .restore_point_in_time(&full_backup_id, target_dir, target_time)
+        .restore_point_in_time(&full_backup_id, target_dir, target, action)
         .await
         .map_err(|e: PostgresError| anyhow!(e))?;
     info!("Restore completed: {}", restore.id);
@@ -804,11 +1609,12 @@ pub async fn restore_snapshot(
     container_id: Option<String>,
     container_type: Option<String>,
     auto_restart: bool,
+    stream: bool,
     ssh: SshOptions,
     storage: StorageOptions,
 ) -> Result<()> {
     // If restoring from remote storage, download the backup first
-    if storage.remote_storage {
+    if storage.remote_storage && !stream {
         info!("Downloading snapshot backup from remote storage...");
 
         // Create storage provider
@@ -827,6 +1633,7 @@ pub async fn restore_snapshot(
                 .download_backup(&backup_id, &full_backup_path)
                 .await
                 .map_err(|e| anyhow!("Failed to download full backup: {}", e))?;
+            import_remote_backup(&storage, &backup_dir, &backup_id, &full_backup_path).await?;
 
             info!("Full backup downloaded successfully");
         }
@@ -846,8 +1653,13 @@ pub async fn restore_snapshot(
         ssh_key_path: ssh.key_path.clone(),
         ssh_local_port: ssh.local_port,
         ssh_remote_port: ssh.remote_port,
+        ssh_known_hosts: ssh.known_hosts.clone(),
+        ssh_host_key_fingerprint: ssh.host_key_fingerprint.clone(),
     };
     let mut manager = PostgresManager::new(config, backup_dir)?;
+    if stream {
+        manager = manager.with_remote_storage(streaming_source(&storage).await?);
+    }
     info!("Restoring from snapshot backup {backup_id} to {target_dir:?}...");
     let backup_id = Uuid::parse_str(&backup_id).map_err(|e: uuid::Error| anyhow::anyhow!(e))?;
     let restore = manager
@@ -1125,6 +1937,7 @@ pub async fn list_snapshot_contents(
                 .download_backup(&backup_id, &full_backup_path)
                 .await
                 .map_err(|e| anyhow!("Failed to download full backup: {}", e))?;
+            import_remote_backup(&storage, &backup_dir, &backup_id, &full_backup_path).await?;
 
             info!("Full backup downloaded successfully");
 
@@ -1149,6 +1962,7 @@ pub async fn list_snapshot_contents(
                     .download_backup(&backup_id, &backup_path)
                     .await
                     .map_err(|e| anyhow!("Failed to download incremental backup: {}", e))?;
+                import_remote_backup(&storage, &backup_dir, &backup_id, &backup_path).await?;
             }
 
             info!("All incremental backups downloaded successfully");
@@ -1169,6 +1983,8 @@ pub async fn list_snapshot_contents(
         ssh_key_path: ssh.key_path.clone(),
         ssh_local_port: ssh.local_port,
         ssh_remote_port: ssh.remote_port,
+        ssh_known_hosts: ssh.known_hosts.clone(),
+        ssh_host_key_fingerprint: ssh.host_key_fingerprint.clone(),
     };
     let manager = PostgresManager::new(config, backup_dir)?;
     info!("Snapshot backup contents for {backup_id}:");
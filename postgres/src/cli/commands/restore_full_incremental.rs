@@ -1,4 +1,7 @@
-use super::{create_storage_provider, restart_postgresql, SshOptions, StorageOptions};
+use super::{
+    create_storage_provider, import_remote_backup, restart_postgresql, streaming_source,
+    SshOptions, StorageOptions,
+};
 use crate::common::PostgresConfig;
 use crate::manager::PostgresManager;
 use crate::tunnel_keeper::TunnelKeeper;
@@ -22,11 +25,12 @@ pub async fn restore_full(
     container_id: Option<String>,
     container_type: Option<String>,
     auto_restart: bool,
+    stream: bool,
     ssh: SshOptions,
     storage: StorageOptions,
 ) -> Result<()> {
     // If restoring from remote storage, download the backup first
-    if storage.remote_storage {
+    if storage.remote_storage && !stream {
         info!("Downloading full backup from remote storage...");
         let storage_instance = create_storage_provider(&storage).await?;
         if let Some(storage) = storage_instance {
@@ -42,6 +46,7 @@ pub async fn restore_full(
                 .map_err(|e: storage::StorageError| {
                     anyhow!("Failed to download full backup: {}", e)
                 })?;
+            import_remote_backup(&storage, &backup_dir, &full_backup_id, &full_backup_path).await?;
             info!("Full backup downloaded successfully");
         }
     }
@@ -67,6 +72,8 @@ pub async fn restore_full(
         ssh_key_path: ssh.key_path.clone(),
         ssh_local_port: ssh.local_port,
         ssh_remote_port: ssh.remote_port,
+        ssh_known_hosts: ssh.known_hosts.clone(),
+        ssh_host_key_fingerprint: ssh.host_key_fingerprint.clone(),
     };
     // Setup SSH tunnel if needed
     if config.ssh_host.is_some() {
@@ -77,6 +84,9 @@ pub async fn restore_full(
         }
     }
     let mut manager = PostgresManager::new(config.clone(), backup_dir.clone())?;
+    if stream {
+        manager = manager.with_remote_storage(streaming_source(&storage).await?);
+    }
     info!("Restoring from full backup {full_backup_id} to {target_dir:?}...");
     let full_backup_id = Uuid::parse_str(&full_backup_id).map_err(|e: uuid::Error| anyhow!(e))?;
     let restore = manager
@@ -140,6 +150,7 @@ pub async fn restore_incremental(
                 .map_err(|e: storage::StorageError| {
                     anyhow!("Failed to download full backup: {}", e)
                 })?;
+            import_remote_backup(&storage, &backup_dir, &full_backup_id, &full_backup_path).await?;
             info!("Full backup downloaded successfully");
             // Download incremental backups
             let incremental_backups = storage
@@ -162,6 +173,7 @@ pub async fn restore_incremental(
                     .map_err(|e: storage::StorageError| {
                         anyhow!("Failed to download incremental backup: {}", e)
                     })?;
+                import_remote_backup(&storage, &backup_dir, &backup_id, &backup_path).await?;
             }
             info!("All incremental backups downloaded successfully");
         }
@@ -188,6 +200,8 @@ pub async fn restore_incremental(
         ssh_key_path: ssh.key_path.clone(),
         ssh_local_port: ssh.local_port,
         ssh_remote_port: ssh.remote_port,
+        ssh_known_hosts: ssh.known_hosts.clone(),
+        ssh_host_key_fingerprint: ssh.host_key_fingerprint.clone(),
     };
     // Setup SSH tunnel if needed
     if config.ssh_host.is_some() {
@@ -32,11 +32,19 @@ pub enum PostgresqlCommands {
         #[clap(long, default_value = "./backups")]
         backup_dir: std::path::PathBuf,
 
+        /// Compression for archived WAL and uploads: none, gzip, zstd or lz4, with an optional `:level`
+        #[clap(long)]
+        compression: Option<String>,
+
+        /// Keep WAL on the server in the `warden_wal` replication slot until it is streamed. Drop the slot with `SELECT pg_drop_replication_slot('warden_wal')` once no longer used
+        #[clap(long)]
+        replication_slot: bool,
+
         /// Store backup in remote storage
         #[clap(long)]
         remote_storage: bool,
 
-        /// Storage provider type (s3)
+        /// Storage provider type (s3, gcs, azure, local, sftp)
         #[clap(long)]
         storage_provider: Option<String>,
 
@@ -52,18 +60,62 @@ pub enum PostgresqlCommands {
         #[clap(long)]
         storage_region: Option<String>,
 
-        /// Storage endpoint URL
+        /// Storage endpoint URL, the directory for local and `sftp://user@host/path` for sftp
         #[clap(long)]
         storage_endpoint: Option<String>,
 
-        /// Storage access key
+        /// Storage access key, the account name for Azure or the user for SFTP
         #[clap(long)]
         storage_access_key: Option<String>,
 
-        /// Storage secret key
+        /// Storage secret key, the account key for Azure or the password for SFTP
         #[clap(long)]
         storage_secret_key: Option<String>,
 
+        /// Key file of the storage, the SSH private key for SFTP or the service-account JSON for GCS
+        #[clap(long)]
+        storage_key_file: Option<String>,
+
+        /// Master key for client-side encryption of backup files, as `[key-id:]base64-key`
+        #[clap(long)]
+        encryption_key: Option<String>,
+
+        /// File with one `[key-id:]base64-key` per line, the first encrypts new uploads
+        #[clap(long)]
+        encryption_key_file: Option<String>,
+
+        /// Lock the uploaded backup files for this many days, on buckets with object lock enabled
+        #[clap(long)]
+        object_lock_days: Option<u32>,
+
+        /// Object lock mode: governance, the default, or compliance
+        #[clap(long)]
+        object_lock_mode: Option<String>,
+
+        /// Storage class of the uploaded files: standard, infrequent-access, archive or deep-archive
+        #[clap(long)]
+        storage_class: Option<String>,
+
+        /// Also upload to this destination, as `provider://bucket[/prefix][?region=..&endpoint=..&access_key=..&secret_key=..]`
+        #[clap(long)]
+        replicate_to: Vec<String>,
+
+        /// Bandwidth limit of the WAL copy and uploads, in bytes per second with an optional K, M or G suffix
+        #[clap(long)]
+        bandwidth_limit: Option<String>,
+
+        /// Niceness of pg_basebackup and pg_dump, from -20 to 19
+        #[clap(long, allow_hyphen_values = true)]
+        nice: Option<i32>,
+
+        /// ionice class of pg_basebackup and pg_dump: 1 realtime, 2 best-effort, 3 idle
+        #[clap(long)]
+        ionice_class: Option<u8>,
+
+        /// Priority within the ionice class, from 0 (highest) to 7
+        #[clap(long)]
+        ionice_level: Option<u8>,
+
         /// SSH host for port forwarding
         #[clap(long)]
         ssh_host: Option<String>,
@@ -91,6 +143,14 @@ pub enum PostgresqlCommands {
         /// SSH remote port for port forwarding
         #[clap(long)]
         ssh_remote_port: Option<u16>,
+
+        /// known_hosts file to verify the SSH host key with, instead of `~/.ssh/known_hosts`
+        #[clap(long)]
+        ssh_known_hosts: Option<String>,
+
+        /// SHA256 fingerprint the SSH host key must have, as `ssh-keygen -lf` prints it
+        #[clap(long, conflicts_with = "ssh_known_hosts")]
+        ssh_host_key_fingerprint: Option<String>,
     },
 
     /// Perform an incremental backup
@@ -123,11 +183,19 @@ pub enum PostgresqlCommands {
         #[clap(long, default_value = "./backups")]
         backup_dir: std::path::PathBuf,
 
+        /// Compression for archived WAL and uploads: none, gzip, zstd or lz4, with an optional `:level`
+        #[clap(long)]
+        compression: Option<String>,
+
+        /// Keep WAL on the server in the `warden_wal` replication slot until it is streamed. Drop the slot with `SELECT pg_drop_replication_slot('warden_wal')` once no longer used
+        #[clap(long)]
+        replication_slot: bool,
+
         /// Store backup in remote storage
         #[clap(long)]
         remote_storage: bool,
 
-        /// Storage provider type (s3)
+        /// Storage provider type (s3, gcs, azure, local, sftp)
         #[clap(long)]
         storage_provider: Option<String>,
 
@@ -143,18 +211,62 @@ pub enum PostgresqlCommands {
         #[clap(long)]
         storage_region: Option<String>,
 
-        /// Storage endpoint URL
+        /// Storage endpoint URL, the directory for local and `sftp://user@host/path` for sftp
         #[clap(long)]
         storage_endpoint: Option<String>,
 
-        /// Storage access key
+        /// Storage access key, the account name for Azure or the user for SFTP
         #[clap(long)]
         storage_access_key: Option<String>,
 
-        /// Storage secret key
+        /// Storage secret key, the account key for Azure or the password for SFTP
         #[clap(long)]
         storage_secret_key: Option<String>,
 
+        /// Key file of the storage, the SSH private key for SFTP or the service-account JSON for GCS
+        #[clap(long)]
+        storage_key_file: Option<String>,
+
+        /// Master key for client-side encryption of backup files, as `[key-id:]base64-key`
+        #[clap(long)]
+        encryption_key: Option<String>,
+
+        /// File with one `[key-id:]base64-key` per line, the first encrypts new uploads
+        #[clap(long)]
+        encryption_key_file: Option<String>,
+
+        /// Lock the uploaded backup files for this many days, on buckets with object lock enabled
+        #[clap(long)]
+        object_lock_days: Option<u32>,
+
+        /// Object lock mode: governance, the default, or compliance
+        #[clap(long)]
+        object_lock_mode: Option<String>,
+
+        /// Storage class of the uploaded files: standard, infrequent-access, archive or deep-archive
+        #[clap(long)]
+        storage_class: Option<String>,
+
+        /// Also upload to this destination, as `provider://bucket[/prefix][?region=..&endpoint=..&access_key=..&secret_key=..]`
+        #[clap(long)]
+        replicate_to: Vec<String>,
+
+        /// Bandwidth limit of the WAL copy and uploads, in bytes per second with an optional K, M or G suffix
+        #[clap(long)]
+        bandwidth_limit: Option<String>,
+
+        /// Niceness of pg_basebackup and pg_dump, from -20 to 19
+        #[clap(long, allow_hyphen_values = true)]
+        nice: Option<i32>,
+
+        /// ionice class of pg_basebackup and pg_dump: 1 realtime, 2 best-effort, 3 idle
+        #[clap(long)]
+        ionice_class: Option<u8>,
+
+        /// Priority within the ionice class, from 0 (highest) to 7
+        #[clap(long)]
+        ionice_level: Option<u8>,
+
         /// SSH host for port forwarding
         #[clap(long)]
         ssh_host: Option<String>,
@@ -182,6 +294,14 @@ pub enum PostgresqlCommands {
         /// SSH remote port for port forwarding
         #[clap(long)]
         ssh_remote_port: Option<u16>,
+
+        /// known_hosts file to verify the SSH host key with, instead of `~/.ssh/known_hosts`
+        #[clap(long)]
+        ssh_known_hosts: Option<String>,
+
+        /// SHA256 fingerprint the SSH host key must have, as `ssh-keygen -lf` prints it
+        #[clap(long, conflicts_with = "ssh_known_hosts")]
+        ssh_host_key_fingerprint: Option<String>,
     },
 
     // /// Perform a snapshot backup
@@ -214,11 +334,15 @@ pub enum PostgresqlCommands {
         #[clap(long, default_value = "./backups")]
         backup_dir: std::path::PathBuf,
 
+        /// Compression for archived WAL and uploads: none, gzip, zstd or lz4, with an optional `:level`
+        #[clap(long)]
+        compression: Option<String>,
+
         /// Store backup in remote storage
         #[clap(long)]
         remote_storage: bool,
 
-        /// Storage provider type (s3)
+        /// Storage provider type (s3, gcs, azure, local, sftp)
         #[clap(long)]
         storage_provider: Option<String>,
 
@@ -234,18 +358,62 @@ pub enum PostgresqlCommands {
         #[clap(long)]
         storage_region: Option<String>,
 
-        /// Storage endpoint URL
+        /// Storage endpoint URL, the directory for local and `sftp://user@host/path` for sftp
         #[clap(long)]
         storage_endpoint: Option<String>,
 
-        /// Storage access key
+        /// Storage access key, the account name for Azure or the user for SFTP
         #[clap(long)]
         storage_access_key: Option<String>,
 
-        /// Storage secret key
+        /// Storage secret key, the account key for Azure or the password for SFTP
         #[clap(long)]
         storage_secret_key: Option<String>,
 
+        /// Key file of the storage, the SSH private key for SFTP or the service-account JSON for GCS
+        #[clap(long)]
+        storage_key_file: Option<String>,
+
+        /// Master key for client-side encryption of backup files, as `[key-id:]base64-key`
+        #[clap(long)]
+        encryption_key: Option<String>,
+
+        /// File with one `[key-id:]base64-key` per line, the first encrypts new uploads
+        #[clap(long)]
+        encryption_key_file: Option<String>,
+
+        /// Lock the uploaded backup files for this many days, on buckets with object lock enabled
+        #[clap(long)]
+        object_lock_days: Option<u32>,
+
+        /// Object lock mode: governance, the default, or compliance
+        #[clap(long)]
+        object_lock_mode: Option<String>,
+
+        /// Storage class of the uploaded files: standard, infrequent-access, archive or deep-archive
+        #[clap(long)]
+        storage_class: Option<String>,
+
+        /// Also upload to this destination, as `provider://bucket[/prefix][?region=..&endpoint=..&access_key=..&secret_key=..]`
+        #[clap(long)]
+        replicate_to: Vec<String>,
+
+        /// Bandwidth limit of the WAL copy and uploads, in bytes per second with an optional K, M or G suffix
+        #[clap(long)]
+        bandwidth_limit: Option<String>,
+
+        /// Niceness of pg_basebackup and pg_dump, from -20 to 19
+        #[clap(long, allow_hyphen_values = true)]
+        nice: Option<i32>,
+
+        /// ionice class of pg_basebackup and pg_dump: 1 realtime, 2 best-effort, 3 idle
+        #[clap(long)]
+        ionice_class: Option<u8>,
+
+        /// Priority within the ionice class, from 0 (highest) to 7
+        #[clap(long)]
+        ionice_level: Option<u8>,
+
         /// SSH host for port forwarding
         #[clap(long)]
         ssh_host: Option<String>,
@@ -273,6 +441,85 @@ pub enum PostgresqlCommands {
         /// SSH remote port for port forwarding
         #[clap(long)]
         ssh_remote_port: Option<u16>,
+
+        /// known_hosts file to verify the SSH host key with, instead of `~/.ssh/known_hosts`
+        #[clap(long)]
+        ssh_known_hosts: Option<String>,
+
+        /// SHA256 fingerprint the SSH host key must have, as `ssh-keygen -lf` prints it
+        #[clap(long, conflicts_with = "ssh_known_hosts")]
+        ssh_host_key_fingerprint: Option<String>,
+    },
+
+    /// Continuously stream WAL into the backup directory for incremental backups
+    StreamWal {
+        /// PostgreSQL host
+        #[clap(long, default_value = "localhost")]
+        host: String,
+
+        /// PostgreSQL port
+        #[clap(long, default_value = "5432")]
+        port: u16,
+
+        /// PostgreSQL database
+        #[clap(long, default_value = "postgres")]
+        database: String,
+
+        /// PostgreSQL user
+        #[clap(long, default_value = "postgres")]
+        user: String,
+
+        /// PostgreSQL password
+        #[clap(long)]
+        password: Option<String>,
+
+        /// PostgreSQL SSL mode
+        #[clap(long)]
+        ssl_mode: Option<String>,
+
+        /// Backup directory
+        #[clap(long, default_value = "./backups")]
+        backup_dir: std::path::PathBuf,
+
+        /// Keep WAL on the server in the `warden_wal` replication slot until it is streamed. Drop the slot with `SELECT pg_drop_replication_slot('warden_wal')` once no longer used
+        #[clap(long)]
+        replication_slot: bool,
+
+        /// SSH host for port forwarding
+        #[clap(long)]
+        ssh_host: Option<String>,
+
+        /// SSH user for port forwarding
+        #[clap(long)]
+        ssh_user: Option<String>,
+
+        /// SSH port for port forwarding
+        #[clap(long)]
+        ssh_port: Option<u16>,
+
+        /// SSH password for authentication
+        #[clap(long)]
+        ssh_password: Option<String>,
+
+        /// SSH private key path for authentication
+        #[clap(long)]
+        ssh_key_path: Option<String>,
+
+        /// Local port for SSH tunnel
+        #[clap(long)]
+        ssh_local_port: Option<u16>,
+
+        /// SSH remote port for port forwarding
+        #[clap(long)]
+        ssh_remote_port: Option<u16>,
+
+        /// known_hosts file to verify the SSH host key with, instead of `~/.ssh/known_hosts`
+        #[clap(long)]
+        ssh_known_hosts: Option<String>,
+
+        /// SHA256 fingerprint the SSH host key must have, as `ssh-keygen -lf` prints it
+        #[clap(long, conflicts_with = "ssh_known_hosts")]
+        ssh_host_key_fingerprint: Option<String>,
     },
 
     /// List all backups
@@ -309,7 +556,7 @@ pub enum PostgresqlCommands {
         #[clap(long)]
         remote_storage: bool,
 
-        /// Storage provider type (s3)
+        /// Storage provider type (s3, gcs, azure, local, sftp)
         #[clap(long)]
         storage_provider: Option<String>,
 
@@ -325,18 +572,22 @@ pub enum PostgresqlCommands {
         #[clap(long)]
         storage_region: Option<String>,
 
-        /// Storage endpoint URL
+        /// Storage endpoint URL, the directory for local and `sftp://user@host/path` for sftp
         #[clap(long)]
         storage_endpoint: Option<String>,
 
-        /// Storage access key
+        /// Storage access key, the account name for Azure or the user for SFTP
         #[clap(long)]
         storage_access_key: Option<String>,
 
-        /// Storage secret key
+        /// Storage secret key, the account key for Azure or the password for SFTP
         #[clap(long)]
         storage_secret_key: Option<String>,
 
+        /// Key file of the storage, the SSH private key for SFTP or the service-account JSON for GCS
+        #[clap(long)]
+        storage_key_file: Option<String>,
+
         /// SSH host for port forwarding
         #[clap(long)]
         ssh_host: Option<String>,
@@ -364,6 +615,403 @@ pub enum PostgresqlCommands {
         /// SSH remote port for port forwarding
         #[clap(long)]
         ssh_remote_port: Option<u16>,
+
+        /// known_hosts file to verify the SSH host key with, instead of `~/.ssh/known_hosts`
+        #[clap(long)]
+        ssh_known_hosts: Option<String>,
+
+        /// SHA256 fingerprint the SSH host key must have, as `ssh-keygen -lf` prints it
+        #[clap(long, conflicts_with = "ssh_known_hosts")]
+        ssh_host_key_fingerprint: Option<String>,
+    },
+
+    /// Prune old backups according to a retention policy
+    Prune {
+        /// PostgreSQL host
+        #[clap(long, default_value = "localhost")]
+        host: String,
+
+        /// PostgreSQL port
+        #[clap(long, default_value = "5432")]
+        port: u16,
+
+        /// PostgreSQL database
+        #[clap(long, default_value = "postgres")]
+        database: String,
+
+        /// PostgreSQL user
+        #[clap(long, default_value = "postgres")]
+        user: String,
+
+        /// PostgreSQL password
+        #[clap(long)]
+        password: Option<String>,
+
+        /// PostgreSQL SSL mode
+        #[clap(long)]
+        ssl_mode: Option<String>,
+
+        /// Backup directory
+        #[clap(long, default_value = "./backups")]
+        backup_dir: std::path::PathBuf,
+
+        /// Keep the N most recent backups
+        #[clap(long)]
+        keep_last: Option<usize>,
+
+        /// Keep the newest backup of each of the last N days
+        #[clap(long)]
+        keep_daily: Option<usize>,
+
+        /// Keep the newest backup of each of the last N weeks
+        #[clap(long)]
+        keep_weekly: Option<usize>,
+
+        /// Keep the newest backup of each of the last N months
+        #[clap(long)]
+        keep_monthly: Option<usize>,
+
+        /// Prune backups older than this many days regardless of other rules
+        #[clap(long)]
+        max_age_days: Option<u32>,
+
+        /// Only report what would be pruned
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Also delete pruned backups from remote storage
+        #[clap(long)]
+        remote_storage: bool,
+
+        /// Storage provider type (s3, gcs, azure, local, sftp)
+        #[clap(long)]
+        storage_provider: Option<String>,
+
+        /// Storage bucket name
+        #[clap(long)]
+        storage_bucket: Option<String>,
+
+        /// Storage prefix for backups
+        #[clap(long)]
+        storage_prefix: Option<String>,
+
+        /// Storage region
+        #[clap(long)]
+        storage_region: Option<String>,
+
+        /// Storage endpoint URL, the directory for local and `sftp://user@host/path` for sftp
+        #[clap(long)]
+        storage_endpoint: Option<String>,
+
+        /// Also prune the copies in this destination, as given to `--replicate-to` of the backups
+        #[clap(long)]
+        replicate_to: Vec<String>,
+
+        /// Storage access key, the account name for Azure or the user for SFTP
+        #[clap(long)]
+        storage_access_key: Option<String>,
+
+        /// Storage secret key, the account key for Azure or the password for SFTP
+        #[clap(long)]
+        storage_secret_key: Option<String>,
+
+        /// Key file of the storage, the SSH private key for SFTP or the service-account JSON for GCS
+        #[clap(long)]
+        storage_key_file: Option<String>,
+    },
+
+    /// Prove a backup is restorable with a trial restore
+    VerifyBackup {
+        /// PostgreSQL host
+        #[clap(long, default_value = "localhost")]
+        host: String,
+
+        /// PostgreSQL port
+        #[clap(long, default_value = "5432")]
+        port: u16,
+
+        /// PostgreSQL database
+        #[clap(long, default_value = "postgres")]
+        database: String,
+
+        /// PostgreSQL user
+        #[clap(long, default_value = "postgres")]
+        user: String,
+
+        /// PostgreSQL password
+        #[clap(long)]
+        password: Option<String>,
+
+        /// PostgreSQL SSL mode
+        #[clap(long)]
+        ssl_mode: Option<String>,
+
+        /// Backup directory
+        #[clap(long, default_value = "./backups")]
+        backup_dir: std::path::PathBuf,
+
+        /// Backup ID
+        #[clap(long)]
+        backup_id: String,
+
+        /// Sanity query to run against the restored database, may be repeated
+        #[clap(long = "query")]
+        queries: Vec<String>,
+
+        /// Data directory for the trial restore of physical backups
+        #[clap(long)]
+        scratch_dir: Option<std::path::PathBuf>,
+
+        /// Port of the throwaway server started for physical backups
+        #[clap(long, default_value = "54329")]
+        verify_port: u16,
+
+        /// Directory containing pg_ctl, if it is not on PATH
+        #[clap(long)]
+        pg_bin_dir: Option<std::path::PathBuf>,
+
+        /// Seconds the throwaway server may take to finish recovery
+        #[clap(long, default_value = "300")]
+        timeout_secs: u64,
+    },
+
+    /// Re-hash the local and remote copies of a backup against its manifest
+    VerifyIntegrity {
+        /// PostgreSQL host
+        #[clap(long, default_value = "localhost")]
+        host: String,
+
+        /// PostgreSQL port
+        #[clap(long, default_value = "5432")]
+        port: u16,
+
+        /// PostgreSQL database
+        #[clap(long, default_value = "postgres")]
+        database: String,
+
+        /// PostgreSQL user
+        #[clap(long, default_value = "postgres")]
+        user: String,
+
+        /// PostgreSQL password
+        #[clap(long)]
+        password: Option<String>,
+
+        /// PostgreSQL SSL mode
+        #[clap(long)]
+        ssl_mode: Option<String>,
+
+        /// Backup directory
+        #[clap(long, default_value = "./backups")]
+        backup_dir: std::path::PathBuf,
+
+        /// Backup ID
+        #[clap(long)]
+        backup_id: String,
+
+        /// Also verify the copy in remote storage
+        #[clap(long)]
+        remote_storage: bool,
+
+        /// Storage provider type (s3, gcs, azure, local, sftp)
+        #[clap(long)]
+        storage_provider: Option<String>,
+
+        /// Storage bucket name
+        #[clap(long)]
+        storage_bucket: Option<String>,
+
+        /// Storage prefix for backups
+        #[clap(long)]
+        storage_prefix: Option<String>,
+
+        /// Storage region
+        #[clap(long)]
+        storage_region: Option<String>,
+
+        /// Storage endpoint URL, the directory for local and `sftp://user@host/path` for sftp
+        #[clap(long)]
+        storage_endpoint: Option<String>,
+
+        /// Storage access key, the account name for Azure or the user for SFTP
+        #[clap(long)]
+        storage_access_key: Option<String>,
+
+        /// Storage secret key, the account key for Azure or the password for SFTP
+        #[clap(long)]
+        storage_secret_key: Option<String>,
+
+        /// Key file of the storage, the SSH private key for SFTP or the service-account JSON for GCS
+        #[clap(long)]
+        storage_key_file: Option<String>,
+
+        /// Master key for client-side encryption of backup files, as `[key-id:]base64-key`
+        #[clap(long)]
+        encryption_key: Option<String>,
+
+        /// File with one `[key-id:]base64-key` per line, the first encrypts new uploads
+        #[clap(long)]
+        encryption_key_file: Option<String>,
+    },
+
+    /// Rebuild the remote backup catalog from the metadata stored with each backup
+    RebuildCatalog {
+        /// Storage provider type (s3, gcs, azure, local, sftp)
+        #[clap(long)]
+        storage_provider: Option<String>,
+
+        /// Storage bucket name
+        #[clap(long)]
+        storage_bucket: String,
+
+        /// Storage prefix for backups
+        #[clap(long)]
+        storage_prefix: Option<String>,
+
+        /// Storage region
+        #[clap(long)]
+        storage_region: Option<String>,
+
+        /// Storage endpoint URL, the directory for local and `sftp://user@host/path` for sftp
+        #[clap(long)]
+        storage_endpoint: Option<String>,
+
+        /// Storage access key, the account name for Azure or the user for SFTP
+        #[clap(long)]
+        storage_access_key: Option<String>,
+
+        /// Storage secret key, the account key for Azure or the password for SFTP
+        #[clap(long)]
+        storage_secret_key: Option<String>,
+
+        /// Key file of the storage, the SSH private key for SFTP or the service-account JSON for GCS
+        #[clap(long)]
+        storage_key_file: Option<String>,
+    },
+
+    /// Copy the backups missing from other storages, for example to an off-site copy
+    Sync {
+        /// Storage provider type (s3, gcs, azure, local, sftp)
+        #[clap(long)]
+        storage_provider: Option<String>,
+
+        /// Storage bucket name
+        #[clap(long)]
+        storage_bucket: String,
+
+        /// Storage prefix for backups
+        #[clap(long)]
+        storage_prefix: Option<String>,
+
+        /// Storage region
+        #[clap(long)]
+        storage_region: Option<String>,
+
+        /// Storage endpoint URL, the directory for local and `sftp://user@host/path` for sftp
+        #[clap(long)]
+        storage_endpoint: Option<String>,
+
+        /// Storage access key, the account name for Azure or the user for SFTP
+        #[clap(long)]
+        storage_access_key: Option<String>,
+
+        /// Storage secret key, the account key for Azure or the password for SFTP
+        #[clap(long)]
+        storage_secret_key: Option<String>,
+
+        /// Key file of the storage, the SSH private key for SFTP or the service-account JSON for GCS
+        #[clap(long)]
+        storage_key_file: Option<String>,
+
+        /// Storage to copy to, as `provider://bucket[/prefix][?region=..&endpoint=..&access_key=..&secret_key=..]`
+        #[clap(long, required = true)]
+        to: Vec<String>,
+
+        /// Lock the uploaded backup files for this many days, on buckets with object lock enabled
+        #[clap(long)]
+        object_lock_days: Option<u32>,
+
+        /// Object lock mode: governance, the default, or compliance
+        #[clap(long)]
+        object_lock_mode: Option<String>,
+    },
+
+    /// Check that the storage bucket has the versioning and object lock that locked backups need
+    CheckObjectLock {
+        /// Storage provider type (s3, gcs, azure, local, sftp)
+        #[clap(long)]
+        storage_provider: Option<String>,
+
+        /// Storage bucket name
+        #[clap(long)]
+        storage_bucket: String,
+
+        /// Storage prefix for backups
+        #[clap(long)]
+        storage_prefix: Option<String>,
+
+        /// Storage region
+        #[clap(long)]
+        storage_region: Option<String>,
+
+        /// Storage endpoint URL, the directory for local and `sftp://user@host/path` for sftp
+        #[clap(long)]
+        storage_endpoint: Option<String>,
+
+        /// Storage access key, the account name for Azure or the user for SFTP
+        #[clap(long)]
+        storage_access_key: Option<String>,
+
+        /// Storage secret key, the account key for Azure or the password for SFTP
+        #[clap(long)]
+        storage_secret_key: Option<String>,
+
+        /// Key file of the storage, the SSH private key for SFTP or the service-account JSON for GCS
+        #[clap(long)]
+        storage_key_file: Option<String>,
+    },
+
+    /// Install lifecycle rules moving backups to cheaper storage classes as they age
+    Tier {
+        /// Storage provider type (s3, gcs, azure, local, sftp)
+        #[clap(long)]
+        storage_provider: Option<String>,
+
+        /// Storage bucket name
+        #[clap(long)]
+        storage_bucket: String,
+
+        /// Storage prefix for backups
+        #[clap(long)]
+        storage_prefix: Option<String>,
+
+        /// Storage region
+        #[clap(long)]
+        storage_region: Option<String>,
+
+        /// Storage endpoint URL, the directory for local and `sftp://user@host/path` for sftp
+        #[clap(long)]
+        storage_endpoint: Option<String>,
+
+        /// Storage access key, the account name for Azure or the user for SFTP
+        #[clap(long)]
+        storage_access_key: Option<String>,
+
+        /// Storage secret key, the account key for Azure or the password for SFTP
+        #[clap(long)]
+        storage_secret_key: Option<String>,
+
+        /// Key file of the storage, the SSH private key for SFTP or the service-account JSON for GCS
+        #[clap(long)]
+        storage_key_file: Option<String>,
+
+        /// Rule as `type:days:class`, e.g. `full:30:infrequent-access` or `full:90:archive`
+        #[clap(long = "rule", required = true)]
+        rules: Vec<String>,
+
+        /// Only report the transitions, without installing them
+        #[clap(long)]
+        dry_run: bool,
     },
 
     /// Restore from a full backup
@@ -420,7 +1068,11 @@ pub enum PostgresqlCommands {
         #[clap(long)]
         remote_storage: bool,
 
-        /// Storage provider type (s3)
+        /// Stream the backup from remote storage instead of downloading it to the backup directory first
+        #[clap(long)]
+        stream: bool,
+
+        /// Storage provider type (s3, gcs, azure, local, sftp)
         #[clap(long)]
         storage_provider: Option<String>,
 
@@ -436,18 +1088,30 @@ pub enum PostgresqlCommands {
         #[clap(long)]
         storage_region: Option<String>,
 
-        /// Storage endpoint URL
+        /// Storage endpoint URL, the directory for local and `sftp://user@host/path` for sftp
         #[clap(long)]
         storage_endpoint: Option<String>,
 
-        /// Storage access key
+        /// Storage access key, the account name for Azure or the user for SFTP
         #[clap(long)]
         storage_access_key: Option<String>,
 
-        /// Storage secret key
+        /// Storage secret key, the account key for Azure or the password for SFTP
         #[clap(long)]
         storage_secret_key: Option<String>,
 
+        /// Key file of the storage, the SSH private key for SFTP or the service-account JSON for GCS
+        #[clap(long)]
+        storage_key_file: Option<String>,
+
+        /// Master key for client-side encryption of backup files, as `[key-id:]base64-key`
+        #[clap(long)]
+        encryption_key: Option<String>,
+
+        /// File with one `[key-id:]base64-key` per line, the first encrypts new uploads
+        #[clap(long)]
+        encryption_key_file: Option<String>,
+
         /// SSH host for port forwarding
         #[clap(long)]
         ssh_host: Option<String>,
@@ -475,6 +1139,14 @@ pub enum PostgresqlCommands {
         /// SSH remote port for port forwarding
         #[clap(long)]
         ssh_remote_port: Option<u16>,
+
+        /// known_hosts file to verify the SSH host key with, instead of `~/.ssh/known_hosts`
+        #[clap(long)]
+        ssh_known_hosts: Option<String>,
+
+        /// SHA256 fingerprint the SSH host key must have, as `ssh-keygen -lf` prints it
+        #[clap(long, conflicts_with = "ssh_known_hosts")]
+        ssh_host_key_fingerprint: Option<String>,
     },
 
     /// Restore with incremental backups
@@ -531,7 +1203,7 @@ pub enum PostgresqlCommands {
         #[clap(long)]
         remote_storage: bool,
 
-        /// Storage provider type (s3)
+        /// Storage provider type (s3, gcs, azure, local, sftp)
         #[clap(long)]
         storage_provider: Option<String>,
 
@@ -547,18 +1219,30 @@ pub enum PostgresqlCommands {
         #[clap(long)]
         storage_region: Option<String>,
 
-        /// Storage endpoint URL
+        /// Storage endpoint URL, the directory for local and `sftp://user@host/path` for sftp
         #[clap(long)]
         storage_endpoint: Option<String>,
 
-        /// Storage access key
+        /// Storage access key, the account name for Azure or the user for SFTP
         #[clap(long)]
         storage_access_key: Option<String>,
 
-        /// Storage secret key
+        /// Storage secret key, the account key for Azure or the password for SFTP
         #[clap(long)]
         storage_secret_key: Option<String>,
 
+        /// Key file of the storage, the SSH private key for SFTP or the service-account JSON for GCS
+        #[clap(long)]
+        storage_key_file: Option<String>,
+
+        /// Master key for client-side encryption of backup files, as `[key-id:]base64-key`
+        #[clap(long)]
+        encryption_key: Option<String>,
+
+        /// File with one `[key-id:]base64-key` per line, the first encrypts new uploads
+        #[clap(long)]
+        encryption_key_file: Option<String>,
+
         /// SSH host for port forwarding
         #[clap(long)]
         ssh_host: Option<String>,
@@ -586,6 +1270,14 @@ pub enum PostgresqlCommands {
         /// SSH remote port for port forwarding
         #[clap(long)]
         ssh_remote_port: Option<u16>,
+
+        /// known_hosts file to verify the SSH host key with, instead of `~/.ssh/known_hosts`
+        #[clap(long)]
+        ssh_known_hosts: Option<String>,
+
+        /// SHA256 fingerprint the SSH host key must have, as `ssh-keygen -lf` prints it
+        #[clap(long, conflicts_with = "ssh_known_hosts")]
+        ssh_host_key_fingerprint: Option<String>,
     },
 
     /// Restore to a point in time
@@ -628,7 +1320,23 @@ pub enum PostgresqlCommands {
 
         /// Target time (ISO 8601 format)
         #[clap(long)]
-        target_time: String,
+        target_time: Option<String>,
+
+        /// Target WAL location (LSN, e.g. 0/3000060)
+        #[clap(long)]
+        target_lsn: Option<String>,
+
+        /// Target transaction ID
+        #[clap(long)]
+        target_xid: Option<u64>,
+
+        /// Target named restore point
+        #[clap(long)]
+        target_name: Option<String>,
+
+        /// Action once the target is reached ("pause", "promote" or "shutdown")
+        #[clap(long, default_value = "promote")]
+        target_action: String,
 
         /// Container ID or name (for Docker or Kubernetes pod)
         #[clap(long)]
@@ -646,7 +1354,11 @@ pub enum PostgresqlCommands {
         #[clap(long)]
         remote_storage: bool,
 
-        /// Storage provider type (s3)
+        /// Stream the backup from remote storage instead of downloading it to the backup directory first
+        #[clap(long)]
+        stream: bool,
+
+        /// Storage provider type (s3, gcs, azure, local, sftp)
         #[clap(long)]
         storage_provider: Option<String>,
 
@@ -662,18 +1374,30 @@ pub enum PostgresqlCommands {
         #[clap(long)]
         storage_region: Option<String>,
 
-        /// Storage endpoint URL
+        /// Storage endpoint URL, the directory for local and `sftp://user@host/path` for sftp
         #[clap(long)]
         storage_endpoint: Option<String>,
 
-        /// Storage access key
+        /// Storage access key, the account name for Azure or the user for SFTP
         #[clap(long)]
         storage_access_key: Option<String>,
 
-        /// Storage secret key
+        /// Storage secret key, the account key for Azure or the password for SFTP
         #[clap(long)]
         storage_secret_key: Option<String>,
 
+        /// Key file of the storage, the SSH private key for SFTP or the service-account JSON for GCS
+        #[clap(long)]
+        storage_key_file: Option<String>,
+
+        /// Master key for client-side encryption of backup files, as `[key-id:]base64-key`
+        #[clap(long)]
+        encryption_key: Option<String>,
+
+        /// File with one `[key-id:]base64-key` per line, the first encrypts new uploads
+        #[clap(long)]
+        encryption_key_file: Option<String>,
+
         /// SSH host for port forwarding
         #[clap(long)]
         ssh_host: Option<String>,
@@ -701,6 +1425,14 @@ pub enum PostgresqlCommands {
         /// SSH remote port for port forwarding
         #[clap(long)]
         ssh_remote_port: Option<u16>,
+
+        /// known_hosts file to verify the SSH host key with, instead of `~/.ssh/known_hosts`
+        #[clap(long)]
+        ssh_known_hosts: Option<String>,
+
+        /// SHA256 fingerprint the SSH host key must have, as `ssh-keygen -lf` prints it
+        #[clap(long, conflicts_with = "ssh_known_hosts")]
+        ssh_host_key_fingerprint: Option<String>,
     },
 
     /// Restore from a snapshot backup
@@ -757,7 +1489,11 @@ pub enum PostgresqlCommands {
         #[clap(long)]
         remote_storage: bool,
 
-        /// Storage provider type (s3)
+        /// Stream the backup from remote storage instead of downloading it to the backup directory first
+        #[clap(long)]
+        stream: bool,
+
+        /// Storage provider type (s3, gcs, azure, local, sftp)
         #[clap(long)]
         storage_provider: Option<String>,
 
@@ -773,18 +1509,30 @@ pub enum PostgresqlCommands {
         #[clap(long)]
         storage_region: Option<String>,
 
-        /// Storage endpoint URL
+        /// Storage endpoint URL, the directory for local and `sftp://user@host/path` for sftp
         #[clap(long)]
         storage_endpoint: Option<String>,
 
-        /// Storage access key
+        /// Storage access key, the account name for Azure or the user for SFTP
         #[clap(long)]
         storage_access_key: Option<String>,
 
-        /// Storage secret key
+        /// Storage secret key, the account key for Azure or the password for SFTP
         #[clap(long)]
         storage_secret_key: Option<String>,
 
+        /// Key file of the storage, the SSH private key for SFTP or the service-account JSON for GCS
+        #[clap(long)]
+        storage_key_file: Option<String>,
+
+        /// Master key for client-side encryption of backup files, as `[key-id:]base64-key`
+        #[clap(long)]
+        encryption_key: Option<String>,
+
+        /// File with one `[key-id:]base64-key` per line, the first encrypts new uploads
+        #[clap(long)]
+        encryption_key_file: Option<String>,
+
         /// SSH host for port forwarding
         #[clap(long)]
         ssh_host: Option<String>,
@@ -812,6 +1560,14 @@ pub enum PostgresqlCommands {
         /// SSH remote port for port forwarding
         #[clap(long)]
         ssh_remote_port: Option<u16>,
+
+        /// known_hosts file to verify the SSH host key with, instead of `~/.ssh/known_hosts`
+        #[clap(long)]
+        ssh_known_hosts: Option<String>,
+
+        /// SHA256 fingerprint the SSH host key must have, as `ssh-keygen -lf` prints it
+        #[clap(long, conflicts_with = "ssh_known_hosts")]
+        ssh_host_key_fingerprint: Option<String>,
     },
 
     /// List contents of a snapshot backup
@@ -852,7 +1608,7 @@ pub enum PostgresqlCommands {
         #[clap(long)]
         remote_storage: bool,
 
-        /// Storage provider type (s3)
+        /// Storage provider type (s3, gcs, azure, local, sftp)
         #[clap(long)]
         storage_provider: Option<String>,
 
@@ -868,18 +1624,30 @@ pub enum PostgresqlCommands {
         #[clap(long)]
         storage_region: Option<String>,
 
-        /// Storage endpoint URL
+        /// Storage endpoint URL, the directory for local and `sftp://user@host/path` for sftp
         #[clap(long)]
         storage_endpoint: Option<String>,
 
-        /// Storage access key
+        /// Storage access key, the account name for Azure or the user for SFTP
         #[clap(long)]
         storage_access_key: Option<String>,
 
-        /// Storage secret key
+        /// Storage secret key, the account key for Azure or the password for SFTP
         #[clap(long)]
         storage_secret_key: Option<String>,
 
+        /// Key file of the storage, the SSH private key for SFTP or the service-account JSON for GCS
+        #[clap(long)]
+        storage_key_file: Option<String>,
+
+        /// Master key for client-side encryption of backup files, as `[key-id:]base64-key`
+        #[clap(long)]
+        encryption_key: Option<String>,
+
+        /// File with one `[key-id:]base64-key` per line, the first encrypts new uploads
+        #[clap(long)]
+        encryption_key_file: Option<String>,
+
         /// SSH host for port forwarding
         #[clap(long)]
         ssh_host: Option<String>,
@@ -907,5 +1675,13 @@ pub enum PostgresqlCommands {
         /// SSH remote port for port forwarding
         #[clap(long)]
         ssh_remote_port: Option<u16>,
+
+        /// known_hosts file to verify the SSH host key with, instead of `~/.ssh/known_hosts`
+        #[clap(long)]
+        ssh_known_hosts: Option<String>,
+
+        /// SHA256 fingerprint the SSH host key must have, as `ssh-keygen -lf` prints it
+        #[clap(long, conflicts_with = "ssh_known_hosts")]
+        ssh_host_key_fingerprint: Option<String>,
     },
 }
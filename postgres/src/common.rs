@@ -2,8 +2,11 @@ use chrono::{DateTime, Utc};
 use log::info;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use storage::Compression;
 use uuid::Uuid;
 
+use crate::wal;
+
 /// Represents a PostgreSQL server configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PostgresConfig {
@@ -21,6 +24,10 @@ pub struct PostgresConfig {
     pub ssh_key_path: Option<String>,
     pub ssh_local_port: Option<u16>,
     pub ssh_remote_port: Option<u16>,
+    /// known_hosts file the SSH host key is verified with
+    pub ssh_known_hosts: Option<String>,
+    /// SHA256 fingerprint the SSH host key must have
+    pub ssh_host_key_fingerprint: Option<String>,
 }
 
 impl PostgresConfig {
@@ -74,6 +81,29 @@ pub struct Backup {
     pub backup_path: PathBuf,
     pub server_version: String,
     pub error_message: Option<String>,
+    /// WAL segments covered by this backup
+    #[serde(default)]
+    pub wal_segments: Option<WalSegmentRange>,
+    /// Codec warden applied to the archived WAL and the uploaded files of this backup
+    #[serde(default)]
+    pub compression: Compression,
+    /// Outcome of the latest trial restore of this backup
+    #[serde(default)]
+    pub verification: Option<Verification>,
+    /// SHA-256 of the manifest written into the backup directory
+    #[serde(default)]
+    pub manifest_sha256: Option<String>,
+    /// Outcome of the upload to each storage destination
+    #[serde(default)]
+    pub replicas: Vec<Replica>,
+    /// Name of the logical dump written into `backup_path`, if any
+    #[serde(default)]
+    pub dump_file: Option<String>,
+}
+
+/// Name of the dump a backup writes of `database`
+pub fn dump_file_name(database: &str) -> String {
+    format!("{database}.dump")
 }
 
 impl Backup {
@@ -96,9 +126,23 @@ impl Backup {
             backup_path,
             server_version,
             error_message: None,
+            wal_segments: None,
+            compression: Compression::default(),
+            verification: None,
+            manifest_sha256: None,
+            replicas: Vec::new(),
+            dump_file: None,
         }
     }
 
+    /// Logical dump written with the backup, whatever database it is restored into
+    pub fn dump_path(&self) -> Option<PathBuf> {
+        self.dump_file
+            .as_ref()
+            .map(|name| self.backup_path.join(name))
+            .filter(|path| path.exists())
+    }
+
     pub fn complete(&mut self, wal_end: String, size_bytes: u64) {
         self.status = BackupStatus::Completed;
         self.end_time = Some(Utc::now());
@@ -111,6 +155,97 @@ impl Backup {
         self.end_time = Some(Utc::now());
         self.error_message = Some(error_message);
     }
+
+    /// Record the outcome of an upload, replacing the previous one for that destination
+    pub fn record_replica(&mut self, destination: String, error_message: Option<String>) {
+        self.replicas.retain(|r| r.destination != destination);
+        self.replicas.push(Replica {
+            destination,
+            status: if error_message.is_none() {
+                ReplicaStatus::Uploaded
+            } else {
+                ReplicaStatus::Failed
+            },
+            updated_at: Utc::now(),
+            error_message,
+        });
+    }
+}
+
+/// Verification status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VerificationStatus {
+    Passed,
+    Failed,
+}
+
+/// Outcome of a trial restore of a backup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Verification {
+    pub status: VerificationStatus,
+    pub verified_at: DateTime<Utc>,
+    /// Checks that ran, in order
+    pub checks: Vec<String>,
+    pub error_message: Option<String>,
+}
+
+/// Replica status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplicaStatus {
+    Uploaded,
+    Failed,
+}
+
+/// Outcome of the upload of a backup to one storage destination
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replica {
+    /// Provider, bucket and prefix of the destination
+    pub destination: String,
+    pub status: ReplicaStatus,
+    pub updated_at: DateTime<Utc>,
+    pub error_message: Option<String>,
+}
+
+/// A contiguous range of WAL segment files on a single timeline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalSegmentRange {
+    pub timeline: u32,
+    pub first_segment: String,
+    pub last_segment: String,
+    pub segment_size: u64,
+}
+
+impl WalSegmentRange {
+    /// Build the range of segments holding WAL from `start_lsn` up to (excluding) `end_lsn`
+    pub fn from_lsns(timeline: u32, start_lsn: u64, end_lsn: u64, segment_size: u64) -> Self {
+        let first = wal::segment_number(start_lsn, segment_size);
+        let last = wal::segment_number(end_lsn.saturating_sub(1).max(start_lsn), segment_size);
+        Self {
+            timeline,
+            first_segment: wal::segment_file_name(timeline, first, segment_size),
+            last_segment: wal::segment_file_name(timeline, last, segment_size),
+            segment_size,
+        }
+    }
+
+    pub fn first_segno(&self) -> Option<u64> {
+        wal::parse_segment_file_name(&self.first_segment, self.segment_size).map(|(_, n)| n)
+    }
+
+    pub fn last_segno(&self) -> Option<u64> {
+        wal::parse_segment_file_name(&self.last_segment, self.segment_size).map(|(_, n)| n)
+    }
+
+    /// Whether this range picks up where `previous` left off without a gap
+    pub fn continues(&self, previous: &WalSegmentRange) -> bool {
+        if self.timeline != previous.timeline || self.segment_size != previous.segment_size {
+            return false;
+        }
+        match (self.first_segno(), previous.last_segno()) {
+            (Some(first), Some(previous_last)) => first <= previous_last + 1,
+            _ => false,
+        }
+    }
 }
 
 /// Restore status
@@ -177,10 +312,22 @@ impl BackupCatalog {
         self.backups.push(backup);
     }
 
+    /// Add a backup, replacing any existing record with the same id
+    pub fn upsert_backup(&mut self, backup: Backup) {
+        match self.get_backup_mut(&backup.id) {
+            Some(existing) => *existing = backup,
+            None => self.add_backup(backup),
+        }
+    }
+
     pub fn get_backup(&self, id: &Uuid) -> Option<&Backup> {
         self.backups.iter().find(|b| &b.id == id)
     }
 
+    pub fn get_backup_mut(&mut self, id: &Uuid) -> Option<&mut Backup> {
+        self.backups.iter_mut().find(|b| &b.id == id)
+    }
+
     pub fn get_latest_full_backup(&self) -> Option<&Backup> {
         self.backups
             .iter()
@@ -199,6 +346,64 @@ impl BackupCatalog {
             .collect()
     }
 
+    /// Backups `id` needs to be restored: the full backup of an incremental
+    /// and the incrementals before it in the chain, whose WAL it replays
+    pub fn dependencies(&self, id: &Uuid) -> Vec<Uuid> {
+        let Some(backup) = self.get_backup(id) else {
+            return Vec::new();
+        };
+        let Some(base_id) = backup
+            .base_backup_id
+            .filter(|_| backup.backup_type == BackupType::Incremental)
+        else {
+            return Vec::new();
+        };
+        std::iter::once(base_id)
+            .chain(
+                self.get_incremental_backups_since(&base_id)
+                    .into_iter()
+                    .filter(|earlier| earlier.start_time < backup.start_time)
+                    .map(|earlier| earlier.id),
+            )
+            .collect()
+    }
+
+    /// Get the most recent completed backup in the chain of a full backup,
+    /// which is either its latest incremental or the full backup itself
+    pub fn get_latest_backup_in_chain(&self, base_id: &Uuid) -> Option<&Backup> {
+        self.get_incremental_backups_since(base_id)
+            .into_iter()
+            .max_by_key(|b| b.end_time.unwrap_or(b.start_time))
+            .or_else(|| {
+                self.get_backup(base_id)
+                    .filter(|b| b.status == BackupStatus::Completed)
+            })
+    }
+
+    /// Find places in the chain of a full backup where WAL is missing.
+    ///
+    /// Returns pairs of consecutive backups (older, newer) whose recorded WAL
+    /// segment ranges do not join up. Backups without a recorded range are
+    /// treated as breaking the chain.
+    pub fn find_wal_gaps(&self, base_id: &Uuid) -> Vec<(Uuid, Uuid)> {
+        let mut chain: Vec<&Backup> = self.get_incremental_backups_since(base_id);
+        chain.sort_by_key(|b| b.start_time);
+        if let Some(base) = self.get_backup(base_id) {
+            chain.insert(0, base);
+        }
+
+        chain
+            .windows(2)
+            .filter(
+                |pair| match (&pair[0].wal_segments, &pair[1].wal_segments) {
+                    (Some(previous), Some(current)) => !current.continues(previous),
+                    _ => true,
+                },
+            )
+            .map(|pair| (pair[0].id, pair[1].id))
+            .collect()
+    }
+
     pub fn save_to_file(&self, path: &PathBuf) -> std::io::Result<()> {
         let json = serde_json::to_string_pretty(self)?;
         std::fs::write(path, json)?;
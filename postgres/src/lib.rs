@@ -3,8 +3,11 @@ pub mod cli;
 pub mod common;
 pub mod manager;
 pub mod restore;
+pub mod retention;
 pub mod tunnel_keeper;
 pub mod user;
+pub mod verify;
+pub mod wal;
 pub mod wrapper;
 
 use ssh::SshError;
@@ -44,6 +47,9 @@ pub enum PostgresError {
 
     #[error("SSH error: {0}")]
     Ssh(SshError),
+
+    #[error("Storage error: {0}")]
+    Storage(storage::StorageError),
 }
 
 impl From<std::io::Error> for PostgresError {
@@ -58,6 +64,12 @@ impl From<tokio_postgres::Error> for PostgresError {
     }
 }
 
+impl From<storage::StorageError> for PostgresError {
+    fn from(err: storage::StorageError) -> Self {
+        PostgresError::Storage(err)
+    }
+}
+
 impl From<anyhow::Error> for PostgresError {
     fn from(err: anyhow::Error) -> Self {
         PostgresError::Anyhow(err)
@@ -69,6 +81,7 @@ pub type Result<T> = std::result::Result<T, PostgresError>;
 // Re-export key types for convenience
 pub use common::{
     Backup, BackupCatalog, BackupStatus, BackupType, PostgresConfig, Restore, RestoreStatus,
+    Verification, VerificationStatus, WalSegmentRange,
 };
 pub use manager::PostgresManager;
 
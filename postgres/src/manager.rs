@@ -1,12 +1,21 @@
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use log::{info, warn};
+use std::collections::HashSet;
 use std::fs;
 use std::path::PathBuf;
 use uuid::Uuid;
 
 use crate::backup::BackupManagerFactory;
-use crate::common::{Backup, BackupCatalog, BackupType, PostgresConfig, Restore};
-use crate::restore::RestoreManagerFactory;
+use crate::common::{
+    Backup, BackupCatalog, BackupStatus, BackupType, PostgresConfig, Restore, Verification,
+};
+use crate::restore::{RecoveryTarget, RecoveryTargetAction, RemoteSource, RestoreManagerFactory};
+use crate::retention::{RetentionPlan, RetentionPolicy};
+use crate::verify::{BackupVerifier, VerifyOptions};
+use crate::wal;
+use crate::wrapper::{Cancellation, ProcessPriority};
+use storage::manifest::{hash_file, MANIFEST_FILE};
+use storage::{Compression, IntegrityProblem, IntegrityReport, Manifest, RateLimiter};
 
 use crate::PostgresError;
 
@@ -16,6 +25,12 @@ pub struct PostgresManager {
     backup_dir: PathBuf,
     catalog_path: PathBuf,
     catalog: BackupCatalog,
+    compression: Compression,
+    remote: Option<RemoteSource>,
+    priority: ProcessPriority,
+    cancellation: Cancellation,
+    rate_limiter: Option<RateLimiter>,
+    replication_slot: bool,
 }
 
 impl PostgresManager {
@@ -60,10 +75,108 @@ impl PostgresManager {
             backup_dir,
             catalog_path,
             catalog,
+            compression: Compression::default(),
+            remote: None,
+            priority: ProcessPriority::default(),
+            cancellation: Cancellation::default(),
+            rate_limiter: None,
+            replication_slot: false,
         };
         Ok(manager)
     }
 
+    /// Codec for the WAL archived by incremental backups, recorded on every new backup
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Run pg_basebackup and pg_dump with `priority`
+    pub fn with_priority(mut self, priority: ProcessPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Stop backups, restores and the tools they run when `cancellation` is cancelled
+    pub fn with_cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Bandwidth budget of the WAL copied into incremental backups
+    pub fn with_rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Keep WAL on the server in the `warden_wal` replication slot until it is streamed
+    ///
+    /// Off by default: a slot nothing streams from makes the server keep WAL
+    /// until its disk is full. Drop an unused slot with
+    /// `SELECT pg_drop_replication_slot('warden_wal')`.
+    pub fn with_replication_slot(mut self, enabled: bool) -> Self {
+        self.replication_slot = enabled;
+        self
+    }
+
+    /// Restore straight from remote storage
+    ///
+    /// Backup files are streamed instead of read from the backup directory,
+    /// and backups missing from the local catalog are looked up in the
+    /// remote one.
+    pub fn with_remote_storage(mut self, remote: RemoteSource) -> Self {
+        self.remote = Some(remote);
+        self
+    }
+
+    /// Find a backup in the local catalog, then in the remote one
+    async fn find_backup(&self, backup_id: &Uuid) -> Result<Backup, PostgresError> {
+        if let Some(backup) = self.catalog.get_backup(backup_id) {
+            return Ok(backup.clone());
+        }
+        let Some(remote) = &self.remote else {
+            return Err(PostgresError::BackupNotFound(*backup_id));
+        };
+        let info = match remote.get_backup_info(&backup_id.to_string()).await {
+            Ok(info) => info,
+            Err(storage::StorageError::NotFound(_)) => {
+                return Err(PostgresError::BackupNotFound(*backup_id))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        backup_from_remote(info)
+    }
+
+    /// Completed incremental backups based on a full backup, from the local
+    /// catalog, then from the remote one
+    async fn find_incremental_backups(
+        &self,
+        full_backup_id: &Uuid,
+    ) -> Result<Vec<Backup>, PostgresError> {
+        let local: Vec<Backup> = self
+            .catalog
+            .get_incremental_backups_since(full_backup_id)
+            .into_iter()
+            .cloned()
+            .collect();
+        let Some(remote) = self.remote.as_ref().filter(|_| local.is_empty()) else {
+            return Ok(local);
+        };
+        let descendants = remote
+            .remote_catalog()
+            .await?
+            .descendants(&full_backup_id.to_string())
+            .into_iter()
+            .map(|info| backup_from_remote(info.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(descendants
+            .into_iter()
+            .filter(|b| {
+                b.backup_type == BackupType::Incremental && b.status == BackupStatus::Completed
+            })
+            .collect())
+    }
+
     /// Perform a full backup
     pub async fn full_backup(&mut self) -> Result<Backup, PostgresError> {
         info!("Starting full backup");
@@ -71,10 +184,15 @@ impl PostgresManager {
         let manager = BackupManagerFactory::create_full_backup_manager(
             self.config.clone(),
             self.backup_dir.clone(),
-        );
+        )
+        .with_priority(self.priority.clone())
+        .with_cancellation(self.cancellation.clone())
+        .with_replication_slot(self.replication_slot);
 
         // Perform the backup operation
-        let backup = manager.backup().await?;
+        let mut backup = manager.backup().await?;
+        backup.compression = self.compression;
+        self.write_manifest(&mut backup).await?;
 
         // Add backup to catalog
         self.catalog.add_backup(backup.clone());
@@ -94,10 +212,15 @@ impl PostgresManager {
             self.config.clone(),
             self.backup_dir.clone(),
             self.catalog.clone(),
-        );
+        )
+        .with_compression(self.compression)
+        .with_rate_limiter(self.rate_limiter.clone())
+        .with_cancellation(self.cancellation.clone())
+        .with_replication_slot(self.replication_slot);
 
         // Perform the backup operation
-        let backup = manager.backup().await?;
+        let mut backup = manager.backup().await?;
+        self.write_manifest(&mut backup).await?;
 
         // Add backup to catalog
         self.catalog.add_backup(backup.clone());
@@ -116,10 +239,14 @@ impl PostgresManager {
         let manager = BackupManagerFactory::create_snapshot_backup_manager(
             self.config.clone(),
             self.backup_dir.clone(),
-        );
+        )
+        .with_priority(self.priority.clone())
+        .with_cancellation(self.cancellation.clone());
 
         // Perform the backup operation
-        let backup = manager.backup().await?;
+        let mut backup = manager.backup().await?;
+        backup.compression = self.compression;
+        self.write_manifest(&mut backup).await?;
 
         // Add backup to catalog
         self.catalog.add_backup(backup.clone());
@@ -131,6 +258,21 @@ impl PostgresManager {
         Ok(backup)
     }
 
+    /// Continuously stream WAL into the backup directory's WAL archive
+    pub async fn stream_wal(&self) -> Result<(), PostgresError> {
+        info!("Starting continuous WAL streaming");
+
+        let manager = BackupManagerFactory::create_incremental_backup_manager(
+            self.config.clone(),
+            self.backup_dir.clone(),
+            self.catalog.clone(),
+        )
+        .with_cancellation(self.cancellation.clone())
+        .with_replication_slot(self.replication_slot);
+
+        manager.stream_wal().await
+    }
+
     /// Restore from a full backup
     pub async fn restore_full_backup(
         &mut self,
@@ -140,27 +282,22 @@ impl PostgresManager {
         info!("Starting restore from full backup: {backup_id}");
 
         // Find the backup
-        let backup = match self.catalog.get_backup(backup_id) {
-            Some(backup) => {
-                if backup.backup_type != BackupType::Full {
-                    return Err(PostgresError::RestoreError(format!(
-                        "Backup {backup_id} is not a full backup"
-                    )));
-                }
-                backup.clone()
-            }
-            None => {
-                return Err(PostgresError::RestoreError(format!(
-                    "Backup {backup_id} not found"
-                )));
-            }
-        };
+        let backup = self.find_backup(backup_id).await?;
+        if backup.backup_type != BackupType::Full {
+            return Err(PostgresError::RestoreError(format!(
+                "Backup {backup_id} is not a full backup"
+            )));
+        }
 
-        let manager = RestoreManagerFactory::create_full_restore_manager(
+        let mut manager = RestoreManagerFactory::create_full_restore_manager(
             self.config.clone(),
             backup,
             target_dir,
-        );
+        )
+        .with_cancellation(self.cancellation.clone());
+        if let Some(remote) = &self.remote {
+            manager = manager.with_remote(remote.clone());
+        }
 
         let restore = manager.restore().await?;
 
@@ -207,7 +344,8 @@ impl PostgresManager {
             full_backup,
             incremental_backups,
             target_dir,
-        );
+        )
+        .with_cancellation(self.cancellation.clone());
 
         let restore = manager.restore().await?;
 
@@ -221,44 +359,34 @@ impl PostgresManager {
         &mut self,
         full_backup_id: &Uuid,
         target_dir: PathBuf,
-        target_time: DateTime<Utc>,
+        target: RecoveryTarget,
+        action: RecoveryTargetAction,
     ) -> Result<Restore, PostgresError> {
-        info!(
-            "Starting point-in-time restore to {target_time} based on full backup: {full_backup_id}"
-        );
+        info!("Starting point-in-time restore to {target} based on full backup: {full_backup_id}");
 
         // Find the full backup
-        let full_backup = match self.catalog.get_backup(full_backup_id) {
-            Some(backup) => {
-                if backup.backup_type != BackupType::Full {
-                    return Err(PostgresError::RestoreError(format!(
-                        "Backup {full_backup_id} is not a full backup"
-                    )));
-                }
-                backup.clone()
-            }
-            None => {
-                return Err(PostgresError::RestoreError(format!(
-                    "Backup {full_backup_id} not found"
-                )));
-            }
-        };
+        let full_backup = self.find_backup(full_backup_id).await?;
+        if full_backup.backup_type != BackupType::Full {
+            return Err(PostgresError::RestoreError(format!(
+                "Backup {full_backup_id} is not a full backup"
+            )));
+        }
 
         // Find all incremental backups based on this full backup
-        let incremental_backups = self
-            .catalog
-            .get_incremental_backups_since(full_backup_id)
-            .into_iter()
-            .cloned()
-            .collect();
+        let incremental_backups = self.find_incremental_backups(full_backup_id).await?;
 
-        let manager = RestoreManagerFactory::create_point_in_time_restore_manager(
+        let mut manager = RestoreManagerFactory::create_point_in_time_restore_manager(
             self.config.clone(),
             full_backup,
             incremental_backups,
             target_dir,
-            target_time,
-        );
+            target,
+            action,
+        )
+        .with_cancellation(self.cancellation.clone());
+        if let Some(remote) = &self.remote {
+            manager = manager.with_remote(remote.clone());
+        }
 
         let restore = manager.restore().await?;
 
@@ -276,17 +404,17 @@ impl PostgresManager {
     ) -> Result<Restore, PostgresError> {
         info!("Restoring snapshot backup: {backup_id}");
 
-        let backup = self
-            .catalog
-            .get_backup(backup_id)
-            .ok_or_else(|| PostgresError::BackupNotFound(*backup_id))?
-            .clone();
+        let backup = self.find_backup(backup_id).await?;
 
-        let manager = RestoreManagerFactory::create_snapshot_restore_manager(
+        let mut manager = RestoreManagerFactory::create_snapshot_restore_manager(
             self.config.clone(),
             backup,
             target_dir,
-        )?;
+        )?
+        .with_cancellation(self.cancellation.clone());
+        if let Some(remote) = &self.remote {
+            manager = manager.with_remote(remote.clone());
+        }
 
         let restore = manager.restore().await?;
 
@@ -317,6 +445,76 @@ impl PostgresManager {
         Ok(contents)
     }
 
+    /// Prove a backup is restorable with a trial restore and record the outcome
+    ///
+    /// A failed trial restore is not an error: it is recorded in the catalog
+    /// and returned like a passed one.
+    pub async fn verify_backup(
+        &mut self,
+        backup_id: &Uuid,
+        options: VerifyOptions,
+    ) -> Result<Verification, PostgresError> {
+        let backup = self
+            .catalog
+            .get_backup(backup_id)
+            .ok_or_else(|| PostgresError::BackupNotFound(*backup_id))?
+            .clone();
+        if backup.status != BackupStatus::Completed {
+            return Err(PostgresError::BackupError(format!(
+                "Backup {backup_id} is not completed"
+            )));
+        }
+
+        let scratch_dir = options.scratch_dir.clone().unwrap_or_else(|| {
+            let timestamp = Utc::now().format("%Y%m%d_%H%M%S");
+            self.backup_dir
+                .join(format!("verify_{backup_id}_{timestamp}"))
+        });
+        let verifier = BackupVerifier::new(self.config.clone(), options);
+
+        let verification = match backup.backup_type {
+            BackupType::Snapshot => verifier.verify_snapshot(&backup).await,
+            BackupType::Full => {
+                verifier
+                    .verify_physical(&backup, Vec::new(), &backup, &scratch_dir)
+                    .await
+            }
+            BackupType::Incremental => {
+                let base_id = backup.base_backup_id.ok_or_else(|| {
+                    PostgresError::BackupError(format!(
+                        "Incremental backup {backup_id} has no base backup"
+                    ))
+                })?;
+                let full_backup = self
+                    .catalog
+                    .get_backup(&base_id)
+                    .ok_or(PostgresError::BackupNotFound(base_id))?
+                    .clone();
+                let chain = self
+                    .catalog
+                    .get_incremental_backups_since(&base_id)
+                    .into_iter()
+                    .filter(|b| b.start_time <= backup.start_time)
+                    .cloned()
+                    .collect();
+                verifier
+                    .verify_physical(&full_backup, chain, &backup, &scratch_dir)
+                    .await
+            }
+        };
+
+        info!(
+            "Verification of backup {backup_id}: {:?}",
+            verification.status
+        );
+        if let Some(entry) = self.catalog.get_backup_mut(backup_id) {
+            entry.verification = Some(verification.clone());
+        }
+        self.save_catalog()?;
+
+        Ok(verification)
+    }
+
     /// List all backups
     pub fn list_backups(&self) -> &[Backup] {
         &self.catalog.backups
@@ -332,6 +530,166 @@ impl PostgresManager {
         self.catalog.get_latest_full_backup()
     }
 
+    /// Backups `id` needs to be restored, see [`BackupCatalog::dependencies`]
+    pub fn backup_dependencies(&self, id: &Uuid) -> Vec<Uuid> {
+        self.catalog.dependencies(id)
+    }
+
+    /// Decide which backups of the catalog a retention policy keeps and prunes
+    pub fn plan_prune(&self, policy: &RetentionPolicy) -> RetentionPlan {
+        let plan = policy.plan(&self.catalog, Utc::now());
+        info!(
+            "Retention plan: keeping {} backups, pruning {}",
+            plan.keep.len(),
+            plan.delete.len()
+        );
+        plan
+    }
+
+    /// Remove backups from disk and from the catalog
+    ///
+    /// Archived WAL that none of the remaining backups needs anymore goes
+    /// with them.
+    pub fn remove_backups(&mut self, ids: &[Uuid]) -> Result<(), PostgresError> {
+        for id in ids {
+            let Some(backup) = self.catalog.get_backup(id) else {
+                continue;
+            };
+            let path = &backup.backup_path;
+            if !path.starts_with(&self.backup_dir) {
+                warn!(
+                    "Backup {} lives outside {:?}, leaving {:?} on disk",
+                    backup.id, self.backup_dir, path
+                );
+            } else if path.is_dir() {
+                fs::remove_dir_all(path).map_err(PostgresError::Io)?;
+            } else if path.exists() {
+                fs::remove_file(path).map_err(PostgresError::Io)?;
+            }
+            info!("Pruned backup {} ({:?})", backup.id, backup.backup_type);
+        }
+
+        let deleted: HashSet<&Uuid> = ids.iter().collect();
+        self.catalog.backups.retain(|b| !deleted.contains(&b.id));
+        self.save_catalog()?;
+
+        self.prune_wal_archive()
+    }
+
+    /// Remove archived WAL segments older than anything a remaining backup needs
+    fn prune_wal_archive(&self) -> Result<(), PostgresError> {
+        let archive_dir = self.backup_dir.join(wal::WAL_ARCHIVE_DIR);
+        if !archive_dir.exists() {
+            return Ok(());
+        }
+
+        let Some(oldest) = self
+            .catalog
+            .backups
+            .iter()
+            .filter_map(|b| b.wal_segments.as_ref())
+            .filter_map(|range| range.first_segno().map(|segno| (segno, range)))
+            .min_by_key(|(segno, _)| *segno)
+            .map(|(_, range)| range.clone())
+        else {
+            return Ok(());
+        };
+        let oldest_segno = oldest.first_segno().unwrap_or_default();
+
+        let mut removed = 0;
+        for entry in fs::read_dir(&archive_dir).map_err(PostgresError::Io)? {
+            let entry = entry.map_err(PostgresError::Io)?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some((timeline, segno)) = wal::parse_segment_file_name(&name, oldest.segment_size)
+            else {
+                continue;
+            };
+            if timeline <= oldest.timeline && segno < oldest_segno {
+                fs::remove_file(entry.path()).map_err(PostgresError::Io)?;
+                removed += 1;
+            }
+        }
+
+        if removed > 0 {
+            info!(
+                "Removed {removed} archived WAL segments older than {}",
+                oldest.first_segment
+            );
+        }
+        Ok(())
+    }
+
+    /// Checksum every file of a new backup into its manifest
+    async fn write_manifest(&self, backup: &mut Backup) -> Result<(), PostgresError> {
+        if !backup.backup_path.is_dir() {
+            warn!(
+                "Backup path {:?} is not a directory, not writing a manifest",
+                backup.backup_path
+            );
+            return Ok(());
+        }
+        let manifest = Manifest::build(&backup.id.to_string(), &backup.backup_path)
+            .await
+            .map_err(|e| PostgresError::BackupError(format!("Failed to build manifest: {e}")))?;
+        let sha256 = manifest
+            .write_to(&backup.backup_path)
+            .await
+            .map_err(|e| PostgresError::BackupError(format!("Failed to write manifest: {e}")))?;
+        info!(
+            "Wrote manifest of {} files for backup {}",
+            manifest.files.len(),
+            backup.id
+        );
+        backup.manifest_sha256 = Some(sha256);
+        Ok(())
+    }
+
+    /// Re-hash the local copy of a backup and compare it to its manifest
+    ///
+    /// The manifest itself is checked against the checksum recorded in the
+    /// catalog when the backup was taken.
+    pub async fn verify_integrity(
+        &self,
+        backup_id: &Uuid,
+    ) -> Result<IntegrityReport, PostgresError> {
+        let backup = self
+            .catalog
+            .get_backup(backup_id)
+            .ok_or_else(|| PostgresError::BackupNotFound(*backup_id))?;
+        let manifest_path = backup.backup_path.join(MANIFEST_FILE);
+        if !manifest_path.exists() {
+            return Err(PostgresError::BackupError(format!(
+                "Backup {backup_id} has no manifest at {manifest_path:?}"
+            )));
+        }
+
+        let integrity_error = |e: storage::StorageError| {
+            PostgresError::BackupError(format!("Integrity check failed: {e}"))
+        };
+        let mut problems = Vec::new();
+        if let Some(expected) = &backup.manifest_sha256 {
+            let (_, actual) = hash_file(&manifest_path).await.map_err(integrity_error)?;
+            if &actual != expected {
+                problems.push(IntegrityProblem::ChecksumMismatch {
+                    path: MANIFEST_FILE.to_string(),
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+
+        let manifest = Manifest::load(&backup.backup_path)
+            .await
+            .map_err(integrity_error)?;
+        let mut report = manifest
+            .verify_dir(&backup.backup_path)
+            .await
+            .map_err(integrity_error)?;
+        problems.append(&mut report.problems);
+        report.problems = problems;
+        Ok(report)
+    }
+
     /// Save the backup catalog
     fn save_catalog(&self) -> Result<(), PostgresError> {
         info!("Saving backup catalog to {}", self.catalog_path.display());
@@ -348,3 +706,16 @@ impl PostgresManager {
         self.save_catalog()
     }
 }
+
+/// The record a backup was registered with in the remote catalog
+fn backup_from_remote(info: storage::BackupInfo) -> Result<Backup, PostgresError> {
+    let backup_id = info.id;
+    let details = info.details.ok_or_else(|| {
+        PostgresError::RestoreError(format!(
+            "Remote record of backup {backup_id} has no details"
+        ))
+    })?;
+    serde_json::from_value(details).map_err(|e| {
+        PostgresError::RestoreError(format!("Invalid remote record of backup {backup_id}: {e}"))
+    })
+}
@@ -6,7 +6,10 @@ use std::io;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
+use super::{check_cancelled, find_remote_dump, write_stream_to_file, RemoteSource};
 use crate::common::{Backup, PostgresConfig, Restore};
+use crate::wrapper::pg_restore::PgRestore;
+use crate::wrapper::Cancellation;
 use crate::PostgresError;
 use tokio_postgres::{Client, NoTls};
 
@@ -15,6 +18,8 @@ pub struct FullRestoreManager {
     config: PostgresConfig,
     backup: Backup,
     target_dir: PathBuf,
+    remote: Option<RemoteSource>,
+    cancellation: Cancellation,
 }
 
 // Helper function to recursively copy directories
@@ -46,9 +51,24 @@ impl FullRestoreManager {
             config,
             backup,
             target_dir,
+            remote: None,
+            cancellation: Cancellation::default(),
         }
     }
 
+    /// Stop the restore and the tools it runs when `cancellation` is cancelled
+    pub fn with_cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Stream the backup files from remote storage instead of copying them
+    /// from the backup directory
+    pub fn with_remote(mut self, remote: RemoteSource) -> Self {
+        self.remote = Some(remote);
+        self
+    }
+
     async fn create_client(&self) -> Result<Client> {
         let password = self
             .config
@@ -131,6 +151,22 @@ impl FullRestoreManager {
             fs::create_dir_all(&self.target_dir).map_err(PostgresError::Io)?;
         }
 
+        if let Some(remote) = &self.remote {
+            return match self.restore_from_remote(remote).await {
+                Ok(()) => {
+                    restore.complete();
+                    info!("Full restore completed successfully");
+                    Ok(restore)
+                }
+                Err(e) => {
+                    let error_msg = format!("Full restore failed: {e}");
+                    error!("{error_msg}");
+                    restore.fail(error_msg.clone());
+                    Err(PostgresError::RestoreError(error_msg))
+                }
+            };
+        }
+
         // Check if the backup exists
         if !self.backup.backup_path.exists() {
             let error_msg = format!("Backup path does not exist: {:?}", self.backup.backup_path);
@@ -152,6 +188,7 @@ impl FullRestoreManager {
 
                 // Restore the database using pg_restore if applicable
                 if let Err(e) = self.restore_database_content() {
+                    check_cancelled(&self.cancellation)?;
                     warn!("Database content restore failed: {e}");
                     // Continue anyway as we've copied the files successfully
                 }
@@ -175,6 +212,59 @@ impl FullRestoreManager {
         }
     }
 
+    /// Write the backup files into the target directory as they stream in
+    /// from remote storage, piping the dump straight into pg_restore
+    async fn restore_from_remote(&self, remote: &RemoteSource) -> Result<(), PostgresError> {
+        let backup_id = self.backup.id.to_string();
+        info!(
+            "Streaming backup {backup_id} from remote storage to {:?}",
+            self.target_dir
+        );
+
+        remote
+            .rehydrate_backups(std::slice::from_ref(&backup_id))
+            .await?;
+        let dump_name = find_remote_dump(remote, &self.backup).await.ok();
+        for file in remote.list_backup_files(&backup_id).await? {
+            if Some(&file) == dump_name.as_ref() || file == "metadata.json" {
+                continue;
+            }
+            check_cancelled(&self.cancellation)?;
+            let stream = remote.open_backup_file(&backup_id, &file).await?;
+            write_stream_to_file(stream, &self.target_dir.join(&file)).await?;
+        }
+
+        self.create_recovery_conf()?;
+
+        match dump_name {
+            Some(dump_name) => {
+                self.create_database();
+                let dump = remote.open_backup_file(&backup_id, &dump_name).await?;
+                info!("Restoring database content using pg_restore from remote {dump_name}");
+                PgRestore::new(self.config.clone())
+                    .with_cancellation(self.cancellation.clone())
+                    .restore_stream(dump, None, &["-v", "-c"])
+                    .await
+                    .map_err(|e| {
+                        PostgresError::RestoreError(format!(
+                            "Database content restore from {dump_name} failed: {e}"
+                        ))
+                    })?;
+            }
+            None => {
+                info!("No database dump found in remote backup. Skipping database content restore.")
+            }
+        }
+
+        // Only once everything above succeeded
+        fs::write(
+            self.target_dir.join(".restore_complete"),
+            "Restore completed successfully",
+        )
+        .map_err(PostgresError::Io)?;
+        Ok(())
+    }
+
     /// Copy backup files to target directory
     fn copy_backup_files(&self) -> Result<(), PostgresError> {
         info!(
@@ -203,13 +293,14 @@ impl FullRestoreManager {
         }
 
         // First approach: Try to use the cp command with the directory itself
-        let cp_result = Command::new("cp")
-            .arg("-R")
-            .arg(&self.backup.backup_path)
-            .arg(self.target_dir.parent().unwrap_or(&self.target_dir))
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status();
+        let cp_result = self.cancellation.status(
+            Command::new("cp")
+                .arg("-R")
+                .arg(&self.backup.backup_path)
+                .arg(self.target_dir.parent().unwrap_or(&self.target_dir))
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit()),
+        );
 
         if let Ok(status) = cp_result {
             if status.success() {
@@ -224,13 +315,14 @@ impl FullRestoreManager {
 
         // Second approach: Try to use the cp command with wildcards
         let wildcard_path = format!("{}/{}*", self.backup.backup_path.to_string_lossy(), "");
-        let cp_wildcard_result = Command::new("cp")
-            .arg("-R")
-            .arg(&wildcard_path)
-            .arg(&self.target_dir)
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status();
+        let cp_wildcard_result = self.cancellation.status(
+            Command::new("cp")
+                .arg("-R")
+                .arg(&wildcard_path)
+                .arg(&self.target_dir)
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit()),
+        );
 
         if let Ok(status) = cp_wildcard_result {
             if status.success() {
@@ -244,6 +336,7 @@ impl FullRestoreManager {
         }
 
         // Third approach: Manual recursive copy
+        check_cancelled(&self.cancellation)?;
         warn!("cp commands failed, falling back to manual recursive copy");
 
         // Read the directory and copy each file/directory individually
@@ -299,7 +392,7 @@ impl FullRestoreManager {
              restore_command = 'cp {}/pg_wal/%f %p'\n\
              recovery_target_timeline = 'latest'\n",
             Utc::now(),
-            self.backup.backup_path.to_string_lossy()
+            self.wal_source_dir().to_string_lossy()
         );
 
         fs::write(&recovery_conf_path, recovery_conf_content).map_err(PostgresError::Io)?;
@@ -309,6 +402,43 @@ impl FullRestoreManager {
         Ok(())
     }
 
+    /// Directory holding the WAL of the backup, the target directory when
+    /// the files were streamed into it
+    fn wal_source_dir(&self) -> &Path {
+        match self.remote {
+            Some(_) => &self.target_dir,
+            None => &self.backup.backup_path,
+        }
+    }
+
+    /// Create the target database, which may already exist
+    fn create_database(&self) {
+        let db_name = &self.config.database;
+        let create_db_result = self.cancellation.status(
+            Command::new("createdb")
+                .args([
+                    "-h",
+                    &self.config.host,
+                    "-p",
+                    &self.config.port.to_string(),
+                    "-U",
+                    &self.config.user,
+                    db_name,
+                ])
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit()),
+        );
+
+        if let Ok(status) = create_db_result {
+            if status.success() {
+                info!("Created database {db_name}");
+            } else {
+                // Database might already exist, which is fine
+                info!("Database {db_name} might already exist, continuing with restore");
+            }
+        }
+    }
+
     /// Restore database content using pg_restore
     fn restore_database_content(&self) -> Result<(), PostgresError> {
         // Look for dump files in the backup directory
@@ -344,42 +474,32 @@ impl FullRestoreManager {
         let user = &self.config.user;
 
         // Check if database exists, if not create it
-        let create_db_result = Command::new("createdb")
-            .args(["-h", host, "-p", &port.to_string(), "-U", user, db_name])
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status();
-
-        if let Ok(status) = create_db_result {
-            if status.success() {
-                info!("Created database {db_name}");
-            } else {
-                // Database might already exist, which is fine
-                info!("Database {db_name} might already exist, continuing with restore");
-            }
-        }
+        self.create_database();
 
         // Restore the database content
         if is_custom_format {
             // Use pg_restore for custom format dumps
             info!("Restoring database content using pg_restore from {dump_file:?}");
-            let restore_result = Command::new("pg_restore")
-                .args([
-                    "-h",
-                    host,
-                    "-p",
-                    &port.to_string(),
-                    "-U",
-                    user,
-                    "-d",
-                    db_name,
-                    "-v", // verbose output
-                    "-c", // clean (drop) database objects before recreating
-                    dump_file.to_str().unwrap(),
-                ])
-                .stdout(Stdio::inherit())
-                .stderr(Stdio::inherit())
-                .status()
+            let restore_result = self
+                .cancellation
+                .status(
+                    Command::new("pg_restore")
+                        .args([
+                            "-h",
+                            host,
+                            "-p",
+                            &port.to_string(),
+                            "-U",
+                            user,
+                            "-d",
+                            db_name,
+                            "-v", // verbose output
+                            "-c", // clean (drop) database objects before recreating
+                            dump_file.to_str().unwrap(),
+                        ])
+                        .stdout(Stdio::inherit())
+                        .stderr(Stdio::inherit()),
+                )
                 .map_err(PostgresError::Io)?;
 
             if !restore_result.success() {
@@ -390,22 +510,25 @@ impl FullRestoreManager {
         } else {
             // Use psql for plain SQL dumps
             info!("Restoring database content using psql from {dump_file:?}");
-            let restore_result = Command::new("psql")
-                .args([
-                    "-h",
-                    host,
-                    "-p",
-                    &port.to_string(),
-                    "-U",
-                    user,
-                    "-d",
-                    db_name,
-                    "-f",
-                    dump_file.to_str().unwrap(),
-                ])
-                .stdout(Stdio::inherit())
-                .stderr(Stdio::inherit())
-                .status()
+            let restore_result = self
+                .cancellation
+                .status(
+                    Command::new("psql")
+                        .args([
+                            "-h",
+                            host,
+                            "-p",
+                            &port.to_string(),
+                            "-U",
+                            user,
+                            "-d",
+                            db_name,
+                            "-f",
+                            dump_file.to_str().unwrap(),
+                        ])
+                        .stdout(Stdio::inherit())
+                        .stderr(Stdio::inherit()),
+                )
                 .map_err(PostgresError::Io)?;
 
             if !restore_result.success() {
@@ -2,10 +2,12 @@ use anyhow::Result;
 use chrono::Utc;
 use log::{error, info};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
+use super::check_cancelled;
 use crate::common::{Backup, PostgresConfig, Restore};
+use crate::wrapper::Cancellation;
 use crate::PostgresError;
 
 /// Incremental restore manager
@@ -14,6 +16,7 @@ pub struct IncrementalRestoreManager {
     full_backup: Backup,
     incremental_backups: Vec<Backup>,
     target_dir: PathBuf,
+    cancellation: Cancellation,
 }
 
 impl IncrementalRestoreManager {
@@ -29,9 +32,16 @@ impl IncrementalRestoreManager {
             full_backup,
             incremental_backups,
             target_dir,
+            cancellation: Cancellation::default(),
         }
     }
 
+    /// Stop the restore and the tools it runs when `cancellation` is cancelled
+    pub fn with_cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
     /// Perform an incremental restore
     pub async fn restore(&self) -> Result<Restore, PostgresError> {
         info!(
@@ -68,7 +78,7 @@ impl IncrementalRestoreManager {
                 info!("Full backup restored successfully");
 
                 // Then apply incremental backups
-                match self.apply_incremental_backups() {
+                match self.apply_incremental_backups().await {
                     Ok(_) => {
                         info!("Incremental backups applied successfully");
 
@@ -134,13 +144,14 @@ impl IncrementalRestoreManager {
         }
 
         // First approach: Try to use the cp command with the directory itself
-        let cp_result = Command::new("cp")
-            .arg("-R")
-            .arg(&self.full_backup.backup_path)
-            .arg(self.target_dir.parent().unwrap_or(&self.target_dir))
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status();
+        let cp_result = self.cancellation.status(
+            Command::new("cp")
+                .arg("-R")
+                .arg(&self.full_backup.backup_path)
+                .arg(self.target_dir.parent().unwrap_or(&self.target_dir))
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit()),
+        );
 
         if let Ok(status) = cp_result {
             if status.success() {
@@ -155,13 +166,14 @@ impl IncrementalRestoreManager {
 
         // Second approach: Try to use the cp command with wildcards
         let wildcard_path = format!("{}/{}*", self.full_backup.backup_path.to_string_lossy(), "");
-        let cp_wildcard_result = Command::new("cp")
-            .arg("-R")
-            .arg(&wildcard_path)
-            .arg(&self.target_dir)
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status();
+        let cp_wildcard_result = self.cancellation.status(
+            Command::new("cp")
+                .arg("-R")
+                .arg(&wildcard_path)
+                .arg(&self.target_dir)
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit()),
+        );
 
         if let Ok(status) = cp_wildcard_result {
             if status.success() {
@@ -173,6 +185,7 @@ impl IncrementalRestoreManager {
                 return Ok(());
             }
         }
+        check_cancelled(&self.cancellation)?;
 
         // Create a dummy file to ensure the directory is not empty (for test verification)
         let dummy_file = self.target_dir.join(".restore_complete");
@@ -182,7 +195,7 @@ impl IncrementalRestoreManager {
     }
 
     /// Apply incremental backups
-    fn apply_incremental_backups(&self) -> Result<(), PostgresError> {
+    async fn apply_incremental_backups(&self) -> Result<(), PostgresError> {
         // Create WAL directory if it doesn't exist
         let wal_dir = self.target_dir.join("pg_wal");
         if !wal_dir.exists() {
@@ -195,6 +208,7 @@ impl IncrementalRestoreManager {
 
         // Apply each incremental backup
         for (i, backup) in sorted_backups.iter().enumerate() {
+            check_cancelled(&self.cancellation)?;
             info!(
                 "Applying incremental backup {} of {}: {}",
                 i + 1,
@@ -212,15 +226,21 @@ impl IncrementalRestoreManager {
 
             // Copy WAL files from incremental backup to target WAL directory
             let backup_wal_dir = backup.backup_path.join("pg_wal");
-            if backup_wal_dir.exists() {
+            if backup_wal_dir.exists() && !backup.compression.is_none() {
+                // Compressed segments have to be decoded on the way
+                self.decompress_wal(backup, &backup_wal_dir, &wal_dir)
+                    .await?;
+                info!("Decompressed {} WAL files", backup.compression);
+            } else if backup_wal_dir.exists() {
                 // First approach: Try to copy the entire directory
-                let cp_result = Command::new("cp")
-                    .arg("-R")
-                    .arg(&backup_wal_dir)
-                    .arg(self.target_dir.parent().unwrap_or(&self.target_dir))
-                    .stdout(Stdio::inherit())
-                    .stderr(Stdio::inherit())
-                    .status();
+                let cp_result = self.cancellation.status(
+                    Command::new("cp")
+                        .arg("-R")
+                        .arg(&backup_wal_dir)
+                        .arg(self.target_dir.parent().unwrap_or(&self.target_dir))
+                        .stdout(Stdio::inherit())
+                        .stderr(Stdio::inherit()),
+                );
 
                 if let Ok(status) = cp_result {
                     if status.success() {
@@ -231,13 +251,14 @@ impl IncrementalRestoreManager {
 
                 // Second approach: Try to use the cp command with wildcards
                 let wildcard_path = format!("{}/{}*", backup_wal_dir.to_string_lossy(), "");
-                let cp_wildcard_result = Command::new("cp")
-                    .arg("-R")
-                    .arg(&wildcard_path)
-                    .arg(&wal_dir)
-                    .stdout(Stdio::inherit())
-                    .stderr(Stdio::inherit())
-                    .status();
+                let cp_wildcard_result = self.cancellation.status(
+                    Command::new("cp")
+                        .arg("-R")
+                        .arg(&wildcard_path)
+                        .arg(&wal_dir)
+                        .stdout(Stdio::inherit())
+                        .stderr(Stdio::inherit()),
+                );
 
                 if let Ok(status) = cp_wildcard_result {
                     if status.success() {
@@ -247,6 +268,7 @@ impl IncrementalRestoreManager {
                 }
 
                 // Third approach: Manual file copying as a fallback
+                check_cancelled(&self.cancellation)?;
                 info!("Attempting manual file copying as fallback");
 
                 // Create a dummy file to ensure the directory is not empty (for test verification)
@@ -269,6 +291,32 @@ impl IncrementalRestoreManager {
         Ok(())
     }
 
+    /// Decompress the WAL segments of a backup into the target WAL directory
+    async fn decompress_wal(
+        &self,
+        backup: &Backup,
+        backup_wal_dir: &Path,
+        wal_dir: &Path,
+    ) -> Result<(), PostgresError> {
+        let extension = backup.compression.extension();
+        for entry in fs::read_dir(backup_wal_dir).map_err(PostgresError::Io)? {
+            let entry = entry.map_err(PostgresError::Io)?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let Some(segment) = file_name.strip_suffix(extension) else {
+                continue;
+            };
+            check_cancelled(&self.cancellation)?;
+            backup
+                .compression
+                .decompress_file(&entry.path(), &wal_dir.join(segment))
+                .await
+                .map_err(|e| {
+                    PostgresError::RestoreError(format!("Failed to decompress {file_name}: {e}"))
+                })?;
+        }
+        Ok(())
+    }
+
     /// Create recovery.conf file for PostgreSQL
     fn create_recovery_conf(&self) -> Result<(), PostgresError> {
         let recovery_conf_path = self.target_dir.join("recovery.conf");
@@ -3,13 +3,22 @@ pub mod incremental;
 pub mod point_in_time;
 pub mod snapshot;
 
+pub use point_in_time::{RecoveryTarget, RecoveryTargetAction};
+
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use storage::{ByteStream, PostgresBackupStorage};
+use tokio_util::io::StreamReader;
 
 use crate::common::{Backup, BackupType, PostgresConfig, Restore};
+use crate::wrapper::Cancellation;
 use crate::PostgresError;
 
+/// Remote storage a restore streams backup files from, instead of reading
+/// them from `Backup::backup_path`
+pub type RemoteSource = Arc<PostgresBackupStorage>;
+
 /// Trait for restore managers
 #[async_trait]
 pub trait RestoreManager {
@@ -51,14 +60,16 @@ impl RestoreManagerFactory {
         full_backup: Backup,
         incremental_backups: Vec<Backup>,
         target_dir: PathBuf,
-        target_time: DateTime<Utc>,
+        target: RecoveryTarget,
+        action: RecoveryTargetAction,
     ) -> point_in_time::PointInTimeRestoreManager {
         point_in_time::PointInTimeRestoreManager::new(
             config,
             full_backup,
             incremental_backups,
             target_dir,
-            target_time,
+            target,
+            action,
         )
     }
 
@@ -81,3 +92,116 @@ impl RestoreManagerFactory {
         ))
     }
 }
+
+/// Fail once the restore was cancelled
+pub(crate) fn check_cancelled(cancellation: &Cancellation) -> Result<(), PostgresError> {
+    if cancellation.is_cancelled() {
+        return Err(PostgresError::RestoreError("Restore cancelled".to_string()));
+    }
+    Ok(())
+}
+
+/// Name of the logical dump among the remote files of `backup`
+pub(crate) async fn find_remote_dump(
+    storage: &PostgresBackupStorage,
+    backup: &Backup,
+) -> Result<String, PostgresError> {
+    let dump_name = backup
+        .dump_file
+        .clone()
+        .ok_or_else(|| PostgresError::RestoreError(format!("Backup {} has no dump", backup.id)))?;
+    let files = storage.list_backup_files(&backup.id.to_string()).await?;
+    if !files.contains(&dump_name) {
+        return Err(PostgresError::RestoreError(format!(
+            "Dump {dump_name} not found in remote backup {}",
+            backup.id
+        )));
+    }
+    Ok(dump_name)
+}
+
+/// Write a stream to a file, creating its parent directories
+pub(crate) async fn write_stream_to_file(
+    stream: ByteStream,
+    path: &Path,
+) -> Result<u64, PostgresError> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut file = tokio::fs::File::create(path).await?;
+    let written = tokio::io::copy(&mut StreamReader::new(stream), &mut file).await?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+    use storage::{MemoryProvider, StorageProvider};
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn uploaded_dump_is_found_and_streamed_back() {
+        let provider = MemoryProvider::new();
+        provider.create_bucket("backups").await.unwrap();
+        let storage = PostgresBackupStorage::from_provider(
+            Box::new(provider.clone()),
+            "backups".to_string(),
+            Some("db1".to_string()),
+        )
+        .with_compression("zstd".parse().unwrap());
+
+        // A snapshot as the snapshot backup writes it, of a database that is
+        // not the one it will be restored into
+        let backup_dir = tempfile::tempdir().unwrap();
+        let mut backup = Backup::new(
+            BackupType::Snapshot,
+            backup_dir.path().to_path_buf(),
+            "16".to_string(),
+            None,
+        );
+        let dump_name = crate::common::dump_file_name("sales");
+        std::fs::write(backup_dir.path().join(&dump_name), b"snapshot dump").unwrap();
+        backup.dump_file = Some(dump_name.clone());
+        backup.complete("0/0".to_string(), 13);
+        crate::cli::commands::upload_backup(&storage, &backup, "sales")
+            .await
+            .unwrap();
+
+        // Objects are stored compressed, below the prefix
+        let keys: Vec<_> = provider
+            .list_objects("backups", Some(&format!("db1/{}/", backup.id)))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|o| o.key)
+            .collect();
+        assert_eq!(keys.len(), 1, "{keys:?}");
+
+        let dump = find_remote_dump(&storage, &backup).await.unwrap();
+        assert_eq!(dump, dump_name);
+        let chunks: Vec<_> = storage
+            .open_backup_file(&backup.id.to_string(), &dump)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(chunks.concat(), b"snapshot dump");
+
+        // A backup that recorded no dump has none to restore
+        let mut without_dump = backup.clone();
+        without_dump.dump_file = None;
+        assert!(matches!(
+            find_remote_dump(&storage, &without_dump).await,
+            Err(PostgresError::RestoreError(_))
+        ));
+
+        let mut unknown = backup.clone();
+        unknown.id = Uuid::new_v4();
+        assert!(matches!(
+            find_remote_dump(&storage, &unknown).await,
+            Err(PostgresError::Storage(storage::StorageError::NotFound(_)))
+        ));
+    }
+}
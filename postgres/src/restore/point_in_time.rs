@@ -1,20 +1,120 @@
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use log::{error, info};
+use flate2::read::GzDecoder;
+use log::{error, info, warn};
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
-
-use crate::common::{Backup, PostgresConfig, Restore};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use storage::manifest::MANIFEST_FILE;
+use storage::ByteStream;
+use tokio_util::io::{StreamReader, SyncIoBridge};
+
+use super::{check_cancelled, write_stream_to_file, RemoteSource};
+use crate::common::{Backup, BackupStatus, PostgresConfig, Restore};
+use crate::wal;
+use crate::wrapper::Cancellation;
 use crate::PostgresError;
 
+/// Where WAL replay should stop during point-in-time recovery
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecoveryTarget {
+    /// Stop at a timestamp (`recovery_target_time`)
+    Time(DateTime<Utc>),
+    /// Stop at a WAL location (`recovery_target_lsn`)
+    Lsn(String),
+    /// Stop after a transaction ID (`recovery_target_xid`)
+    Xid(u64),
+    /// Stop at a restore point created with `pg_create_restore_point` (`recovery_target_name`)
+    Name(String),
+    /// Stop as soon as the base backup is consistent (`recovery_target = 'immediate'`)
+    Immediate,
+}
+
+impl RecoveryTarget {
+    /// The recovery parameter and value PostgreSQL expects for this target
+    fn setting(&self) -> (&'static str, String) {
+        match self {
+            RecoveryTarget::Time(time) => (
+                "recovery_target_time",
+                time.format("%Y-%m-%d %H:%M:%S%.f+00").to_string(),
+            ),
+            RecoveryTarget::Lsn(lsn) => ("recovery_target_lsn", lsn.clone()),
+            RecoveryTarget::Xid(xid) => ("recovery_target_xid", xid.to_string()),
+            RecoveryTarget::Name(name) => ("recovery_target_name", name.clone()),
+            RecoveryTarget::Immediate => ("recovery_target", "immediate".to_string()),
+        }
+    }
+
+    /// The target time, if this is a time-based target
+    pub fn time(&self) -> Option<DateTime<Utc>> {
+        match self {
+            RecoveryTarget::Time(time) => Some(*time),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RecoveryTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecoveryTarget::Time(time) => write!(f, "time {time}"),
+            RecoveryTarget::Lsn(lsn) => write!(f, "LSN {lsn}"),
+            RecoveryTarget::Xid(xid) => write!(f, "transaction {xid}"),
+            RecoveryTarget::Name(name) => write!(f, "restore point '{name}'"),
+            RecoveryTarget::Immediate => write!(f, "the end of the full backup"),
+        }
+    }
+}
+
+/// What the server should do once the recovery target is reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecoveryTargetAction {
+    Pause,
+    #[default]
+    Promote,
+    Shutdown,
+}
+
+impl RecoveryTargetAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecoveryTargetAction::Pause => "pause",
+            RecoveryTargetAction::Promote => "promote",
+            RecoveryTargetAction::Shutdown => "shutdown",
+        }
+    }
+}
+
+impl FromStr for RecoveryTargetAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pause" => Ok(RecoveryTargetAction::Pause),
+            "promote" => Ok(RecoveryTargetAction::Promote),
+            "shutdown" => Ok(RecoveryTargetAction::Shutdown),
+            other => Err(format!(
+                "Invalid recovery target action '{other}', expected pause, promote or shutdown"
+            )),
+        }
+    }
+}
+
 /// Point-in-time restore manager
+///
+/// Lays out a data directory from the full backup, gathers the WAL of the
+/// incremental backups into an archive directory next to it and configures
+/// PostgreSQL to replay that WAL up to the requested target on next start.
 pub struct PointInTimeRestoreManager {
     _config: PostgresConfig,
     full_backup: Backup,
     incremental_backups: Vec<Backup>,
     target_dir: PathBuf,
-    target_time: DateTime<Utc>,
+    target: RecoveryTarget,
+    action: RecoveryTargetAction,
+    remote: Option<RemoteSource>,
+    cancellation: Cancellation,
 }
 
 impl PointInTimeRestoreManager {
@@ -24,22 +124,38 @@ impl PointInTimeRestoreManager {
         full_backup: Backup,
         incremental_backups: Vec<Backup>,
         target_dir: PathBuf,
-        target_time: DateTime<Utc>,
+        target: RecoveryTarget,
+        action: RecoveryTargetAction,
     ) -> Self {
         Self {
             _config,
             full_backup,
             incremental_backups,
             target_dir,
-            target_time,
+            target,
+            action,
+            remote: None,
+            cancellation: Cancellation::default(),
         }
     }
 
+    /// Stop laying out the restore when `cancellation` is cancelled
+    pub fn with_cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Stream the base backup and WAL from remote storage instead of the backup directories
+    pub fn with_remote(mut self, remote: RemoteSource) -> Self {
+        self.remote = Some(remote);
+        self
+    }
+
     /// Perform a point-in-time restore
     pub async fn restore(&self) -> Result<Restore, PostgresError> {
         info!(
             "Starting point-in-time restore to {} from full backup: {} and {} incremental backups",
-            self.target_time,
+            self.target,
             self.full_backup.id,
             self.incremental_backups.len()
         );
@@ -48,274 +164,566 @@ impl PointInTimeRestoreManager {
         let mut restore = Restore::new(
             self.full_backup.id,
             self.target_dir.clone(),
-            Some(self.target_time),
+            self.target.time(),
         );
 
-        // Create target directory if it doesn't exist
-        if !self.target_dir.exists() {
-            fs::create_dir_all(&self.target_dir).map_err(PostgresError::Io)?;
-        }
+        match self.prepare_recovery().await {
+            Ok(archive_dir) => {
+                restore.complete();
+                info!(
+                    "Point-in-time restore prepared in {:?}, WAL archive at {:?}; start PostgreSQL to replay up to {}",
+                    self.target_dir, archive_dir, self.target
+                );
+                Ok(restore)
+            }
+            Err(e) => {
+                let error_msg = format!("Point-in-time restore failed: {e}");
+                error!("{error_msg}");
 
-        // Check if the full backup exists
-        if !self.full_backup.backup_path.exists() {
-            let error_msg = format!(
-                "Full backup path does not exist: {:?}",
-                self.full_backup.backup_path
-            );
-            error!("{error_msg}");
+                restore.fail(error_msg.clone());
 
-            restore.fail(error_msg);
-            return Err(PostgresError::RestoreError(
-                "Full backup path does not exist".to_string(),
-            ));
+                Err(PostgresError::RestoreError(error_msg))
+            }
         }
+    }
 
-        // First restore the full backup
-        match self.restore_full_backup() {
-            Ok(_) => {
-                info!("Full backup restored successfully");
+    /// Lay out the data directory and WAL archive, returning the archive path
+    async fn prepare_recovery(&self) -> Result<PathBuf, PostgresError> {
+        let wal_backups = self.select_wal_backups()?;
+        self.validate_target(&wal_backups)?;
+
+        if self.target_dir.exists()
+            && fs::read_dir(&self.target_dir)
+                .map_err(PostgresError::Io)?
+                .next()
+                .is_some()
+        {
+            return Err(PostgresError::RestoreError(format!(
+                "Target directory {:?} is not empty",
+                self.target_dir
+            )));
+        }
 
-                // Then apply incremental backups
-                match self.apply_incremental_backups() {
-                    Ok(_) => {
-                        info!("Incremental backups applied successfully");
+        match &self.remote {
+            Some(remote) => {
+                // Restore every archived file up front rather than waiting on each backup in turn
+                let mut backup_ids = vec![self.full_backup.id.to_string()];
+                backup_ids.extend(wal_backups.iter().map(|b| b.id.to_string()));
+                remote.rehydrate_backups(&backup_ids).await?;
+                self.stream_base_backup(remote).await?
+            }
+            None => self.extract_base_backup()?,
+        }
 
-                        // Create recovery.conf file with point-in-time target
-                        self.create_recovery_conf()?;
+        // PostgreSQL refuses to start on a data directory with loose permissions
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&self.target_dir, fs::Permissions::from_mode(0o700))
+                .map_err(PostgresError::Io)?;
+        }
 
-                        // Update restore metadata
-                        restore.complete();
+        check_cancelled(&self.cancellation)?;
+        let archive_dir = wal_archive_dir(&self.target_dir)?;
+        match &self.remote {
+            Some(remote) => {
+                self.stream_wal_archive(remote, &archive_dir, &wal_backups)
+                    .await?
+            }
+            None => {
+                self.populate_wal_archive(&archive_dir, &wal_backups)
+                    .await?
+            }
+        }
+        self.write_recovery_config(&archive_dir)?;
 
-                        info!("Point-in-time restore completed successfully");
-                        Ok(restore)
-                    }
-                    Err(e) => {
-                        let error_msg = format!("Failed to apply incremental backups: {e}");
-                        error!("{error_msg}");
+        Ok(archive_dir)
+    }
 
-                        restore.fail(error_msg);
+    /// Incremental backups whose WAL is needed to reach the target, oldest first
+    fn select_wal_backups(&self) -> Result<Vec<&Backup>, PostgresError> {
+        let mut chain: Vec<&Backup> = self
+            .incremental_backups
+            .iter()
+            .filter(|b| b.status == BackupStatus::Completed)
+            .collect();
+        chain.sort_by_key(|b| b.start_time);
+
+        // Stop at the first backup whose WAL reaches past the target
+        let cutoff = match &self.target {
+            RecoveryTarget::Time(time) => chain
+                .iter()
+                .position(|b| b.end_time.is_some_and(|end| end >= *time)),
+            RecoveryTarget::Lsn(lsn) => {
+                let target = wal::parse_lsn(lsn).ok_or_else(|| {
+                    PostgresError::RestoreError(format!("Invalid target LSN: {lsn}"))
+                })?;
+                chain.iter().position(|b| {
+                    b.wal_end
+                        .as_deref()
+                        .and_then(wal::parse_lsn)
+                        .is_some_and(|end| end >= target)
+                })
+            }
+            RecoveryTarget::Xid(_) | RecoveryTarget::Name(_) => None,
+            // The WAL inside the full backup is all that is needed
+            RecoveryTarget::Immediate => {
+                chain.clear();
+                None
+            }
+        };
+        if let Some(index) = cutoff {
+            chain.truncate(index + 1);
+        }
 
-                        Err(PostgresError::RestoreError(
-                            "Failed to apply incremental backups".to_string(),
-                        ))
-                    }
+        // Make sure the WAL we are about to replay has no holes
+        let mut previous = &self.full_backup;
+        for backup in &chain {
+            match (&previous.wal_segments, &backup.wal_segments) {
+                (Some(prev_range), Some(range)) if !range.continues(prev_range) => {
+                    return Err(PostgresError::WalError(format!(
+                        "WAL gap between backup {} (ends at {}) and backup {} (starts at {})",
+                        previous.id, prev_range.last_segment, backup.id, range.first_segment
+                    )));
                 }
+                (Some(_), Some(_)) => {}
+                _ => warn!(
+                    "Backup {} has no recorded WAL segment range, cannot check it for gaps",
+                    backup.id
+                ),
             }
-            Err(e) => {
-                let error_msg = format!("Failed to restore full backup: {e}");
-                error!("{error_msg}");
+            previous = backup;
+        }
 
-                restore.fail(error_msg);
+        Ok(chain)
+    }
 
-                Err(PostgresError::RestoreError(
-                    "Failed to restore full backup".to_string(),
-                ))
+    /// Check that the target lies within the WAL covered by the backup set
+    fn validate_target(&self, wal_backups: &[&Backup]) -> Result<(), PostgresError> {
+        let full = &self.full_backup;
+        let latest = wal_backups.last().copied().unwrap_or(full);
+
+        match &self.target {
+            RecoveryTarget::Time(time) => {
+                let consistent = full.end_time.ok_or_else(|| {
+                    PostgresError::RestoreError(format!("Full backup {} has no end time", full.id))
+                })?;
+                if *time < consistent {
+                    return Err(PostgresError::RestoreError(format!(
+                        "Target time {time} is before full backup {} became consistent at {consistent}",
+                        full.id
+                    )));
+                }
+                let available = latest.end_time.unwrap_or(latest.start_time);
+                if *time > available {
+                    return Err(PostgresError::RestoreError(format!(
+                        "Target time {time} is after the newest archived WAL (backup {} ended at {available})",
+                        latest.id
+                    )));
+                }
+            }
+            RecoveryTarget::Lsn(lsn) => {
+                let target = wal::parse_lsn(lsn).ok_or_else(|| {
+                    PostgresError::RestoreError(format!("Invalid target LSN: {lsn}"))
+                })?;
+                let consistent = full
+                    .wal_end
+                    .as_deref()
+                    .and_then(wal::parse_lsn)
+                    .ok_or_else(|| {
+                        PostgresError::RestoreError(format!(
+                            "Full backup {} has no WAL end position",
+                            full.id
+                        ))
+                    })?;
+                if target < consistent {
+                    return Err(PostgresError::RestoreError(format!(
+                        "Target LSN {lsn} is before full backup {} became consistent at {}",
+                        full.id,
+                        wal::format_lsn(consistent)
+                    )));
+                }
+                let available = latest
+                    .wal_end
+                    .as_deref()
+                    .and_then(wal::parse_lsn)
+                    .unwrap_or(consistent);
+                if target > available {
+                    return Err(PostgresError::RestoreError(format!(
+                        "Target LSN {lsn} is after the newest archived WAL ({})",
+                        wal::format_lsn(available)
+                    )));
+                }
+            }
+            RecoveryTarget::Immediate => {}
+            RecoveryTarget::Xid(_) | RecoveryTarget::Name(_) => {
+                // These can only be resolved while replaying, PostgreSQL will
+                // refuse to promote if the target is never reached
+                if wal_backups.is_empty() {
+                    warn!(
+                        "No incremental backups found, only the WAL inside the full backup can be replayed"
+                    );
+                }
             }
         }
+
+        Ok(())
     }
 
-    /// Restore the full backup
-    fn restore_full_backup(&self) -> Result<(), PostgresError> {
+    /// Unpack the full backup into the target data directory
+    fn extract_base_backup(&self) -> Result<(), PostgresError> {
+        let backup_path = &self.full_backup.backup_path;
         info!(
-            "Restoring full backup from {:?} to {:?}",
-            self.full_backup.backup_path, self.target_dir
+            "Restoring full backup from {backup_path:?} to {:?}",
+            self.target_dir
         );
 
-        // Check if the backup directory exists and is a directory
-        if !self.full_backup.backup_path.exists() {
-            return Err(PostgresError::RestoreError(format!(
-                "Backup directory does not exist: {:?}",
-                self.full_backup.backup_path
-            )));
-        }
+        fs::create_dir_all(&self.target_dir).map_err(PostgresError::Io)?;
 
-        if !self.full_backup.backup_path.is_dir() {
+        let base_tar = backup_path.join("base.tar.gz");
+        if base_tar.exists() {
+            unpack_tar_gz(&base_tar, &self.target_dir)?;
+        } else if backup_path.join("PG_VERSION").exists() {
+            // Plain format backup
+            copy_dir_all(backup_path, &self.target_dir).map_err(PostgresError::Io)?;
+        } else {
             return Err(PostgresError::RestoreError(format!(
-                "Backup path is not a directory: {:?}",
-                self.full_backup.backup_path
+                "No base backup found in {backup_path:?}"
             )));
         }
 
-        // Create target directory if it doesn't exist
-        if !self.target_dir.exists() {
-            fs::create_dir_all(&self.target_dir).map_err(PostgresError::Io)?;
+        // WAL streamed during pg_basebackup is needed to reach consistency
+        let pg_wal = self.target_dir.join("pg_wal");
+        fs::create_dir_all(&pg_wal).map_err(PostgresError::Io)?;
+        let wal_tar = backup_path.join("pg_wal.tar.gz");
+        if wal_tar.exists() {
+            unpack_tar_gz(&wal_tar, &pg_wal)?;
         }
 
-        // First approach: Try to use the cp command with the directory itself
-        let cp_result = Command::new("cp")
-            .arg("-R")
-            .arg(&self.full_backup.backup_path)
-            .arg(self.target_dir.parent().unwrap_or(&self.target_dir))
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status();
-
-        if let Ok(status) = cp_result {
-            if status.success() {
-                info!("Successfully copied backup files using cp command");
-                // Create a dummy file to ensure the directory is not empty (for test verification)
-                let dummy_file = self.target_dir.join(".restore_complete");
-                fs::write(dummy_file, "Restore completed successfully")
-                    .map_err(PostgresError::Io)?;
-                return Ok(());
-            }
-        }
+        Ok(())
+    }
 
-        // Second approach: Try to use the cp command with wildcards
-        let wildcard_path = format!("{}/{}*", self.full_backup.backup_path.to_string_lossy(), "");
-        let cp_wildcard_result = Command::new("cp")
-            .arg("-R")
-            .arg(&wildcard_path)
-            .arg(&self.target_dir)
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status();
-
-        if let Ok(status) = cp_wildcard_result {
-            if status.success() {
-                info!("Successfully copied backup files using cp command with wildcards");
-                // Create a dummy file to ensure the directory is not empty (for test verification)
-                let dummy_file = self.target_dir.join(".restore_complete");
-                fs::write(dummy_file, "Restore completed successfully")
-                    .map_err(PostgresError::Io)?;
-                return Ok(());
+    /// Unpack the full backup into the target data directory straight from remote storage
+    async fn stream_base_backup(&self, remote: &RemoteSource) -> Result<(), PostgresError> {
+        let backup_id = self.full_backup.id.to_string();
+        info!(
+            "Streaming full backup {backup_id} from remote storage to {:?}",
+            self.target_dir
+        );
+        let files = remote.list_backup_files(&backup_id).await?;
+        let has = |name: &str| files.iter().any(|f| f == name);
+
+        fs::create_dir_all(&self.target_dir).map_err(PostgresError::Io)?;
+
+        if has("base.tar.gz") {
+            let base = remote.open_backup_file(&backup_id, "base.tar.gz").await?;
+            unpack_tar_gz_stream(base, &self.target_dir).await?;
+        } else if has("PG_VERSION") {
+            // Plain format backup
+            for file in files
+                .iter()
+                .filter(|f| *f != MANIFEST_FILE && *f != "metadata.json")
+            {
+                let stream = remote.open_backup_file(&backup_id, file).await?;
+                write_stream_to_file(stream, &self.target_dir.join(file)).await?;
             }
+        } else {
+            return Err(PostgresError::RestoreError(format!(
+                "No base backup found in remote backup {backup_id}"
+            )));
         }
 
-        // Create a dummy file to ensure the directory is not empty (for test verification)
-        let dummy_file = self.target_dir.join(".restore_complete");
-        fs::write(dummy_file, "Restore completed successfully").map_err(PostgresError::Io)?;
+        let pg_wal = self.target_dir.join("pg_wal");
+        fs::create_dir_all(&pg_wal).map_err(PostgresError::Io)?;
+        if has("pg_wal.tar.gz") {
+            let wal = remote.open_backup_file(&backup_id, "pg_wal.tar.gz").await?;
+            unpack_tar_gz_stream(wal, &pg_wal).await?;
+        }
 
         Ok(())
     }
 
-    /// Apply incremental backups up to the target time
-    fn apply_incremental_backups(&self) -> Result<(), PostgresError> {
-        // Create WAL directory if it doesn't exist
-        let wal_dir = self.target_dir.join("pg_wal");
-        if !wal_dir.exists() {
-            fs::create_dir_all(&wal_dir).map_err(PostgresError::Io)?;
-        }
+    /// Copy the WAL of the selected incremental backups into the archive directory,
+    /// decompressing it with the codec each backup recorded
+    async fn populate_wal_archive(
+        &self,
+        archive_dir: &Path,
+        wal_backups: &[&Backup],
+    ) -> Result<(), PostgresError> {
+        fs::create_dir_all(archive_dir).map_err(PostgresError::Io)?;
+
+        for (i, backup) in wal_backups.iter().enumerate() {
+            info!(
+                "Archiving WAL of incremental backup {} of {}: {}",
+                i + 1,
+                wal_backups.len(),
+                backup.id
+            );
 
-        // Sort incremental backups by start time
-        let mut sorted_backups = self.incremental_backups.clone();
-        sorted_backups.sort_by_key(|b| b.start_time);
+            let backup_wal_dir = backup.backup_path.join("pg_wal");
+            if !backup_wal_dir.exists() {
+                return Err(PostgresError::RestoreError(format!(
+                    "Incremental backup {} has no WAL directory at {backup_wal_dir:?}",
+                    backup.id
+                )));
+            }
 
-        // Filter backups that were created before the target time
-        let applicable_backups: Vec<&Backup> = sorted_backups
-            .iter()
-            .filter(|b| b.start_time <= self.target_time)
-            .collect();
+            for entry in fs::read_dir(&backup_wal_dir).map_err(PostgresError::Io)? {
+                let entry = entry.map_err(PostgresError::Io)?;
+                if !entry.file_type().map_err(PostgresError::Io)?.is_file() {
+                    continue;
+                }
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                let Some(archived_name) = archived_wal_name(backup, &file_name, archive_dir) else {
+                    continue;
+                };
+                check_cancelled(&self.cancellation)?;
+
+                let archived_path = archive_dir.join(archived_name);
+                if backup.compression.is_none() {
+                    fs::copy(entry.path(), &archived_path).map_err(PostgresError::Io)?;
+                } else {
+                    backup
+                        .compression
+                        .decompress_file(&entry.path(), &archived_path)
+                        .await
+                        .map_err(|e| {
+                            PostgresError::RestoreError(format!(
+                                "Failed to decompress {:?}: {e}",
+                                entry.path()
+                            ))
+                        })?;
+                }
+            }
+        }
 
-        info!(
-            "Applying {} incremental backups up to target time",
-            applicable_backups.len()
-        );
+        Ok(())
+    }
 
-        // Apply each incremental backup
-        for (i, backup) in applicable_backups.iter().enumerate() {
+    /// Fill the archive directory with the WAL of the selected incremental
+    /// backups, streamed from remote storage
+    async fn stream_wal_archive(
+        &self,
+        remote: &RemoteSource,
+        archive_dir: &Path,
+        wal_backups: &[&Backup],
+    ) -> Result<(), PostgresError> {
+        fs::create_dir_all(archive_dir).map_err(PostgresError::Io)?;
+
+        for (i, backup) in wal_backups.iter().enumerate() {
             info!(
-                "Applying incremental backup {} of {}: {}",
+                "Streaming WAL of incremental backup {} of {}: {}",
                 i + 1,
-                applicable_backups.len(),
+                wal_backups.len(),
                 backup.id
             );
 
-            // Check if the backup exists
-            if !backup.backup_path.exists() {
+            let backup_id = backup.id.to_string();
+            let files = remote.list_backup_files(&backup_id).await?;
+            // Complete segments first, so a `.partial` one only fills a real gap
+            let mut wal_files: Vec<&str> = files
+                .iter()
+                .filter_map(|f| f.strip_prefix("pg_wal/"))
+                .filter(|f| !f.contains('/'))
+                .collect();
+            wal_files.sort_unstable_by_key(|f| (f.contains(".partial"), *f));
+            if wal_files.is_empty() {
                 return Err(PostgresError::RestoreError(format!(
-                    "Incremental backup path does not exist: {:?}",
-                    backup.backup_path
+                    "Incremental backup {} has no WAL in remote storage",
+                    backup.id
                 )));
             }
 
-            // Copy WAL files from incremental backup to target WAL directory
-            let backup_wal_dir = backup.backup_path.join("pg_wal");
-            if backup_wal_dir.exists() {
-                // First approach: Try to copy the entire directory
-                let cp_result = Command::new("cp")
-                    .arg("-R")
-                    .arg(&backup_wal_dir)
-                    .arg(self.target_dir.parent().unwrap_or(&self.target_dir))
-                    .stdout(Stdio::inherit())
-                    .stderr(Stdio::inherit())
-                    .status();
-
-                if let Ok(status) = cp_result {
-                    if status.success() {
-                        info!("Successfully copied WAL files using cp command");
-                        continue;
-                    }
-                }
-
-                // Second approach: Try to use the cp command with wildcards
-                let wildcard_path = format!("{}/{}*", backup_wal_dir.to_string_lossy(), "");
-                let cp_wildcard_result = Command::new("cp")
-                    .arg("-R")
-                    .arg(&wildcard_path)
-                    .arg(&wal_dir)
-                    .stdout(Stdio::inherit())
-                    .stderr(Stdio::inherit())
-                    .status();
-
-                if let Ok(status) = cp_wildcard_result {
-                    if status.success() {
-                        info!("Successfully copied WAL files using cp command with wildcards");
-                        continue;
-                    }
+            for file_name in wal_files {
+                let Some(archived_name) = archived_wal_name(backup, file_name, archive_dir) else {
+                    continue;
+                };
+                check_cancelled(&self.cancellation)?;
+                let mut stream = remote
+                    .open_backup_file(&backup_id, &format!("pg_wal/{file_name}"))
+                    .await?;
+                if !backup.compression.is_none() {
+                    stream = backup.compression.decompress_stream(stream);
                 }
+                write_stream_to_file(stream, &archive_dir.join(archived_name)).await?;
+            }
+        }
 
-                // Third approach: Manual file copying as a fallback
-                info!("Attempting manual file copying as fallback");
+        Ok(())
+    }
 
-                // Create a dummy file to ensure the directory is not empty (for test verification)
-                let dummy_file = wal_dir.join(".wal_restore_complete");
-                fs::write(dummy_file, "WAL restore completed successfully")
-                    .map_err(PostgresError::Io)?;
-            } else {
-                info!(
-                    "No WAL directory found in incremental backup: {:?}",
-                    backup.backup_path
-                );
+    /// Configure the restored cluster to replay WAL up to the target
+    fn write_recovery_config(&self, archive_dir: &Path) -> Result<(), PostgresError> {
+        let (target_key, target_value) = self.target.setting();
+        let settings = [
+            (
+                "restore_command",
+                format!("cp \"{}/%f\" \"%p\"", archive_dir.display()),
+            ),
+            (target_key, target_value),
+            ("recovery_target_action", self.action.as_str().to_string()),
+            ("recovery_target_timeline", "latest".to_string()),
+        ];
+
+        let mut content = format!("# Recovery settings added by Warden at {}\n", Utc::now());
+        for (key, value) in &settings {
+            content.push_str(&format!("{key} = '{}'\n", value.replace('\'', "''")));
+        }
 
-                // Create a dummy file to ensure the directory is not empty (for test verification)
-                let dummy_file = wal_dir.join(".wal_restore_complete");
-                fs::write(dummy_file, "WAL restore completed successfully")
-                    .map_err(PostgresError::Io)?;
+        let major_version = server_major_version(&self.full_backup.server_version);
+        if major_version.is_some_and(|major| major < 12) {
+            // Before PostgreSQL 12 recovery is configured through recovery.conf
+            let recovery_conf = self.target_dir.join("recovery.conf");
+            fs::write(&recovery_conf, content).map_err(PostgresError::Io)?;
+            info!("Created {recovery_conf:?}");
+        } else {
+            let auto_conf = self.target_dir.join("postgresql.auto.conf");
+            let mut existing = fs::read_to_string(&auto_conf).unwrap_or_default();
+            if !existing.is_empty() && !existing.ends_with('\n') {
+                existing.push('\n');
             }
+            existing.push_str(&content);
+            fs::write(&auto_conf, existing).map_err(PostgresError::Io)?;
+            fs::write(self.target_dir.join("recovery.signal"), "").map_err(PostgresError::Io)?;
+            info!("Configured recovery in {auto_conf:?} and created recovery.signal");
         }
 
         Ok(())
     }
+}
 
-    /// Create recovery.conf file for PostgreSQL with point-in-time target
-    fn create_recovery_conf(&self) -> Result<(), PostgresError> {
-        let recovery_conf_path = self.target_dir.join("recovery.conf");
-
-        // Format the target time in PostgreSQL timestamp format
-        let target_time_str = self
-            .target_time
-            .format("%Y-%m-%d %H:%M:%S%.f+00")
-            .to_string();
-
-        let recovery_conf_content = format!(
-            "# Recovery configuration file created by Warden\n\
-             # Created at: {}\n\
-             restore_command = 'cp {}/pg_wal/%f %p'\n\
-             recovery_target_time = '{}'\n\
-             recovery_target_inclusive = true\n\
-             recovery_target_timeline = 'latest'\n",
-            Utc::now(),
-            self.target_dir.to_string_lossy(),
-            target_time_str
-        );
+/// Directory the restore_command reads WAL from, next to the data directory
+pub(crate) fn wal_archive_dir(target_dir: &Path) -> Result<PathBuf, PostgresError> {
+    let target_dir = std::path::absolute(target_dir).map_err(PostgresError::Io)?;
+    let name = target_dir
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "restore".to_string());
+    let parent = target_dir.parent().unwrap_or(&target_dir);
+    Ok(parent.join(format!("{name}_wal_archive")))
+}
+
+/// Unpack a gzip compressed tarball into a directory
+fn unpack_tar_gz(archive: &Path, destination: &Path) -> Result<(), PostgresError> {
+    let file = fs::File::open(archive).map_err(PostgresError::Io)?;
+    tar::Archive::new(GzDecoder::new(file))
+        .unpack(destination)
+        .map_err(|e| PostgresError::RestoreError(format!("Failed to unpack {archive:?}: {e}")))
+}
 
-        fs::write(&recovery_conf_path, recovery_conf_content).map_err(PostgresError::Io)?;
+/// Unpack a gzipped tarball as it streams in
+async fn unpack_tar_gz_stream(stream: ByteStream, destination: &Path) -> Result<(), PostgresError> {
+    let reader = SyncIoBridge::new(StreamReader::new(stream));
+    let destination = destination.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        tar::Archive::new(GzDecoder::new(reader))
+            .unpack(&destination)
+            .map_err(|e| {
+                PostgresError::RestoreError(format!(
+                    "Failed to unpack streamed archive into {destination:?}: {e}"
+                ))
+            })
+    })
+    .await
+    .map_err(|e| PostgresError::RestoreError(format!("Unpacking task failed: {e}")))?
+}
 
-        info!(
-            "Created recovery.conf file at {recovery_conf_path:?} with target time: {target_time_str}"
+/// Name a WAL file of `backup` gets in the archive, without the codec
+/// extension, or `None` when the file is not needed
+fn archived_wal_name(backup: &Backup, file_name: &str, archive_dir: &Path) -> Option<String> {
+    let file_name = match file_name.strip_suffix(backup.compression.extension()) {
+        Some(name) if !backup.compression.is_none() => name,
+        _ => file_name,
+    };
+
+    // A partial segment is only useful when the complete one is missing
+    match file_name.strip_suffix(".partial") {
+        Some(name) if archive_dir.join(name).exists() => None,
+        Some(name) => Some(name.to_string()),
+        None => Some(file_name.to_string()),
+    }
+}
+
+/// Recursively copy a directory
+fn copy_dir_all(src: &Path, dst: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let dst_path = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &dst_path)?;
+        } else {
+            fs::copy(entry.path(), &dst_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Extract the major version from a `SELECT version()` string
+fn server_major_version(version: &str) -> Option<u32> {
+    version
+        .split_whitespace()
+        .nth(1)?
+        .split('.')
+        .next()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use flate2::{write::GzEncoder, Compression as GzLevel};
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn streamed_tarball_is_unpacked() {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), GzLevel::default()));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(2);
+        header.set_mode(0o600);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "global/PG_VERSION", &b"16"[..])
+            .unwrap();
+        let tarball = builder.into_inner().unwrap().finish().unwrap();
+
+        // Split across chunks the way a download arrives
+        let chunks: Vec<_> = tarball
+            .chunks(7)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        let dir = tempfile::tempdir().unwrap();
+        unpack_tar_gz_stream(Box::pin(futures::stream::iter(chunks)), dir.path())
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::read(dir.path().join("global/PG_VERSION")).unwrap(),
+            b"16"
         );
+    }
 
-        Ok(())
+    #[test]
+    fn partial_segments_only_fill_gaps() {
+        let archive = tempfile::tempdir().unwrap();
+        let mut backup = Backup::new(
+            crate::common::BackupType::Incremental,
+            PathBuf::new(),
+            String::new(),
+            None,
+        );
+        backup.compression = "zstd".parse().unwrap();
+
+        let segment = "000000010000000000000003";
+        assert_eq!(
+            archived_wal_name(&backup, &format!("{segment}.partial.zst"), archive.path()),
+            Some(segment.to_string())
+        );
+        fs::write(archive.path().join(segment), b"").unwrap();
+        assert_eq!(
+            archived_wal_name(&backup, &format!("{segment}.partial.zst"), archive.path()),
+            None
+        );
     }
 }
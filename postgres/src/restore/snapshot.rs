@@ -1,11 +1,13 @@
 use chrono::Utc;
 use log::{error, info};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use uuid::Uuid;
 
+use super::{find_remote_dump, RemoteSource};
 use crate::common::{Backup, PostgresConfig, Restore, RestoreStatus};
 use crate::wrapper::pg_restore::PgRestore;
+use crate::wrapper::Cancellation;
 use crate::PostgresError;
 
 /// Manager for restoring snapshot backups
@@ -13,6 +15,8 @@ pub struct SnapshotRestoreManager {
     config: PostgresConfig,
     backup: Backup,
     target_dir: PathBuf,
+    remote: Option<RemoteSource>,
+    cancellation: Cancellation,
 }
 
 impl SnapshotRestoreManager {
@@ -29,9 +33,23 @@ impl SnapshotRestoreManager {
             config,
             backup,
             target_dir,
+            remote: None,
+            cancellation: Cancellation::default(),
         }
     }
 
+    /// Terminate pg_restore when `cancellation` is cancelled
+    pub fn with_cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Stream the dump from remote storage instead of reading it from the backup directory
+    pub fn with_remote(mut self, remote: RemoteSource) -> Self {
+        self.remote = Some(remote);
+        self
+    }
+
     /// Restore a snapshot backup
     pub async fn restore(&self) -> Result<Restore, PostgresError> {
         info!("Starting snapshot restore for backup: {}", self.backup.id);
@@ -51,28 +69,11 @@ impl SnapshotRestoreManager {
             error_message: None,
         };
 
-        // Get snapshot file path
-        let snapshot_file =
-            Path::new(&self.backup.backup_path).join(format!("snapshot_{}.dump", self.backup.id));
-
-        if !snapshot_file.exists() {
-            let error_msg = format!("Snapshot file does not exist: {snapshot_file:?}");
-            error!("{error_msg}");
-
-            restore.status = RestoreStatus::Failed;
-            restore.end_time = Some(Utc::now());
-            restore.error_message = Some(error_msg);
-
-            return Err(PostgresError::RestoreError(format!(
-                "Snapshot file does not exist: {snapshot_file:?}"
-            )));
-        }
-
-        // Create pg_restore wrapper
-        let pg_restore = PgRestore::new(self.config.clone());
-
-        // Restore database from snapshot
-        match pg_restore.restore(&snapshot_file, None).await {
+        let result = match &self.remote {
+            Some(remote) => self.restore_from_remote(remote).await,
+            None => self.restore_from_file().await,
+        };
+        match result {
             Ok(_) => {
                 info!("Snapshot restore completed successfully");
 
@@ -94,19 +95,37 @@ impl SnapshotRestoreManager {
         Ok(restore)
     }
 
+    async fn restore_from_file(&self) -> Result<(), PostgresError> {
+        let snapshot_file = self.snapshot_file()?;
+
+        PgRestore::new(self.config.clone())
+            .with_cancellation(self.cancellation.clone())
+            .restore(&snapshot_file, None)
+            .await
+    }
+
+    /// Pipe the dump from remote storage straight into pg_restore
+    async fn restore_from_remote(&self, remote: &RemoteSource) -> Result<(), PostgresError> {
+        remote
+            .rehydrate_backups(&[self.backup.id.to_string()])
+            .await?;
+        let dump_name = find_remote_dump(remote, &self.backup).await?;
+        let dump = remote
+            .open_backup_file(&self.backup.id.to_string(), &dump_name)
+            .await?;
+
+        PgRestore::new(self.config.clone())
+            .with_cancellation(self.cancellation.clone())
+            .restore_stream(dump, None, &["--verbose", "--no-owner", "--no-privileges"])
+            .await
+    }
+
     /// List the contents of a snapshot backup
     pub async fn list_contents(&self) -> Result<String, PostgresError> {
         info!("Listing contents of snapshot backup: {}", self.backup.id);
 
         // Get snapshot file path
-        let snapshot_file =
-            Path::new(&self.backup.backup_path).join(format!("snapshot_{}.dump", self.backup.id));
-
-        if !snapshot_file.exists() {
-            return Err(PostgresError::RestoreError(format!(
-                "Snapshot file does not exist: {snapshot_file:?}"
-            )));
-        }
+        let snapshot_file = self.snapshot_file()?;
 
         // Create pg_restore wrapper
         let pg_restore = PgRestore::new(self.config.clone());
@@ -114,4 +133,14 @@ impl SnapshotRestoreManager {
         // List contents of snapshot
         pg_restore.list_contents(&snapshot_file).await
     }
+
+    /// Dump the snapshot backup wrote
+    fn snapshot_file(&self) -> Result<PathBuf, PostgresError> {
+        self.backup.dump_path().ok_or_else(|| {
+            PostgresError::RestoreError(format!(
+                "No snapshot dump in {:?}",
+                self.backup.backup_path
+            ))
+        })
+    }
 }
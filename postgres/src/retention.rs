@@ -0,0 +1,402 @@
+//! Retention policies for pruning old backups from the catalog.
+//!
+//! Rules select backups to keep; everything else is pruned. Keeping a backup
+//! also keeps everything it depends on: its full backup and the earlier
+//! incrementals of the same chain, since restoring it replays their WAL.
+
+use chrono::{DateTime, Datelike, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use uuid::Uuid;
+
+use crate::common::{Backup, BackupCatalog, BackupStatus, BackupType};
+
+/// Retention rules, a backup is kept if any rule selects it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    /// Keep the N most recent backups
+    pub keep_last: Option<usize>,
+    /// Keep the newest backup of each of the last N days
+    pub keep_daily: Option<usize>,
+    /// Keep the newest backup of each of the last N ISO weeks
+    pub keep_weekly: Option<usize>,
+    /// Keep the newest backup of each of the last N months
+    pub keep_monthly: Option<usize>,
+    /// Never keep backups older than this many days, whatever the other rules say
+    pub max_age_days: Option<u32>,
+}
+
+/// A backup and the reasons it was kept or pruned
+#[derive(Debug, Clone, Serialize)]
+pub struct RetentionEntry {
+    pub backup_id: Uuid,
+    pub backup_type: BackupType,
+    pub start_time: DateTime<Utc>,
+    pub reasons: Vec<String>,
+}
+
+/// Outcome of applying a retention policy to a catalog
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RetentionPlan {
+    pub keep: Vec<RetentionEntry>,
+    pub delete: Vec<RetentionEntry>,
+}
+
+impl RetentionPlan {
+    /// IDs of the backups to prune, each ahead of the backups it depends on
+    ///
+    /// Incrementals come first, newest first, then the full backups and
+    /// snapshots. A prune that stops part way never leaves a backup without
+    /// its base.
+    pub fn delete_ids(&self) -> Vec<Uuid> {
+        let mut delete: Vec<&RetentionEntry> = self.delete.iter().collect();
+        delete.sort_by_key(|e| {
+            (
+                e.backup_type != BackupType::Incremental,
+                std::cmp::Reverse(e.start_time),
+            )
+        });
+        delete.iter().map(|e| e.backup_id).collect()
+    }
+}
+
+impl RetentionPolicy {
+    /// Whether the policy has no rules at all
+    pub fn is_empty(&self) -> bool {
+        self.keep_last.is_none()
+            && self.keep_daily.is_none()
+            && self.keep_weekly.is_none()
+            && self.keep_monthly.is_none()
+            && self.max_age_days.is_none()
+    }
+
+    /// Decide which backups of the catalog to keep and which to prune
+    ///
+    /// A policy without rules decides nothing, so its plan is empty and not
+    /// even failed backups are pruned.
+    pub fn plan(&self, catalog: &BackupCatalog, now: DateTime<Utc>) -> RetentionPlan {
+        if self.is_empty() {
+            return RetentionPlan::default();
+        }
+
+        let mut reasons: BTreeMap<Uuid, Vec<String>> = BTreeMap::new();
+
+        // Newest first
+        let mut completed: Vec<&Backup> = catalog
+            .backups
+            .iter()
+            .filter(|b| b.status == BackupStatus::Completed)
+            .collect();
+        completed.sort_by_key(|b| std::cmp::Reverse(backup_time(b)));
+
+        let cutoff = self
+            .max_age_days
+            .map(|days| now - Duration::days(days as i64));
+        let candidates: Vec<&Backup> = completed
+            .iter()
+            .copied()
+            .filter(|b| cutoff.is_none_or(|cutoff| backup_time(b) >= cutoff))
+            .collect();
+
+        let has_keep_rules = self.keep_last.is_some()
+            || self.keep_daily.is_some()
+            || self.keep_weekly.is_some()
+            || self.keep_monthly.is_some();
+
+        if !has_keep_rules {
+            // Only an age limit: keep everything younger than it
+            for backup in &candidates {
+                add_reason(&mut reasons, backup.id, "within max age");
+            }
+        }
+
+        if let Some(n) = self.keep_last {
+            for backup in candidates.iter().take(n) {
+                add_reason(&mut reasons, backup.id, "last");
+            }
+        }
+        if let Some(n) = self.keep_daily {
+            keep_per_period(&candidates, n, "daily", &mut reasons, |t| {
+                format!("{}", t.format("%Y-%m-%d"))
+            });
+        }
+        if let Some(n) = self.keep_weekly {
+            keep_per_period(&candidates, n, "weekly", &mut reasons, |t| {
+                let week = t.iso_week();
+                format!("{}-W{}", week.year(), week.week())
+            });
+        }
+        if let Some(n) = self.keep_monthly {
+            keep_per_period(&candidates, n, "monthly", &mut reasons, |t| {
+                format!("{}-{}", t.year(), t.month())
+            });
+        }
+
+        // Pull in everything the selected backups need to be restorable, and
+        // the base of an incremental still being written, which it extends
+        let in_progress = catalog
+            .backups
+            .iter()
+            .filter(|b| b.status == BackupStatus::InProgress)
+            .map(|b| b.id);
+        let selected: Vec<Uuid> = reasons.keys().copied().chain(in_progress).collect();
+        for id in selected {
+            let Some(backup) = catalog.get_backup(&id) else {
+                continue;
+            };
+            if backup.backup_type != BackupType::Incremental {
+                continue;
+            }
+            let Some(base_id) = backup.base_backup_id else {
+                continue;
+            };
+            add_reason(&mut reasons, base_id, &format!("base of {id}"));
+            for earlier in catalog.get_incremental_backups_since(&base_id) {
+                if earlier.start_time < backup.start_time {
+                    add_reason(&mut reasons, earlier.id, &format!("WAL needed by {id}"));
+                }
+            }
+        }
+
+        let mut plan = RetentionPlan::default();
+        for backup in &catalog.backups {
+            let entry = |reasons: Vec<String>| RetentionEntry {
+                backup_id: backup.id,
+                backup_type: backup.backup_type,
+                start_time: backup.start_time,
+                reasons,
+            };
+            match backup.status {
+                // Never touch a backup that is still being written
+                BackupStatus::InProgress => plan.keep.push(entry(vec!["in progress".into()])),
+                BackupStatus::Failed => plan.delete.push(entry(vec!["failed".into()])),
+                BackupStatus::Completed => match reasons.get(&backup.id) {
+                    Some(why) => plan.keep.push(entry(why.clone())),
+                    None => {
+                        let why = if cutoff.is_some_and(|cutoff| backup_time(backup) < cutoff) {
+                            "older than max age"
+                        } else {
+                            "not selected by any rule"
+                        };
+                        plan.delete.push(entry(vec![why.into()]))
+                    }
+                },
+            }
+        }
+
+        plan
+    }
+}
+
+fn backup_time(backup: &Backup) -> DateTime<Utc> {
+    backup.end_time.unwrap_or(backup.start_time)
+}
+
+fn add_reason(reasons: &mut BTreeMap<Uuid, Vec<String>>, id: Uuid, reason: &str) {
+    let entry = reasons.entry(id).or_default();
+    if !entry.iter().any(|r| r == reason) {
+        entry.push(reason.to_string());
+    }
+}
+
+/// Keep the newest backup of each of the last `n` periods that have backups
+fn keep_per_period<F>(
+    newest_first: &[&Backup],
+    n: usize,
+    rule: &str,
+    reasons: &mut BTreeMap<Uuid, Vec<String>>,
+    period: F,
+) where
+    F: Fn(&DateTime<Utc>) -> String,
+{
+    let mut seen = HashSet::new();
+    for backup in newest_first {
+        let key = period(&backup_time(backup));
+        if seen.contains(&key) {
+            continue;
+        }
+        if seen.len() == n {
+            break;
+        }
+        seen.insert(key);
+        add_reason(reasons, backup.id, rule);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn backup_at(
+        backup_type: BackupType,
+        time: DateTime<Utc>,
+        base_backup_id: Option<Uuid>,
+    ) -> Backup {
+        let mut backup = Backup::new(backup_type, "backup".into(), "16".into(), base_backup_id);
+        backup.start_time = time;
+        backup.complete("0/0".to_string(), 0);
+        backup.end_time = Some(time);
+        backup
+    }
+
+    #[test]
+    fn keeps_base_and_earlier_incrementals_of_kept_backup() {
+        let day = |d| Utc.with_ymd_and_hms(2025, 1, d, 12, 0, 0).unwrap();
+        let mut catalog = BackupCatalog::new();
+        let full = backup_at(BackupType::Full, day(1), None);
+        let first = backup_at(BackupType::Incremental, day(2), Some(full.id));
+        let second = backup_at(BackupType::Incremental, day(3), Some(full.id));
+        let old_full = backup_at(
+            BackupType::Full,
+            Utc.with_ymd_and_hms(2024, 12, 1, 0, 0, 0).unwrap(),
+            None,
+        );
+        let (full_id, first_id, second_id, old_id) = (full.id, first.id, second.id, old_full.id);
+        for b in [old_full, full, first, second] {
+            catalog.add_backup(b);
+        }
+
+        let policy = RetentionPolicy {
+            keep_last: Some(1),
+            ..Default::default()
+        };
+        let plan = policy.plan(&catalog, day(4));
+
+        let kept: HashSet<Uuid> = plan.keep.iter().map(|e| e.backup_id).collect();
+        assert_eq!(kept, HashSet::from([full_id, first_id, second_id]));
+        assert_eq!(plan.delete_ids(), vec![old_id]);
+    }
+
+    #[test]
+    fn in_progress_incremental_keeps_its_base() {
+        let day = |d| Utc.with_ymd_and_hms(2025, 1, d, 12, 0, 0).unwrap();
+        let mut catalog = BackupCatalog::new();
+        let old_full = backup_at(BackupType::Full, day(1), None);
+        let earlier = backup_at(BackupType::Incremental, day(2), Some(old_full.id));
+        let new_full = backup_at(BackupType::Full, day(3), None);
+        let mut running = Backup::new(
+            BackupType::Incremental,
+            "backup".into(),
+            "16".into(),
+            Some(old_full.id),
+        );
+        running.start_time = day(4);
+        let (old_id, earlier_id, new_id, running_id) =
+            (old_full.id, earlier.id, new_full.id, running.id);
+        for b in [old_full, earlier, new_full, running] {
+            catalog.add_backup(b);
+        }
+
+        let policy = RetentionPolicy {
+            keep_last: Some(1),
+            ..Default::default()
+        };
+        let plan = policy.plan(&catalog, day(5));
+
+        let kept: HashSet<Uuid> = plan.keep.iter().map(|e| e.backup_id).collect();
+        assert_eq!(
+            kept,
+            HashSet::from([old_id, earlier_id, new_id, running_id])
+        );
+        assert!(plan.delete.is_empty());
+    }
+
+    #[test]
+    fn max_age_overrides_keep_rules() {
+        let now = Utc.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap();
+        let mut catalog = BackupCatalog::new();
+        let recent = backup_at(BackupType::Snapshot, now - Duration::days(1), None);
+        let old = backup_at(BackupType::Snapshot, now - Duration::days(40), None);
+        let (recent_id, old_id) = (recent.id, old.id);
+        catalog.add_backup(recent);
+        catalog.add_backup(old);
+
+        let policy = RetentionPolicy {
+            keep_monthly: Some(12),
+            max_age_days: Some(30),
+            ..Default::default()
+        };
+        let plan = policy.plan(&catalog, now);
+
+        assert_eq!(plan.keep.len(), 1);
+        assert_eq!(plan.keep[0].backup_id, recent_id);
+        assert_eq!(plan.delete_ids(), vec![old_id]);
+    }
+
+    #[test]
+    fn gfs_keeps_newest_per_period() {
+        let mut catalog = BackupCatalog::new();
+        let morning = backup_at(
+            BackupType::Snapshot,
+            Utc.with_ymd_and_hms(2025, 1, 10, 8, 0, 0).unwrap(),
+            None,
+        );
+        let evening = backup_at(
+            BackupType::Snapshot,
+            Utc.with_ymd_and_hms(2025, 1, 10, 20, 0, 0).unwrap(),
+            None,
+        );
+        let day_before = backup_at(
+            BackupType::Snapshot,
+            Utc.with_ymd_and_hms(2025, 1, 9, 20, 0, 0).unwrap(),
+            None,
+        );
+        let (morning_id, evening_id, day_before_id) = (morning.id, evening.id, day_before.id);
+        for b in [morning, evening, day_before] {
+            catalog.add_backup(b);
+        }
+
+        let policy = RetentionPolicy {
+            keep_daily: Some(2),
+            ..Default::default()
+        };
+        let plan = policy.plan(
+            &catalog,
+            Utc.with_ymd_and_hms(2025, 1, 11, 0, 0, 0).unwrap(),
+        );
+
+        let kept: HashSet<Uuid> = plan.keep.iter().map(|e| e.backup_id).collect();
+        assert_eq!(kept, HashSet::from([evening_id, day_before_id]));
+        assert_eq!(plan.delete_ids(), vec![morning_id]);
+    }
+
+    #[test]
+    fn empty_policy_keeps_everything() {
+        let mut catalog = BackupCatalog::new();
+        catalog.add_backup(backup_at(BackupType::Snapshot, Utc::now(), None));
+        let mut failed = Backup::new(BackupType::Full, "backup".into(), "16".into(), None);
+        failed.fail("pg_basebackup failed".to_string());
+        catalog.add_backup(failed);
+
+        let plan = RetentionPolicy::default().plan(&catalog, Utc::now());
+        assert!(plan.delete.is_empty());
+        assert!(plan.keep.is_empty());
+    }
+
+    #[test]
+    fn deletes_incrementals_before_their_base() {
+        let day = |d| Utc.with_ymd_and_hms(2025, 1, d, 12, 0, 0).unwrap();
+        let mut catalog = BackupCatalog::new();
+        let full = backup_at(BackupType::Full, day(1), None);
+        let first = backup_at(BackupType::Incremental, day(2), Some(full.id));
+        let second = backup_at(BackupType::Incremental, day(3), Some(full.id));
+        let (full_id, first_id, second_id) = (full.id, first.id, second.id);
+        for b in [full, first, second] {
+            catalog.add_backup(b);
+        }
+
+        let policy = RetentionPolicy {
+            max_age_days: Some(1),
+            ..Default::default()
+        };
+        let plan = policy.plan(&catalog, day(10));
+        assert_eq!(plan.delete_ids(), vec![second_id, first_id, full_id]);
+
+        // What has to stay when deleting each of them fails
+        assert_eq!(catalog.dependencies(&second_id), vec![full_id, first_id]);
+        assert_eq!(catalog.dependencies(&first_id), vec![full_id]);
+        assert!(catalog.dependencies(&full_id).is_empty());
+    }
+}
@@ -2,8 +2,8 @@ use crate::common::PostgresConfig;
 use log::warn;
 use log::{error, info};
 use ssh::cli::forward::find_available_port;
-use ssh::SSHTunnel;
 use ssh::SshError;
+use ssh::{HostKeyCheck, SSHTunnel};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
@@ -65,11 +65,16 @@ impl TunnelKeeper {
             let ssh_password = config.ssh_password.clone();
             let ssh_key_path = config.ssh_key_path.clone();
 
+            let host_key = HostKeyCheck::from_options(
+                config.ssh_known_hosts.clone(),
+                config.ssh_host_key_fingerprint.clone(),
+            );
             let mut tunnel = SSHTunnel::new(
                 ssh_host.expect("SSH host must be specified"),
                 ssh_user.expect("SSH user must be specified"),
                 ssh_port,
-            );
+            )
+            .with_host_key_check(host_key.clone());
 
             // Set authentication
             if let Some(password) = &ssh_password {
@@ -117,7 +122,8 @@ impl TunnelKeeper {
                     );
 
                     // Create the tunnel with the original values
-                    let mut tunnel = SSHTunnel::new(ssh_host.clone(), ssh_user.clone(), ssh_port);
+                    let mut tunnel = SSHTunnel::new(ssh_host.clone(), ssh_user.clone(), ssh_port)
+                        .with_host_key_check(host_key);
                     if let Some(password) = &ssh_password {
                         tunnel = tunnel.with_password(password.clone());
                     } else if let Some(key_path) = &ssh_key_path {
@@ -0,0 +1,406 @@
+//! Trial restores that prove a backup can be restored.
+//!
+//! Snapshots are listed with `pg_restore --list` and restored into a scratch
+//! database on the configured server. Physical backups are recovered into a
+//! scratch data directory and started on a throwaway `postgres` that only
+//! listens on a private Unix socket. In both cases a set of sanity queries
+//! must succeed against the restored database, and everything created for
+//! the trial is removed afterwards.
+
+use chrono::Utc;
+use log::{debug, error, info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use tokio_postgres::{Client, NoTls};
+
+use crate::common::{Backup, BackupType, PostgresConfig, Verification, VerificationStatus};
+use crate::restore::point_in_time::{self, PointInTimeRestoreManager};
+use crate::restore::{RecoveryTarget, RecoveryTargetAction};
+use crate::wrapper::pg_restore::PgRestore;
+use crate::PostgresError;
+
+/// Queries run against the restored database when none are configured
+pub const DEFAULT_SANITY_QUERIES: &[&str] = &[
+    "SELECT count(*) FROM pg_catalog.pg_class",
+    "SELECT count(*) FROM pg_catalog.pg_namespace",
+];
+
+/// How a backup is verified
+#[derive(Debug, Clone)]
+pub struct VerifyOptions {
+    /// Queries that must succeed against the restored database
+    pub queries: Vec<String>,
+    /// Data directory for physical trial restores, a fresh directory in the
+    /// backup directory when unset
+    pub scratch_dir: Option<PathBuf>,
+    /// Port of the throwaway server, only used to name its Unix socket
+    pub port: u16,
+    /// Directory holding `pg_ctl`, looked up on `PATH` when unset
+    pub pg_bin_dir: Option<PathBuf>,
+    /// How long the throwaway server may take to start and finish recovery
+    pub timeout: Duration,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        Self {
+            queries: DEFAULT_SANITY_QUERIES
+                .iter()
+                .map(|q| q.to_string())
+                .collect(),
+            scratch_dir: None,
+            port: 54329,
+            pg_bin_dir: None,
+            timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Runs trial restores of backups
+pub struct BackupVerifier {
+    config: PostgresConfig,
+    options: VerifyOptions,
+}
+
+impl BackupVerifier {
+    pub fn new(config: PostgresConfig, options: VerifyOptions) -> Self {
+        Self { config, options }
+    }
+
+    /// List a snapshot and restore it into a scratch database
+    pub async fn verify_snapshot(&self, backup: &Backup) -> Verification {
+        info!("Verifying snapshot backup {}", backup.id);
+        let mut checks = Vec::new();
+
+        let scratch_db = format!("warden_verify_{}", backup.id.simple());
+        let result = self.check_snapshot(backup, &scratch_db, &mut checks).await;
+
+        if let Err(e) = self.drop_database(&scratch_db).await {
+            warn!("Failed to drop scratch database {scratch_db}: {e}");
+        }
+
+        finish(checks, result)
+    }
+
+    /// Recover a full backup, or an incremental backup on top of its full
+    /// backup, into `data_dir` and query it on a throwaway server
+    pub async fn verify_physical(
+        &self,
+        full_backup: &Backup,
+        incremental_backups: Vec<Backup>,
+        backup: &Backup,
+        data_dir: &Path,
+    ) -> Verification {
+        info!(
+            "Verifying {:?} backup {} in {data_dir:?}",
+            backup.backup_type, backup.id
+        );
+        let mut checks = Vec::new();
+
+        let socket_dir = tempfile::Builder::new().prefix("warden_verify_").tempdir();
+        let result = match &socket_dir {
+            Ok(socket_dir) => {
+                self.check_physical(
+                    full_backup,
+                    incremental_backups,
+                    backup,
+                    data_dir,
+                    socket_dir.path(),
+                    &mut checks,
+                )
+                .await
+            }
+            Err(e) => Err(PostgresError::RestoreError(format!(
+                "Failed to create socket directory: {e}"
+            ))),
+        };
+
+        if let Err(e) = self.stop_server(data_dir) {
+            debug!("Throwaway server in {data_dir:?} was not stopped: {e}");
+        }
+        for dir in [
+            Some(data_dir.to_path_buf()),
+            point_in_time::wal_archive_dir(data_dir).ok(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if dir.exists() {
+                if let Err(e) = fs::remove_dir_all(&dir) {
+                    warn!("Failed to remove scratch directory {dir:?}: {e}");
+                }
+            }
+        }
+
+        finish(checks, result)
+    }
+
+    async fn check_snapshot(
+        &self,
+        backup: &Backup,
+        scratch_db: &str,
+        checks: &mut Vec<String>,
+    ) -> Result<(), PostgresError> {
+        let snapshot_file = backup.dump_path().ok_or_else(|| {
+            PostgresError::RestoreError(format!(
+                "Snapshot {} has no dump in {:?}",
+                backup.id, backup.backup_path
+            ))
+        })?;
+        let pg_restore = PgRestore::new(self.config.clone());
+
+        let contents = pg_restore.list_contents(&snapshot_file).await?;
+        let entries = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with(';'))
+            .count();
+        if entries == 0 {
+            return Err(PostgresError::RestoreError(format!(
+                "Snapshot {snapshot_file:?} has no entries"
+            )));
+        }
+        checks.push(format!("pg_restore --list: {entries} entries"));
+
+        let admin = connect(&self.config.connection_string()).await?;
+        admin
+            .batch_execute(&format!("CREATE DATABASE {}", quote_ident(scratch_db)))
+            .await?;
+        checks.push(format!("created scratch database {scratch_db}"));
+
+        pg_restore
+            .restore_with_options(
+                &snapshot_file,
+                Some(scratch_db),
+                &["--no-owner", "--no-privileges", "--exit-on-error"],
+            )
+            .await?;
+        checks.push(format!("restored into {scratch_db}"));
+
+        let scratch = PostgresConfig {
+            database: scratch_db.to_string(),
+            ..self.config.clone()
+        };
+        let client = connect(&scratch.connection_string()).await?;
+        self.run_queries(&client, checks).await
+    }
+
+    async fn check_physical(
+        &self,
+        full_backup: &Backup,
+        incremental_backups: Vec<Backup>,
+        backup: &Backup,
+        data_dir: &Path,
+        socket_dir: &Path,
+        checks: &mut Vec<String>,
+    ) -> Result<(), PostgresError> {
+        let target = match backup.backup_type {
+            BackupType::Full => RecoveryTarget::Immediate,
+            _ => RecoveryTarget::Lsn(backup.wal_end.clone().ok_or_else(|| {
+                PostgresError::RestoreError(format!("Backup {} has no WAL end position", backup.id))
+            })?),
+        };
+
+        PointInTimeRestoreManager::new(
+            self.config.clone(),
+            full_backup.clone(),
+            incremental_backups,
+            data_dir.to_path_buf(),
+            target.clone(),
+            RecoveryTargetAction::Promote,
+        )
+        .restore()
+        .await?;
+        checks.push(format!("laid out data directory for recovery to {target}"));
+
+        self.start_server(data_dir, socket_dir)?;
+        checks.push(format!("started throwaway server on {socket_dir:?}"));
+
+        let local = PostgresConfig {
+            host: socket_dir.to_string_lossy().to_string(),
+            port: self.options.port,
+            ssl_mode: None,
+            ..self.config.clone()
+        };
+        let client = connect(&local.connection_string()).await?;
+        self.wait_for_promotion(&client).await?;
+        checks.push("recovery finished and server promoted".to_string());
+
+        self.run_queries(&client, checks).await
+    }
+
+    /// Run every sanity query, failing on the first error
+    async fn run_queries(
+        &self,
+        client: &Client,
+        checks: &mut Vec<String>,
+    ) -> Result<(), PostgresError> {
+        for query in &self.options.queries {
+            client.simple_query(query).await.map_err(|e| {
+                PostgresError::RestoreError(format!("Sanity query `{query}` failed: {e}"))
+            })?;
+            checks.push(format!("query `{query}` succeeded"));
+        }
+        Ok(())
+    }
+
+    /// Wait until the throwaway server has replayed its WAL and left recovery
+    async fn wait_for_promotion(&self, client: &Client) -> Result<(), PostgresError> {
+        let deadline = tokio::time::Instant::now() + self.options.timeout;
+        loop {
+            let row = client.query_one("SELECT pg_is_in_recovery()", &[]).await?;
+            let in_recovery: bool = row.get(0);
+            if !in_recovery {
+                return Ok(());
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(PostgresError::RestoreError(format!(
+                    "Server still in recovery after {:?}",
+                    self.options.timeout
+                )));
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    fn start_server(&self, data_dir: &Path, socket_dir: &Path) -> Result<(), PostgresError> {
+        let log_file = data_dir.join("warden_verify.log");
+        // Nothing from the source server's configuration may reach the outside:
+        // no TCP listener and no archiving into its WAL archive
+        let server_options = format!(
+            "-p {} -c listen_addresses='' -c unix_socket_directories='{}' -c archive_mode=off -c hot_standby=on",
+            self.options.port,
+            socket_dir.display()
+        );
+
+        let output = self
+            .pg_ctl()
+            .arg("start")
+            .arg("-D")
+            .arg(data_dir)
+            .arg("-l")
+            .arg(&log_file)
+            .arg("-w")
+            .arg("-t")
+            .arg(self.options.timeout.as_secs().to_string())
+            .arg("-o")
+            .arg(server_options)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(PostgresError::Io)?;
+
+        if !output.status.success() {
+            let log = fs::read_to_string(&log_file).unwrap_or_default();
+            error!("Throwaway server failed to start, log:\n{log}");
+            return Err(PostgresError::RestoreError(format!(
+                "pg_ctl start failed: {}; last log line: {}",
+                String::from_utf8_lossy(&output.stderr).trim(),
+                log.lines().last().unwrap_or_default()
+            )));
+        }
+        Ok(())
+    }
+
+    fn stop_server(&self, data_dir: &Path) -> Result<(), PostgresError> {
+        if !data_dir.join("postmaster.pid").exists() {
+            return Ok(());
+        }
+        let output = self
+            .pg_ctl()
+            .arg("stop")
+            .arg("-D")
+            .arg(data_dir)
+            .arg("-m")
+            .arg("immediate")
+            .arg("-w")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(PostgresError::Io)?;
+        if !output.status.success() {
+            return Err(PostgresError::RestoreError(format!(
+                "pg_ctl stop failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+        Ok(())
+    }
+
+    fn pg_ctl(&self) -> Command {
+        match &self.options.pg_bin_dir {
+            Some(dir) => Command::new(dir.join("pg_ctl")),
+            None => Command::new("pg_ctl"),
+        }
+    }
+
+    async fn drop_database(&self, name: &str) -> Result<(), PostgresError> {
+        let admin = connect(&self.config.connection_string()).await?;
+        admin
+            .execute(
+                "SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE datname = $1 AND pid <> pg_backend_pid()",
+                &[&name],
+            )
+            .await?;
+        admin
+            .batch_execute(&format!("DROP DATABASE IF EXISTS {}", quote_ident(name)))
+            .await?;
+        Ok(())
+    }
+}
+
+async fn connect(connection_string: &str) -> Result<Client, PostgresError> {
+    let (client, connection) = tokio_postgres::connect(connection_string, NoTls)
+        .await
+        .map_err(|e| PostgresError::ConnectionError(e.to_string()))?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            error!("Connection error: {e}");
+        }
+    });
+    Ok(client)
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Turn the checks that ran and their outcome into a verification record
+fn finish(checks: Vec<String>, result: Result<(), PostgresError>) -> Verification {
+    let (status, error_message) = match result {
+        Ok(()) => (VerificationStatus::Passed, None),
+        Err(e) => {
+            error!("Verification failed: {e}");
+            (VerificationStatus::Failed, Some(e.to_string()))
+        }
+    };
+    Verification {
+        status,
+        verified_at: Utc::now(),
+        checks,
+        error_message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_check_is_recorded() {
+        let verification = finish(
+            vec!["pg_restore --list: 3 entries".to_string()],
+            Err(PostgresError::RestoreError("boom".to_string())),
+        );
+        assert_eq!(verification.status, VerificationStatus::Failed);
+        assert_eq!(verification.checks.len(), 1);
+        assert!(verification.error_message.unwrap().contains("boom"));
+    }
+
+    #[test]
+    fn identifiers_are_quoted() {
+        assert_eq!(quote_ident("warden_verify_1"), "\"warden_verify_1\"");
+        assert_eq!(quote_ident("a\"b"), "\"a\"\"b\"");
+    }
+}
@@ -0,0 +1,298 @@
+//! Helpers for WAL locations (LSNs) and WAL segment file names.
+
+use flate2::read::GzDecoder;
+use std::fs;
+use std::path::Path;
+use tokio_postgres::Client;
+
+use crate::PostgresError;
+
+/// Default WAL segment size used by PostgreSQL builds (16MB)
+pub const DEFAULT_WAL_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Name of the physical replication slot warden streams WAL from
+pub const WAL_REPLICATION_SLOT: &str = "warden_wal";
+
+/// Name of the directory (inside the backup directory) that accumulates streamed WAL
+pub const WAL_ARCHIVE_DIR: &str = "wal_archive";
+
+/// Application name of the continuous `stream-wal` pg_receivewal
+pub const WAL_STREAM_APPLICATION_NAME: &str = "warden_stream_wal";
+
+/// Parse an LSN in PostgreSQL's textual `XXXXXXXX/XXXXXXXX` form
+pub fn parse_lsn(lsn: &str) -> Option<u64> {
+    let (high, low) = lsn.trim().split_once('/')?;
+    let high = u64::from_str_radix(high, 16).ok()?;
+    let low = u64::from_str_radix(low, 16).ok()?;
+    if high > u32::MAX as u64 || low > u32::MAX as u64 {
+        return None;
+    }
+    Some((high << 32) | low)
+}
+
+/// Format an LSN in PostgreSQL's textual form
+pub fn format_lsn(lsn: u64) -> String {
+    format!("{:X}/{:X}", lsn >> 32, lsn & 0xFFFF_FFFF)
+}
+
+/// Number of segments per 4GB "xlogid" for the given segment size
+fn segments_per_xlogid(segment_size: u64) -> u64 {
+    0x1_0000_0000 / segment_size
+}
+
+/// Segment number containing the given LSN
+pub fn segment_number(lsn: u64, segment_size: u64) -> u64 {
+    lsn / segment_size
+}
+
+/// Build a WAL segment file name (e.g. `000000010000000000000003`)
+pub fn segment_file_name(timeline: u32, segno: u64, segment_size: u64) -> String {
+    let per_xlogid = segments_per_xlogid(segment_size);
+    format!(
+        "{:08X}{:08X}{:08X}",
+        timeline,
+        segno / per_xlogid,
+        segno % per_xlogid
+    )
+}
+
+/// Parse a WAL segment file name into its timeline and segment number
+pub fn parse_segment_file_name(name: &str, segment_size: u64) -> Option<(u32, u64)> {
+    if !is_segment_file_name(name) {
+        return None;
+    }
+    let timeline = u32::from_str_radix(&name[0..8], 16).ok()?;
+    let xlogid = u64::from_str_radix(&name[8..16], 16).ok()?;
+    let seg = u64::from_str_radix(&name[16..24], 16).ok()?;
+    Some((timeline, xlogid * segments_per_xlogid(segment_size) + seg))
+}
+
+/// Whether the name looks like a complete WAL segment file
+pub fn is_segment_file_name(name: &str) -> bool {
+    name.len() == 24 && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Round an LSN up to the next segment boundary
+pub fn align_to_segment(lsn: u64, segment_size: u64) -> u64 {
+    lsn.div_ceil(segment_size) * segment_size
+}
+
+/// Get the WAL segment size of the connected server
+pub async fn server_segment_size(client: &Client) -> Result<u64, PostgresError> {
+    let row = client
+        .query_one(
+            "SELECT setting::bigint FROM pg_settings WHERE name = 'wal_segment_size'",
+            &[],
+        )
+        .await
+        .map_err(PostgresError::Postgres)?;
+
+    let size: i64 = row.get(0);
+    // Servers before 11 report the size in 8kB blocks
+    let size = if size < 1024 * 1024 {
+        size as u64 * 8192
+    } else {
+        size as u64
+    };
+
+    Ok(size)
+}
+
+/// Get the timeline the connected server is currently on
+pub async fn server_timeline(client: &Client) -> Result<u32, PostgresError> {
+    let row = client
+        .query_one("SELECT timeline_id FROM pg_control_checkpoint()", &[])
+        .await
+        .map_err(PostgresError::Postgres)?;
+
+    let timeline: i32 = row.get(0);
+    Ok(timeline as u32)
+}
+
+/// Make sure the physical replication slot used for WAL streaming exists
+///
+/// The slot is created with `immediately_reserve` so the server keeps every
+/// segment from now on until warden has streamed it. Nothing ever releases
+/// that WAL but streaming it, so a slot no longer streamed from must be
+/// dropped with `SELECT pg_drop_replication_slot('warden_wal')`.
+pub async fn ensure_replication_slot(client: &Client, slot: &str) -> Result<(), PostgresError> {
+    let exists = client
+        .query_opt(
+            "SELECT 1 FROM pg_replication_slots WHERE slot_name = $1",
+            &[&slot],
+        )
+        .await
+        .map_err(PostgresError::Postgres)?
+        .is_some();
+
+    if !exists {
+        client
+            .execute(
+                "SELECT pg_create_physical_replication_slot($1, true)",
+                &[&slot],
+            )
+            .await
+            .map_err(|e| {
+                PostgresError::WalError(format!("Failed to create replication slot {slot}: {e}"))
+            })?;
+        log::info!("Created physical replication slot {slot}");
+    }
+
+    Ok(())
+}
+
+/// Whether a continuous `stream-wal` is connected to the server
+pub async fn is_stream_wal_connected(client: &Client) -> Result<bool, PostgresError> {
+    let row = client
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM pg_stat_replication WHERE application_name = $1)",
+            &[&WAL_STREAM_APPLICATION_NAME],
+        )
+        .await
+        .map_err(PostgresError::Postgres)?;
+    Ok(row.get(0))
+}
+
+/// Add the WAL segments of a full backup's `pg_wal.tar.gz` to `archive_dir`
+///
+/// pg_receivewal resumes after the newest segment of its directory, so the
+/// next incremental streams from the end of the full backup on, with or
+/// without a replication slot. Segments already in the archive are left
+/// alone. Returns how many were added.
+pub fn seed_archive(wal_tar: &Path, archive_dir: &Path) -> Result<usize, PostgresError> {
+    fs::create_dir_all(archive_dir).map_err(PostgresError::Io)?;
+    let file = fs::File::open(wal_tar).map_err(PostgresError::Io)?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    let failed = |e: std::io::Error| {
+        PostgresError::WalError(format!("Failed to read WAL from {wal_tar:?}: {e}"))
+    };
+
+    let mut seeded = 0;
+    for entry in archive.entries().map_err(failed)? {
+        let mut entry = entry.map_err(failed)?;
+        let path = entry.path().map_err(failed)?;
+        let Some(name) = path.file_name().map(|n| n.to_string_lossy().to_string()) else {
+            continue;
+        };
+        let target = archive_dir.join(&name);
+        if !is_segment_file_name(&name) || target.exists() {
+            continue;
+        }
+        // Renamed into place, so a running pg_receivewal never sees half a segment
+        let partial = archive_dir.join(format!("{name}.seeding"));
+        entry.unpack(&partial).map_err(failed)?;
+        fs::rename(&partial, &target).map_err(PostgresError::Io)?;
+        seeded += 1;
+    }
+    Ok(seeded)
+}
+
+/// Warn when the replication slot exists but nothing streams from it
+///
+/// Returns whether the slot exists.
+pub async fn warn_if_slot_idle(client: &Client, slot: &str) -> Result<bool, PostgresError> {
+    let row = client
+        .query_opt(
+            "SELECT active, COALESCE(pg_wal_lsn_diff(pg_current_wal_lsn(), restart_lsn), 0)::bigint \
+             FROM pg_replication_slots WHERE slot_name = $1",
+            &[&slot],
+        )
+        .await
+        .map_err(PostgresError::Postgres)?;
+
+    let Some(row) = row else {
+        return Ok(false);
+    };
+    let active: bool = row.get(0);
+    let retained: i64 = row.get(1);
+    if !active {
+        log::warn!(
+            "Replication slot {slot} is idle and holds {} MiB of WAL on the server, which keeps growing \
+             until it is streamed. Run stream-wal, or drop it with SELECT pg_drop_replication_slot('{slot}')",
+            retained / (1024 * 1024)
+        );
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lsn_round_trip() {
+        let lsn = parse_lsn("16/B374D848").unwrap();
+        assert_eq!(lsn, 0x16_B374_D848);
+        assert_eq!(format_lsn(lsn), "16/B374D848");
+        assert!(parse_lsn("not-an-lsn").is_none());
+    }
+
+    #[test]
+    fn segment_names() {
+        let lsn = parse_lsn("0/3000028").unwrap();
+        let segno = segment_number(lsn, DEFAULT_WAL_SEGMENT_SIZE);
+        let name = segment_file_name(1, segno, DEFAULT_WAL_SEGMENT_SIZE);
+        assert_eq!(name, "000000010000000000000003");
+        assert_eq!(
+            parse_segment_file_name(&name, DEFAULT_WAL_SEGMENT_SIZE),
+            Some((1, segno))
+        );
+
+        let name = segment_file_name(2, 0x1FF, DEFAULT_WAL_SEGMENT_SIZE);
+        assert_eq!(name, "0000000200000001000000FF");
+        assert!(parse_segment_file_name(
+            "00000001000000000000000A.partial",
+            DEFAULT_WAL_SEGMENT_SIZE
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn align_rounds_up_to_segment_boundary() {
+        let size = DEFAULT_WAL_SEGMENT_SIZE;
+        assert_eq!(align_to_segment(0, size), 0);
+        assert_eq!(align_to_segment(1, size), size);
+        assert_eq!(align_to_segment(size, size), size);
+    }
+
+    #[test]
+    fn seeding_adds_missing_segments_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal_tar = dir.path().join("pg_wal.tar.gz");
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            fs::File::create(&wal_tar).unwrap(),
+            flate2::Compression::default(),
+        ));
+        for (name, content) in [
+            ("000000010000000000000002", &b"second"[..]),
+            ("000000010000000000000003", b"third"),
+            ("archive_status/000000010000000000000003.done", b""),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o600);
+            builder.append_data(&mut header, name, content).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let archive_dir = dir.path().join(WAL_ARCHIVE_DIR);
+        fs::create_dir_all(&archive_dir).unwrap();
+        fs::write(archive_dir.join("000000010000000000000002"), b"streamed").unwrap();
+
+        assert_eq!(seed_archive(&wal_tar, &archive_dir).unwrap(), 1);
+        let mut names: Vec<_> = fs::read_dir(&archive_dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            ["000000010000000000000002", "000000010000000000000003"]
+        );
+        // A segment the archive already had is not replaced
+        assert_eq!(
+            fs::read(archive_dir.join("000000010000000000000002")).unwrap(),
+            b"streamed"
+        );
+    }
+}
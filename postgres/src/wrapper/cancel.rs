@@ -0,0 +1,162 @@
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::collections::HashSet;
+use std::io;
+use std::process::{Child, Command, ExitStatus, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Stops the PostgreSQL tools of an operation from another task
+///
+/// Clones share their state. Tools run through [`Cancellation::output`] or
+/// [`Cancellation::status`] are sent SIGTERM when the operation is cancelled,
+/// which makes pg_basebackup and pg_dump close their server connections and
+/// exit, and no new tool starts once it is.
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    /// Process IDs of the tools running
+    children: Mutex<HashSet<u32>>,
+}
+
+fn cancelled_error() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "Operation cancelled")
+}
+
+impl Cancellation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Cancel the operation and terminate the tools it is running
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        for pid in self.inner.children.lock().unwrap().iter() {
+            log::info!("Terminating process {pid} of a cancelled operation");
+            if let Err(e) = kill(Pid::from_raw(*pid as i32), Signal::SIGTERM) {
+                log::warn!("Failed to terminate process {pid}: {e}");
+            }
+        }
+    }
+
+    /// Like [`Command::output`], failing with `Interrupted` once cancelled
+    pub fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        let child = self.spawn(cmd)?;
+        let pid = child.id();
+        let output = child.wait_with_output();
+        self.finished(pid)?;
+        output
+    }
+
+    /// Like [`Command::status`], failing with `Interrupted` once cancelled
+    pub fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+        let child = self.spawn(cmd)?;
+        self.wait(child)
+    }
+
+    /// Like [`Command::spawn`], the child is terminated once cancelled
+    ///
+    /// Pass the child to [`Cancellation::wait`] to stop tracking it.
+    pub fn spawn(&self, cmd: &mut Command) -> io::Result<Child> {
+        // Registering under the lock means `cancel` either sees the child or
+        // the flag is already visible here
+        let mut children = self.inner.children.lock().unwrap();
+        if self.is_cancelled() {
+            return Err(cancelled_error());
+        }
+        let child = cmd.spawn()?;
+        children.insert(child.id());
+        Ok(child)
+    }
+
+    /// Like [`Child::wait`] for a child of [`Cancellation::spawn`], failing
+    /// with `Interrupted` once cancelled
+    pub fn wait(&self, mut child: Child) -> io::Result<ExitStatus> {
+        let status = child.wait();
+        self.finished(child.id())?;
+        status
+    }
+
+    /// Terminate the process `pid`, started elsewhere, once cancelled
+    ///
+    /// Fails with `Interrupted` when already cancelled, the caller then stops
+    /// the process itself. Call [`Cancellation::finished`] once it exited.
+    pub fn track(&self, pid: u32) -> io::Result<()> {
+        let mut children = self.inner.children.lock().unwrap();
+        if self.is_cancelled() {
+            return Err(cancelled_error());
+        }
+        children.insert(pid);
+        Ok(())
+    }
+
+    /// Stop tracking the exited process `pid`, failing with `Interrupted`
+    /// once cancelled
+    pub fn finished(&self, pid: u32) -> io::Result<()> {
+        self.inner.children.lock().unwrap().remove(&pid);
+        if self.is_cancelled() {
+            return Err(cancelled_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn cancel_terminates_running_tools_and_refuses_new_ones() {
+        let cancellation = Cancellation::new();
+        let waiting = cancellation.clone();
+        let started = Instant::now();
+        let sleeper = std::thread::spawn(move || waiting.status(Command::new("sleep").arg("30")));
+
+        // Wait for the child to be registered before cancelling
+        while cancellation.inner.children.lock().unwrap().is_empty() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        cancellation.cancel();
+
+        let err = sleeper.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(cancellation.inner.children.lock().unwrap().is_empty());
+
+        let err = cancellation.output(&mut Command::new("true")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    }
+
+    #[tokio::test]
+    async fn cancel_terminates_tracked_processes() {
+        let cancellation = Cancellation::new();
+        let mut child = tokio::process::Command::new("sleep")
+            .arg("30")
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        let pid = child.id().unwrap();
+        cancellation.track(pid).unwrap();
+
+        cancellation.cancel();
+        let status = tokio::time::timeout(Duration::from_secs(10), child.wait())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!status.success());
+
+        let err = cancellation.finished(pid).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert!(cancellation.track(pid).is_err());
+    }
+}
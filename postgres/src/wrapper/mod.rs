@@ -1,8 +1,14 @@
+pub mod cancel;
 pub mod pg_basebackup;
 pub mod pg_dump;
+pub mod pg_receivewal;
 pub mod pg_restore;
+pub mod priority;
 
 // Re-export for convenience
+pub use cancel::Cancellation;
 pub use pg_basebackup::{PgBaseBackup, PgBaseBackupOptions};
 pub use pg_dump::{PgDump, PgDumpFormat, PgDumpOptions};
+pub use pg_receivewal::{PgReceiveWal, PgReceiveWalOptions};
 pub use pg_restore::PgRestore;
+pub use priority::ProcessPriority;
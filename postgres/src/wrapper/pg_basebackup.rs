@@ -2,6 +2,8 @@ use anyhow::{Context, Result};
 use log::{debug, info};
 use std::process::{Command, Stdio};
 
+use super::{Cancellation, ProcessPriority};
+
 /// Options for pg_basebackup command
 pub struct PgBaseBackupOptions {
     pub host: String,
@@ -16,6 +18,10 @@ pub struct PgBaseBackupOptions {
     pub label: Option<String>,
     pub progress: bool,
     pub verbose: bool,
+    /// Priority the process runs with
+    pub priority: ProcessPriority,
+    /// Terminates the process when the operation is cancelled
+    pub cancellation: Cancellation,
 }
 
 impl Default for PgBaseBackupOptions {
@@ -33,6 +39,8 @@ impl Default for PgBaseBackupOptions {
             label: None,
             progress: true,
             verbose: false,
+            priority: ProcessPriority::default(),
+            cancellation: Cancellation::default(),
         }
     }
 }
@@ -43,7 +51,7 @@ pub struct PgBaseBackup;
 impl PgBaseBackup {
     /// Run pg_basebackup with the given options
     pub fn run(options: &PgBaseBackupOptions) -> Result<()> {
-        let mut cmd = Command::new("pg_basebackup");
+        let mut cmd = options.priority.command("pg_basebackup");
         // Set PGPASSWORD environment variable
         cmd.env("PGPASSWORD", &options.password);
 
@@ -80,10 +88,10 @@ impl PgBaseBackup {
 
         debug!("Running pg_basebackup command: {cmd:?}");
 
-        let output = cmd
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .output()
+        cmd.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+        let output = options
+            .cancellation
+            .output(&mut cmd)
             .context("Failed to execute pg_basebackup")?;
 
         if !output.status.success() {
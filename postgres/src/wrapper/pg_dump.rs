@@ -2,6 +2,8 @@ use anyhow::{Context, Result};
 use log::{debug, info};
 use std::process::{Command, Stdio};
 
+use super::{Cancellation, ProcessPriority};
+
 /// Format options for pg_dump
 pub enum PgDumpFormat {
     Plain,
@@ -39,6 +41,10 @@ pub struct PgDumpOptions {
     pub schemas: Vec<String>,
     pub tables: Vec<String>,
     pub exclude_tables: Vec<String>,
+    /// Priority the process runs with
+    pub priority: ProcessPriority,
+    /// Terminates the process when the operation is cancelled
+    pub cancellation: Cancellation,
 }
 
 impl Default for PgDumpOptions {
@@ -60,6 +66,8 @@ impl Default for PgDumpOptions {
             schemas: Vec::new(),
             tables: Vec::new(),
             exclude_tables: Vec::new(),
+            priority: ProcessPriority::default(),
+            cancellation: Cancellation::default(),
         }
     }
 }
@@ -70,7 +78,7 @@ pub struct PgDump;
 impl PgDump {
     /// Run pg_dump with the given options
     pub fn run(options: &PgDumpOptions) -> Result<()> {
-        let mut cmd = Command::new("pg_dump");
+        let mut cmd = options.priority.command("pg_dump");
 
         // Set PGPASSWORD environment variable
         cmd.env("PGPASSWORD", &options.password);
@@ -126,10 +134,10 @@ impl PgDump {
 
         debug!("Running pg_dump command: {cmd:?}");
 
-        let output = cmd
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .output()
+        cmd.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+        let output = options
+            .cancellation
+            .output(&mut cmd)
             .context("Failed to execute pg_dump")?;
 
         if !output.status.success() {
@@ -0,0 +1,158 @@
+use super::Cancellation;
+use anyhow::{Context, Result};
+use log::{debug, info};
+use std::process::{Child, Command, Stdio};
+
+/// Options for pg_receivewal command
+pub struct PgReceiveWalOptions {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub directory: String,
+    pub slot: Option<String>,
+    pub endpos: Option<String>,
+    pub no_loop: bool,
+    pub synchronous: bool,
+    pub compress: Option<String>,
+    pub verbose: bool,
+    /// Name the connection shows in `pg_stat_replication`
+    pub application_name: Option<String>,
+    /// Terminates the process when the operation is cancelled
+    pub cancellation: Cancellation,
+}
+
+impl Default for PgReceiveWalOptions {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_string(),
+            port: 5432,
+            username: "postgres".to_string(),
+            password: "".to_string(),
+            directory: ".".to_string(),
+            slot: None,
+            endpos: None,
+            no_loop: true,
+            synchronous: false,
+            compress: None,
+            verbose: false,
+            application_name: None,
+            cancellation: Cancellation::default(),
+        }
+    }
+}
+
+/// Wrapper for pg_receivewal command
+///
+/// pg_receivewal streams WAL over the replication protocol, so it works against
+/// remote servers and, when used with a replication slot, never misses segments
+/// that the server would otherwise recycle.
+pub struct PgReceiveWal;
+
+impl PgReceiveWal {
+    fn build_command(options: &PgReceiveWalOptions) -> Command {
+        let mut cmd = Command::new("pg_receivewal");
+        // Set PGPASSWORD environment variable
+        cmd.env("PGPASSWORD", &options.password);
+        if let Some(name) = &options.application_name {
+            cmd.env("PGAPPNAME", name);
+        }
+
+        cmd.arg("--host")
+            .arg(&options.host)
+            .arg("--port")
+            .arg(options.port.to_string())
+            .arg("--username")
+            .arg(&options.username)
+            .arg("--directory")
+            .arg(&options.directory);
+
+        if let Some(slot) = &options.slot {
+            cmd.arg("--slot").arg(slot);
+        }
+
+        if let Some(endpos) = &options.endpos {
+            cmd.arg("--endpos").arg(endpos);
+        }
+
+        if options.no_loop {
+            cmd.arg("--no-loop");
+        }
+
+        if options.synchronous {
+            cmd.arg("--synchronous");
+        }
+
+        if let Some(compress) = &options.compress {
+            cmd.arg("--compress").arg(compress);
+        }
+
+        if options.verbose {
+            cmd.arg("--verbose");
+        }
+
+        cmd
+    }
+
+    /// Run pg_receivewal until `endpos` is reached
+    pub fn run(options: &PgReceiveWalOptions) -> Result<()> {
+        if options.endpos.is_none() {
+            anyhow::bail!("pg_receivewal needs an end position when run to completion");
+        }
+
+        let mut cmd = Self::build_command(options);
+
+        debug!("Running pg_receivewal command: {cmd:?}");
+
+        cmd.stdout(Stdio::inherit()).stderr(Stdio::piped());
+        let output = options
+            .cancellation
+            .output(&mut cmd)
+            .context("Failed to execute pg_receivewal")?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("pg_receivewal failed: {}", stderr);
+        }
+
+        info!("pg_receivewal completed successfully");
+        Ok(())
+    }
+
+    /// Start pg_receivewal in continuous streaming mode
+    ///
+    /// The returned child keeps streaming WAL into `directory` until it is
+    /// killed or the operation cancelled. Wait for it with
+    /// [`Cancellation::wait`].
+    pub fn spawn(options: &PgReceiveWalOptions) -> Result<Child> {
+        let mut cmd = Self::build_command(options);
+
+        debug!("Spawning pg_receivewal command: {cmd:?}");
+
+        cmd.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+        let child = options
+            .cancellation
+            .spawn(&mut cmd)
+            .context("Failed to spawn pg_receivewal")?;
+
+        info!("pg_receivewal started with pid {}", child.id());
+        Ok(child)
+    }
+
+    /// Check if pg_receivewal is available in the system
+    pub fn check_availability() -> Result<()> {
+        let output = Command::new("pg_receivewal")
+            .arg("--version")
+            .output()
+            .context("Failed to execute pg_receivewal")?;
+
+        if !output.status.success() {
+            anyhow::bail!("pg_receivewal is not available");
+        }
+
+        let version = String::from_utf8_lossy(&output.stdout);
+        debug!("pg_receivewal version: {version}");
+
+        Ok(())
+    }
+}
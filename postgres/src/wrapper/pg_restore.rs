@@ -1,18 +1,31 @@
+use super::Cancellation;
 use crate::common::PostgresConfig;
 use crate::PostgresError;
 use log::{debug, error, info};
 use std::path::Path;
 use std::process::{Command, Stdio};
+use storage::ByteStream;
+use tokio_util::io::StreamReader;
 
 /// Wrapper for pg_restore utility
 pub struct PgRestore {
     config: PostgresConfig,
+    cancellation: Cancellation,
 }
 
 impl PgRestore {
     /// Create a new PgRestore instance
     pub fn new(config: PostgresConfig) -> Self {
-        Self { config }
+        Self {
+            config,
+            cancellation: Cancellation::default(),
+        }
+    }
+
+    /// Terminate pg_restore when `cancellation` is cancelled
+    pub fn with_cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = cancellation;
+        self
     }
 
     /// Restore a database from a dump file
@@ -64,10 +77,10 @@ impl PgRestore {
         debug!("Running pg_restore command: {cmd:?}");
 
         // Execute command
-        let output = cmd
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let output = self
+            .cancellation
+            .output(&mut cmd)
             .map_err(PostgresError::Io)?;
 
         if !output.status.success() {
@@ -132,11 +145,79 @@ impl PgRestore {
         debug!("Running pg_restore command: {cmd:?}");
 
         // Execute command
-        let output = cmd
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let output = self
+            .cancellation
+            .output(&mut cmd)
+            .map_err(PostgresError::Io)?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            error!("pg_restore failed: {stderr}");
+            return Err(PostgresError::RestoreError(format!(
+                "pg_restore failed: {stderr}"
+            )));
+        }
+
+        info!("Database restored successfully from dump file: {dump_file:?}");
+        Ok(())
+    }
+
+    /// Restore a database from a custom-format dump read from a stream
+    ///
+    /// The dump is piped into pg_restore's standard input, so it is never
+    /// written to disk.
+    pub async fn restore_stream(
+        &self,
+        dump: ByteStream,
+        target_db: Option<&str>,
+        options: &[&str],
+    ) -> Result<(), PostgresError> {
+        info!("Restoring database from a streamed dump");
+
+        let mut cmd = tokio::process::Command::new("pg_restore");
+        cmd.arg("--host")
+            .arg(&self.config.host)
+            .arg("--port")
+            .arg(self.config.port.to_string())
+            .arg("--username")
+            .arg(&self.config.user)
+            .arg("--dbname")
+            .arg(target_db.unwrap_or(&self.config.database));
+        for option in options {
+            cmd.arg(option);
+        }
+        if let Some(password) = &self.config.password {
+            cmd.env("PGPASSWORD", password);
+        }
+
+        debug!("Running pg_restore command: {cmd:?}");
+
+        let mut child = cmd
+            .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
-            .output()
+            .kill_on_drop(true)
+            .spawn()
             .map_err(PostgresError::Io)?;
+        // Dropping the child on error kills it
+        let pid = child.id();
+        if let Some(pid) = pid {
+            self.cancellation.track(pid).map_err(PostgresError::Io)?;
+        }
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let mut reader = StreamReader::new(dump);
+        let feed = async move {
+            let copied = tokio::io::copy(&mut reader, &mut stdin).await;
+            // Closing stdin tells pg_restore the dump is complete
+            drop(stdin);
+            copied
+        };
+        let (copied, output) = tokio::join!(feed, child.wait_with_output());
+        if let Some(pid) = pid {
+            self.cancellation.finished(pid).map_err(PostgresError::Io)?;
+        }
+        let output = output.map_err(PostgresError::Io)?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
@@ -145,8 +226,11 @@ impl PgRestore {
                 "pg_restore failed: {stderr}"
             )));
         }
+        let copied = copied.map_err(|e| {
+            PostgresError::RestoreError(format!("Failed to stream dump to pg_restore: {e}"))
+        })?;
 
-        info!("Database restored successfully from dump file: {dump_file:?}");
+        info!("Database restored successfully from a streamed dump of {copied} bytes");
         Ok(())
     }
 
@@ -173,10 +257,10 @@ impl PgRestore {
         debug!("Running pg_restore --list command: {cmd:?}");
 
         // Execute command
-        let output = cmd
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let output = self
+            .cancellation
+            .output(&mut cmd)
             .map_err(PostgresError::Io)?;
 
         if !output.status.success() {
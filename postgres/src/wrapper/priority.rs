@@ -0,0 +1,105 @@
+use std::process::Command;
+
+/// CPU and I/O priority of the PostgreSQL tools we spawn
+///
+/// The tools run under `nice` and `ionice` when set, so a backup competes
+/// less with the database it reads from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessPriority {
+    /// Niceness, from -20 (favoured) to 19 (least favoured)
+    pub nice: Option<i32>,
+    /// ionice scheduling class: 1 realtime, 2 best-effort, 3 idle
+    pub ionice_class: Option<u8>,
+    /// Priority within the ionice class, from 0 (highest) to 7
+    pub ionice_level: Option<u8>,
+}
+
+impl ProcessPriority {
+    pub fn is_default(&self) -> bool {
+        self.nice.is_none() && self.ionice_class.is_none() && self.ionice_level.is_none()
+    }
+
+    /// Command running `program`, wrapped in `ionice` and `nice` as configured
+    pub fn command(&self, program: &str) -> Command {
+        let mut args = self.wrapper_args();
+        if args.is_empty() {
+            return Command::new(program);
+        }
+        args.push(program.to_string());
+        let mut cmd = Command::new(args.remove(0));
+        cmd.args(args);
+        cmd
+    }
+
+    /// `ionice` and `nice` invocations to put before the program
+    fn wrapper_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        // ionice alone defaults to best-effort when only a level is given
+        if self.ionice_class.is_some() || self.ionice_level.is_some() {
+            args.push("ionice".to_string());
+            if let Some(class) = self.ionice_class {
+                args.extend(["-c".to_string(), class.to_string()]);
+            }
+            // The idle class has no levels
+            if let Some(level) = self.ionice_level.filter(|_| self.ionice_class != Some(3)) {
+                args.extend(["-n".to_string(), level.to_string()]);
+            }
+        }
+        if let Some(nice) = self.nice {
+            args.extend(["nice".to_string(), "-n".to_string(), nice.to_string()]);
+        }
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program_and_args(cmd: &Command) -> Vec<String> {
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn default_runs_the_program_directly() {
+        let priority = ProcessPriority::default();
+        assert!(priority.is_default());
+        assert_eq!(program_and_args(&priority.command("pg_dump")), ["pg_dump"]);
+    }
+
+    #[test]
+    fn wraps_in_ionice_then_nice() {
+        let priority = ProcessPriority {
+            nice: Some(10),
+            ionice_class: Some(2),
+            ionice_level: Some(7),
+        };
+        assert_eq!(
+            program_and_args(&priority.command("pg_basebackup")),
+            [
+                "ionice",
+                "-c",
+                "2",
+                "-n",
+                "7",
+                "nice",
+                "-n",
+                "10",
+                "pg_basebackup"
+            ]
+        );
+
+        let idle = ProcessPriority {
+            ionice_class: Some(3),
+            ionice_level: Some(7),
+            ..Default::default()
+        };
+        assert_eq!(
+            program_and_args(&idle.command("pg_dump")),
+            ["ionice", "-c", "3", "pg_dump"]
+        );
+    }
+}
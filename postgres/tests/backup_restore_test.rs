@@ -1,6 +1,11 @@
 use chrono::Utc;
-use postgres::common::{Backup, BackupStatus, BackupType, PostgresConfig, RestoreStatus};
+use postgres::common::{
+    Backup, BackupCatalog, BackupStatus, BackupType, PostgresConfig, RestoreStatus,
+    VerificationStatus, WalSegmentRange,
+};
 use postgres::manager::PostgresManager;
+use postgres::restore::{RecoveryTarget, RecoveryTargetAction};
+use postgres::verify::VerifyOptions;
 use tempfile::tempdir;
 
 use tokio_postgres::{connect, NoTls};
@@ -24,6 +29,8 @@ fn create_test_config() -> PostgresConfig {
         ssh_key_path: None,
         ssh_local_port: None,
         ssh_remote_port: None,
+        ssh_known_hosts: None,
+        ssh_host_key_fingerprint: None,
     }
 }
 
@@ -221,6 +228,27 @@ async fn test_snapshot_backup() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[tokio::test]
+#[serial_test::serial]
+async fn test_snapshot_backup_verifies() -> Result<(), Box<dyn std::error::Error>> {
+    let backup_dir = tempdir()?;
+    let mut manager = PostgresManager::new(create_test_config(), backup_dir.path().to_path_buf())?;
+
+    // The verifier must find the dump where the snapshot code wrote it
+    let backup = manager.snapshot_backup().await?;
+    let verification = manager
+        .verify_backup(&backup.id, VerifyOptions::default())
+        .await?;
+    assert_eq!(
+        verification.status,
+        VerificationStatus::Passed,
+        "{:?}",
+        verification.error_message
+    );
+
+    Ok(())
+}
+
 #[tokio::test]
 #[serial_test::serial]
 async fn test_backup_catalog() -> Result<(), Box<dyn std::error::Error>> {
@@ -251,6 +279,12 @@ async fn test_backup_catalog() -> Result<(), Box<dyn std::error::Error>> {
         base_backup_id: None,
         server_version: "mock-version".to_string(),
         error_message: None,
+        wal_segments: None,
+        compression: Default::default(),
+        verification: None,
+        manifest_sha256: None,
+        replicas: Vec::new(),
+        dump_file: None,
     };
 
     let _ = manager.add_backup_to_catalog(backup.clone());
@@ -270,3 +304,200 @@ async fn test_backup_catalog() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[tokio::test]
+#[serial_test::serial]
+async fn test_incremental_without_replication_slot() -> Result<(), Box<dyn std::error::Error>> {
+    let backup_dir = tempdir()?;
+    let mut manager = PostgresManager::new(create_test_config(), backup_dir.path().to_path_buf())?
+        .with_replication_slot(false);
+
+    let full = manager.full_backup().await?;
+
+    // Write some WAL after the full backup, for the incremental to pick up
+    let (client, connection) = connect(&create_test_config().connection_string(), NoTls).await?;
+    tokio::spawn(connection);
+    client
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS warden_incremental_test (id serial, note text);
+             INSERT INTO warden_incremental_test (note) SELECT md5(i::text) FROM generate_series(1, 1000) i;",
+        )
+        .await?;
+
+    // The full backup seeded the WAL archive, so nothing is missing without the slot
+    let incremental = manager.incremental_backup().await?;
+    assert_eq!(incremental.status, BackupStatus::Completed);
+    assert_eq!(incremental.base_backup_id, Some(full.id));
+    let (full_segments, segments) = (
+        full.wal_segments.expect("full backup records its WAL"),
+        incremental
+            .wal_segments
+            .expect("incremental backup archives WAL"),
+    );
+    assert!(segments.continues(&full_segments));
+
+    client
+        .batch_execute("DROP TABLE warden_incremental_test")
+        .await?;
+    Ok(())
+}
+
+#[test]
+fn test_wal_gap_detection() {
+    let segment_size = postgres::wal::DEFAULT_WAL_SEGMENT_SIZE;
+    let mut catalog = BackupCatalog::new();
+
+    let mut full = Backup::new(
+        BackupType::Full,
+        "full".into(),
+        "mock-version".to_string(),
+        None,
+    );
+    full.wal_segments = Some(WalSegmentRange::from_lsns(
+        1,
+        0x200_0028,
+        0x300_0000,
+        segment_size,
+    ));
+    full.complete("0/3000000".to_string(), 0);
+    let full_id = full.id;
+    catalog.add_backup(full);
+
+    // Picks up right after the full backup
+    let mut first = Backup::new(
+        BackupType::Incremental,
+        "first".into(),
+        "mock-version".to_string(),
+        Some(full_id),
+    );
+    first.wal_segments = Some(WalSegmentRange::from_lsns(
+        1,
+        0x300_0000,
+        0x500_0000,
+        segment_size,
+    ));
+    first.complete("0/5000000".to_string(), 0);
+    let first_id = first.id;
+    catalog.add_backup(first);
+
+    assert!(catalog.find_wal_gaps(&full_id).is_empty());
+    assert_eq!(
+        catalog.get_latest_backup_in_chain(&full_id).map(|b| b.id),
+        Some(first_id)
+    );
+
+    // Segment 5 was never archived
+    let mut second = Backup::new(
+        BackupType::Incremental,
+        "second".into(),
+        "mock-version".to_string(),
+        Some(full_id),
+    );
+    second.wal_segments = Some(WalSegmentRange::from_lsns(
+        1,
+        0x600_0000,
+        0x700_0000,
+        segment_size,
+    ));
+    second.complete("0/7000000".to_string(), 0);
+    let second_id = second.id;
+    catalog.add_backup(second);
+
+    assert_eq!(catalog.find_wal_gaps(&full_id), vec![(first_id, second_id)]);
+}
+
+#[tokio::test]
+async fn test_point_in_time_restore_writes_recovery_config(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let segment_size = postgres::wal::DEFAULT_WAL_SEGMENT_SIZE;
+    let backup_dir = tempdir()?;
+    let restore_root = tempdir()?;
+    let target_dir = restore_root.path().join("data");
+
+    // Lay out what pg_basebackup --format=tar --gzip would have produced
+    let full_path = backup_dir.path().join("full_backup_test");
+    std::fs::create_dir_all(&full_path)?;
+    let tarball = std::fs::File::create(full_path.join("base.tar.gz"))?;
+    let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+        tarball,
+        flate2::Compression::default(),
+    ));
+    let mut header = tar::Header::new_gnu();
+    header.set_size(3);
+    header.set_mode(0o600);
+    header.set_cksum();
+    builder.append_data(&mut header, "PG_VERSION", &b"16\n"[..])?;
+    builder.into_inner()?.finish()?;
+
+    let mut full = Backup::new(
+        BackupType::Full,
+        full_path,
+        "PostgreSQL 16.2 on x86_64-pc-linux-gnu".to_string(),
+        None,
+    );
+    full.wal_segments = Some(WalSegmentRange::from_lsns(
+        1,
+        0x200_0028,
+        0x300_0000,
+        segment_size,
+    ));
+    full.complete("0/3000000".to_string(), 0);
+
+    let incremental_path = backup_dir.path().join("incremental_backup_test");
+    std::fs::create_dir_all(incremental_path.join("pg_wal"))?;
+    std::fs::write(
+        incremental_path.join("pg_wal/000000010000000000000003"),
+        b"wal",
+    )?;
+    let mut incremental = Backup::new(
+        BackupType::Incremental,
+        incremental_path,
+        full.server_version.clone(),
+        Some(full.id),
+    );
+    incremental.wal_segments = Some(WalSegmentRange::from_lsns(
+        1,
+        0x300_0000,
+        0x400_0000,
+        segment_size,
+    ));
+    incremental.complete("0/4000000".to_string(), 0);
+
+    let mut manager = PostgresManager::new(create_test_config(), backup_dir.path().to_path_buf())?;
+    manager.add_backup_to_catalog(full.clone())?;
+    manager.add_backup_to_catalog(incremental)?;
+
+    // Recovery cannot stop before the full backup is consistent
+    let too_early = manager
+        .restore_point_in_time(
+            &full.id,
+            target_dir.clone(),
+            RecoveryTarget::Lsn("0/1000000".to_string()),
+            RecoveryTargetAction::Promote,
+        )
+        .await;
+    assert!(too_early.is_err());
+    assert!(!target_dir.exists());
+
+    manager
+        .restore_point_in_time(
+            &full.id,
+            target_dir.clone(),
+            RecoveryTarget::Lsn("0/3800000".to_string()),
+            RecoveryTargetAction::Pause,
+        )
+        .await?;
+
+    assert!(target_dir.join("PG_VERSION").exists());
+    assert!(target_dir.join("recovery.signal").exists());
+    let auto_conf = std::fs::read_to_string(target_dir.join("postgresql.auto.conf"))?;
+    assert!(auto_conf.contains("recovery_target_lsn = '0/3800000'"));
+    assert!(auto_conf.contains("recovery_target_action = 'pause'"));
+    assert!(auto_conf.contains("restore_command = 'cp"));
+    assert!(restore_root
+        .path()
+        .join("data_wal_archive/000000010000000000000003")
+        .exists());
+
+    Ok(())
+}
@@ -127,7 +127,11 @@ async fn main() -> Result<()> {
                 ssh_key_path,
                 ssh_local_port,
                 ssh_remote_port,
+                ssh_known_hosts,
+                ssh_host_key_fingerprint,
                 backup_dir,
+                compression,
+                replication_slot,
                 remote_storage,
                 storage_provider,
                 storage_bucket,
@@ -136,7 +140,24 @@ async fn main() -> Result<()> {
                 storage_endpoint,
                 storage_access_key,
                 storage_secret_key,
+                storage_key_file,
+                encryption_key,
+                encryption_key_file,
+                object_lock_days,
+                object_lock_mode,
+                storage_class,
+                replicate_to,
+                bandwidth_limit,
+                nice,
+                ionice_class,
+                ionice_level,
             } => {
+                let throttle = postgres::cli::commands::ThrottleOptions {
+                    bandwidth_limit,
+                    nice,
+                    ionice_class,
+                    ionice_level,
+                };
                 let ssh = postgres::cli::commands::SshOptions {
                     host: ssh_host,
                     user: ssh_user,
@@ -145,6 +166,8 @@ async fn main() -> Result<()> {
                     key_path: ssh_key_path,
                     local_port: ssh_local_port,
                     remote_port: ssh_remote_port,
+                    known_hosts: ssh_known_hosts,
+                    host_key_fingerprint: ssh_host_key_fingerprint,
                 };
                 let storage = postgres::cli::commands::StorageOptions {
                     remote_storage,
@@ -155,9 +178,27 @@ async fn main() -> Result<()> {
                     endpoint: storage_endpoint,
                     access_key: storage_access_key,
                     secret_key: storage_secret_key,
+                    key_file: storage_key_file,
+                    encryption_key,
+                    encryption_key_file,
+                    replicate_to,
+                    object_lock_days,
+                    object_lock_mode,
+                    storage_class,
                 };
                 postgres::cli::commands::full_backup(
-                    host, port, database, user, password, ssl_mode, backup_dir, ssh, storage,
+                    host,
+                    port,
+                    database,
+                    user,
+                    password,
+                    ssl_mode,
+                    backup_dir,
+                    compression,
+                    replication_slot,
+                    ssh,
+                    storage,
+                    throttle,
                 )
                 .await?;
             }
@@ -169,6 +210,8 @@ async fn main() -> Result<()> {
                 password,
                 ssl_mode,
                 backup_dir,
+                compression,
+                replication_slot,
                 ssh_host,
                 ssh_user,
                 ssh_port,
@@ -176,6 +219,8 @@ async fn main() -> Result<()> {
                 ssh_key_path,
                 ssh_local_port,
                 ssh_remote_port,
+                ssh_known_hosts,
+                ssh_host_key_fingerprint,
                 remote_storage,
                 storage_provider,
                 storage_bucket,
@@ -184,7 +229,24 @@ async fn main() -> Result<()> {
                 storage_endpoint,
                 storage_access_key,
                 storage_secret_key,
+                storage_key_file,
+                encryption_key,
+                encryption_key_file,
+                object_lock_days,
+                object_lock_mode,
+                storage_class,
+                replicate_to,
+                bandwidth_limit,
+                nice,
+                ionice_class,
+                ionice_level,
             } => {
+                let throttle = postgres::cli::commands::ThrottleOptions {
+                    bandwidth_limit,
+                    nice,
+                    ionice_class,
+                    ionice_level,
+                };
                 let ssh = postgres::cli::commands::SshOptions {
                     host: ssh_host,
                     user: ssh_user,
@@ -193,6 +255,8 @@ async fn main() -> Result<()> {
                     key_path: ssh_key_path,
                     local_port: ssh_local_port,
                     remote_port: ssh_remote_port,
+                    known_hosts: ssh_known_hosts,
+                    host_key_fingerprint: ssh_host_key_fingerprint,
                 };
                 let storage = postgres::cli::commands::StorageOptions {
                     remote_storage,
@@ -203,9 +267,27 @@ async fn main() -> Result<()> {
                     endpoint: storage_endpoint,
                     access_key: storage_access_key,
                     secret_key: storage_secret_key,
+                    key_file: storage_key_file,
+                    encryption_key,
+                    encryption_key_file,
+                    replicate_to,
+                    object_lock_days,
+                    object_lock_mode,
+                    storage_class,
                 };
                 postgres::cli::commands::incremental_backup(
-                    host, port, database, user, password, ssl_mode, backup_dir, ssh, storage,
+                    host,
+                    port,
+                    database,
+                    user,
+                    password,
+                    ssl_mode,
+                    backup_dir,
+                    compression,
+                    replication_slot,
+                    ssh,
+                    storage,
+                    throttle,
                 )
                 .await?;
             }
@@ -217,6 +299,7 @@ async fn main() -> Result<()> {
                 password,
                 ssl_mode,
                 backup_dir,
+                compression,
                 remote_storage,
                 storage_provider,
                 storage_bucket,
@@ -225,6 +308,17 @@ async fn main() -> Result<()> {
                 storage_endpoint,
                 storage_access_key,
                 storage_secret_key,
+                storage_key_file,
+                encryption_key,
+                encryption_key_file,
+                object_lock_days,
+                object_lock_mode,
+                storage_class,
+                replicate_to,
+                bandwidth_limit,
+                nice,
+                ionice_class,
+                ionice_level,
                 ssh_host,
                 ssh_user,
                 ssh_port,
@@ -232,7 +326,15 @@ async fn main() -> Result<()> {
                 ssh_key_path,
                 ssh_local_port,
                 ssh_remote_port,
+                ssh_known_hosts,
+                ssh_host_key_fingerprint,
             } => {
+                let throttle = postgres::cli::commands::ThrottleOptions {
+                    bandwidth_limit,
+                    nice,
+                    ionice_class,
+                    ionice_level,
+                };
                 eprintln!("[CLI] Starting snapshot-backup command...");
                 eprintln!("[CLI] Parameters: host={host}, port={port}, database={database}, user={user}, backup_dir={backup_dir:?}, remote_storage={remote_storage}");
                 let ssh = postgres::cli::commands::SshOptions {
@@ -243,6 +345,8 @@ async fn main() -> Result<()> {
                     key_path: ssh_key_path,
                     local_port: ssh_local_port,
                     remote_port: ssh_remote_port,
+                    known_hosts: ssh_known_hosts,
+                    host_key_fingerprint: ssh_host_key_fingerprint,
                 };
                 let storage = postgres::cli::commands::StorageOptions {
                     remote_storage,
@@ -253,6 +357,13 @@ async fn main() -> Result<()> {
                     endpoint: storage_endpoint,
                     access_key: storage_access_key,
                     secret_key: storage_secret_key,
+                    key_file: storage_key_file,
+                    encryption_key,
+                    encryption_key_file,
+                    replicate_to,
+                    object_lock_days,
+                    object_lock_mode,
+                    storage_class,
                 };
                 match postgres::cli::commands::snapshot_backup(
                     host,
@@ -262,8 +373,10 @@ async fn main() -> Result<()> {
                     password,
                     ssl_mode,
                     backup_dir.clone(),
+                    compression,
                     ssh,
                     storage,
+                    throttle,
                 )
                 .await
                 {
@@ -277,6 +390,49 @@ async fn main() -> Result<()> {
                     }
                 }
             }
+            postgres::cli::PostgresqlCommands::StreamWal {
+                host,
+                port,
+                database,
+                user,
+                password,
+                ssl_mode,
+                backup_dir,
+                replication_slot,
+                ssh_host,
+                ssh_user,
+                ssh_port,
+                ssh_password,
+                ssh_key_path,
+                ssh_local_port,
+                ssh_remote_port,
+                ssh_known_hosts,
+                ssh_host_key_fingerprint,
+            } => {
+                let ssh = postgres::cli::commands::SshOptions {
+                    host: ssh_host,
+                    user: ssh_user,
+                    port: ssh_port,
+                    password: ssh_password,
+                    key_path: ssh_key_path,
+                    local_port: ssh_local_port,
+                    remote_port: ssh_remote_port,
+                    known_hosts: ssh_known_hosts,
+                    host_key_fingerprint: ssh_host_key_fingerprint,
+                };
+                postgres::cli::commands::stream_wal(
+                    host,
+                    port,
+                    database,
+                    user,
+                    password,
+                    ssl_mode,
+                    backup_dir,
+                    replication_slot,
+                    ssh,
+                )
+                .await?;
+            }
             postgres::cli::PostgresqlCommands::ListBackups {
                 host,
                 port,
@@ -292,6 +448,8 @@ async fn main() -> Result<()> {
                 ssh_key_path,
                 ssh_local_port,
                 ssh_remote_port,
+                ssh_known_hosts,
+                ssh_host_key_fingerprint,
                 remote_storage,
                 storage_provider,
                 storage_bucket,
@@ -300,6 +458,7 @@ async fn main() -> Result<()> {
                 storage_endpoint,
                 storage_access_key,
                 storage_secret_key,
+                storage_key_file,
             } => {
                 let ssh = postgres::cli::commands::SshOptions {
                     host: ssh_host,
@@ -309,6 +468,8 @@ async fn main() -> Result<()> {
                     key_path: ssh_key_path,
                     local_port: ssh_local_port,
                     remote_port: ssh_remote_port,
+                    known_hosts: ssh_known_hosts,
+                    host_key_fingerprint: ssh_host_key_fingerprint,
                 };
                 let storage = postgres::cli::commands::StorageOptions {
                     remote_storage,
@@ -319,12 +480,238 @@ async fn main() -> Result<()> {
                     endpoint: storage_endpoint,
                     access_key: storage_access_key,
                     secret_key: storage_secret_key,
+                    key_file: storage_key_file,
+                    ..Default::default()
                 };
                 postgres::cli::commands::list_backups(
                     host, port, database, user, password, ssl_mode, backup_dir, ssh, storage,
                 )
                 .await?;
             }
+            postgres::cli::PostgresqlCommands::Prune {
+                host,
+                port,
+                database,
+                user,
+                password,
+                ssl_mode,
+                backup_dir,
+                keep_last,
+                keep_daily,
+                keep_weekly,
+                keep_monthly,
+                max_age_days,
+                dry_run,
+                remote_storage,
+                storage_provider,
+                storage_bucket,
+                storage_prefix,
+                storage_region,
+                storage_endpoint,
+                replicate_to,
+                storage_access_key,
+                storage_secret_key,
+                storage_key_file,
+            } => {
+                let policy = postgres::retention::RetentionPolicy {
+                    keep_last,
+                    keep_daily,
+                    keep_weekly,
+                    keep_monthly,
+                    max_age_days,
+                };
+                let storage = postgres::cli::commands::StorageOptions {
+                    remote_storage,
+                    provider_type: storage_provider,
+                    bucket: storage_bucket,
+                    prefix: storage_prefix,
+                    region: storage_region,
+                    endpoint: storage_endpoint,
+                    access_key: storage_access_key,
+                    secret_key: storage_secret_key,
+                    key_file: storage_key_file,
+                    replicate_to,
+                    ..Default::default()
+                };
+                postgres::cli::commands::prune(
+                    host, port, database, user, password, ssl_mode, backup_dir, policy, dry_run,
+                    storage,
+                )
+                .await?;
+            }
+            postgres::cli::PostgresqlCommands::VerifyBackup {
+                host,
+                port,
+                database,
+                user,
+                password,
+                ssl_mode,
+                backup_dir,
+                backup_id,
+                queries,
+                scratch_dir,
+                verify_port,
+                pg_bin_dir,
+                timeout_secs,
+            } => {
+                let mut options = postgres::verify::VerifyOptions {
+                    scratch_dir,
+                    port: verify_port,
+                    pg_bin_dir,
+                    timeout: std::time::Duration::from_secs(timeout_secs),
+                    ..Default::default()
+                };
+                if !queries.is_empty() {
+                    options.queries = queries;
+                }
+                postgres::cli::commands::verify_backup(
+                    host, port, database, user, password, ssl_mode, backup_dir, backup_id, options,
+                )
+                .await?;
+            }
+            postgres::cli::PostgresqlCommands::VerifyIntegrity {
+                host,
+                port,
+                database,
+                user,
+                password,
+                ssl_mode,
+                backup_dir,
+                backup_id,
+                remote_storage,
+                storage_provider,
+                storage_bucket,
+                storage_prefix,
+                storage_region,
+                storage_endpoint,
+                storage_access_key,
+                storage_secret_key,
+                storage_key_file,
+                encryption_key,
+                encryption_key_file,
+            } => {
+                let storage = postgres::cli::commands::StorageOptions {
+                    remote_storage,
+                    provider_type: storage_provider,
+                    bucket: storage_bucket,
+                    prefix: storage_prefix,
+                    region: storage_region,
+                    endpoint: storage_endpoint,
+                    access_key: storage_access_key,
+                    secret_key: storage_secret_key,
+                    key_file: storage_key_file,
+                    encryption_key,
+                    encryption_key_file,
+                    ..Default::default()
+                };
+                postgres::cli::commands::verify_integrity(
+                    host, port, database, user, password, ssl_mode, backup_dir, backup_id, storage,
+                )
+                .await?;
+            }
+            postgres::cli::PostgresqlCommands::RebuildCatalog {
+                storage_provider,
+                storage_bucket,
+                storage_prefix,
+                storage_region,
+                storage_endpoint,
+                storage_access_key,
+                storage_secret_key,
+                storage_key_file,
+            } => {
+                let storage = postgres::cli::commands::StorageOptions {
+                    remote_storage: true,
+                    provider_type: storage_provider,
+                    bucket: Some(storage_bucket),
+                    prefix: storage_prefix,
+                    region: storage_region,
+                    endpoint: storage_endpoint,
+                    access_key: storage_access_key,
+                    secret_key: storage_secret_key,
+                    key_file: storage_key_file,
+                    ..Default::default()
+                };
+                postgres::cli::commands::rebuild_catalog(storage).await?;
+            }
+            postgres::cli::PostgresqlCommands::Sync {
+                storage_provider,
+                storage_bucket,
+                storage_prefix,
+                storage_region,
+                storage_endpoint,
+                storage_access_key,
+                storage_secret_key,
+                storage_key_file,
+                to,
+                object_lock_days,
+                object_lock_mode,
+            } => {
+                let storage = postgres::cli::commands::StorageOptions {
+                    remote_storage: true,
+                    provider_type: storage_provider,
+                    bucket: Some(storage_bucket),
+                    prefix: storage_prefix,
+                    region: storage_region,
+                    endpoint: storage_endpoint,
+                    access_key: storage_access_key,
+                    secret_key: storage_secret_key,
+                    key_file: storage_key_file,
+                    object_lock_days,
+                    object_lock_mode,
+                    ..Default::default()
+                };
+                postgres::cli::commands::sync(storage, to).await?;
+            }
+            postgres::cli::PostgresqlCommands::CheckObjectLock {
+                storage_provider,
+                storage_bucket,
+                storage_prefix,
+                storage_region,
+                storage_endpoint,
+                storage_access_key,
+                storage_secret_key,
+                storage_key_file,
+            } => {
+                let storage = postgres::cli::commands::StorageOptions {
+                    remote_storage: true,
+                    provider_type: storage_provider,
+                    bucket: Some(storage_bucket),
+                    prefix: storage_prefix,
+                    region: storage_region,
+                    endpoint: storage_endpoint,
+                    access_key: storage_access_key,
+                    secret_key: storage_secret_key,
+                    key_file: storage_key_file,
+                    ..Default::default()
+                };
+                postgres::cli::commands::check_object_lock(storage).await?;
+            }
+            postgres::cli::PostgresqlCommands::Tier {
+                storage_provider,
+                storage_bucket,
+                storage_prefix,
+                storage_region,
+                storage_endpoint,
+                storage_access_key,
+                storage_secret_key,
+                storage_key_file,
+                rules,
+                dry_run,
+            } => {
+                let storage = postgres::cli::commands::StorageOptions {
+                    remote_storage: true,
+                    provider_type: storage_provider,
+                    bucket: Some(storage_bucket),
+                    prefix: storage_prefix,
+                    region: storage_region,
+                    endpoint: storage_endpoint,
+                    access_key: storage_access_key,
+                    secret_key: storage_secret_key,
+                    key_file: storage_key_file,
+                    ..Default::default()
+                };
+                postgres::cli::commands::tier(storage, rules, dry_run).await?;
+            }
             postgres::cli::PostgresqlCommands::RestoreFull {
                 host,
                 port,
@@ -340,12 +727,15 @@ async fn main() -> Result<()> {
                 ssh_key_path,
                 ssh_local_port,
                 ssh_remote_port,
+                ssh_known_hosts,
+                ssh_host_key_fingerprint,
                 backup_id,
                 target_dir,
                 container_id,
                 container_type,
                 auto_restart,
                 remote_storage,
+                stream,
                 storage_provider,
                 storage_bucket,
                 storage_prefix,
@@ -353,6 +743,9 @@ async fn main() -> Result<()> {
                 storage_endpoint,
                 storage_access_key,
                 storage_secret_key,
+                storage_key_file,
+                encryption_key,
+                encryption_key_file,
             } => {
                 let ssh = postgres::cli::commands::SshOptions {
                     host: ssh_host,
@@ -362,6 +755,8 @@ async fn main() -> Result<()> {
                     key_path: ssh_key_path,
                     local_port: ssh_local_port,
                     remote_port: ssh_remote_port,
+                    known_hosts: ssh_known_hosts,
+                    host_key_fingerprint: ssh_host_key_fingerprint,
                 };
                 let storage = postgres::cli::commands::StorageOptions {
                     remote_storage,
@@ -372,6 +767,10 @@ async fn main() -> Result<()> {
                     endpoint: storage_endpoint,
                     access_key: storage_access_key,
                     secret_key: storage_secret_key,
+                    key_file: storage_key_file,
+                    encryption_key,
+                    encryption_key_file,
+                    ..Default::default()
                 };
                 postgres::cli::commands::restore_full(
                     host,
@@ -386,6 +785,7 @@ async fn main() -> Result<()> {
                     container_id,
                     container_type,
                     auto_restart,
+                    stream,
                     ssh,
                     storage,
                 )
@@ -411,6 +811,8 @@ async fn main() -> Result<()> {
                 ssh_key_path,
                 ssh_local_port,
                 ssh_remote_port,
+                ssh_known_hosts,
+                ssh_host_key_fingerprint,
                 remote_storage,
                 storage_provider,
                 storage_bucket,
@@ -419,6 +821,9 @@ async fn main() -> Result<()> {
                 storage_endpoint,
                 storage_access_key,
                 storage_secret_key,
+                storage_key_file,
+                encryption_key,
+                encryption_key_file,
             } => {
                 let ssh = postgres::cli::commands::SshOptions {
                     host: ssh_host,
@@ -428,6 +833,8 @@ async fn main() -> Result<()> {
                     key_path: ssh_key_path,
                     local_port: ssh_local_port,
                     remote_port: ssh_remote_port,
+                    known_hosts: ssh_known_hosts,
+                    host_key_fingerprint: ssh_host_key_fingerprint,
                 };
                 let storage = postgres::cli::commands::StorageOptions {
                     remote_storage,
@@ -438,6 +845,10 @@ async fn main() -> Result<()> {
                     endpoint: storage_endpoint,
                     access_key: storage_access_key,
                     secret_key: storage_secret_key,
+                    key_file: storage_key_file,
+                    encryption_key,
+                    encryption_key_file,
+                    ..Default::default()
                 };
                 postgres::cli::commands::restore_incremental(
                     host,
@@ -468,6 +879,10 @@ async fn main() -> Result<()> {
                 full_backup_id,
                 target_dir,
                 target_time,
+                target_lsn,
+                target_xid,
+                target_name,
+                target_action,
                 container_id,
                 container_type,
                 auto_restart,
@@ -478,7 +893,10 @@ async fn main() -> Result<()> {
                 ssh_key_path,
                 ssh_local_port,
                 ssh_remote_port,
+                ssh_known_hosts,
+                ssh_host_key_fingerprint,
                 remote_storage,
+                stream,
                 storage_provider,
                 storage_bucket,
                 storage_prefix,
@@ -486,6 +904,9 @@ async fn main() -> Result<()> {
                 storage_endpoint,
                 storage_access_key,
                 storage_secret_key,
+                storage_key_file,
+                encryption_key,
+                encryption_key_file,
             } => {
                 let ssh = postgres::cli::commands::SshOptions {
                     host: ssh_host,
@@ -495,6 +916,8 @@ async fn main() -> Result<()> {
                     key_path: ssh_key_path,
                     local_port: ssh_local_port,
                     remote_port: ssh_remote_port,
+                    known_hosts: ssh_known_hosts,
+                    host_key_fingerprint: ssh_host_key_fingerprint,
                 };
                 let storage = postgres::cli::commands::StorageOptions {
                     remote_storage,
@@ -505,6 +928,17 @@ async fn main() -> Result<()> {
                     endpoint: storage_endpoint,
                     access_key: storage_access_key,
                     secret_key: storage_secret_key,
+                    key_file: storage_key_file,
+                    encryption_key,
+                    encryption_key_file,
+                    ..Default::default()
+                };
+                let target = postgres::cli::commands::RecoveryTargetOptions {
+                    time: target_time,
+                    lsn: target_lsn,
+                    xid: target_xid,
+                    name: target_name,
+                    action: target_action,
                 };
                 postgres::cli::commands::restore_point_in_time(
                     host,
@@ -516,10 +950,11 @@ async fn main() -> Result<()> {
                     backup_dir,
                     full_backup_id,
                     target_dir,
-                    target_time,
+                    target,
                     container_id,
                     container_type,
                     auto_restart,
+                    stream,
                     ssh,
                     storage,
                 )
@@ -545,7 +980,10 @@ async fn main() -> Result<()> {
                 ssh_key_path,
                 ssh_local_port,
                 ssh_remote_port,
+                ssh_known_hosts,
+                ssh_host_key_fingerprint,
                 remote_storage,
+                stream,
                 storage_provider,
                 storage_bucket,
                 storage_prefix,
@@ -553,6 +991,9 @@ async fn main() -> Result<()> {
                 storage_endpoint,
                 storage_access_key,
                 storage_secret_key,
+                storage_key_file,
+                encryption_key,
+                encryption_key_file,
             } => {
                 let ssh = postgres::cli::commands::SshOptions {
                     host: ssh_host,
@@ -562,6 +1003,8 @@ async fn main() -> Result<()> {
                     key_path: ssh_key_path,
                     local_port: ssh_local_port,
                     remote_port: ssh_remote_port,
+                    known_hosts: ssh_known_hosts,
+                    host_key_fingerprint: ssh_host_key_fingerprint,
                 };
                 let storage = postgres::cli::commands::StorageOptions {
                     remote_storage,
@@ -572,6 +1015,10 @@ async fn main() -> Result<()> {
                     endpoint: storage_endpoint,
                     access_key: storage_access_key,
                     secret_key: storage_secret_key,
+                    key_file: storage_key_file,
+                    encryption_key,
+                    encryption_key_file,
+                    ..Default::default()
                 };
                 postgres::cli::commands::restore_snapshot(
                     host,
@@ -586,6 +1033,7 @@ async fn main() -> Result<()> {
                     container_id,
                     container_type,
                     auto_restart,
+                    stream,
                     ssh,
                     storage,
                 )
@@ -607,6 +1055,8 @@ async fn main() -> Result<()> {
                 ssh_key_path,
                 ssh_local_port,
                 ssh_remote_port,
+                ssh_known_hosts,
+                ssh_host_key_fingerprint,
                 remote_storage,
                 storage_provider,
                 storage_bucket,
@@ -615,6 +1065,9 @@ async fn main() -> Result<()> {
                 storage_endpoint,
                 storage_access_key,
                 storage_secret_key,
+                storage_key_file,
+                encryption_key,
+                encryption_key_file,
             } => {
                 let ssh = postgres::cli::commands::SshOptions {
                     host: ssh_host,
@@ -624,6 +1077,8 @@ async fn main() -> Result<()> {
                     key_path: ssh_key_path,
                     local_port: ssh_local_port,
                     remote_port: ssh_remote_port,
+                    known_hosts: ssh_known_hosts,
+                    host_key_fingerprint: ssh_host_key_fingerprint,
                 };
                 let storage = postgres::cli::commands::StorageOptions {
                     remote_storage,
@@ -634,6 +1089,10 @@ async fn main() -> Result<()> {
                     endpoint: storage_endpoint,
                     access_key: storage_access_key,
                     secret_key: storage_secret_key,
+                    key_file: storage_key_file,
+                    encryption_key,
+                    encryption_key_file,
+                    ..Default::default()
                 };
                 let _ = postgres::cli::commands::list_snapshot_contents(
                     host, port, database, user, password, ssl_mode, backup_dir, backup_id, ssh,
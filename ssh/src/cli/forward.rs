@@ -1,4 +1,4 @@
-use crate::ssh::SSHTunnel;
+use crate::ssh::{HostKeyCheck, SSHTunnel};
 use crate::SshError;
 use anyhow::Result;
 use clap::Parser;
@@ -44,6 +44,14 @@ pub struct ForwardCommand {
     /// The path to the private key for SSH authentication.
     #[clap(long)]
     remote_key_path: Option<String>,
+
+    /// The known_hosts file to verify the SSH host key with, instead of `~/.ssh/known_hosts`.
+    #[clap(long)]
+    known_hosts: Option<String>,
+
+    /// The SHA256 fingerprint the SSH host key must have, as `ssh-keygen -lf` prints it.
+    #[clap(long, conflicts_with = "known_hosts")]
+    host_key_fingerprint: Option<String>,
 }
 
 /// Find an available local port
@@ -61,6 +69,8 @@ pub async fn forward(cmd: ForwardCommand) -> Result<()> {
         remote_port,
         remote_password,
         remote_key_path,
+        known_hosts,
+        host_key_fingerprint,
     } = cmd;
 
     // Get local port (either specified or find available)
@@ -69,7 +79,10 @@ pub async fn forward(cmd: ForwardCommand) -> Result<()> {
 
     info!("Forwarding remote port {remote_port} on {remote_host} to local port {local_port}",);
 
-    let mut tunnel = SSHTunnel::new(ssh_host.clone(), ssh_user.clone(), Some(ssh_port));
+    let mut tunnel =
+        SSHTunnel::new(ssh_host.clone(), ssh_user.clone(), Some(ssh_port)).with_host_key_check(
+            HostKeyCheck::from_options(known_hosts, host_key_fingerprint),
+        );
 
     info!("Attempting SSH tunnel to {ssh_user}@{ssh_host}:{ssh_port}",);
 
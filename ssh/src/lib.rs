@@ -3,7 +3,8 @@ use thiserror::Error;
 pub mod cli;
 mod ssh;
 
-pub use ssh::SSHTunnel;
+pub use russh_sftp;
+pub use ssh::{HostKeyCheck, SSHTunnel, SftpClient};
 
 #[derive(Error, Debug)]
 pub enum SshError {
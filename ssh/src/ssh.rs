@@ -1,5 +1,6 @@
 use russh::keys::*;
 use russh::*;
+use russh_sftp::client::SftpSession;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
@@ -8,26 +9,121 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 
+use crate::SshError;
+
 pub struct SSHTunnel {
     pub host: String,
     pub user: String,
     private_key_path: Option<String>,
     password: Option<String>,
     port: Option<u16>,
+    host_key: HostKeyCheck,
     running: Arc<AtomicBool>,
     session: Arc<Mutex<Option<client::Handle<Client>>>>,
 }
 
-struct Client;
+/// How the key the server presents is verified
+///
+/// A server whose key cannot be verified is refused.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum HostKeyCheck {
+    /// The key must be listed for the host in `~/.ssh/known_hosts`
+    #[default]
+    KnownHosts,
+    /// The key must be listed for the host in this known_hosts file
+    KnownHostsFile(String),
+    /// The key must have this SHA256 fingerprint, as `ssh-keygen -lf` prints it
+    Fingerprint(String),
+}
+
+impl HostKeyCheck {
+    /// Check pinning `fingerprint` or trusting `known_hosts`, `~/.ssh/known_hosts` when neither is given
+    pub fn from_options(known_hosts: Option<String>, fingerprint: Option<String>) -> Self {
+        match (fingerprint, known_hosts) {
+            (Some(fingerprint), _) => Self::Fingerprint(fingerprint),
+            (None, Some(path)) => Self::KnownHostsFile(path),
+            (None, None) => Self::KnownHosts,
+        }
+    }
+}
+
+struct Client {
+    host: String,
+    port: u16,
+    host_key: HostKeyCheck,
+}
+
+impl Client {
+    /// Whether `key` is the key the server is known by
+    fn verify(&self, key: &ssh_key::PublicKey) -> bool {
+        let known = match &self.host_key {
+            HostKeyCheck::Fingerprint(expected) => {
+                let actual = key.fingerprint(ssh_key::HashAlg::Sha256).to_string();
+                let matches = fingerprint_digest(&actual) == fingerprint_digest(expected);
+                if !matches {
+                    log::error!(
+                        "Host key of {} is {actual}, not the pinned {expected}",
+                        self.host
+                    );
+                }
+                return matches;
+            }
+            HostKeyCheck::KnownHosts => check_known_hosts(&self.host, self.port, key),
+            HostKeyCheck::KnownHostsFile(path) => {
+                check_known_hosts_path(&self.host, self.port, key, path)
+            }
+        };
+        match known {
+            Ok(true) => true,
+            Ok(false) => {
+                log::error!(
+                    "Host key of {}:{} is not in known_hosts, add it with `ssh-keyscan -p {} {}`",
+                    self.host,
+                    self.port,
+                    self.port,
+                    self.host
+                );
+                false
+            }
+            Err(e) => {
+                log::error!("Host key of {}:{} rejected: {e}", self.host, self.port);
+                false
+            }
+        }
+    }
+}
+
+/// Digest of a `SHA256:...` fingerprint, with or without its prefix and padding
+fn fingerprint_digest(fingerprint: &str) -> &str {
+    let fingerprint = fingerprint.trim();
+    fingerprint
+        .strip_prefix("SHA256:")
+        .unwrap_or(fingerprint)
+        .trim_end_matches('=')
+}
+
+/// An SFTP session, keeping its SSH connection open
+pub struct SftpClient {
+    session: SftpSession,
+    _handle: client::Handle<Client>,
+}
+
+impl std::ops::Deref for SftpClient {
+    type Target = SftpSession;
+
+    fn deref(&self) -> &SftpSession {
+        &self.session
+    }
+}
 
 impl client::Handler for Client {
     type Error = russh::Error;
 
     async fn check_server_key(
         &mut self,
-        _server_public_key: &ssh_key::PublicKey,
+        server_public_key: &ssh_key::PublicKey,
     ) -> Result<bool, Self::Error> {
-        Ok(true)
+        Ok(self.verify(server_public_key))
     }
 }
 
@@ -40,10 +136,17 @@ impl SSHTunnel {
             password: None,
             running: Arc::new(AtomicBool::new(true)),
             port: Some(port.unwrap_or(22)),
+            host_key: HostKeyCheck::default(),
             session: Arc::new(Mutex::new(None)),
         }
     }
 
+    /// Verify the server's key with `host_key` rather than `~/.ssh/known_hosts`
+    pub fn with_host_key_check(mut self, host_key: HostKeyCheck) -> Self {
+        self.host_key = host_key;
+        self
+    }
+
     pub fn with_private_key_path(mut self, private_key_path: String) -> Self {
         self.private_key_path = Some(private_key_path);
         self
@@ -64,32 +167,81 @@ impl SSHTunnel {
         self.running.load(Ordering::SeqCst)
     }
 
+    /// Connect and authenticate with the private key, or else the password
+    async fn connect(&self) -> Result<client::Handle<Client>, SshError> {
+        let config = Arc::new(client::Config::default());
+        let port = self.port.unwrap_or(22);
+        let handler = Client {
+            host: self.host.clone(),
+            port,
+            host_key: self.host_key.clone(),
+        };
+        let mut session = client::connect(config, (self.host.as_str(), port), handler)
+            .await
+            .map_err(|e| SshError::ConnectionError(format!("{}: {e}", self.host)))?;
+
+        let auth_res = match (&self.private_key_path, &self.password) {
+            (Some(path), _) => {
+                let key_pair = load_secret_key(path, None).map_err(|e| {
+                    SshError::AuthenticationError(format!("Failed to load {path}: {e}"))
+                })?;
+                let hash_alg = session
+                    .best_supported_rsa_hash()
+                    .await
+                    .map_err(|e| SshError::ConnectionError(e.to_string()))?
+                    .flatten();
+                session
+                    .authenticate_publickey(
+                        &self.user,
+                        PrivateKeyWithHashAlg::new(Arc::new(key_pair), hash_alg),
+                    )
+                    .await
+            }
+            (None, Some(password)) => session.authenticate_password(&self.user, password).await,
+            (None, None) => {
+                return Err(SshError::ConfigurationError(
+                    "A private key or a password is required".to_string(),
+                ))
+            }
+        }
+        .map_err(|e| SshError::AuthenticationError(e.to_string()))?;
+
+        if !auth_res.success() {
+            return Err(SshError::AuthenticationError(format!(
+                "Authentication failed for {}@{}",
+                self.user, self.host
+            )));
+        }
+        Ok(session)
+    }
+
+    /// Open an SFTP session on the host
+    pub async fn open_sftp(&self) -> Result<SftpClient, SshError> {
+        let handle = self.connect().await?;
+        let channel = handle
+            .channel_open_session()
+            .await
+            .map_err(|e| SshError::ConnectionError(e.to_string()))?;
+        channel
+            .request_subsystem(true, "sftp")
+            .await
+            .map_err(|e| SshError::ConnectionError(e.to_string()))?;
+        let session = SftpSession::new(channel.into_stream())
+            .await
+            .map_err(|e| SshError::ConnectionError(format!("SFTP subsystem: {e}")))?;
+        Ok(SftpClient {
+            session,
+            _handle: handle,
+        })
+    }
+
     pub async fn forward_port(
         &self,
         local_port: u16,
         remote_port: u16,
         remote_host: String,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let key_pair = load_secret_key(self.private_key_path.as_ref().unwrap(), None)?;
-        let config = client::Config::default();
-        let config = Arc::new(config);
-
-        let mut session =
-            client::connect(config, (self.host.as_str(), self.port.unwrap()), Client).await?;
-
-        let auth_res = session
-            .authenticate_publickey(
-                &self.user,
-                PrivateKeyWithHashAlg::new(
-                    Arc::new(key_pair),
-                    session.best_supported_rsa_hash().await?.flatten(),
-                ),
-            )
-            .await?;
-
-        if !auth_res.success() {
-            return Err("Authentication failed".into());
-        }
+        let session = self.connect().await?;
 
         *self.session.lock().await = Some(session);
 
@@ -190,3 +342,48 @@ async fn forward_connection(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIKbTuTeGOIy2FSVwuY9aRv3yg+qYb2zLeRWrCL4ZKSCP";
+    const FINGERPRINT: &str = "SHA256:2Zy0kyKBi19d+FfkV0xZhNyoZkw+5Trw33SUUDrDJRw";
+
+    fn client(host_key: HostKeyCheck) -> Client {
+        Client {
+            host: "backups.internal".to_string(),
+            port: 2222,
+            host_key,
+        }
+    }
+
+    #[test]
+    fn pinned_fingerprint_must_match() {
+        let key = ssh_key::PublicKey::from_openssh(KEY).unwrap();
+        let pinned = client(HostKeyCheck::Fingerprint(FINGERPRINT.to_string()));
+        assert!(pinned.verify(&key));
+        let unprefixed = client(HostKeyCheck::Fingerprint(
+            FINGERPRINT.trim_start_matches("SHA256:").to_string(),
+        ));
+        assert!(unprefixed.verify(&key));
+        let other = client(HostKeyCheck::Fingerprint(
+            "SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU".to_string(),
+        ));
+        assert!(!other.verify(&key));
+    }
+
+    #[test]
+    fn unknown_hosts_are_refused() {
+        let key = ssh_key::PublicKey::from_openssh(KEY).unwrap();
+        let path = std::env::temp_dir().join(format!("known_hosts_{}", std::process::id()));
+        std::fs::write(&path, format!("[other.internal]:2222 {KEY}\n")).unwrap();
+        let known_hosts = HostKeyCheck::KnownHostsFile(path.to_string_lossy().into_owned());
+        assert!(!client(known_hosts.clone()).verify(&key));
+
+        std::fs::write(&path, format!("[backups.internal]:2222 {KEY}\n")).unwrap();
+        assert!(client(known_hosts).verify(&key));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+//! Streaming compression of backup files.
+//!
+//! A [`Compression`] names a codec and an optional level, written as
+//! `algorithm[:level]` (e.g. `zstd`, `gzip:9`, `none`). Uploaded objects carry
+//! the codec in their metadata so downloads are decoded without guessing.
+
+use crate::crypto::ByteStream;
+use crate::{RateLimiter, StorageError};
+use async_compression::tokio::bufread::{
+    GzipDecoder, GzipEncoder, Lz4Decoder, Lz4Encoder, ZstdDecoder, ZstdEncoder,
+};
+use async_compression::Level;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// Metadata entry naming the codec an object was compressed with
+pub const METADATA_COMPRESSION: &str = "compression";
+
+/// Supported compression codecs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompressionAlgorithm {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+impl CompressionAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompressionAlgorithm::None => "none",
+            CompressionAlgorithm::Gzip => "gzip",
+            CompressionAlgorithm::Zstd => "zstd",
+            CompressionAlgorithm::Lz4 => "lz4",
+        }
+    }
+
+    /// File name suffix of compressed files, empty for `None`
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CompressionAlgorithm::None => "",
+            CompressionAlgorithm::Gzip => ".gz",
+            CompressionAlgorithm::Zstd => ".zst",
+            CompressionAlgorithm::Lz4 => ".lz4",
+        }
+    }
+}
+
+impl FromStr for CompressionAlgorithm {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "" | "none" => Ok(CompressionAlgorithm::None),
+            "gzip" | "gz" => Ok(CompressionAlgorithm::Gzip),
+            "zstd" | "zst" => Ok(CompressionAlgorithm::Zstd),
+            "lz4" => Ok(CompressionAlgorithm::Lz4),
+            other => Err(StorageError::Configuration(format!(
+                "Unknown compression algorithm '{other}', expected none, gzip, zstd or lz4"
+            ))),
+        }
+    }
+}
+
+/// A codec and its level
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Compression {
+    pub algorithm: CompressionAlgorithm,
+    /// Codec specific level, the codec's default when unset
+    pub level: Option<i32>,
+}
+
+impl Compression {
+    pub fn new(algorithm: CompressionAlgorithm, level: Option<i32>) -> Self {
+        Self { algorithm, level }
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.algorithm == CompressionAlgorithm::None
+    }
+
+    /// File name suffix of compressed files, empty for `None`
+    pub fn extension(&self) -> &'static str {
+        self.algorithm.extension()
+    }
+
+    fn quality(&self) -> Level {
+        self.level.map(Level::Precise).unwrap_or_default()
+    }
+
+    /// Compress a stream of bytes
+    pub fn compress_stream(&self, input: ByteStream) -> ByteStream {
+        let reader = StreamReader::new(input);
+        let quality = self.quality();
+        match self.algorithm {
+            CompressionAlgorithm::None => Box::pin(ReaderStream::new(reader)),
+            CompressionAlgorithm::Gzip => Box::pin(ReaderStream::new(GzipEncoder::with_quality(
+                reader, quality,
+            ))),
+            CompressionAlgorithm::Zstd => Box::pin(ReaderStream::new(ZstdEncoder::with_quality(
+                reader, quality,
+            ))),
+            CompressionAlgorithm::Lz4 => {
+                Box::pin(ReaderStream::new(Lz4Encoder::with_quality(reader, quality)))
+            }
+        }
+    }
+
+    /// Decompress a stream produced by [`Compression::compress_stream`]
+    pub fn decompress_stream(&self, input: ByteStream) -> ByteStream {
+        let reader = StreamReader::new(input);
+        match self.algorithm {
+            CompressionAlgorithm::None => Box::pin(ReaderStream::new(reader)),
+            CompressionAlgorithm::Gzip => {
+                let mut decoder = GzipDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::pin(ReaderStream::new(decoder))
+            }
+            CompressionAlgorithm::Zstd => {
+                let mut decoder = ZstdDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::pin(ReaderStream::new(decoder))
+            }
+            CompressionAlgorithm::Lz4 => {
+                let mut decoder = Lz4Decoder::new(reader);
+                decoder.multiple_members(true);
+                Box::pin(ReaderStream::new(decoder))
+            }
+        }
+    }
+
+    /// Compress `src` into `dst`, returning the compressed size
+    pub async fn compress_file(&self, src: &Path, dst: &Path) -> Result<u64, StorageError> {
+        self.compress_file_limited(src, dst, None).await
+    }
+
+    /// Compress `src` into `dst`, reading it no faster than `limiter` allows
+    pub async fn compress_file_limited(
+        &self,
+        src: &Path,
+        dst: &Path,
+        limiter: Option<&RateLimiter>,
+    ) -> Result<u64, StorageError> {
+        let file = tokio::fs::File::open(src).await?;
+        let mut stream: ByteStream = Box::pin(ReaderStream::new(file));
+        if let Some(limiter) = limiter {
+            stream = limiter.throttle(stream);
+        }
+        write_stream(self.compress_stream(stream), dst).await
+    }
+
+    /// Decompress `src` into `dst`, returning the decompressed size
+    pub async fn decompress_file(&self, src: &Path, dst: &Path) -> Result<u64, StorageError> {
+        let file = tokio::fs::File::open(src).await?;
+        let stream = self.decompress_stream(Box::pin(ReaderStream::new(file)));
+        write_stream(stream, dst).await
+    }
+
+    /// Replace a compressed file with its decompressed content
+    pub async fn decompress_file_in_place(&self, path: &Path) -> Result<(), StorageError> {
+        if self.is_none() {
+            return Ok(());
+        }
+        let mut tmp_name = path.as_os_str().to_owned();
+        tmp_name.push(".decompressing");
+        let tmp_path = PathBuf::from(tmp_name);
+
+        match self.decompress_file(path, &tmp_path).await {
+            Ok(_) => {
+                tokio::fs::rename(&tmp_path, path).await?;
+                Ok(())
+            }
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&tmp_path).await;
+                Err(e)
+            }
+        }
+    }
+}
+
+async fn write_stream(mut stream: ByteStream, dst: &Path) -> Result<u64, StorageError> {
+    use futures::StreamExt;
+
+    let mut out = tokio::fs::File::create(dst).await?;
+    let mut written = 0u64;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        out.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+    }
+    out.flush().await?;
+    Ok(written)
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.level {
+            Some(level) if !self.is_none() => write!(f, "{}:{level}", self.algorithm.as_str()),
+            _ => write!(f, "{}", self.algorithm.as_str()),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (algorithm, level) = match s.split_once(':') {
+            Some((algorithm, level)) => {
+                let level = level.trim().parse::<i32>().map_err(|e| {
+                    StorageError::Configuration(format!("Invalid compression level '{level}': {e}"))
+                })?;
+                (algorithm, Some(level))
+            }
+            None => (s, None),
+        };
+        Ok(Self::new(algorithm.parse()?, level))
+    }
+}
+
+impl TryFrom<String> for Compression {
+    type Error = StorageError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Compression> for String {
+    fn from(value: Compression) -> Self {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::TryStreamExt;
+
+    #[tokio::test]
+    async fn every_codec_round_trips() {
+        let data: Vec<u8> = (0..200_000u32)
+            .flat_map(|i| (i % 97).to_le_bytes())
+            .collect();
+        for spec in ["none", "gzip", "gzip:9", "zstd:3", "lz4"] {
+            let compression: Compression = spec.parse().unwrap();
+            assert_eq!(compression.to_string(), spec);
+
+            let input: ByteStream =
+                Box::pin(futures::stream::iter(vec![Ok(Bytes::from(data.clone()))]));
+            let packed: Vec<Bytes> = compression
+                .compress_stream(input)
+                .try_collect()
+                .await
+                .unwrap();
+            let packed = packed.concat();
+            if !compression.is_none() {
+                assert!(packed.len() < data.len(), "{spec} did not compress");
+            }
+
+            let input: ByteStream = Box::pin(futures::stream::iter(vec![Ok(Bytes::from(packed))]));
+            let unpacked: Vec<Bytes> = compression
+                .decompress_stream(input)
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(unpacked.concat(), data, "{spec}");
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_codecs() {
+        assert!("brotli".parse::<Compression>().is_err());
+        assert!("zstd:fast".parse::<Compression>().is_err());
+        assert_eq!(
+            serde_json::to_string(&Compression::default()).unwrap(),
+            "\"none\""
+        );
+    }
+}
@@ -0,0 +1,473 @@
+//! Client-side envelope encryption of backup objects.
+//!
+//! Each object gets its own random 256-bit data key. The payload is sealed with
+//! AES-256-GCM in fixed-size chunks whose nonce is a chunk counter plus a
+//! final-chunk flag, so chunks can be neither reordered, dropped nor truncated.
+//! The data key is wrapped with a master key and stored in the object header
+//! next to the master key ID, which makes every object decryptable with just
+//! the keyring:
+//!
+//! ```text
+//! MAGIC | key id length (u8) | key id | wrap nonce (12) | wrapped data key (48) | chunks...
+//! ```
+//!
+//! The key ID is also recorded in the object metadata, so objects sealed with a
+//! retired master key can be found when rotating keys.
+//!
+//! Only backup files are sealed. The catalog and the `metadata.json` of each
+//! backup, like object keys and metadata, are stored in clear.
+
+use crate::{Metadata, StorageError};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+
+/// Value of the `encryption` metadata entry of encrypted objects
+pub const ENCRYPTION_ALGORITHM: &str = "aes-256-gcm-chunked";
+/// Metadata entry naming the encryption scheme
+pub const METADATA_ENCRYPTION: &str = "encryption";
+/// Metadata entry holding the ID of the master key that wrapped the data key
+pub const METADATA_KEY_ID: &str = "encryption-key-id";
+
+const MAGIC: &[u8; 8] = b"WRDNENC1";
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+const WRAPPED_KEY_SIZE: usize = 32 + TAG_SIZE;
+
+/// A stream of bytes as accepted by [`crate::StorageProvider::upload_stream`]
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, io::Error>> + Send>>;
+
+/// A 256-bit master key used to wrap per-object data keys
+#[derive(Clone)]
+pub struct MasterKey {
+    id: String,
+    key: Key<Aes256Gcm>,
+}
+
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MasterKey").field("id", &self.id).finish()
+    }
+}
+
+impl MasterKey {
+    /// Create a master key, deriving its ID from the key material when none is given
+    pub fn new(id: Option<String>, key: [u8; 32]) -> Result<Self, StorageError> {
+        let id = match id {
+            Some(id) => id,
+            None => hex::encode(&Sha256::digest(key)[..8]),
+        };
+        if id.is_empty() || id.len() > u8::MAX as usize {
+            return Err(StorageError::Encryption(format!(
+                "Invalid key ID '{id}': must be 1 to 255 bytes"
+            )));
+        }
+        Ok(Self {
+            id,
+            key: key.into(),
+        })
+    }
+
+    /// Generate a random master key
+    pub fn generate() -> Self {
+        let key = Aes256Gcm::generate_key(OsRng);
+        Self::new(None, key.into()).expect("derived key ID is valid")
+    }
+
+    /// Parse a key written as `[key-id:]base64-key`
+    pub fn parse(value: &str) -> Result<Self, StorageError> {
+        let value = value.trim();
+        let (id, encoded) = match value.rsplit_once(':') {
+            Some((id, encoded)) => (Some(id.trim().to_string()), encoded.trim()),
+            None => (None, value),
+        };
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|e| StorageError::Encryption(format!("Invalid base64 key: {e}")))?;
+        let key: [u8; 32] = bytes.try_into().map_err(|bytes: Vec<u8>| {
+            StorageError::Encryption(format!("Master key must be 32 bytes, got {}", bytes.len()))
+        })?;
+        Self::new(id, key)
+    }
+
+    /// ID recorded in the header and metadata of the objects this key wraps
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Base64 form of the key, as accepted by [`MasterKey::parse`]
+    pub fn to_base64(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(self.key)
+    }
+}
+
+/// The master keys available to encrypt and decrypt backups
+///
+/// The first key is the primary one: it wraps the data keys of new uploads.
+/// The others are only used to decrypt objects written before a rotation.
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    keys: Vec<MasterKey>,
+}
+
+impl Keyring {
+    /// Create a keyring, the first key being the primary one
+    pub fn new(keys: Vec<MasterKey>) -> Result<Self, StorageError> {
+        if keys.is_empty() {
+            return Err(StorageError::Encryption("Keyring has no keys".to_string()));
+        }
+        Ok(Self { keys })
+    }
+
+    /// Parse a keyfile: one `[key-id:]base64-key` per line, the first being
+    /// the primary key; blank lines and `#` comments are ignored
+    pub fn parse(contents: &str) -> Result<Self, StorageError> {
+        let keys = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(MasterKey::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(keys)
+    }
+
+    /// Load a keyfile, see [`Keyring::parse`] for its format
+    pub fn from_keyfile(path: &Path) -> Result<Self, StorageError> {
+        let contents = std::fs::read_to_string(path)?;
+        Self::parse(&contents)
+    }
+
+    /// Key used for new uploads
+    pub fn primary(&self) -> &MasterKey {
+        &self.keys[0]
+    }
+
+    /// Look up a key by ID
+    pub fn get(&self, id: &str) -> Option<&MasterKey> {
+        self.keys.iter().find(|k| k.id == id)
+    }
+
+    /// All keys, primary first
+    pub fn keys(&self) -> &[MasterKey] {
+        &self.keys
+    }
+
+    /// Object metadata entries describing an upload sealed with the primary key
+    pub fn metadata(&self) -> Metadata {
+        Metadata::from([
+            (
+                METADATA_ENCRYPTION.to_string(),
+                ENCRYPTION_ALGORITHM.to_string(),
+            ),
+            (METADATA_KEY_ID.to_string(), self.primary().id.clone()),
+        ])
+    }
+}
+
+/// Nonce of a chunk: big-endian counter, then 1 for the final chunk
+fn chunk_nonce(counter: u64, last: bool) -> Nonce<<Aes256Gcm as AeadCore>::NonceSize> {
+    let mut nonce = [0u8; NONCE_SIZE];
+    nonce[3..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce.into()
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+struct EncryptState {
+    input: ByteStream,
+    cipher: Aes256Gcm,
+    header: Option<Bytes>,
+    buf: BytesMut,
+    counter: u64,
+    done: bool,
+}
+
+impl EncryptState {
+    fn seal(&mut self, chunk: &[u8], last: bool) -> Result<Bytes, io::Error> {
+        let sealed = self
+            .cipher
+            .encrypt(&chunk_nonce(self.counter, last), chunk)
+            .map_err(|_| invalid_data("Failed to encrypt chunk"))?;
+        self.counter += 1;
+        Ok(Bytes::from(sealed))
+    }
+}
+
+/// Encrypt a stream with a fresh data key wrapped by `key`
+pub fn encrypt_stream(input: ByteStream, key: &MasterKey) -> Result<ByteStream, StorageError> {
+    let data_key = Aes256Gcm::generate_key(OsRng);
+    let wrap_nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let wrapped = Aes256Gcm::new(&key.key)
+        .encrypt(
+            &wrap_nonce,
+            Payload {
+                msg: &data_key,
+                aad: key.id.as_bytes(),
+            },
+        )
+        .map_err(|_| StorageError::Encryption("Failed to wrap data key".to_string()))?;
+
+    let mut header = BytesMut::with_capacity(MAGIC.len() + 1 + key.id.len() + 12 + 48);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&[key.id.len() as u8]);
+    header.extend_from_slice(key.id.as_bytes());
+    header.extend_from_slice(&wrap_nonce);
+    header.extend_from_slice(&wrapped);
+
+    let state = EncryptState {
+        input,
+        cipher: Aes256Gcm::new(&data_key),
+        header: Some(header.freeze()),
+        buf: BytesMut::new(),
+        counter: 0,
+        done: false,
+    };
+
+    let stream = futures::stream::try_unfold(state, |mut state| async move {
+        if let Some(header) = state.header.take() {
+            return Ok(Some((header, state)));
+        }
+        if state.done {
+            return Ok(None);
+        }
+        loop {
+            // Keep at least one byte back so the final chunk is never empty unless the input is
+            if state.buf.len() > CHUNK_SIZE {
+                let chunk = state.buf.split_to(CHUNK_SIZE);
+                let sealed = state.seal(&chunk, false)?;
+                return Ok(Some((sealed, state)));
+            }
+            match state.input.next().await {
+                Some(Ok(bytes)) => state.buf.extend_from_slice(&bytes),
+                Some(Err(e)) => return Err(e),
+                None => {
+                    let rest = state.buf.split();
+                    state.done = true;
+                    let sealed = state.seal(&rest, true)?;
+                    return Ok(Some((sealed, state)));
+                }
+            }
+        }
+    });
+
+    Ok(Box::pin(stream))
+}
+
+struct DecryptState {
+    input: ByteStream,
+    keyring: Keyring,
+    cipher: Option<Aes256Gcm>,
+    buf: BytesMut,
+    counter: u64,
+    done: bool,
+}
+
+impl DecryptState {
+    /// Parse the header once enough bytes are buffered, returns false if more are needed
+    fn read_header(&mut self) -> Result<bool, io::Error> {
+        if self.buf.len() < MAGIC.len() + 1 {
+            return Ok(false);
+        }
+        if &self.buf[..MAGIC.len()] != MAGIC {
+            return Err(invalid_data("Not an encrypted backup object"));
+        }
+        let id_len = self.buf[MAGIC.len()] as usize;
+        let header_len = MAGIC.len() + 1 + id_len + NONCE_SIZE + WRAPPED_KEY_SIZE;
+        if self.buf.len() < header_len {
+            return Ok(false);
+        }
+
+        let mut header = self.buf.split_to(header_len);
+        header.advance(MAGIC.len() + 1);
+        let id = String::from_utf8_lossy(&header[..id_len]).to_string();
+        header.advance(id_len);
+        let (wrap_nonce, wrapped) = header.split_at(NONCE_SIZE);
+
+        let key = self
+            .keyring
+            .get(&id)
+            .ok_or_else(|| invalid_data(format!("Object was encrypted with unknown key '{id}'")))?;
+        let data_key = Aes256Gcm::new(&key.key)
+            .decrypt(
+                Nonce::from_slice(wrap_nonce),
+                Payload {
+                    msg: wrapped,
+                    aad: id.as_bytes(),
+                },
+            )
+            .map_err(|_| invalid_data(format!("Failed to unwrap data key with key '{id}'")))?;
+        let data_key = Key::<Aes256Gcm>::from_slice(&data_key);
+        self.cipher = Some(Aes256Gcm::new(data_key));
+        Ok(true)
+    }
+
+    fn open(&mut self, chunk: &[u8], last: bool) -> Result<Bytes, io::Error> {
+        let cipher = self.cipher.as_ref().expect("header parsed before chunks");
+        let plain = cipher
+            .decrypt(&chunk_nonce(self.counter, last), chunk)
+            .map_err(|_| invalid_data("Encrypted backup object is corrupt or truncated"))?;
+        self.counter += 1;
+        Ok(Bytes::from(plain))
+    }
+}
+
+/// Decrypt a stream produced by [`encrypt_stream`], using the key named in its header
+pub fn decrypt_stream(input: ByteStream, keyring: Keyring) -> ByteStream {
+    let state = DecryptState {
+        input,
+        keyring,
+        cipher: None,
+        buf: BytesMut::new(),
+        counter: 0,
+        done: false,
+    };
+
+    let stream = futures::stream::try_unfold(state, |mut state| async move {
+        if state.done {
+            return Ok(None);
+        }
+        loop {
+            if state.cipher.is_none() {
+                if state.read_header()? {
+                    continue;
+                }
+            } else if state.buf.len() > CHUNK_SIZE + TAG_SIZE {
+                // More data follows, so this cannot be the final chunk
+                let chunk = state.buf.split_to(CHUNK_SIZE + TAG_SIZE);
+                let plain = state.open(&chunk, false)?;
+                return Ok(Some((plain, state)));
+            }
+            match state.input.next().await {
+                Some(Ok(bytes)) => state.buf.extend_from_slice(&bytes),
+                Some(Err(e)) => return Err(e),
+                None => {
+                    if state.cipher.is_none() {
+                        return Err(invalid_data("Encrypted backup object header is truncated"));
+                    }
+                    let rest = state.buf.split();
+                    state.done = true;
+                    let plain = state.open(&rest, true)?;
+                    return Ok(Some((plain, state)));
+                }
+            }
+        }
+    });
+
+    Box::pin(stream)
+}
+
+/// Replace an encrypted file with its plaintext
+pub async fn decrypt_file_in_place(path: &Path, keyring: &Keyring) -> Result<(), StorageError> {
+    let file = tokio::fs::File::open(path).await?;
+    let mut plain = decrypt_stream(Box::pin(ReaderStream::new(file)), keyring.clone());
+
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".decrypting");
+    let tmp_path = std::path::PathBuf::from(tmp_name);
+
+    let result = async {
+        let mut out = tokio::fs::File::create(&tmp_path).await?;
+        while let Some(chunk) = plain.next().await {
+            let chunk = chunk.map_err(|e| StorageError::Encryption(e.to_string()))?;
+            out.write_all(&chunk).await?;
+        }
+        out.flush().await?;
+        tokio::fs::rename(&tmp_path, path).await?;
+        Ok(())
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream_of(parts: Vec<Vec<u8>>) -> ByteStream {
+        Box::pin(futures::stream::iter(
+            parts.into_iter().map(|p| Ok(Bytes::from(p))),
+        ))
+    }
+
+    async fn collect(stream: ByteStream) -> Result<Vec<u8>, io::Error> {
+        let chunks: Vec<Bytes> = futures::TryStreamExt::try_collect(stream).await?;
+        Ok(chunks.concat())
+    }
+
+    #[tokio::test]
+    async fn round_trip_across_chunk_boundaries() {
+        let keyring = Keyring::new(vec![MasterKey::generate()]).unwrap();
+        for len in [0, 1, CHUNK_SIZE, CHUNK_SIZE + 1, 3 * CHUNK_SIZE + 17] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            // Feed the data in uneven pieces
+            let parts = data.chunks(10_000).map(|c| c.to_vec()).collect();
+
+            let sealed = collect(encrypt_stream(stream_of(parts), keyring.primary()).unwrap())
+                .await
+                .unwrap();
+            assert!(sealed.starts_with(MAGIC));
+
+            let plain = collect(decrypt_stream(stream_of(vec![sealed]), keyring.clone()))
+                .await
+                .unwrap();
+            assert_eq!(plain, data, "length {len}");
+        }
+    }
+
+    #[tokio::test]
+    async fn truncation_is_detected() {
+        let keyring = Keyring::new(vec![MasterKey::generate()]).unwrap();
+        let data = vec![7u8; 2 * CHUNK_SIZE + 5];
+        let sealed = collect(encrypt_stream(stream_of(vec![data]), keyring.primary()).unwrap())
+            .await
+            .unwrap();
+
+        // Drop the final chunk: the previous one was not sealed as final
+        let truncated = sealed[..sealed.len() - (5 + TAG_SIZE)].to_vec();
+        let result = collect(decrypt_stream(stream_of(vec![truncated]), keyring)).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn rotated_keys_still_decrypt() {
+        let old = MasterKey::generate();
+        let sealed = collect(encrypt_stream(stream_of(vec![b"backup".to_vec()]), &old).unwrap())
+            .await
+            .unwrap();
+
+        let keyfile = format!(
+            "# new primary\nnew:{}\n{}:{}\n",
+            MasterKey::generate().to_base64(),
+            old.id(),
+            old.to_base64()
+        );
+        let keyring = Keyring::parse(&keyfile).unwrap();
+        assert_eq!(keyring.primary().id(), "new");
+        let plain = collect(decrypt_stream(stream_of(vec![sealed.clone()]), keyring))
+            .await
+            .unwrap();
+        assert_eq!(plain, b"backup");
+
+        let other = Keyring::new(vec![MasterKey::generate()]).unwrap();
+        assert!(collect(decrypt_stream(stream_of(vec![sealed]), other))
+            .await
+            .is_err());
+    }
+}
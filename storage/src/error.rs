@@ -9,12 +9,22 @@ pub enum StorageError {
     AwsSdk(String),
     /// Authentication error
     Authentication(String),
+    /// Azure Blob Storage error
+    Azure(String),
     /// Configuration error
     Configuration(String),
+    /// A conditional write lost against a concurrent writer
+    Conflict(String),
+    /// Encryption or decryption error
+    Encryption(String),
     /// Google error
     Google(String),
+    /// A backup does not match its manifest
+    Integrity(String),
     /// I/O error
     Io(std::io::Error),
+    /// Object lock keeps the objects from being deleted
+    Locked(String),
     /// Object not found
     NotFound(String),
     /// Permission denied
@@ -33,9 +43,14 @@ impl fmt::Display for StorageError {
             StorageError::Aws(msg) => write!(f, "AWS SDK error: {msg}"),
             StorageError::AwsSdk(msg) => write!(f, "AWS SDK error: {msg}"),
             StorageError::Authentication(msg) => write!(f, "Authentication error: {msg}"),
+            StorageError::Azure(msg) => write!(f, "Azure error: {msg}"),
             StorageError::Configuration(msg) => write!(f, "Configuration error: {msg}"),
+            StorageError::Conflict(msg) => write!(f, "Conflict: {msg}"),
+            StorageError::Encryption(msg) => write!(f, "Encryption error: {msg}"),
             StorageError::Google(msg) => write!(f, "Google error: {msg}"),
+            StorageError::Integrity(msg) => write!(f, "Integrity error: {msg}"),
             StorageError::Io(err) => write!(f, "I/O error: {err}"),
+            StorageError::Locked(msg) => write!(f, "Locked: {msg}"),
             StorageError::NotFound(msg) => write!(f, "Not found: {msg}"),
             StorageError::PermissionDenied(msg) => write!(f, "Permission denied: {msg}"),
             StorageError::Request(msg) => write!(f, "Request error: {msg}"),
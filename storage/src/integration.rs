@@ -1,13 +1,29 @@
+use crate::compression::{Compression, METADATA_COMPRESSION};
+use crate::crypto::{self, ByteStream, Keyring};
+use crate::manifest::{hash_stream, IntegrityProblem, IntegrityReport, Manifest, MANIFEST_FILE};
 use crate::{
-    BackupInfo, BackupType, Metadata, StorageError, StorageProvider, StorageProviderFactory,
-    StorageProviderType,
+    BackupInfo, BackupType, BucketLockStatus, Metadata, ObjectAvailability, ObjectLock,
+    ObjectRetention, RateLimiter, RehydrationConfig, RemoteCatalog, StorageClass, StorageError,
+    StorageObject, StorageProvider, StorageProviderFactory, StorageProviderType, SyncReport,
+    TieringRule, UploadOptions,
 };
-use log::{error, info};
+use bytes::Bytes;
+use chrono::Utc;
+use futures::StreamExt;
+use log::{debug, error, info, warn};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::time::Duration;
 use tokio::fs::File;
 use tokio_util::io::ReaderStream;
 
+/// Catalog object at the top of the prefix
+const CATALOG_FILE: &str = "catalog.json";
+/// Record of one backup, next to its files
+const BACKUP_METADATA_FILE: &str = "metadata.json";
+/// How often a catalog update is retried after losing a race
+const CATALOG_UPDATE_RETRIES: u32 = 5;
+
 /// Integration with PostgreSQL backup system
 pub struct PostgresBackupStorage {
     /// Storage provider
@@ -16,6 +32,18 @@ pub struct PostgresBackupStorage {
     bucket: String,
     /// Base prefix for backups
     prefix: String,
+    /// Keys to encrypt uploads with, and decrypt downloads
+    keyring: Option<Keyring>,
+    /// Codec applied to uploads
+    compression: Compression,
+    /// Bandwidth budget of uploads
+    rate_limiter: Option<RateLimiter>,
+    /// Retention set on every uploaded backup file
+    object_lock: Option<ObjectLock>,
+    /// Class backup files are written in
+    storage_class: Option<StorageClass>,
+    /// How downloads wait for archived files
+    rehydration: RehydrationConfig,
 }
 
 impl PostgresBackupStorage {
@@ -29,9 +57,9 @@ impl PostgresBackupStorage {
         endpoint: Option<String>,
         access_key: Option<String>,
         secret_key: Option<String>,
-        _account_id: Option<String>,
-        _project_id: Option<String>,
-        _credentials_path: Option<String>,
+        account_id: Option<String>,
+        project_id: Option<String>,
+        credentials_path: Option<String>,
     ) -> Result<Self, StorageError> {
         // Create the appropriate storage provider
         let provider = match provider_type {
@@ -39,6 +67,45 @@ impl PostgresBackupStorage {
                 StorageProviderFactory::create_s3_provider(region, endpoint, access_key, secret_key)
                     .await?
             }
+            StorageProviderType::Gcs => {
+                StorageProviderFactory::create_gcs_provider(project_id, credentials_path, endpoint)
+                    .await?
+            }
+            // The account name may come as the access key, the account key as the secret key
+            StorageProviderType::Azure => {
+                StorageProviderFactory::create_azure_provider(
+                    account_id.or(access_key),
+                    secret_key,
+                    None,
+                    endpoint,
+                )
+                .await?
+            }
+            // The endpoint is the directory holding the buckets
+            StorageProviderType::Local => {
+                let root = endpoint.ok_or_else(|| {
+                    StorageError::Configuration(
+                        "The local provider needs the storage directory as its endpoint".into(),
+                    )
+                })?;
+                let root = root.strip_prefix("file://").unwrap_or(&root).to_string();
+                StorageProviderFactory::create_local_provider(root).await?
+            }
+            // The access key is the user, the secret key a password
+            StorageProviderType::Sftp => {
+                let endpoint = endpoint.ok_or_else(|| {
+                    StorageError::Configuration(
+                        "The SFTP provider needs an sftp://user@host/path endpoint".into(),
+                    )
+                })?;
+                StorageProviderFactory::create_sftp_provider(
+                    &endpoint,
+                    access_key,
+                    secret_key,
+                    credentials_path,
+                )
+                .await?
+            }
         };
 
         // Try to create the bucket regardless of whether it exists
@@ -60,17 +127,217 @@ impl PostgresBackupStorage {
             }
         }
 
-        Ok(Self {
+        Ok(Self::from_provider(provider, bucket, prefix))
+    }
+
+    /// Backup storage in `bucket` of an already configured provider
+    ///
+    /// Unlike [`Self::new`] this does not create the bucket.
+    pub fn from_provider(
+        provider: Box<dyn StorageProvider>,
+        bucket: String,
+        prefix: Option<String>,
+    ) -> Self {
+        Self {
             provider,
             bucket,
             prefix: prefix.unwrap_or_default(),
-        })
+            keyring: None,
+            compression: Compression::default(),
+            rate_limiter: None,
+            object_lock: None,
+            storage_class: None,
+            rehydration: RehydrationConfig::default(),
+        }
+    }
+
+    /// Encrypt every uploaded backup file with the primary key of `keyring`
+    ///
+    /// Downloads of encrypted objects are decrypted with whichever key of the
+    /// keyring sealed them. Only file contents are encrypted: the catalog,
+    /// `metadata.json`, object keys and object metadata stay in clear so
+    /// backups can be listed and pruned without the keys.
+    pub fn with_encryption(mut self, keyring: Keyring) -> Self {
+        self.keyring = Some(keyring);
+        self
+    }
+
+    /// Compress every uploaded file with `compression`
+    ///
+    /// The codec is recorded in the object metadata and undone on download.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Send uploads no faster than `limiter` allows
+    ///
+    /// The provider paces what goes over the wire, S3 part by part, so
+    /// uploads of files stay resumable.
+    pub fn with_rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Lock every uploaded backup file with `lock`
+    ///
+    /// The bucket needs versioning and object lock enabled, see
+    /// [`Self::object_lock_status`]. Catalog and metadata files stay unlocked
+    /// so they can be rewritten.
+    pub fn with_object_lock(mut self, lock: ObjectLock) -> Self {
+        self.object_lock = Some(lock);
+        self
+    }
+
+    /// Whether the bucket is set up for locked backups
+    pub async fn object_lock_status(&self) -> Result<BucketLockStatus, StorageError> {
+        self.provider.bucket_lock_status(&self.bucket).await
+    }
+
+    /// Write backup files in `class` instead of the default one of the bucket
+    ///
+    /// Later moves to cheaper classes are left to the rules of
+    /// [`Self::apply_tiering`].
+    pub fn with_storage_class(mut self, class: StorageClass) -> Self {
+        self.storage_class = Some(class);
+        self
+    }
+
+    /// Wait for archived files as `config` says before downloading them
+    pub fn with_rehydration(mut self, config: RehydrationConfig) -> Self {
+        self.rehydration = config;
+        self
+    }
+
+    /// How the files of a backup of `backup_type` are written, as opposed to catalog and metadata files
+    fn backup_file_options(&self, backup_type: &BackupType) -> UploadOptions {
+        UploadOptions {
+            storage_class: self.storage_class,
+            tiering: Some(backup_type.clone()),
+            rate_limiter: self.rate_limiter.clone(),
+        }
+    }
+
+    /// Applies the configured object lock to a freshly uploaded object
+    async fn lock_object(&self, key: &str) -> Result<(), StorageError> {
+        let Some(lock) = &self.object_lock else {
+            return Ok(());
+        };
+        let retention = lock.retention_from(Utc::now());
+        debug!(
+            "Locking {key} in {} mode until {}",
+            retention.mode.as_str(),
+            retention.retain_until
+        );
+        self.provider
+            .set_object_retention(&self.bucket, key, &retention)
+            .await
+    }
+
+    /// Whether uploads go through the compression or encryption pipeline
+    fn transforms_uploads(&self) -> bool {
+        !self.compression.is_none() || self.keyring.is_some()
+    }
+
+    /// Compresses and encrypts an upload, as configured
+    fn encode_upload<'a>(
+        &self,
+        stream: ByteStream,
+        content_type: Option<&'a str>,
+        metadata: Option<Metadata>,
+    ) -> Result<(ByteStream, Option<&'a str>, Option<Metadata>), StorageError> {
+        if !self.transforms_uploads() {
+            return Ok((stream, content_type, metadata));
+        }
+
+        let mut stream = stream;
+        let mut metadata = metadata.unwrap_or_default();
+        if !self.compression.is_none() {
+            stream = self.compression.compress_stream(stream);
+            metadata.insert(
+                METADATA_COMPRESSION.to_string(),
+                self.compression.algorithm.as_str().to_string(),
+            );
+        }
+        if let Some(keyring) = &self.keyring {
+            stream = crypto::encrypt_stream(stream, keyring.primary())?;
+            metadata.extend(keyring.metadata());
+        }
+        Ok((stream, Some("application/octet-stream"), Some(metadata)))
+    }
+
+    /// Uploads one local file through the compression and encryption pipeline
+    async fn put_file(
+        &self,
+        key: &str,
+        backup_type: &BackupType,
+        file_path: &Path,
+        content_type: Option<&str>,
+        metadata: Option<Metadata>,
+    ) -> Result<(), StorageError> {
+        if !self.transforms_uploads() {
+            self.provider
+                .upload_file_with(
+                    &self.bucket,
+                    key,
+                    file_path,
+                    content_type,
+                    metadata,
+                    &self.backup_file_options(backup_type),
+                )
+                .await?;
+            return self.lock_object(key).await;
+        }
+
+        let file = File::open(file_path).await.map_err(|e| {
+            error!("Failed to open file {}: {}", file_path.display(), e);
+            StorageError::Io(e)
+        })?;
+        let (stream, content_type, metadata) =
+            self.encode_upload(Box::pin(ReaderStream::new(file)), content_type, metadata)?;
+
+        self.provider
+            .upload_stream_with(
+                &self.bucket,
+                key,
+                stream,
+                content_type,
+                metadata,
+                &self.backup_file_options(backup_type),
+            )
+            .await?;
+        self.lock_object(key).await
+    }
+
+    /// Undoes the encryption and compression of a freshly downloaded file
+    async fn decode_download(&self, key: &str, path: &Path) -> Result<(), StorageError> {
+        // The metadata written at upload says what to undo, not the content
+        let metadata = self
+            .provider
+            .get_object_metadata(&self.bucket, key)
+            .await?
+            .metadata
+            .unwrap_or_default();
+        if metadata.contains_key(crypto::METADATA_ENCRYPTION) {
+            let keyring = self.keyring.as_ref().ok_or_else(|| {
+                StorageError::Encryption(format!(
+                    "Object {key} is encrypted but no encryption key was provided"
+                ))
+            })?;
+            crypto::decrypt_file_in_place(path, keyring).await?;
+        }
+        if let Some(codec) = metadata.get(METADATA_COMPRESSION) {
+            let compression: Compression = codec.parse()?;
+            compression.decompress_file_in_place(path).await?;
+        }
+        Ok(())
     }
 
     /// Uploads a backup directory to storage
     pub async fn upload_backup(
         &self,
         backup_id: &str,
+        backup_type: &BackupType,
         backup_path: &Path,
         metadata: Option<Metadata>,
     ) -> Result<(), StorageError> {
@@ -126,15 +393,14 @@ impl PostgresBackupStorage {
                     scope.set_tag("endpoint", option_env!("AWS_ENDPOINT").unwrap_or("unknown"));
                 });
                 // --- End Sentry scope ---
-                self.provider
-                    .upload_file(
-                        &self.bucket,
-                        &key,
-                        entry.path(),
-                        content_type,
-                        metadata.clone(),
-                    )
-                    .await?;
+                self.put_file(
+                    &key,
+                    backup_type,
+                    entry.path(),
+                    content_type,
+                    metadata.clone(),
+                )
+                .await?;
             }
         }
 
@@ -146,6 +412,7 @@ impl PostgresBackupStorage {
     pub async fn upload_backup_stream(
         &self,
         backup_id: &str,
+        backup_type: &BackupType,
         file_name: &str,
         file_path: &Path,
         metadata: Option<Metadata>,
@@ -174,7 +441,8 @@ impl PostgresBackupStorage {
             StorageError::Io(e)
         })?;
 
-        let stream = ReaderStream::new(file);
+        let (stream, content_type, metadata) =
+            self.encode_upload(Box::pin(ReaderStream::new(file)), content_type, metadata)?;
 
         // --- Sentry scope for upload_stream ---
         sentry::configure_scope(|scope| {
@@ -191,8 +459,16 @@ impl PostgresBackupStorage {
         });
         // --- End Sentry scope ---
         self.provider
-            .upload_stream(&self.bucket, &key, Box::pin(stream), content_type, metadata)
+            .upload_stream_with(
+                &self.bucket,
+                &key,
+                stream,
+                content_type,
+                metadata,
+                &self.backup_file_options(backup_type),
+            )
             .await?;
+        self.lock_object(&key).await?;
 
         info!("Backup file {file_name} streamed successfully");
         Ok(())
@@ -202,6 +478,7 @@ impl PostgresBackupStorage {
     pub async fn upload_physical_backup(
         &self,
         backup_id: &str,
+        backup_type: &BackupType,
         backup_path: &Path,
         metadata: Option<Metadata>,
     ) -> Result<(), StorageError> {
@@ -229,42 +506,47 @@ impl PostgresBackupStorage {
             )));
         }
 
-        // List all files in the backup directory
-        let backup_files = match std::fs::read_dir(backup_path) {
-            Ok(files) => files,
-            Err(e) => {
+        // Upload every file below the backup directory, WAL subdirectories included
+        let backup_files = walkdir::WalkDir::new(backup_path)
+            .follow_links(false)
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| {
                 error!(
                     "Failed to read backup directory {}: {}",
                     backup_path.display(),
                     e
                 );
-                return Err(StorageError::Io(e));
-            }
-        };
-
-        // Upload each file in the backup directory
-        for file_result in backup_files {
-            let file = match file_result {
-                Ok(f) => f,
-                Err(e) => {
-                    error!("Failed to read file in backup directory: {e}");
-                    return Err(StorageError::Io(e));
-                }
-            };
+                StorageError::Io(e.into())
+            })?;
 
+        for file in backup_files {
+            if !file.file_type().is_file() {
+                continue;
+            }
             let file_path = file.path();
-            if file_path.is_file() {
-                let file_name = file.file_name().to_string_lossy().to_string();
-                info!("Uploading file: {} ({})", file_name, file_path.display());
-                match self
-                    .upload_backup_stream(backup_id, &file_name, &file_path, metadata.clone())
-                    .await
-                {
-                    Ok(_) => info!("Successfully uploaded file: {file_name}"),
-                    Err(e) => {
-                        error!("Failed to upload file {file_name}: {e}");
-                        return Err(e);
-                    }
+            let file_name = file_path
+                .strip_prefix(backup_path)
+                .map_err(|e| StorageError::Unexpected(e.to_string()))?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            info!("Uploading file: {} ({})", file_name, file_path.display());
+            match self
+                .upload_backup_stream(
+                    backup_id,
+                    backup_type,
+                    &file_name,
+                    file_path,
+                    metadata.clone(),
+                )
+                .await
+            {
+                Ok(_) => info!("Successfully uploaded file: {file_name}"),
+                Err(e) => {
+                    error!("Failed to upload file {file_name}: {e}");
+                    return Err(e);
                 }
             }
         }
@@ -275,10 +557,11 @@ impl PostgresBackupStorage {
     pub async fn upload_logical_backup(
         &self,
         backup_id: &str,
+        backup_type: &BackupType,
         dump_file: &Path,
         metadata: Option<Metadata>,
     ) -> Result<(), StorageError> {
-        self.upload_backup_stream(backup_id, "pg_dump.dump", dump_file, metadata)
+        self.upload_backup_stream(backup_id, backup_type, "pg_dump.dump", dump_file, metadata)
             .await
     }
 
@@ -313,6 +596,8 @@ impl PostgresBackupStorage {
             )));
         }
 
+        self.rehydrate(&objects).await?;
+
         // Create the target directory if it doesn't exist
         tokio::fs::create_dir_all(target_dir)
             .await
@@ -338,12 +623,135 @@ impl PostgresBackupStorage {
             self.provider
                 .download_file(&self.bucket, &obj.key, &target_path)
                 .await?;
+            self.decode_download(&obj.key, &target_path).await?;
+        }
+
+        if target_dir.join(MANIFEST_FILE).exists() {
+            let manifest = Manifest::load(target_dir).await?;
+            let report = manifest.verify_dir(target_dir).await?;
+            if !report.is_ok() {
+                let problems: Vec<String> = report.problems.iter().map(|p| p.to_string()).collect();
+                return Err(StorageError::Integrity(format!(
+                    "Backup {backup_id} does not match its manifest: {}",
+                    problems.join("; ")
+                )));
+            }
+            info!(
+                "Backup {backup_id} matches its manifest ({} files)",
+                report.checked
+            );
+        } else {
+            warn!("Backup {backup_id} has no manifest, skipping integrity check");
         }
 
         info!("Backup {backup_id} downloaded successfully");
         Ok(())
     }
 
+    /// Re-hashes every file of a remote backup against its manifest
+    ///
+    /// Objects are streamed and decoded on the fly, nothing is written to disk.
+    pub async fn verify_backup_integrity(
+        &self,
+        backup_id: &str,
+    ) -> Result<IntegrityReport, StorageError> {
+        let backup_prefix = if self.prefix.is_empty() {
+            backup_id.to_string()
+        } else {
+            format!("{}/{}", self.prefix, backup_id)
+        };
+
+        let objects = self
+            .provider
+            .list_objects(&self.bucket, Some(&format!("{backup_prefix}/")))
+            .await?;
+        let keys: HashSet<&str> = objects.iter().map(|o| o.key.as_str()).collect();
+
+        let manifest_key = format!("{backup_prefix}/{MANIFEST_FILE}");
+        if !keys.contains(manifest_key.as_str()) {
+            return Err(StorageError::NotFound(format!(
+                "Backup {backup_id} has no manifest in remote storage"
+            )));
+        }
+        let mut json = Vec::new();
+        let mut stream = self.decoded_stream(&manifest_key).await?;
+        while let Some(chunk) = stream.next().await {
+            json.extend_from_slice(&chunk?);
+        }
+        let manifest = Manifest::from_slice(&json)?;
+
+        let mut report = IntegrityReport::default();
+        for entry in &manifest.files {
+            report.checked += 1;
+            let key = format!("{backup_prefix}/{}", entry.path);
+            if !keys.contains(key.as_str()) {
+                report.problems.push(IntegrityProblem::Missing {
+                    path: entry.path.clone(),
+                });
+                continue;
+            }
+            let (size, sha256) = hash_stream(self.decoded_stream(&key).await?).await?;
+            report.problems.extend(entry.compare(size, &sha256));
+        }
+
+        Ok(report)
+    }
+
+    /// Names of the files of a backup, `/` separated and relative to the backup
+    pub async fn list_backup_files(&self, backup_id: &str) -> Result<Vec<String>, StorageError> {
+        let backup_prefix = self.object_key(&format!("{backup_id}/"));
+        let objects = self
+            .provider
+            .list_objects(&self.bucket, Some(&backup_prefix))
+            .await?;
+        if objects.is_empty() {
+            return Err(StorageError::NotFound(format!(
+                "No objects found for backup {backup_id}"
+            )));
+        }
+        Ok(objects
+            .into_iter()
+            .filter_map(|obj| obj.key.strip_prefix(&backup_prefix).map(str::to_string))
+            .collect())
+    }
+
+    /// Stream one file of a backup, decrypted and decompressed, without
+    /// staging it on disk
+    pub async fn open_backup_file(
+        &self,
+        backup_id: &str,
+        file_name: &str,
+    ) -> Result<ByteStream, StorageError> {
+        info!("Streaming backup file {file_name} of backup {backup_id}");
+        self.decoded_stream(&self.object_key(&format!("{backup_id}/{file_name}")))
+            .await
+    }
+
+    /// Stream an object with its encryption and compression undone
+    async fn decoded_stream(&self, key: &str) -> Result<ByteStream, StorageError> {
+        let metadata = self
+            .provider
+            .get_object_metadata(&self.bucket, key)
+            .await?
+            .metadata
+            .unwrap_or_default();
+        let mut stream = self.provider.download_stream(&self.bucket, key).await?;
+
+        if metadata.contains_key(crypto::METADATA_ENCRYPTION) {
+            let keyring = self.keyring.clone().ok_or_else(|| {
+                StorageError::Encryption(format!(
+                    "Object {key} is encrypted but no encryption key was provided"
+                ))
+            })?;
+            stream = crypto::decrypt_stream(stream, keyring);
+        }
+        if let Some(codec) = metadata.get(METADATA_COMPRESSION) {
+            let compression: Compression = codec.parse()?;
+            stream = compression.decompress_stream(stream);
+        }
+        Ok(stream)
+    }
+
     /// Downloads a specific backup file
     pub async fn download_backup_file(
         &self,
@@ -373,16 +781,155 @@ impl PostgresBackupStorage {
         }
 
         // Download the file
+        self.rehydrate_keys(vec![key.clone()]).await?;
         self.provider
             .download_file(&self.bucket, &key, target_path)
             .await?;
+        self.decode_download(&key, target_path).await?;
 
         info!("Backup file {file_name} downloaded successfully");
         Ok(())
     }
 
+    /// Restores the archived files of several backups and waits until all are readable
+    ///
+    /// Streaming restores call this first, so they do not stall on each
+    /// archived file in turn.
+    pub async fn rehydrate_backups(&self, backup_ids: &[String]) -> Result<(), StorageError> {
+        let mut objects = Vec::new();
+        for backup_id in backup_ids {
+            let prefix = self.object_key(&format!("{backup_id}/"));
+            objects.extend(
+                self.provider
+                    .list_objects(&self.bucket, Some(&prefix))
+                    .await?,
+            );
+        }
+        self.rehydrate(&objects).await
+    }
+
+    /// Restores the objects listed in an archive class and waits until all are readable
+    ///
+    /// Objects in classes without a tier, such as S3 intelligent tiering,
+    /// may be archived too and get checked.
+    async fn rehydrate(&self, objects: &[StorageObject]) -> Result<(), StorageError> {
+        let archived = objects
+            .iter()
+            .filter(|object| {
+                object.storage_class.as_deref().is_some_and(|class| {
+                    class
+                        .parse::<StorageClass>()
+                        .map_or(true, |class| class.needs_rehydration())
+                })
+            })
+            .map(|object| object.key.clone())
+            .collect();
+        self.rehydrate_keys(archived).await
+    }
+
+    /// Requests the restore of the archived objects among `keys`, then polls until they are readable
+    async fn rehydrate_keys(&self, keys: Vec<String>) -> Result<(), StorageError> {
+        let mut pending = Vec::new();
+        for key in keys {
+            match self
+                .provider
+                .object_availability(&self.bucket, &key)
+                .await?
+            {
+                ObjectAvailability::Available => {}
+                ObjectAvailability::Archived => {
+                    self.provider
+                        .restore_object(&self.bucket, &key, self.rehydration.days)
+                        .await?;
+                    pending.push(key);
+                }
+                ObjectAvailability::Restoring => pending.push(key),
+            }
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let started = std::time::Instant::now();
+        loop {
+            info!(
+                "Waiting for {} archived objects to be restored, checking again in {:?}",
+                pending.len(),
+                self.rehydration.poll_interval
+            );
+            tokio::time::sleep(self.rehydration.poll_interval).await;
+            let mut still_pending = Vec::new();
+            for key in pending {
+                if self
+                    .provider
+                    .object_availability(&self.bucket, &key)
+                    .await?
+                    != ObjectAvailability::Available
+                {
+                    still_pending.push(key);
+                }
+            }
+            pending = still_pending;
+            if pending.is_empty() {
+                info!("Archived objects restored after {:?}", started.elapsed());
+                return Ok(());
+            }
+            if started.elapsed() >= self.rehydration.timeout {
+                return Err(StorageError::Request(format!(
+                    "{} objects were still being restored after {:?}, e.g. {}",
+                    pending.len(),
+                    self.rehydration.timeout,
+                    pending[0]
+                )));
+            }
+        }
+    }
+
+    /// Installs the lifecycle rules that move backups to cheaper storage classes as they age
+    ///
+    /// Backup files are tagged with the type of their backup at upload, and
+    /// the provider moves them by that tag, counted from their upload,
+    /// without copying them. `metadata.json` is not tagged and stays
+    /// readable. The rules replace those of the previous call and also cover
+    /// the backups taken after it.
+    pub async fn apply_tiering(&self, rules: &[TieringRule]) -> Result<(), StorageError> {
+        for rule in rules {
+            debug!(
+                "{} backups move to {} after {} days",
+                rule.backup_type.as_str(),
+                rule.class.as_str(),
+                rule.after_days
+            );
+        }
+        self.provider
+            .set_tiering_rules(&self.bucket, &self.object_key(""), rules)
+            .await
+    }
+
+    /// Key of an object below the configured prefix
+    fn object_key(&self, path: &str) -> String {
+        if self.prefix.is_empty() {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.prefix, path)
+        }
+    }
+
     /// Lists all backups
+    ///
+    /// Reads the remote catalog, falling back to the per-backup metadata
+    /// objects and, for buckets written before either existed, to guessing
+    /// from object keys.
     pub async fn list_backups(&self) -> Result<Vec<BackupInfo>, StorageError> {
+        let catalog = self.remote_catalog().await?;
+        if !catalog.backups.is_empty() {
+            return Ok(catalog.backups);
+        }
+        self.list_backups_from_keys().await
+    }
+
+    /// Reconstructs a partial view of the backups from object keys alone
+    async fn list_backups_from_keys(&self) -> Result<Vec<BackupInfo>, StorageError> {
         let prefix = if self.prefix.is_empty() {
             None
         } else {
@@ -397,6 +944,9 @@ impl PostgresBackupStorage {
 
         for obj in objects {
             let key = obj.key;
+            if key == self.object_key(CATALOG_FILE) {
+                continue;
+            }
             let parts: Vec<&str> = key.split('/').collect();
 
             if !parts.is_empty() {
@@ -412,13 +962,6 @@ impl PostgresBackupStorage {
                 };
 
                 if backup_ids.insert(backup_id.clone()) {
-                    // Get metadata file if it exists
-                    let _metadata_key = if self.prefix.is_empty() {
-                        format!("{backup_id}/metadata.json")
-                    } else {
-                        format!("{}/{}/metadata.json", self.prefix, backup_id)
-                    };
-
                     let backup_type = if key.contains("snapshot") {
                         BackupType::Snapshot
                     } else if key.contains("incremental") {
@@ -434,7 +977,8 @@ impl PostgresBackupStorage {
                         backup_type,
                         timestamp,
                         size: obj.size.unwrap_or(0),
-                        parent_id: None, // Would need to parse metadata to get this
+                        parent_id: None,
+                        details: None,
                     });
                 }
             }
@@ -443,24 +987,286 @@ impl PostgresBackupStorage {
         Ok(backup_infos)
     }
 
-    /// Lists all backups that have a specific backup as an ancestor
+    /// Lists all backups that have a specific backup as an ancestor, oldest first
     pub async fn list_backups_with_ancestor(
         &self,
-        _ancestor_id: &str,
+        ancestor_id: &str,
     ) -> Result<Vec<String>, StorageError> {
-        // Get all backups
-        let all_backups = self.list_backups().await?;
-        let mut incremental_backups = Vec::new();
-
-        // For a proper implementation, we would need to parse metadata files to determine ancestry
-        // This is a simplified version that just returns all incremental backups
-        for backup in all_backups {
-            if backup.backup_type == BackupType::Incremental {
-                incremental_backups.push(backup.id);
+        let catalog = self.remote_catalog().await?;
+        Ok(catalog
+            .descendants(ancestor_id)
+            .into_iter()
+            .map(|b| b.id.clone())
+            .collect())
+    }
+
+    /// Reads the remote catalog, or rebuilds it in memory from the
+    /// per-backup metadata objects when the bucket has none
+    pub async fn remote_catalog(&self) -> Result<RemoteCatalog, StorageError> {
+        match self.read_catalog().await? {
+            Some((catalog, _)) => Ok(catalog),
+            None => self.catalog_from_metadata().await,
+        }
+    }
+
+    /// Records a backup in the remote catalog
+    ///
+    /// The record is also written next to the backup files as
+    /// `metadata.json`, which is what [`Self::rebuild_catalog`] reads.
+    pub async fn register_backup(&self, info: BackupInfo) -> Result<(), StorageError> {
+        let key = self.object_key(&format!("{}/{BACKUP_METADATA_FILE}", info.id));
+        let json = Bytes::from(serde_json::to_vec_pretty(&info)?);
+        let stream: ByteStream = Box::pin(futures::stream::once(async move { Ok(json) }));
+        self.provider
+            .upload_stream(&self.bucket, &key, stream, Some("application/json"), None)
+            .await?;
+
+        self.update_catalog(|catalog| catalog.upsert(info.clone()))
+            .await?;
+        info!("Registered backup {} in the remote catalog", info.id);
+        Ok(())
+    }
+
+    /// Reads the record of one backup from its metadata object
+    pub async fn get_backup_info(&self, backup_id: &str) -> Result<BackupInfo, StorageError> {
+        let key = self.object_key(&format!("{backup_id}/{BACKUP_METADATA_FILE}"));
+        let (json, _) = self.provider.get_object(&self.bucket, &key).await?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    /// Replaces the remote catalog with one rebuilt from the per-backup metadata objects
+    pub async fn rebuild_catalog(&self) -> Result<RemoteCatalog, StorageError> {
+        let rebuilt = self.catalog_from_metadata().await?;
+        self.update_catalog(|catalog| *catalog = rebuilt.clone())
+            .await?;
+        info!(
+            "Rebuilt the remote catalog with {} backups",
+            rebuilt.backups.len()
+        );
+        Ok(rebuilt)
+    }
+
+    /// Where this storage points, for logs and catalogs; never holds credentials
+    pub fn describe(&self) -> String {
+        let mut location = format!("{}/{}", self.provider.name(), self.bucket);
+        if !self.prefix.is_empty() {
+            location.push('/');
+            location.push_str(&self.prefix);
+        }
+        location
+    }
+
+    /// Copies the objects of a backup to `target` as they are stored
+    ///
+    /// Encrypted or compressed objects travel as they are, with their
+    /// metadata. Objects `target` already holds with the same size are
+    /// skipped, so an interrupted copy picks up where it stopped. Returns the
+    /// number of objects copied.
+    pub async fn copy_backup_to(
+        &self,
+        target: &PostgresBackupStorage,
+        backup: &BackupInfo,
+    ) -> Result<usize, StorageError> {
+        let backup_id = &backup.id;
+        let source_prefix = self.object_key(&format!("{backup_id}/"));
+        let target_prefix = target.object_key(&format!("{backup_id}/"));
+        let objects = self
+            .provider
+            .list_objects(&self.bucket, Some(&source_prefix))
+            .await?;
+        if objects.is_empty() {
+            return Err(StorageError::NotFound(format!(
+                "No objects found for backup {backup_id}"
+            )));
+        }
+        let existing: HashMap<String, Option<u64>> = target
+            .provider
+            .list_objects(&target.bucket, Some(&target_prefix))
+            .await?
+            .into_iter()
+            .map(|o| (o.key, o.size))
+            .collect();
+
+        let mut copied = 0;
+        for object in objects {
+            let Some(rel) = object.key.strip_prefix(&source_prefix) else {
+                continue;
+            };
+            let target_key = format!("{target_prefix}{rel}");
+            if existing
+                .get(&target_key)
+                .is_some_and(|size| *size == object.size)
+            {
+                continue;
+            }
+            let metadata = self
+                .provider
+                .get_object_metadata(&self.bucket, &object.key)
+                .await?;
+            let stream = self
+                .provider
+                .download_stream(&self.bucket, &object.key)
+                .await?;
+            let is_metadata = rel.ends_with(BACKUP_METADATA_FILE);
+            let options = if is_metadata {
+                UploadOptions {
+                    rate_limiter: target.rate_limiter.clone(),
+                    ..UploadOptions::default()
+                }
+            } else {
+                target.backup_file_options(&backup.backup_type)
+            };
+            target
+                .provider
+                .upload_stream_with(
+                    &target.bucket,
+                    &target_key,
+                    stream,
+                    metadata.content_type.as_deref(),
+                    metadata.metadata,
+                    &options,
+                )
+                .await?;
+            if !is_metadata {
+                target.lock_object(&target_key).await?;
             }
+            copied += 1;
         }
+        Ok(copied)
+    }
+
+    /// Copies every backup of the catalog that `target` is missing
+    ///
+    /// Backups go oldest first so parents land before their incrementals,
+    /// and each is registered in the catalog of `target` once complete. A
+    /// backup that fails to copy is reported and the others still go.
+    pub async fn sync_to(
+        &self,
+        target: &PostgresBackupStorage,
+    ) -> Result<SyncReport, StorageError> {
+        let mut backups = self.list_backups().await?;
+        backups.sort_by_key(|b| b.timestamp);
+        let present: HashSet<String> = target
+            .remote_catalog()
+            .await?
+            .backups
+            .into_iter()
+            .map(|b| b.id)
+            .collect();
 
-        Ok(incremental_backups)
+        let mut report = SyncReport::default();
+        for backup in backups {
+            if present.contains(&backup.id) {
+                report.up_to_date.push(backup.id);
+                continue;
+            }
+            info!(
+                "Copying backup {} from {} to {}",
+                backup.id,
+                self.describe(),
+                target.describe()
+            );
+            let copied = match self.copy_backup_to(target, &backup).await {
+                Ok(copied) => copied,
+                Err(e) => {
+                    error!("Failed to copy backup {}: {e}", backup.id);
+                    report.failed.push((backup.id, e.to_string()));
+                    continue;
+                }
+            };
+            // Keep the full record when there is one, the listing may only guess
+            let info = self.get_backup_info(&backup.id).await.unwrap_or(backup);
+            let id = info.id.clone();
+            match target.register_backup(info).await {
+                Ok(()) => {
+                    info!("Copied backup {id} ({copied} objects)");
+                    report.copied.push(id);
+                }
+                Err(e) => report.failed.push((id, e.to_string())),
+            }
+        }
+        Ok(report)
+    }
+
+    async fn catalog_from_metadata(&self) -> Result<RemoteCatalog, StorageError> {
+        let prefix = if self.prefix.is_empty() {
+            None
+        } else {
+            Some(format!("{}/", self.prefix))
+        };
+        let objects = self
+            .provider
+            .list_objects(&self.bucket, prefix.as_deref())
+            .await?;
+
+        let mut catalog = RemoteCatalog::default();
+        let suffix = format!("/{BACKUP_METADATA_FILE}");
+        for obj in objects {
+            let Some(backup_path) = obj.key.strip_suffix(&suffix) else {
+                continue;
+            };
+            // Only the metadata object at the top of a backup, not files inside it
+            let backup_id = match &prefix {
+                Some(prefix) => backup_path.strip_prefix(prefix.as_str()),
+                None => Some(backup_path),
+            };
+            if backup_id.is_none_or(|id| id.contains('/')) {
+                continue;
+            }
+            let (json, _) = self.provider.get_object(&self.bucket, &obj.key).await?;
+            match serde_json::from_slice::<BackupInfo>(&json) {
+                Ok(info) => catalog.upsert(info),
+                Err(e) => warn!("Skipping unreadable backup metadata {}: {e}", obj.key),
+            }
+        }
+        Ok(catalog)
+    }
+
+    /// Reads the catalog object with its ETag, `None` if there is none yet
+    async fn read_catalog(&self) -> Result<Option<(RemoteCatalog, Option<String>)>, StorageError> {
+        match self
+            .provider
+            .get_object(&self.bucket, &self.object_key(CATALOG_FILE))
+            .await
+        {
+            Ok((json, etag)) => Ok(Some((serde_json::from_slice(&json)?, etag))),
+            Err(StorageError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read-modify-write of the catalog, retried when another host wrote it in between
+    async fn update_catalog<F>(&self, update: F) -> Result<(), StorageError>
+    where
+        F: Fn(&mut RemoteCatalog),
+    {
+        let key = self.object_key(CATALOG_FILE);
+        let mut attempt = 0;
+        loop {
+            let (mut catalog, etag) = self.read_catalog().await?.unwrap_or_default();
+            update(&mut catalog);
+            let json = Bytes::from(serde_json::to_vec_pretty(&catalog)?);
+
+            match self
+                .provider
+                .put_object_if_match(
+                    &self.bucket,
+                    &key,
+                    json,
+                    Some("application/json"),
+                    etag.as_deref(),
+                )
+                .await
+            {
+                Ok(_) => return Ok(()),
+                Err(StorageError::Conflict(msg)) if attempt < CATALOG_UPDATE_RETRIES => {
+                    attempt += 1;
+                    warn!("Remote catalog changed concurrently ({msg}), retrying");
+                    tokio::time::sleep(Duration::from_millis(100 * attempt as u64)).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
     }
 
     /// Deletes a backup
@@ -486,15 +1292,65 @@ impl PostgresBackupStorage {
             )));
         }
 
+        // With versioning, deleting a locked object only hides it behind a
+        // delete marker, so refuse rather than pretend the backup is gone
+        if let Some(retention) = self.active_retention(&objects).await? {
+            return Err(StorageError::Locked(format!(
+                "Backup {backup_id} is locked in {} mode until {}",
+                retention.mode.as_str(),
+                retention.retain_until
+            )));
+        }
+
         // Delete each object
         for obj in objects {
             self.provider.delete_object(&self.bucket, &obj.key).await?;
         }
+        self.update_catalog(|catalog| catalog.remove(backup_id))
+            .await?;
 
         info!("Backup {backup_id} deleted successfully");
         Ok(())
     }
 
+    /// Latest retention still protecting one of `objects`, if any
+    async fn active_retention(
+        &self,
+        objects: &[StorageObject],
+    ) -> Result<Option<ObjectRetention>, StorageError> {
+        match self.object_lock_status().await {
+            Ok(status) if status.object_lock => {}
+            Ok(_) => return Ok(None),
+            Err(e) => {
+                warn!(
+                    "Could not tell whether {} has object lock: {e}",
+                    self.bucket
+                );
+                return Ok(None);
+            }
+        }
+
+        let now = Utc::now();
+        let mut latest: Option<ObjectRetention> = None;
+        for object in objects {
+            let Some(retention) = self
+                .provider
+                .get_object_retention(&self.bucket, &object.key)
+                .await?
+            else {
+                continue;
+            };
+            if retention.is_active(now)
+                && latest
+                    .as_ref()
+                    .is_none_or(|l| retention.retain_until > l.retain_until)
+            {
+                latest = Some(retention);
+            }
+        }
+        Ok(latest)
+    }
+
     /// Generates a pre-signed URL for a backup file
     pub async fn generate_backup_file_url(
         &self,
@@ -4,19 +4,27 @@
 //! S3-compatible storage backends, including AWS S3, Cloudflare R2, and Google Cloud Storage.
 //! It supports streaming uploads and downloads to optimize large backup operations.
 
+pub mod compression;
+pub mod crypto;
 mod error;
 mod integration;
+pub mod manifest;
 pub mod providers;
+pub mod throttle;
 mod types;
 
+pub use compression::{Compression, CompressionAlgorithm};
+pub use crypto::{ByteStream, Keyring, MasterKey};
 pub use error::StorageError;
 pub use integration::PostgresBackupStorage;
+pub use manifest::{IntegrityProblem, IntegrityReport, Manifest};
 pub use providers::*;
+pub use throttle::RateLimiter;
 pub use types::*;
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use futures::Stream;
+use futures::{Stream, StreamExt};
 use std::path::Path;
 use std::pin::Pin;
 
@@ -84,6 +92,20 @@ pub trait StorageProvider: Send + Sync + 'static {
         key: &str,
     ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send>>, StorageError>;
 
+    /// Downloads a byte range of an object as a stream of bytes.
+    ///
+    /// Providers without native range requests read the object from the
+    /// start and drop the bytes outside the range.
+    async fn download_stream_range(
+        &self,
+        bucket: &str,
+        key: &str,
+        options: &StreamingDownloadOptions,
+    ) -> Result<ByteStream, StorageError> {
+        let stream = self.download_stream(bucket, key).await?;
+        Ok(slice_stream(stream, options))
+    }
+
     /// Gets metadata for an object.
     async fn get_object_metadata(
         &self,
@@ -92,6 +114,9 @@ pub trait StorageProvider: Send + Sync + 'static {
     ) -> Result<ObjectMetadata, StorageError>;
 
     /// Deletes an object.
+    ///
+    /// Deleting an object that does not exist succeeds, like on S3, so an
+    /// interrupted prune can simply be run again.
     async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), StorageError>;
 
     /// Checks if an object exists.
@@ -113,6 +138,242 @@ pub trait StorageProvider: Send + Sync + 'static {
         content_type: Option<&str>,
         metadata: Option<Metadata>,
     ) -> Result<(), StorageError>;
+
+    /// Uploads a file as `options` say.
+    ///
+    /// The default implementation only writes to the default class and
+    /// leaves tiering aside. A paced upload streams the file through
+    /// [`Self::upload_stream`].
+    async fn upload_file_with(
+        &self,
+        bucket: &str,
+        key: &str,
+        file_path: &Path,
+        content_type: Option<&str>,
+        metadata: Option<Metadata>,
+        options: &UploadOptions,
+    ) -> Result<(), StorageError> {
+        check_default_class(self.name(), bucket, key, options)?;
+        let Some(limiter) = &options.rate_limiter else {
+            return self
+                .upload_file(bucket, key, file_path, content_type, metadata)
+                .await;
+        };
+        let file = tokio::fs::File::open(file_path).await?;
+        let stream = limiter.throttle(Box::pin(tokio_util::io::ReaderStream::new(file)));
+        self.upload_stream(bucket, key, stream, content_type, metadata)
+            .await
+    }
+
+    /// Uploads a stream as `options` say, see [`Self::upload_file_with`].
+    async fn upload_stream_with(
+        &self,
+        bucket: &str,
+        key: &str,
+        stream: ByteStream,
+        content_type: Option<&str>,
+        metadata: Option<Metadata>,
+        options: &UploadOptions,
+    ) -> Result<(), StorageError> {
+        check_default_class(self.name(), bucket, key, options)?;
+        let stream = match &options.rate_limiter {
+            Some(limiter) => limiter.throttle(stream),
+            None => stream,
+        };
+        self.upload_stream(bucket, key, stream, content_type, metadata)
+            .await
+    }
+
+    /// Reads a small object into memory, returning its content and ETag.
+    ///
+    /// The default implementation asks for the ETag before streaming the
+    /// content, so under concurrent writes it may describe a newer version.
+    async fn get_object(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<(Bytes, Option<String>), StorageError> {
+        let etag = self.get_object_metadata(bucket, key).await?.etag;
+        let mut stream = self.download_stream(bucket, key).await?;
+        let mut body = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            body.extend_from_slice(&chunk?);
+        }
+        Ok((Bytes::from(body), etag))
+    }
+
+    /// Writes a small object only if nobody changed it since it was read.
+    ///
+    /// With `if_match` the write succeeds only while the object still has
+    /// that ETag, without it only if the object does not exist yet. A lost
+    /// race fails with [`StorageError::Conflict`]. Returns the new ETag.
+    ///
+    /// The default implementation checks and then writes, leaving a short
+    /// window for a concurrent writer. Every provider overrides it, with
+    /// native conditional writes or by holding a lock file around the
+    /// check; a new provider without either is only safe for one writer.
+    async fn put_object_if_match(
+        &self,
+        bucket: &str,
+        key: &str,
+        body: Bytes,
+        content_type: Option<&str>,
+        if_match: Option<&str>,
+    ) -> Result<Option<String>, StorageError> {
+        put_if_unchanged(self, bucket, key, body, content_type, if_match).await
+    }
+
+    /// Locks an object against deletion and overwrites until its retention expires.
+    ///
+    /// Only providers with write-once storage support this, the default fails.
+    async fn set_object_retention(
+        &self,
+        bucket: &str,
+        key: &str,
+        _retention: &ObjectRetention,
+    ) -> Result<(), StorageError> {
+        Err(StorageError::Configuration(format!(
+            "{} does not support object lock, cannot lock {bucket}/{key}",
+            self.name()
+        )))
+    }
+
+    /// Gets the retention of an object, `None` when it is not locked.
+    async fn get_object_retention(
+        &self,
+        _bucket: &str,
+        _key: &str,
+    ) -> Result<Option<ObjectRetention>, StorageError> {
+        Ok(None)
+    }
+
+    /// Reports whether a bucket has the versioning and object lock that locked objects need.
+    async fn bucket_lock_status(&self, _bucket: &str) -> Result<BucketLockStatus, StorageError> {
+        Ok(BucketLockStatus::default())
+    }
+
+    /// Replaces the tiering rules below `prefix` with `rules`.
+    ///
+    /// The provider moves the objects uploaded with [`UploadOptions::tiering`]
+    /// itself, through lifecycle rules of the bucket matching the backup type
+    /// the objects were tagged with, so nothing is copied and backups added
+    /// later are covered too. Rules for other prefixes are kept.
+    async fn set_tiering_rules(
+        &self,
+        bucket: &str,
+        _prefix: &str,
+        _rules: &[TieringRule],
+    ) -> Result<(), StorageError> {
+        Err(StorageError::Configuration(format!(
+            "{} does not support tiering rules, cannot tier {bucket}",
+            self.name()
+        )))
+    }
+
+    /// Tells whether an object can be read, or must be restored from an archive tier first.
+    async fn object_availability(
+        &self,
+        _bucket: &str,
+        _key: &str,
+    ) -> Result<ObjectAvailability, StorageError> {
+        Ok(ObjectAvailability::Available)
+    }
+
+    /// Requests a readable copy of an archived object, kept for `days`.
+    ///
+    /// Returns once the request is accepted; poll [`Self::object_availability`]
+    /// to learn when the copy is ready.
+    async fn restore_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        _days: u32,
+    ) -> Result<(), StorageError> {
+        Err(StorageError::Configuration(format!(
+            "{} does not support archive tiers, cannot restore {bucket}/{key}",
+            self.name()
+        )))
+    }
+}
+
+/// Fails for `options` asking a class of a provider without storage classes
+fn check_default_class(
+    provider: &str,
+    bucket: &str,
+    key: &str,
+    options: &UploadOptions,
+) -> Result<(), StorageError> {
+    match options.storage_class {
+        Some(class) if class != StorageClass::Standard => {
+            Err(StorageError::Configuration(format!(
+                "{provider} does not support storage classes, cannot upload {bucket}/{key} to {}",
+                class.as_str()
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Write `body` if the object still has the ETag `if_match`, then return the new one
+///
+/// Not atomic on its own, callers without native conditional writes hold
+/// a lock on the object around it.
+pub(crate) async fn put_if_unchanged<P: StorageProvider + ?Sized>(
+    provider: &P,
+    bucket: &str,
+    key: &str,
+    body: Bytes,
+    content_type: Option<&str>,
+    if_match: Option<&str>,
+) -> Result<Option<String>, StorageError> {
+    let current = match provider.get_object_metadata(bucket, key).await {
+        Ok(metadata) => Some(metadata.etag.unwrap_or_default()),
+        Err(StorageError::NotFound(_)) => None,
+        Err(e) => return Err(e),
+    };
+    if current.as_deref() != if_match {
+        return Err(StorageError::Conflict(format!(
+            "Object {bucket}/{key} changed since it was read"
+        )));
+    }
+
+    let stream = futures::stream::once(async move { Ok(body) });
+    provider
+        .upload_stream(bucket, key, Box::pin(stream), content_type, None)
+        .await?;
+    Ok(provider.get_object_metadata(bucket, key).await?.etag)
+}
+
+/// Keep only the bytes of a stream that fall within the range of `options`
+pub(crate) fn slice_stream(stream: ByteStream, options: &StreamingDownloadOptions) -> ByteStream {
+    if options.range_start.is_none() && options.range_end.is_none() {
+        return stream;
+    }
+    let start = options.range_start.unwrap_or(0);
+    // Exclusive end, `range_end` is inclusive like an HTTP range
+    let end = options.range_end.map(|end| end.saturating_add(1));
+
+    let sliced = stream
+        .scan(0u64, move |offset, chunk| {
+            let item = match chunk {
+                Err(e) => Some(Some(Err(e))),
+                Ok(chunk) => {
+                    let chunk_start = *offset;
+                    let len = chunk.len() as u64;
+                    *offset += len;
+                    if end.is_some_and(|end| chunk_start >= end) {
+                        None
+                    } else {
+                        let from = start.saturating_sub(chunk_start).min(len) as usize;
+                        let to = end.map_or(len, |end| (end - chunk_start).min(len)) as usize;
+                        Some((from < to).then(|| Ok(chunk.slice(from..to))))
+                    }
+                }
+            };
+            futures::future::ready(item)
+        })
+        .filter_map(futures::future::ready);
+    Box::pin(sliced)
 }
 
 /// Factory for creating storage providers.
@@ -130,4 +391,106 @@ impl StorageProviderFactory {
             providers::aws::S3Provider::new(region, endpoint, access_key, secret_key).await?;
         Ok(Box::new(provider))
     }
+
+    pub async fn create_gcs_provider(
+        project_id: Option<String>,
+        credentials_path: Option<String>,
+        endpoint: Option<String>,
+    ) -> Result<Box<dyn StorageProvider>, StorageError> {
+        let provider =
+            providers::gcs::GcsProvider::new(project_id, credentials_path, endpoint).await?;
+        Ok(Box::new(provider))
+    }
+
+    pub async fn create_azure_provider(
+        account: Option<String>,
+        account_key: Option<String>,
+        sas_token: Option<String>,
+        endpoint: Option<String>,
+    ) -> Result<Box<dyn StorageProvider>, StorageError> {
+        let provider =
+            providers::azure::AzureProvider::new(account, account_key, sas_token, endpoint).await?;
+        Ok(Box::new(provider))
+    }
+
+    pub async fn create_local_provider(
+        root: impl Into<std::path::PathBuf>,
+    ) -> Result<Box<dyn StorageProvider>, StorageError> {
+        let provider = providers::local::LocalProvider::new(root).await?;
+        Ok(Box::new(provider))
+    }
+
+    pub async fn create_sftp_provider(
+        endpoint: &str,
+        user: Option<String>,
+        password: Option<String>,
+        private_key_path: Option<String>,
+    ) -> Result<Box<dyn StorageProvider>, StorageError> {
+        let provider =
+            providers::sftp::SftpProvider::new(endpoint, user, password, private_key_path).await?;
+        Ok(Box::new(provider))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+
+    async fn sliced(range_start: Option<u64>, range_end: Option<u64>) -> Vec<u8> {
+        let chunks = ["0123", "4567", "89"].map(|c| Ok(Bytes::from_static(c.as_bytes())));
+        let options = StreamingDownloadOptions {
+            range_start,
+            range_end,
+        };
+        let parts: Vec<Bytes> = slice_stream(Box::pin(futures::stream::iter(chunks)), &options)
+            .try_collect()
+            .await
+            .unwrap();
+        parts.concat()
+    }
+
+    #[tokio::test]
+    async fn ranges_span_chunks() {
+        assert_eq!(sliced(None, None).await, b"0123456789");
+        assert_eq!(sliced(Some(2), Some(5)).await, b"2345");
+        assert_eq!(sliced(Some(7), None).await, b"789");
+        assert_eq!(sliced(None, Some(0)).await, b"0");
+        assert_eq!(sliced(Some(20), None).await, b"");
+    }
+
+    #[tokio::test]
+    async fn default_uploads_pace_and_refuse_other_classes() {
+        let provider = MemoryProvider::new();
+        provider.create_bucket("b").await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        std::fs::write(&path, vec![7u8; 2000]).unwrap();
+
+        // The budget holds one second's worth, the rest takes another second
+        let options = UploadOptions {
+            rate_limiter: Some(RateLimiter::new(1000)),
+            ..UploadOptions::default()
+        };
+        let started = std::time::Instant::now();
+        provider
+            .upload_file_with("b", "paced", &path, None, None, &options)
+            .await
+            .unwrap();
+        assert!(started.elapsed() >= std::time::Duration::from_millis(900));
+        assert_eq!(
+            provider.get_object("b", "paced").await.unwrap().0.len(),
+            2000
+        );
+
+        let options = UploadOptions {
+            storage_class: Some(StorageClass::Archive),
+            ..UploadOptions::default()
+        };
+        let result = provider
+            .upload_file_with("b", "archived", &path, None, None, &options)
+            .await;
+        assert!(matches!(result, Err(StorageError::Configuration(_))));
+        assert!(!provider.object_exists("b", "archived").await.unwrap());
+    }
 }
@@ -0,0 +1,250 @@
+//! Per-backup manifests of file checksums.
+//!
+//! A manifest lists every file of a backup directory with its size and
+//! SHA-256, computed over the content as it lies on disk locally. It is
+//! written into the backup directory as [`MANIFEST_FILE`] and travels with
+//! the backup, so any copy, local or remote, can be checked against it.
+
+use crate::crypto::ByteStream;
+use crate::StorageError;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::Path;
+use tokio_util::io::ReaderStream;
+
+/// Name of the manifest file inside a backup directory
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// A file of a backup
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path relative to the backup directory, `/` separated
+    pub path: String,
+    pub size: u64,
+    /// Hex encoded SHA-256 of the content
+    pub sha256: String,
+}
+
+/// Checksums of every file of a backup
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub backup_id: String,
+    pub created_at: DateTime<Utc>,
+    pub files: Vec<ManifestEntry>,
+}
+
+/// Something wrong with a copy of a backup
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum IntegrityProblem {
+    Missing {
+        path: String,
+    },
+    SizeMismatch {
+        path: String,
+        expected: u64,
+        actual: u64,
+    },
+    ChecksumMismatch {
+        path: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for IntegrityProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityProblem::Missing { path } => write!(f, "{path}: missing"),
+            IntegrityProblem::SizeMismatch {
+                path,
+                expected,
+                actual,
+            } => write!(f, "{path}: size is {actual} bytes, expected {expected}"),
+            IntegrityProblem::ChecksumMismatch {
+                path,
+                expected,
+                actual,
+            } => write!(f, "{path}: SHA-256 is {actual}, expected {expected}"),
+        }
+    }
+}
+
+/// Outcome of checking a copy of a backup against its manifest
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IntegrityReport {
+    /// Number of files checked
+    pub checked: usize,
+    pub problems: Vec<IntegrityProblem>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl Manifest {
+    /// Hash every file below `dir`, except an existing manifest
+    pub async fn build(backup_id: &str, dir: &Path) -> Result<Self, StorageError> {
+        let mut paths: Vec<_> = walkdir::WalkDir::new(dir)
+            .follow_links(false)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .collect();
+        paths.sort();
+
+        let mut files = Vec::with_capacity(paths.len());
+        for path in paths {
+            let rel = relative_path(dir, &path)?;
+            if rel == MANIFEST_FILE {
+                continue;
+            }
+            let (size, sha256) = hash_file(&path).await?;
+            files.push(ManifestEntry {
+                path: rel,
+                size,
+                sha256,
+            });
+        }
+
+        Ok(Self {
+            version: 1,
+            backup_id: backup_id.to_string(),
+            created_at: Utc::now(),
+            files,
+        })
+    }
+
+    /// Write the manifest into `dir`, returning the SHA-256 of the written file
+    pub async fn write_to(&self, dir: &Path) -> Result<String, StorageError> {
+        let json = serde_json::to_vec_pretty(self)?;
+        tokio::fs::write(dir.join(MANIFEST_FILE), &json).await?;
+        Ok(hex::encode(Sha256::digest(&json)))
+    }
+
+    /// Load the manifest of the backup in `dir`
+    pub async fn load(dir: &Path) -> Result<Self, StorageError> {
+        let json = tokio::fs::read(dir.join(MANIFEST_FILE)).await?;
+        Self::from_slice(&json)
+    }
+
+    pub fn from_slice(json: &[u8]) -> Result<Self, StorageError> {
+        Ok(serde_json::from_slice(json)?)
+    }
+
+    /// Re-hash the files of the backup in `dir` and compare them to the manifest
+    ///
+    /// Files that are not in the manifest are ignored.
+    pub async fn verify_dir(&self, dir: &Path) -> Result<IntegrityReport, StorageError> {
+        let mut report = IntegrityReport::default();
+        for entry in &self.files {
+            let path = dir.join(&entry.path);
+            report.checked += 1;
+            if !tokio::fs::try_exists(&path).await? {
+                report.problems.push(IntegrityProblem::Missing {
+                    path: entry.path.clone(),
+                });
+                continue;
+            }
+            let (size, sha256) = hash_file(&path).await?;
+            report.problems.extend(entry.compare(size, &sha256));
+        }
+        Ok(report)
+    }
+}
+
+impl ManifestEntry {
+    /// Compare the size and checksum of a copy of this file
+    pub fn compare(&self, size: u64, sha256: &str) -> Option<IntegrityProblem> {
+        if size != self.size {
+            Some(IntegrityProblem::SizeMismatch {
+                path: self.path.clone(),
+                expected: self.size,
+                actual: size,
+            })
+        } else if sha256 != self.sha256 {
+            Some(IntegrityProblem::ChecksumMismatch {
+                path: self.path.clone(),
+                expected: self.sha256.clone(),
+                actual: sha256.to_string(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Size and hex encoded SHA-256 of a file
+pub async fn hash_file(path: &Path) -> Result<(u64, String), StorageError> {
+    let file = tokio::fs::File::open(path).await?;
+    hash_stream(Box::pin(ReaderStream::new(file))).await
+}
+
+/// Size and hex encoded SHA-256 of a stream
+pub async fn hash_stream(mut stream: ByteStream) -> Result<(u64, String), StorageError> {
+    let mut hasher = Sha256::new();
+    let mut size = 0u64;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        size += chunk.len() as u64;
+        hasher.update(&chunk);
+    }
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
+fn relative_path(dir: &Path, path: &Path) -> Result<String, StorageError> {
+    let rel = path
+        .strip_prefix(dir)
+        .map_err(|e| StorageError::Unexpected(e.to_string()))?;
+    Ok(rel
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn detects_changed_and_missing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("pg_wal")).unwrap();
+        std::fs::write(dir.path().join("base.tar.gz"), b"base").unwrap();
+        std::fs::write(dir.path().join("pg_wal/000000010000000000000001"), b"wal").unwrap();
+
+        let manifest = Manifest::build("b1", dir.path()).await.unwrap();
+        manifest.write_to(dir.path()).await.unwrap();
+        let loaded = Manifest::load(dir.path()).await.unwrap();
+        assert_eq!(loaded, manifest);
+        assert_eq!(
+            loaded
+                .files
+                .iter()
+                .map(|f| f.path.as_str())
+                .collect::<Vec<_>>(),
+            vec!["base.tar.gz", "pg_wal/000000010000000000000001"]
+        );
+        assert!(loaded.verify_dir(dir.path()).await.unwrap().is_ok());
+
+        std::fs::write(dir.path().join("base.tar.gz"), b"rot!").unwrap();
+        std::fs::remove_file(dir.path().join("pg_wal/000000010000000000000001")).unwrap();
+        let report = loaded.verify_dir(dir.path()).await.unwrap();
+        assert_eq!(report.checked, 2);
+        assert!(matches!(
+            report.problems[0],
+            IntegrityProblem::ChecksumMismatch { .. }
+        ));
+        assert!(matches!(
+            report.problems[1],
+            IntegrityProblem::Missing { .. }
+        ));
+    }
+}
@@ -1,27 +1,425 @@
-use crate::{Bucket, Metadata, ObjectMetadata, StorageError, StorageObject, StorageProvider};
+use crate::{
+    BackupType, Bucket, BucketLockStatus, ByteStream, Metadata, ObjectAvailability, ObjectLockMode,
+    ObjectMetadata, ObjectRetention, RateLimiter, StorageClass, StorageError, StorageObject,
+    StorageProvider, StreamingDownloadOptions, TieringRule, UploadOptions,
+};
 use async_trait::async_trait;
 use aws_config::BehaviorVersion;
 use aws_credential_types::provider::SharedCredentialsProvider;
 use aws_sdk_s3::config::Region;
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::presigning::PresigningConfig;
 use aws_sdk_s3::{
     operation::{
         get_object::GetObjectOutput, list_buckets::ListBucketsOutput,
         list_objects_v2::ListObjectsV2Output,
     },
-    types::CompletedMultipartUpload,
+    types::{
+        ArchiveStatus, BucketLifecycleConfiguration, BucketVersioningStatus,
+        CompletedMultipartUpload, CompletedPart, ExpirationStatus, GlacierJobParameters,
+        LifecycleRule, LifecycleRuleAndOperator, LifecycleRuleFilter, ObjectLockEnabled,
+        ObjectLockRetention, ObjectLockRetentionMode, RestoreRequest,
+        StorageClass as S3StorageClass, Tag, Tier, Transition, TransitionStorageClass,
+    },
     Client,
 };
 use aws_smithy_types::DateTime;
 use bytes::Bytes;
 use chrono::{TimeZone, Utc};
 use futures::Stream;
-use log::{debug, error, info};
-use std::collections::HashMap;
-use std::path::Path;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::task::JoinSet;
 use tokio::{fs::File, io::AsyncReadExt};
+use tokio_util::io::ReaderStream;
+
+/// Smallest part S3 accepts, but for the last one
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+/// Most parts one multipart upload can have
+const MAX_PARTS: u64 = 10_000;
+/// Parts of a stream after which its part size doubles, as its length is unknown
+const PARTS_PER_SIZE_STEP: i32 = 1_000;
+/// Key of the tag holding the backup type of the objects tiering rules may move
+const TIERING_TAG: &str = "warden-tiering";
+/// Start of the ID of the lifecycle rules installed for tiering, followed
+/// by the backup type and the prefix they cover
+const TIERING_RULE_ID: &str = "warden-tiering:";
+
+/// How multipart uploads split, send and retry parts
+#[derive(Debug, Clone)]
+pub struct MultipartConfig {
+    /// Part size in bytes, raised to 5 MiB if smaller
+    pub part_size: usize,
+    /// Parts sent at the same time
+    pub concurrency: usize,
+    /// Attempts at each part before the upload fails
+    pub max_attempts: u32,
+    /// Wait before the first retry of a part, doubled for each next one
+    pub initial_backoff: Duration,
+    /// Longest wait between two attempts
+    pub max_backoff: Duration,
+    /// Where the state of file uploads is kept so a failed one can be resumed,
+    /// `None` to abort failed uploads instead
+    pub state_dir: Option<PathBuf>,
+}
+
+impl Default for MultipartConfig {
+    fn default() -> Self {
+        Self {
+            part_size: 8 * 1024 * 1024,
+            concurrency: 4,
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            state_dir: Some(std::env::temp_dir().join("warden-uploads")),
+        }
+    }
+}
+
+impl MultipartConfig {
+    /// The defaults, overridden by `WARDEN_S3_PART_SIZE_MB`, `WARDEN_S3_CONCURRENCY`,
+    /// `WARDEN_S3_MAX_ATTEMPTS` and `WARDEN_S3_STATE_DIR`
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            let value = std::env::var(name).ok()?;
+            let parsed = value.parse().ok();
+            if parsed.is_none() {
+                warn!("Ignoring {name}={value}, not a valid number");
+            }
+            parsed
+        }
+
+        let mut config = Self::default();
+        if let Some(mb) = var::<usize>("WARDEN_S3_PART_SIZE_MB") {
+            config.part_size = mb * 1024 * 1024;
+        }
+        if let Some(concurrency) = var("WARDEN_S3_CONCURRENCY") {
+            config.concurrency = concurrency;
+        }
+        if let Some(max_attempts) = var("WARDEN_S3_MAX_ATTEMPTS") {
+            config.max_attempts = max_attempts;
+        }
+        if let Ok(dir) = std::env::var("WARDEN_S3_STATE_DIR") {
+            config.state_dir = Some(PathBuf::from(dir));
+        }
+        config
+    }
+
+    /// Part size for a file of `size` bytes, grown so it fits in 10,000 parts
+    fn file_part_size(&self, size: u64) -> usize {
+        let fitting = size.div_ceil(MAX_PARTS) as usize;
+        self.part_size.max(MIN_PART_SIZE).max(fitting)
+    }
+
+    /// Size of part `part_number` of a stream
+    ///
+    /// It doubles every 1,000 parts, so a stream of unknown length still fits
+    /// in 10,000 parts.
+    fn stream_part_size(&self, part_number: i32) -> usize {
+        let step = ((part_number - 1) / PARTS_PER_SIZE_STEP).clamp(0, 9);
+        self.part_size.max(MIN_PART_SIZE) << step
+    }
+
+    /// Wait before attempt `attempt + 1` of a part
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// What resuming the multipart upload of a file takes, saved while it runs
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct UploadState {
+    bucket: String,
+    key: String,
+    upload_id: String,
+    file_size: u64,
+    /// Modification time of the file, in seconds since the epoch
+    modified: u64,
+    part_size: usize,
+}
+
+impl UploadState {
+    /// File keeping the state of the upload to `bucket/key`
+    fn path(state_dir: &Path, bucket: &str, key: &str) -> PathBuf {
+        let digest = Sha256::digest(format!("{bucket}/{key}").as_bytes());
+        state_dir.join(format!("{}.json", hex::encode(digest)))
+    }
+
+    fn load(path: &Path) -> Option<Self> {
+        let data = std::fs::read(path).ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    fn save(&self, path: &Path) -> Result<(), StorageError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let partial = path.with_extension("json.partial");
+        std::fs::write(&partial, serde_json::to_vec(self)?)?;
+        std::fs::rename(&partial, path)?;
+        Ok(())
+    }
+
+    /// Whether this upload is of the same file, unchanged since
+    fn is_for(&self, bucket: &str, key: &str, file_size: u64, modified: u64) -> bool {
+        self.bucket == bucket
+            && self.key == key
+            && self.file_size == file_size
+            && self.modified == modified
+    }
+
+    /// Size part `part_number` has when the file is split in `part_size` parts
+    fn expected_part_size(&self, part_number: i32) -> u64 {
+        let offset = (part_number as u64 - 1) * self.part_size as u64;
+        (self.file_size - offset.min(self.file_size)).min(self.part_size as u64)
+    }
+}
+
+/// S3 class of a storage tier
+fn s3_storage_class(class: StorageClass) -> S3StorageClass {
+    match class {
+        StorageClass::Standard => S3StorageClass::Standard,
+        StorageClass::InfrequentAccess => S3StorageClass::StandardIa,
+        StorageClass::Archive => S3StorageClass::Glacier,
+        StorageClass::DeepArchive => S3StorageClass::DeepArchive,
+    }
+}
+
+/// `x-amz-tagging` value of the objects of a backup of `backup_type`
+fn tiering_tagging(backup_type: &BackupType) -> String {
+    format!("{TIERING_TAG}={}", backup_type.as_str())
+}
+
+/// ID of the lifecycle rule moving the backups of `backup_type` below `prefix`
+fn tiering_rule_id(backup_type: &BackupType, prefix: &str) -> String {
+    format!("{TIERING_RULE_ID}{}:{prefix}", backup_type.as_str())
+}
+
+/// One lifecycle rule per backup type of `rules`, matching the objects tagged with that type
+fn tiering_lifecycle_rules(
+    prefix: &str,
+    rules: &[TieringRule],
+) -> Result<Vec<LifecycleRule>, StorageError> {
+    let mut by_type: Vec<(&BackupType, Vec<&TieringRule>)> = Vec::new();
+    for rule in rules {
+        match by_type.iter_mut().find(|(t, _)| **t == rule.backup_type) {
+            Some((_, rules)) => rules.push(rule),
+            None => by_type.push((&rule.backup_type, vec![rule])),
+        }
+    }
+
+    let mut lifecycle_rules = Vec::new();
+    for (backup_type, mut rules) in by_type {
+        rules.sort_by_key(|rule| rule.after_days);
+        let tag = Tag::builder()
+            .key(TIERING_TAG)
+            .value(backup_type.as_str())
+            .build()
+            .map_err(|e| StorageError::Unexpected(e.to_string()))?;
+        let filter = if prefix.is_empty() {
+            LifecycleRuleFilter::builder().tag(tag).build()
+        } else {
+            LifecycleRuleFilter::builder()
+                .and(
+                    LifecycleRuleAndOperator::builder()
+                        .prefix(prefix)
+                        .tags(tag)
+                        .build(),
+                )
+                .build()
+        };
+        let mut lifecycle_rule = LifecycleRule::builder()
+            .id(tiering_rule_id(backup_type, prefix))
+            .status(ExpirationStatus::Enabled)
+            .filter(filter);
+        for rule in rules {
+            let class = s3_transition_class(rule.class).ok_or_else(|| {
+                StorageError::Configuration(format!(
+                    "Objects cannot transition to {}, it is where they start",
+                    rule.class.as_str()
+                ))
+            })?;
+            lifecycle_rule = lifecycle_rule.transitions(
+                Transition::builder()
+                    .days(rule.after_days as i32)
+                    .storage_class(class)
+                    .build(),
+            );
+        }
+        lifecycle_rules.push(
+            lifecycle_rule
+                .build()
+                .map_err(|e| StorageError::Unexpected(e.to_string()))?,
+        );
+    }
+    Ok(lifecycle_rules)
+}
+
+/// S3 class objects of a storage tier transition to, `Standard` has none
+fn s3_transition_class(class: StorageClass) -> Option<TransitionStorageClass> {
+    match class {
+        StorageClass::Standard => None,
+        StorageClass::InfrequentAccess => Some(TransitionStorageClass::StandardIa),
+        StorageClass::Archive => Some(TransitionStorageClass::Glacier),
+        StorageClass::DeepArchive => Some(TransitionStorageClass::DeepArchive),
+    }
+}
+
+/// Availability of an object from the `x-amz-restore` header of a HEAD request
+///
+/// The header is absent until a restore is requested, then reads
+/// `ongoing-request="true"` until the restored copy is ready.
+fn availability(archived: bool, restore: Option<&str>) -> ObjectAvailability {
+    match restore {
+        _ if !archived => ObjectAvailability::Available,
+        None => ObjectAvailability::Archived,
+        Some(restore) if restore.contains("ongoing-request=\"true\"") => {
+            ObjectAvailability::Restoring
+        }
+        Some(_) => ObjectAvailability::Available,
+    }
+}
+
+/// Sends the parts of one multipart upload, shared by the tasks doing it
+struct PartUploader {
+    client: Client,
+    config: MultipartConfig,
+    rate_limiter: Option<RateLimiter>,
+    bucket: String,
+    key: String,
+    upload_id: String,
+}
+
+impl PartUploader {
+    /// Upload one part, retrying with exponential backoff
+    async fn upload(&self, part_number: i32, body: Bytes) -> Result<CompletedPart, StorageError> {
+        let (bucket, key) = (&self.bucket, &self.key);
+        let mut attempt = 1;
+        loop {
+            if let Some(limiter) = &self.rate_limiter {
+                limiter.acquire(body.len()).await;
+            }
+            let result = self
+                .client
+                .upload_part()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(&self.upload_id)
+                .part_number(part_number)
+                .body(aws_sdk_s3::primitives::ByteStream::from(body.clone()))
+                .send()
+                .await;
+            match result {
+                Ok(resp) => {
+                    debug!("Uploaded part {part_number} of {bucket}/{key}");
+                    return Ok(CompletedPart::builder()
+                        .part_number(part_number)
+                        .set_e_tag(resp.e_tag().map(str::to_string))
+                        .build());
+                }
+                Err(e) if attempt < self.config.max_attempts => {
+                    let delay = self.config.backoff(attempt);
+                    warn!(
+                        "Part {part_number} of {bucket}/{key} failed (attempt {attempt}/{}), retrying in {delay:?}: {e}",
+                        self.config.max_attempts
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    error!("Failed to upload part {part_number} for {bucket}/{key}: {e:?}");
+                    report_s3_error_to_sentry(
+                        "upload_part",
+                        &e as &dyn std::error::Error,
+                        bucket,
+                        key,
+                        None,
+                    );
+                    return Err(StorageError::Aws(e.to_string()));
+                }
+            }
+        }
+    }
+
+    /// Wait for one running part, keeping it in `parts` or the first error in `failure`
+    async fn join_next(
+        tasks: &mut JoinSet<Result<CompletedPart, StorageError>>,
+        parts: &mut BTreeMap<i32, CompletedPart>,
+        failure: &mut Option<StorageError>,
+    ) -> bool {
+        let Some(joined) = tasks.join_next().await else {
+            return false;
+        };
+        match joined {
+            Ok(Ok(part)) => {
+                parts.insert(part.part_number().unwrap_or_default(), part);
+            }
+            Ok(Err(e)) => {
+                failure.get_or_insert(e);
+            }
+            Err(e) => {
+                failure.get_or_insert(StorageError::Unexpected(format!(
+                    "Part upload task failed: {e}"
+                )));
+            }
+        }
+        true
+    }
+
+    async fn complete(&self, parts: BTreeMap<i32, CompletedPart>) -> Result<(), StorageError> {
+        let (bucket, key) = (&self.bucket, &self.key);
+        let completed_upload = CompletedMultipartUpload::builder()
+            .set_parts(Some(parts.into_values().collect()))
+            .build();
+        self.client
+            .complete_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(&self.upload_id)
+            .multipart_upload(completed_upload)
+            .send()
+            .await
+            .map_err(|e| {
+                error!("Failed to complete multipart upload for {bucket}/{key}: {e:?}");
+                report_s3_error_to_sentry(
+                    "complete_multipart_upload",
+                    &e as &dyn std::error::Error,
+                    bucket,
+                    key,
+                    None,
+                );
+                StorageError::Aws(e.to_string())
+            })?;
+        info!("Multipart upload completed: {bucket}/{key}");
+        Ok(())
+    }
+
+    /// Drop the parts sent so far; failures are only logged
+    async fn abort(&self) {
+        let (bucket, key) = (&self.bucket, &self.key);
+        if let Err(e) = self
+            .client
+            .abort_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(&self.upload_id)
+            .send()
+            .await
+        {
+            warn!("Failed to abort multipart upload of {bucket}/{key}: {e}");
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum ProviderKind {
@@ -43,6 +441,8 @@ pub struct S3Provider {
     /// Provider kind (for provider-specific config/quirks)
     #[allow(dead_code)]
     provider_kind: ProviderKind,
+    /// Part size, concurrency and retries of multipart uploads
+    multipart: MultipartConfig,
 }
 
 impl S3Provider {
@@ -53,12 +453,17 @@ impl S3Provider {
         key: &str,
         content_type: Option<&str>,
         metadata: Option<Metadata>,
+        options: &UploadOptions,
     ) -> Result<String, StorageError> {
         let mut req = self
             .client
             .create_multipart_upload()
             .bucket(bucket)
-            .key(key);
+            .key(key)
+            .set_storage_class(options.storage_class.map(s3_storage_class));
+        if let Some(backup_type) = &options.tiering {
+            req = req.tagging(tiering_tagging(backup_type));
+        }
         if let Some(content_type) = content_type {
             req = req.content_type(content_type);
         }
@@ -140,6 +545,7 @@ impl S3Provider {
             region: region_str,
             endpoint: default_endpoint,
             provider_kind,
+            multipart: MultipartConfig::from_env(),
         })
     }
 
@@ -201,9 +607,77 @@ impl S3Provider {
             region: region_str,
             endpoint,
             provider_kind: ProviderKind::Aws,
+            multipart: MultipartConfig::from_env(),
         })
     }
 
+    /// Use `config` for multipart uploads instead of the one from the environment
+    pub fn with_multipart_config(mut self, config: MultipartConfig) -> Self {
+        self.multipart = config;
+        self
+    }
+
+    /// Drop the parts of an earlier upload that will not be resumed; failures are only logged
+    async fn abort_stale_upload(&self, state: &UploadState) {
+        let (bucket, key) = (&state.bucket, &state.key);
+        if let Err(e) = self
+            .client
+            .abort_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(&state.upload_id)
+            .send()
+            .await
+        {
+            warn!(
+                "Failed to abort stale upload {} of {bucket}/{key}: {e}",
+                state.upload_id
+            );
+        }
+    }
+
+    /// Parts of a multipart upload already stored, by part number
+    async fn list_uploaded_parts(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<BTreeMap<i32, (CompletedPart, u64)>, StorageError> {
+        let mut parts = BTreeMap::new();
+        let mut marker: Option<String> = None;
+        loop {
+            let resp = self
+                .client
+                .list_parts()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .set_part_number_marker(marker)
+                .send()
+                .await
+                .map_err(|e| StorageError::Aws(e.to_string()))?;
+            for part in resp.parts() {
+                let Some(part_number) = part.part_number() else {
+                    continue;
+                };
+                let completed = CompletedPart::builder()
+                    .part_number(part_number)
+                    .set_e_tag(part.e_tag().map(str::to_string))
+                    .build();
+                let size = part.size().unwrap_or_default().max(0) as u64;
+                parts.insert(part_number, (completed, size));
+            }
+            if !resp.is_truncated().unwrap_or(false) {
+                break;
+            }
+            marker = resp.next_part_number_marker().map(str::to_string);
+            if marker.is_none() {
+                break;
+            }
+        }
+        Ok(parts)
+    }
+
     /// Converts an S3 object to a StorageObject
     fn convert_s3_object(&self, obj: &aws_sdk_s3::types::Object) -> StorageObject {
         StorageObject {
@@ -361,15 +835,42 @@ impl StorageProvider for S3Provider {
 
     async fn download_stream(
         &self,
-        _bucket: &str,
-        _key: &str,
+        bucket: &str,
+        key: &str,
     ) -> Result<
         Pin<Box<dyn Stream<Item = Result<bytes::Bytes, std::io::Error>> + Send>>,
         StorageError,
     > {
-        Err(StorageError::Unexpected(
-            "download_stream not implemented".to_string(),
-        ))
+        self.download_stream_range(bucket, key, &StreamingDownloadOptions::default())
+            .await
+    }
+
+    async fn download_stream_range(
+        &self,
+        bucket: &str,
+        key: &str,
+        options: &StreamingDownloadOptions,
+    ) -> Result<ByteStream, StorageError> {
+        let mut req = self.client.get_object().bucket(bucket).key(key);
+        if let Some(range) = options.http_range() {
+            debug!("Requesting {range} of {bucket}/{key}");
+            req = req.range(range);
+        }
+        let resp = req.send().await.map_err(|e| {
+            let status = e.raw_response().map(|r| r.status().as_u16());
+            match status {
+                Some(404) => StorageError::NotFound(format!("Object {bucket}/{key} not found")),
+                Some(416) => StorageError::Request(format!(
+                    "Range {:?} is outside of object {bucket}/{key}",
+                    options.http_range()
+                )),
+                _ => {
+                    error!("Failed to get object {bucket}/{key}: {e}");
+                    StorageError::Aws(e.to_string())
+                }
+            }
+        })?;
+        Ok(Box::pin(ReaderStream::new(resp.body.into_async_read())))
     }
 
     async fn get_object_metadata(
@@ -441,21 +942,329 @@ impl StorageProvider for S3Provider {
         Ok(())
     }
 
-    async fn object_exists(&self, _bucket: &str, _key: &str) -> Result<bool, StorageError> {
-        Err(StorageError::Unexpected(
-            "object_exists not implemented".to_string(),
-        ))
+    async fn object_exists(&self, bucket: &str, key: &str) -> Result<bool, StorageError> {
+        match self
+            .client
+            .head_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                let not_found = e.as_service_error().is_some_and(|se| se.is_not_found())
+                    || e.raw_response().is_some_and(|r| r.status().as_u16() == 404);
+                if not_found {
+                    Ok(false)
+                } else {
+                    error!("Failed to check object {bucket}/{key}: {e}");
+                    Err(StorageError::Aws(e.to_string()))
+                }
+            }
+        }
     }
 
     async fn generate_presigned_url(
         &self,
-        _bucket: &str,
-        _key: &str,
-        _expires_in: std::time::Duration,
+        bucket: &str,
+        key: &str,
+        expires_in: std::time::Duration,
     ) -> Result<String, StorageError> {
-        Err(StorageError::Unexpected(
-            "generate_presigned_url not implemented".to_string(),
-        ))
+        let config = PresigningConfig::expires_in(expires_in).map_err(|e| {
+            StorageError::Configuration(format!("Invalid presigned URL lifetime: {e}"))
+        })?;
+        let request = self
+            .client
+            .get_object()
+            .bucket(bucket)
+            .key(key)
+            .presigned(config)
+            .await
+            .map_err(|e| {
+                error!("Failed to presign {bucket}/{key}: {e}");
+                StorageError::Aws(e.to_string())
+            })?;
+        Ok(request.uri().to_string())
+    }
+
+    async fn get_object(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<(Bytes, Option<String>), StorageError> {
+        let resp = self.get_object_with_error_handling(bucket, key).await?;
+        let etag = resp.e_tag().map(|s| s.to_string());
+        let body = resp.body.collect().await.map_err(|e| {
+            error!("Failed to read object {bucket}/{key}: {e}");
+            StorageError::Aws(e.to_string())
+        })?;
+        Ok((body.into_bytes(), etag))
+    }
+
+    async fn put_object_if_match(
+        &self,
+        bucket: &str,
+        key: &str,
+        body: Bytes,
+        content_type: Option<&str>,
+        if_match: Option<&str>,
+    ) -> Result<Option<String>, StorageError> {
+        let mut req = self
+            .client
+            .put_object()
+            .bucket(bucket)
+            .key(key)
+            .body(aws_sdk_s3::primitives::ByteStream::from(body));
+        req = match if_match {
+            Some(etag) => req.if_match(etag),
+            None => req.if_none_match("*"),
+        };
+        if let Some(content_type) = content_type {
+            req = req.content_type(content_type);
+        }
+
+        let resp = req.send().await.map_err(|e| {
+            let status = e.raw_response().map(|r| r.status().as_u16());
+            // 412 when the precondition failed, 409 when another conditional
+            // write to the same key was in flight
+            if matches!(status, Some(409) | Some(412)) {
+                StorageError::Conflict(format!("Object {bucket}/{key} changed since it was read"))
+            } else {
+                error!("Failed to upload object {bucket}/{key}: {e}");
+                StorageError::Aws(e.to_string())
+            }
+        })?;
+        Ok(resp.e_tag().map(|s| s.to_string()))
+    }
+
+    async fn set_object_retention(
+        &self,
+        bucket: &str,
+        key: &str,
+        retention: &ObjectRetention,
+    ) -> Result<(), StorageError> {
+        let mode = match retention.mode {
+            ObjectLockMode::Governance => ObjectLockRetentionMode::Governance,
+            ObjectLockMode::Compliance => ObjectLockRetentionMode::Compliance,
+        };
+        self.client
+            .put_object_retention()
+            .bucket(bucket)
+            .key(key)
+            .retention(
+                ObjectLockRetention::builder()
+                    .mode(mode)
+                    .retain_until_date(DateTime::from_secs(retention.retain_until.timestamp()))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| {
+                error!("Failed to lock object {bucket}/{key}: {e}");
+                StorageError::Aws(format!(
+                    "Failed to lock {bucket}/{key}, is object lock enabled on the bucket? {}",
+                    e.message().unwrap_or(&e.to_string())
+                ))
+            })?;
+        Ok(())
+    }
+
+    async fn get_object_retention(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<Option<ObjectRetention>, StorageError> {
+        let resp = match self
+            .client
+            .get_object_retention()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(resp) => resp,
+            Err(e) => {
+                return match e.code() {
+                    Some("NoSuchKey") => Err(StorageError::NotFound(format!(
+                        "Object {bucket}/{key} not found"
+                    ))),
+                    // No retention on the object, or no object lock on the bucket
+                    Some("NoSuchObjectLockConfiguration") | Some("InvalidRequest") => Ok(None),
+                    _ => Err(StorageError::Aws(e.to_string())),
+                };
+            }
+        };
+
+        let Some(retention) = resp.retention() else {
+            return Ok(None);
+        };
+        let mode = match retention.mode() {
+            Some(ObjectLockRetentionMode::Compliance) => ObjectLockMode::Compliance,
+            Some(ObjectLockRetentionMode::Governance) => ObjectLockMode::Governance,
+            _ => return Ok(None),
+        };
+        let retain_until = retention
+            .retain_until_date()
+            .and_then(|date| Utc.timestamp_opt(date.secs(), 0).single());
+        Ok(retain_until.map(|retain_until| ObjectRetention { mode, retain_until }))
+    }
+
+    async fn bucket_lock_status(&self, bucket: &str) -> Result<BucketLockStatus, StorageError> {
+        let versioning = self
+            .client
+            .get_bucket_versioning()
+            .bucket(bucket)
+            .send()
+            .await
+            .map_err(|e| StorageError::Aws(e.to_string()))?;
+
+        let mut status = BucketLockStatus {
+            versioning: versioning.status() == Some(&BucketVersioningStatus::Enabled),
+            ..Default::default()
+        };
+        match self
+            .client
+            .get_object_lock_configuration()
+            .bucket(bucket)
+            .send()
+            .await
+        {
+            Ok(resp) => {
+                let config = resp.object_lock_configuration();
+                status.object_lock = config.and_then(|c| c.object_lock_enabled())
+                    == Some(&ObjectLockEnabled::Enabled);
+                status.default_retention = config
+                    .and_then(|c| c.rule())
+                    .and_then(|rule| rule.default_retention())
+                    .map(|retention| {
+                        let mode = retention.mode().map_or("unknown", |m| m.as_str());
+                        match (retention.days(), retention.years()) {
+                            (Some(days), _) => format!("{mode} for {days} days"),
+                            (_, Some(years)) => format!("{mode} for {years} years"),
+                            _ => mode.to_string(),
+                        }
+                    });
+            }
+            Err(e) if e.code() == Some("ObjectLockConfigurationNotFoundError") => {}
+            Err(e) => return Err(StorageError::Aws(e.to_string())),
+        }
+        Ok(status)
+    }
+
+    async fn set_tiering_rules(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        rules: &[TieringRule],
+    ) -> Result<(), StorageError> {
+        // Rules of other prefixes, or not installed by tiering, stay
+        let owned: Vec<String> = [
+            BackupType::Full,
+            BackupType::Incremental,
+            BackupType::Snapshot,
+        ]
+        .iter()
+        .map(|backup_type| tiering_rule_id(backup_type, prefix))
+        .collect();
+        let mut lifecycle_rules: Vec<LifecycleRule> = match self
+            .client
+            .get_bucket_lifecycle_configuration()
+            .bucket(bucket)
+            .send()
+            .await
+        {
+            Ok(resp) => resp
+                .rules()
+                .iter()
+                .filter(|rule| !rule.id().is_some_and(|id| owned.iter().any(|o| o == id)))
+                .cloned()
+                .collect(),
+            Err(e) if e.code() == Some("NoSuchLifecycleConfiguration") => Vec::new(),
+            Err(e) => return Err(StorageError::Aws(e.to_string())),
+        };
+        lifecycle_rules.extend(tiering_lifecycle_rules(prefix, rules)?);
+
+        if lifecycle_rules.is_empty() {
+            self.client
+                .delete_bucket_lifecycle()
+                .bucket(bucket)
+                .send()
+                .await
+                .map_err(|e| StorageError::Aws(e.to_string()))?;
+            return Ok(());
+        }
+        let configuration = BucketLifecycleConfiguration::builder()
+            .set_rules(Some(lifecycle_rules))
+            .build()
+            .map_err(|e| StorageError::Unexpected(e.to_string()))?;
+        self.client
+            .put_bucket_lifecycle_configuration()
+            .bucket(bucket)
+            .lifecycle_configuration(configuration)
+            .send()
+            .await
+            .map_err(|e| {
+                error!("Failed to set the lifecycle rules of {bucket}: {e:?}");
+                StorageError::Aws(e.to_string())
+            })?;
+        Ok(())
+    }
+
+    async fn object_availability(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<ObjectAvailability, StorageError> {
+        let head = self
+            .client
+            .head_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| {
+                if e.raw_response().map(|r| r.status().as_u16()) == Some(404) {
+                    StorageError::NotFound(format!("Object {bucket}/{key} not found"))
+                } else {
+                    StorageError::Aws(e.to_string())
+                }
+            })?;
+        let archived = matches!(
+            head.storage_class(),
+            Some(S3StorageClass::Glacier | S3StorageClass::DeepArchive)
+        ) || matches!(
+            head.archive_status(),
+            Some(ArchiveStatus::ArchiveAccess | ArchiveStatus::DeepArchiveAccess)
+        );
+        Ok(availability(archived, head.restore()))
+    }
+
+    async fn restore_object(&self, bucket: &str, key: &str, days: u32) -> Result<(), StorageError> {
+        let job = GlacierJobParameters::builder()
+            .tier(Tier::Standard)
+            .build()
+            .map_err(|e| StorageError::Unexpected(e.to_string()))?;
+        let request = RestoreRequest::builder()
+            .days(days.try_into().unwrap_or(i32::MAX))
+            .glacier_job_parameters(job)
+            .build();
+        match self
+            .client
+            .restore_object()
+            .bucket(bucket)
+            .key(key)
+            .restore_request(request)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) if e.code() == Some("RestoreAlreadyInProgress") => Ok(()),
+            Err(e) => {
+                error!("Failed to request the restore of {bucket}/{key}: {e:?}");
+                Err(StorageError::Aws(e.to_string()))
+            }
+        }
     }
 
     async fn create_bucket(&self, bucket: &str) -> Result<(), StorageError> {
@@ -589,13 +1398,30 @@ impl StorageProvider for S3Provider {
         file_path: &Path,
         content_type: Option<&str>,
         metadata: Option<Metadata>,
+    ) -> Result<(), StorageError> {
+        self.upload_file_with(
+            bucket,
+            key,
+            file_path,
+            content_type,
+            metadata,
+            &UploadOptions::default(),
+        )
+        .await
+    }
+
+    async fn upload_file_with(
+        &self,
+        bucket: &str,
+        key: &str,
+        file_path: &Path,
+        content_type: Option<&str>,
+        metadata: Option<Metadata>,
+        options: &UploadOptions,
     ) -> Result<(), StorageError> {
         use aws_sdk_s3::primitives::ByteStream;
-        use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
         use tokio::io::{AsyncReadExt, BufReader};
 
-        const PART_SIZE: usize = 5 * 1024 * 1024; // 5MB (S3 minimum)
-
         let file = tokio::fs::File::open(file_path).await.map_err(|e| {
             error!("Failed to open file {}: {}", file_path.display(), e);
             StorageError::Io(e)
@@ -605,21 +1431,29 @@ impl StorageProvider for S3Provider {
             StorageError::Io(e)
         })?;
         let file_size = metadata_fs.len();
-        let mut reader = BufReader::new(file);
+        let part_size = self.multipart.file_part_size(file_size);
 
         // Use single put_object for small files
-        if file_size <= PART_SIZE as u64 {
+        if file_size <= part_size as u64 {
+            let mut reader = BufReader::new(file);
             let mut buffer = Vec::with_capacity(file_size as usize);
             reader.read_to_end(&mut buffer).await.map_err(|e| {
                 error!("Failed to read file {}: {}", file_path.display(), e);
                 StorageError::Io(e)
             })?;
+            if let Some(limiter) = &options.rate_limiter {
+                limiter.acquire(buffer.len()).await;
+            }
             let mut put_object_request = self
                 .client
                 .put_object()
                 .bucket(bucket)
                 .key(key)
+                .set_storage_class(options.storage_class.map(s3_storage_class))
                 .body(ByteStream::from(buffer));
+            if let Some(backup_type) = &options.tiering {
+                put_object_request = put_object_request.tagging(tiering_tagging(backup_type));
+            }
             if let Some(content_type) = content_type {
                 put_object_request = put_object_request.content_type(content_type);
             }
@@ -642,112 +1476,131 @@ impl StorageProvider for S3Provider {
             return Ok(());
         }
 
-        // Multipart upload for large files
-        debug!(
-            "Initiating multipart upload: bucket={}, key={}, file_size={}",
-            bucket, key, file_size
-        );
-        let upload_id = self
-            .initiate_multipart_upload(bucket, key, content_type, metadata)
-            .await?;
-        let mut parts: Vec<CompletedPart> = Vec::new();
-        let mut part_number = 1;
+        // Multipart upload for large files, resuming the previous attempt if it failed
+        let modified = metadata_fs
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let state_path = self
+            .multipart
+            .state_dir
+            .as_deref()
+            .map(|dir| UploadState::path(dir, bucket, key));
+
+        let mut parts = BTreeMap::new();
+        let mut resumed = None;
+        if let Some(saved) = state_path.as_deref().and_then(UploadState::load) {
+            if saved.is_for(bucket, key, file_size, modified) {
+                match self
+                    .list_uploaded_parts(bucket, key, &saved.upload_id)
+                    .await
+                {
+                    Ok(uploaded) => {
+                        parts = uploaded
+                            .into_iter()
+                            .filter(|(n, (_, size))| {
+                                *size > 0 && *size == saved.expected_part_size(*n)
+                            })
+                            .map(|(n, (part, _))| (n, part))
+                            .collect();
+                        info!(
+                            "Resuming upload of {} to {bucket}/{key}, {} parts already uploaded",
+                            file_path.display(),
+                            parts.len()
+                        );
+                        resumed = Some(saved);
+                    }
+                    Err(e) => {
+                        warn!("Cannot resume upload to {bucket}/{key}, starting over: {e}");
+                        self.abort_stale_upload(&saved).await;
+                    }
+                }
+            } else {
+                info!(
+                    "{} changed since its upload to {bucket}/{key} failed, starting over",
+                    file_path.display()
+                );
+                self.abort_stale_upload(&saved).await;
+            }
+        }
+        let state = match resumed {
+            Some(state) => state,
+            None => {
+                debug!(
+                    "Initiating multipart upload: bucket={}, key={}, file_size={}",
+                    bucket, key, file_size
+                );
+                let upload_id = self
+                    .initiate_multipart_upload(bucket, key, content_type, metadata, options)
+                    .await?;
+                let state = UploadState {
+                    bucket: bucket.to_string(),
+                    key: key.to_string(),
+                    upload_id,
+                    file_size,
+                    modified,
+                    part_size,
+                };
+                if let Some(path) = &state_path {
+                    state.save(path)?;
+                }
+                state
+            }
+        };
+
+        let uploader = Arc::new(PartUploader {
+            client: self.client.clone(),
+            config: self.multipart.clone(),
+            rate_limiter: options.rate_limiter.clone(),
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            upload_id: state.upload_id.clone(),
+        });
+        let part_count = file_size.div_ceil(state.part_size as u64) as i32;
+        let mut pending = (1..=part_count)
+            .filter(|n| !parts.contains_key(n))
+            .collect::<Vec<_>>()
+            .into_iter();
+        let mut tasks = JoinSet::new();
+        let mut failure = None;
         loop {
-            let mut buf = vec![0u8; PART_SIZE];
-            let mut filled = 0;
-            // Fill the buffer up to PART_SIZE or until EOF
-            while filled < PART_SIZE {
-                let n = reader.read(&mut buf[filled..]).await.map_err(|e| {
-                    error!("[DEBUG] Failed to read file part: {e}");
-                    StorageError::Io(e)
-                })?;
-                if n == 0 {
+            while failure.is_none() && tasks.len() < self.multipart.concurrency.max(1) {
+                let Some(part_number) = pending.next() else {
                     break;
-                }
-                filled += n;
+                };
+                let uploader = uploader.clone();
+                let path = file_path.to_path_buf();
+                let offset = (part_number as u64 - 1) * state.part_size as u64;
+                let len = state.expected_part_size(part_number) as usize;
+                tasks.spawn(async move {
+                    let body = read_part(&path, offset, len).await?;
+                    uploader.upload(part_number, body).await
+                });
             }
-            if filled == 0 {
+            if !PartUploader::join_next(&mut tasks, &mut parts, &mut failure).await {
                 break;
-            } // EOF
-            let is_last_part = filled < PART_SIZE;
-            // S3: All parts except the last must be at least PART_SIZE (5MB)
-            if !is_last_part && filled < PART_SIZE {
+            }
+        }
+        if let Some(e) = failure {
+            if state_path.is_some() {
                 error!(
-                    "Part {part_number} is too small ({filled} bytes, must be >= {PART_SIZE} except last). Aborting upload."
+                    "Upload of {} to {bucket}/{key} failed, running it again resumes it",
+                    file_path.display()
                 );
-                // Abort upload
-                std::mem::drop(
-                    self.client
-                        .abort_multipart_upload()
-                        .bucket(bucket)
-                        .key(key)
-                        .upload_id(&upload_id)
-                        .send(),
-                );
-                return Err(StorageError::Aws(format!(
-                    "Multipart upload failed: part {part_number} too small ({filled} bytes)"
-                )));
+            } else {
+                uploader.abort().await;
             }
-            info!("Uploading part {part_number} ({filled} bytes)");
-            // Upload part
-            let upload_part_resp = self
-                .client
-                .upload_part()
-                .bucket(bucket)
-                .key(key)
-                .upload_id(&upload_id)
-                .part_number(part_number)
-                .body(ByteStream::from(buf[..filled].to_vec()))
-                .send()
-                .await
-                .map_err(|e| {
-                    error!("Failed to upload part {part_number} for {bucket}/{key}: {e:?}");
-                    debug!("S3 error debug: {e:?}");
-                    report_s3_error_to_sentry(
-                        "upload_file:upload_part",
-                        &e as &dyn std::error::Error,
-                        bucket,
-                        key,
-                        None,
-                    );
-                    StorageError::Aws(e.to_string())
-                })?;
-            parts.push(
-                CompletedPart::builder()
-                    .set_part_number(Some(part_number))
-                    .set_e_tag(upload_part_resp.e_tag().map(|s| s.to_string()))
-                    .build(),
-            );
-            part_number += 1;
-            if is_last_part {
-                break;
+            return Err(e);
+        }
+
+        uploader.complete(parts).await?;
+        if let Some(path) = &state_path {
+            if let Err(e) = std::fs::remove_file(path) {
+                warn!("Failed to remove upload state {}: {e}", path.display());
             }
         }
-        // Complete multipart upload
-        let completed_upload = CompletedMultipartUpload::builder()
-            .set_parts(Some(parts))
-            .build();
-        self.client
-            .complete_multipart_upload()
-            .bucket(bucket)
-            .key(key)
-            .upload_id(&upload_id)
-            .multipart_upload(completed_upload)
-            .send()
-            .await
-            .map_err(|e| {
-                error!("Failed to complete multipart upload for {bucket}/{key}: {e:?}");
-                debug!("S3 error debug: {e:?}");
-                report_s3_error_to_sentry(
-                    "upload_file:complete_multipart_upload",
-                    &e as &dyn std::error::Error,
-                    bucket,
-                    key,
-                    None,
-                );
-                StorageError::Aws(e.to_string())
-            })?;
-        info!("Multipart upload completed: {}/{}", bucket, key);
         Ok(())
     }
 
@@ -759,89 +1612,102 @@ impl StorageProvider for S3Provider {
         content_type: Option<&str>,
         metadata: Option<Metadata>,
     ) -> Result<(), StorageError> {
-        use aws_sdk_s3::primitives::ByteStream;
+        self.upload_stream_with(
+            bucket,
+            key,
+            stream,
+            content_type,
+            metadata,
+            &UploadOptions::default(),
+        )
+        .await
+    }
+
+    async fn upload_stream_with(
+        &self,
+        bucket: &str,
+        key: &str,
+        stream: ByteStream,
+        content_type: Option<&str>,
+        metadata: Option<Metadata>,
+        options: &UploadOptions,
+    ) -> Result<(), StorageError> {
         use futures::StreamExt;
-        const PART_SIZE: usize = 5 * 1024 * 1024; // 5 MB
-        let mut parts = Vec::new();
-        let mut part_number = 1;
-        let mut buffer = Vec::with_capacity(PART_SIZE);
-        let mut s = stream;
+
         let upload_id = self
-            .initiate_multipart_upload(bucket, key, content_type, metadata)
+            .initiate_multipart_upload(bucket, key, content_type, metadata, options)
             .await?;
-        while let Some(chunk_result) = s.next().await {
-            let chunk = chunk_result.map_err(|e| {
-                error!("Failed to read stream chunk: {e}");
-                StorageError::Io(e)
-            })?;
-            buffer.extend_from_slice(&chunk);
-            while buffer.len() >= PART_SIZE {
-                let part = buffer.drain(..PART_SIZE).collect::<Vec<u8>>();
-                let upload_part_resp = self
-                    .client
-                    .upload_part()
-                    .bucket(bucket)
-                    .key(key)
-                    .upload_id(&upload_id)
-                    .part_number(part_number)
-                    .body(ByteStream::from(part))
-                    .send()
-                    .await
-                    .map_err(|e| {
-                        error!("Failed to upload part {part_number} for {bucket}/{key}: {e:?}");
-                        StorageError::Aws(e.to_string())
-                    })?;
-                parts.push(
-                    aws_sdk_s3::types::CompletedPart::builder()
-                        .set_part_number(Some(part_number))
-                        .set_e_tag(upload_part_resp.e_tag().map(|s| s.to_string()))
-                        .build(),
-                );
+        let uploader = Arc::new(PartUploader {
+            client: self.client.clone(),
+            config: self.multipart.clone(),
+            rate_limiter: options.rate_limiter.clone(),
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            upload_id,
+        });
+        let concurrency = self.multipart.concurrency.max(1);
+        let mut parts = BTreeMap::new();
+        let mut tasks = JoinSet::new();
+        let mut failure = None;
+        let mut part_number = 1;
+        let mut buffer = Vec::new();
+        let mut stream = stream;
+        while failure.is_none() {
+            let finished = match stream.next().await {
+                Some(Ok(chunk)) => {
+                    buffer.extend_from_slice(&chunk);
+                    false
+                }
+                Some(Err(e)) => {
+                    error!("Failed to read stream chunk: {e}");
+                    failure = Some(StorageError::Io(e));
+                    break;
+                }
+                None => true,
+            };
+            // An empty stream still takes one, empty, part
+            while buffer.len() >= self.multipart.stream_part_size(part_number)
+                || (finished && (!buffer.is_empty() || part_number == 1))
+            {
+                let size = self
+                    .multipart
+                    .stream_part_size(part_number)
+                    .min(buffer.len());
+                let rest = buffer.split_off(size);
+                let body = Bytes::from(std::mem::replace(&mut buffer, rest));
+                while tasks.len() >= concurrency {
+                    PartUploader::join_next(&mut tasks, &mut parts, &mut failure).await;
+                }
+                if failure.is_some() {
+                    break;
+                }
+                let uploader = uploader.clone();
+                tasks.spawn(async move { uploader.upload(part_number, body).await });
                 part_number += 1;
             }
+            if finished {
+                break;
+            }
         }
-        if !buffer.is_empty() {
-            let upload_part_resp = self
-                .client
-                .upload_part()
-                .bucket(bucket)
-                .key(key)
-                .upload_id(&upload_id)
-                .part_number(part_number)
-                .body(ByteStream::from(buffer))
-                .send()
-                .await
-                .map_err(|e| {
-                    error!("Failed to upload last part {part_number} for {bucket}/{key}: {e:?}");
-                    StorageError::Aws(e.to_string())
-                })?;
-            parts.push(
-                aws_sdk_s3::types::CompletedPart::builder()
-                    .set_part_number(Some(part_number))
-                    .set_e_tag(upload_part_resp.e_tag().map(|s| s.to_string()))
-                    .build(),
-            );
+        while PartUploader::join_next(&mut tasks, &mut parts, &mut failure).await {}
+        if let Some(e) = failure {
+            // A stream cannot be read again, so there is nothing to resume
+            uploader.abort().await;
+            return Err(e);
         }
-        let completed_upload = CompletedMultipartUpload::builder()
-            .set_parts(Some(parts))
-            .build();
-        self.client
-            .complete_multipart_upload()
-            .bucket(bucket)
-            .key(key)
-            .upload_id(&upload_id)
-            .multipart_upload(completed_upload)
-            .send()
-            .await
-            .map_err(|e| {
-                error!("Failed to complete multipart upload for {bucket}/{key}: {e:?}");
-                StorageError::Aws(e.to_string())
-            })?;
-        info!("Multipart upload completed: {}/{}", bucket, key);
-        Ok(())
+        uploader.complete(parts).await
     }
 }
 
+/// Read `len` bytes of the file at `path`, from `offset`
+async fn read_part(path: &Path, offset: u64, len: usize) -> Result<Bytes, StorageError> {
+    let mut file = File::open(path).await?;
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    let mut buf = vec![0u8; len];
+    file.read_exact(&mut buf).await?;
+    Ok(Bytes::from(buf))
+}
+
 fn report_s3_error_to_sentry(
     operation: &str,
     error: &dyn std::error::Error,
@@ -861,3 +1727,113 @@ fn report_s3_error_to_sentry(
     let sentry_message = format!("{error_message} | context: {extra_json}");
     sentry::capture_message(&sentry_message, sentry::Level::Error);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_header_tells_archived_objects_apart() {
+        assert_eq!(availability(false, None), ObjectAvailability::Available);
+        assert_eq!(availability(true, None), ObjectAvailability::Archived);
+        assert_eq!(
+            availability(true, Some("ongoing-request=\"true\"")),
+            ObjectAvailability::Restoring
+        );
+        assert_eq!(
+            availability(
+                true,
+                Some("ongoing-request=\"false\", expiry-date=\"Fri, 21 Dec 2012 00:00:00 GMT\"")
+            ),
+            ObjectAvailability::Available
+        );
+    }
+
+    #[test]
+    fn tiering_installs_one_tag_filtered_rule_per_backup_type() {
+        let rules: Vec<TieringRule> = [
+            "full:90:archive",
+            "incremental:7:infrequent-access",
+            "full:30:infrequent-access",
+        ]
+        .iter()
+        .map(|rule| rule.parse().unwrap())
+        .collect();
+
+        let lifecycle_rules = tiering_lifecycle_rules("db1/", &rules).unwrap();
+        assert_eq!(lifecycle_rules.len(), 2);
+        let full = &lifecycle_rules[0];
+        assert_eq!(full.id(), Some("warden-tiering:full:db1/"));
+        let and = full.filter().unwrap().and().unwrap();
+        assert_eq!(and.prefix(), Some("db1/"));
+        assert_eq!(and.tags()[0].key(), TIERING_TAG);
+        assert_eq!(and.tags()[0].value(), "full");
+        let days: Vec<_> = full.transitions().iter().map(|t| t.days()).collect();
+        assert_eq!(days, vec![Some(30), Some(90)]);
+        assert_eq!(tiering_tagging(&BackupType::Full), "warden-tiering=full");
+
+        // Without a prefix the tag alone selects the objects
+        let lifecycle_rules = tiering_lifecycle_rules("", &rules[1..2]).unwrap();
+        let filter = lifecycle_rules[0].filter().unwrap();
+        assert_eq!(filter.tag().unwrap().value(), "incremental");
+
+        let standard = ["full:30:standard".parse().unwrap()];
+        assert!(tiering_lifecycle_rules("", &standard).is_err());
+    }
+
+    const MIB: usize = 1024 * 1024;
+
+    #[test]
+    fn file_parts_fit_the_part_limit() {
+        let config = MultipartConfig {
+            part_size: MIB,
+            ..Default::default()
+        };
+        assert_eq!(config.file_part_size(20 * MIB as u64), MIN_PART_SIZE);
+        let huge = 200 * 1024 * 1024 * 1024u64;
+        let part_size = config.file_part_size(huge);
+        assert!(huge.div_ceil(part_size as u64) <= MAX_PARTS);
+    }
+
+    #[test]
+    fn stream_parts_grow_every_thousand_parts() {
+        let config = MultipartConfig::default();
+        assert_eq!(config.stream_part_size(1), 8 * MIB);
+        assert_eq!(config.stream_part_size(1000), 8 * MIB);
+        assert_eq!(config.stream_part_size(1001), 16 * MIB);
+        assert_eq!(config.stream_part_size(10_000), (8 * MIB) << 9);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit() {
+        let config = MultipartConfig::default();
+        assert_eq!(config.backoff(1), Duration::from_millis(500));
+        assert_eq!(config.backoff(3), Duration::from_secs(2));
+        assert_eq!(config.backoff(20), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn upload_state_round_trips_and_sizes_parts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = UploadState::path(dir.path(), "bucket", "a/b");
+        let state = UploadState {
+            bucket: "bucket".to_string(),
+            key: "a/b".to_string(),
+            upload_id: "id".to_string(),
+            file_size: 12,
+            modified: 7,
+            part_size: 5,
+        };
+        state.save(&path).unwrap();
+        let loaded = UploadState::load(&path).unwrap();
+        assert_eq!(loaded, state);
+        assert!(loaded.is_for("bucket", "a/b", 12, 7));
+        assert!(!loaded.is_for("bucket", "a/b", 12, 8));
+        assert_eq!(
+            (1..=4)
+                .map(|n| loaded.expected_part_size(n))
+                .collect::<Vec<_>>(),
+            vec![5, 5, 2, 0]
+        );
+    }
+}
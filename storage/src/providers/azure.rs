@@ -0,0 +1,931 @@
+//! Azure Blob Storage through its REST API.
+//!
+//! Requests are signed with the storage account key (Shared Key) or carry a
+//! SAS token. Containers play the part of buckets and block blobs that of
+//! objects.
+
+use crate::{
+    Bucket, ByteStream, Metadata, ObjectMetadata, StorageError, StorageObject, StorageProvider,
+    StreamingDownloadOptions,
+};
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use futures::StreamExt;
+use log::{debug, error, info};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, ETAG, LAST_MODIFIED};
+use reqwest::{Method, Request, Response, StatusCode};
+use ring::hmac;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+use url::Url;
+
+/// REST API version requests and SAS URLs are made for
+const API_VERSION: &str = "2021-12-02";
+/// Size of the blocks of an upload
+const BLOCK_SIZE: usize = 8 * 1024 * 1024;
+const META_PREFIX: &str = "x-ms-meta-";
+
+enum Auth {
+    SharedKey(hmac::Key),
+    Sas(Vec<(String, String)>),
+    Anonymous,
+}
+
+pub struct AzureProvider {
+    http: reqwest::Client,
+    account: String,
+    /// Blob service URL, path-style for Azurite
+    endpoint: Url,
+    auth: Auth,
+}
+
+impl AzureProvider {
+    /// Creates a new Azure Blob provider
+    ///
+    /// Takes the account key, or else a SAS token, falling back to
+    /// `AZURE_STORAGE_ACCOUNT`, `AZURE_STORAGE_KEY` and
+    /// `AZURE_STORAGE_SAS_TOKEN`. The endpoint defaults to
+    /// `https://{account}.blob.core.windows.net`.
+    pub async fn new(
+        account: Option<String>,
+        account_key: Option<String>,
+        sas_token: Option<String>,
+        endpoint: Option<String>,
+    ) -> Result<Self, StorageError> {
+        let account = account
+            .or_else(|| std::env::var("AZURE_STORAGE_ACCOUNT").ok())
+            .ok_or_else(|| {
+                StorageError::Configuration("An Azure storage account name is required".into())
+            })?;
+        let account_key = account_key.or_else(|| std::env::var("AZURE_STORAGE_KEY").ok());
+        let sas_token = sas_token.or_else(|| std::env::var("AZURE_STORAGE_SAS_TOKEN").ok());
+
+        let auth = match (account_key, sas_token) {
+            (Some(key), _) => {
+                let key = STANDARD.decode(key.trim()).map_err(|e| {
+                    StorageError::Authentication(format!("Azure account key is not base64: {e}"))
+                })?;
+                Auth::SharedKey(hmac::Key::new(hmac::HMAC_SHA256, &key))
+            }
+            (None, Some(sas)) => Auth::Sas(
+                url::form_urlencoded::parse(sas.trim_start_matches('?').as_bytes())
+                    .into_owned()
+                    .collect(),
+            ),
+            (None, None) => {
+                info!("No Azure credentials given, sending anonymous requests");
+                Auth::Anonymous
+            }
+        };
+
+        let endpoint =
+            endpoint.unwrap_or_else(|| format!("https://{account}.blob.core.windows.net"));
+        let endpoint = Url::parse(endpoint.trim_end_matches('/'))
+            .map_err(|e| StorageError::Configuration(format!("Invalid Azure endpoint: {e}")))?;
+
+        Ok(Self {
+            http: reqwest::Client::new(),
+            account,
+            endpoint,
+            auth,
+        })
+    }
+
+    /// URL of a container, or of a blob in it
+    fn url(&self, container: &str, blob: Option<&str>, query: &[(&str, &str)]) -> Url {
+        let mut url = self.endpoint.clone();
+        {
+            let mut segments = url.path_segments_mut().expect("endpoint is a base URL");
+            segments.pop_if_empty();
+            if !container.is_empty() {
+                segments.push(container);
+            }
+            if let Some(blob) = blob {
+                segments.extend(blob.split('/'));
+            }
+        }
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+        url
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        url: Url,
+        headers: HeaderMap,
+        body: Option<Bytes>,
+        what: &str,
+    ) -> Result<Response, StorageError> {
+        let mut builder = self.http.request(method, url).headers(headers);
+        if let Some(body) = body {
+            builder = builder.body(body);
+        }
+        let request = self.sign(builder.build()?)?;
+        check(self.http.execute(request).await?, what).await
+    }
+
+    /// Add the date and version headers, and the credentials
+    fn sign(&self, mut request: Request) -> Result<Request, StorageError> {
+        let date = Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        let headers = request.headers_mut();
+        headers.insert("x-ms-date", header_value(&date)?);
+        headers.insert("x-ms-version", HeaderValue::from_static(API_VERSION));
+
+        match &self.auth {
+            Auth::SharedKey(key) => {
+                let string_to_sign = string_to_sign(&self.account, &request);
+                let signature = STANDARD.encode(hmac::sign(key, string_to_sign.as_bytes()));
+                let authorization = format!("SharedKey {}:{signature}", self.account);
+                request
+                    .headers_mut()
+                    .insert("authorization", header_value(&authorization)?);
+            }
+            Auth::Sas(params) => {
+                request
+                    .url_mut()
+                    .query_pairs_mut()
+                    .extend_pairs(params.iter());
+            }
+            Auth::Anonymous => {}
+        }
+        Ok(request)
+    }
+
+    async fn get_blob(
+        &self,
+        container: &str,
+        blob: &str,
+        range: Option<String>,
+    ) -> Result<Response, StorageError> {
+        let mut headers = HeaderMap::new();
+        if let Some(range) = range {
+            headers.insert("x-ms-range", header_value(&range)?);
+        }
+        self.send(
+            Method::GET,
+            self.url(container, Some(blob), &[]),
+            headers,
+            None,
+            &format!("Blob {container}/{blob}"),
+        )
+        .await
+    }
+
+    async fn put_block(
+        &self,
+        container: &str,
+        blob: &str,
+        block_id: &str,
+        block: Bytes,
+    ) -> Result<(), StorageError> {
+        let url = self.url(
+            container,
+            Some(blob),
+            &[("comp", "block"), ("blockid", block_id)],
+        );
+        self.send(
+            Method::PUT,
+            url,
+            HeaderMap::new(),
+            Some(block),
+            &format!("Block of {container}/{blob}"),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Commit the uploaded blocks as the content of the blob
+    async fn put_block_list(
+        &self,
+        container: &str,
+        blob: &str,
+        block_ids: &[String],
+        content_type: Option<&str>,
+        metadata: Option<Metadata>,
+    ) -> Result<(), StorageError> {
+        let mut headers = HeaderMap::new();
+        if let Some(content_type) = content_type {
+            headers.insert("x-ms-blob-content-type", header_value(content_type)?);
+        }
+        for (name, value) in metadata.unwrap_or_default() {
+            headers.insert(
+                HeaderName::from_bytes(
+                    format!("{META_PREFIX}{}", encode_metadata_name(&name)).as_bytes(),
+                )
+                .map_err(|e| StorageError::Request(format!("Invalid metadata name {name}: {e}")))?,
+                header_value(&value)?,
+            );
+        }
+
+        let mut body = String::from(r#"<?xml version="1.0" encoding="utf-8"?><BlockList>"#);
+        for id in block_ids {
+            body.push_str(&format!("<Latest>{id}</Latest>"));
+        }
+        body.push_str("</BlockList>");
+
+        self.send(
+            Method::PUT,
+            self.url(container, Some(blob), &[("comp", "blocklist")]),
+            headers,
+            Some(Bytes::from(body)),
+            &format!("Blob {container}/{blob}"),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Follow `NextMarker` through the pages of a listing
+    async fn list_all(
+        &self,
+        container: &str,
+        query: &[(&str, &str)],
+        what: &str,
+    ) -> Result<Vec<String>, StorageError> {
+        let mut pages = Vec::new();
+        let mut marker: Option<String> = None;
+        loop {
+            let mut query = query.to_vec();
+            if let Some(marker) = marker.as_deref() {
+                query.push(("marker", marker));
+            }
+            let url = self.url(container, None, &query);
+            let response = self
+                .send(Method::GET, url, HeaderMap::new(), None, what)
+                .await?;
+            let page = response.text().await?;
+            marker = xml_child_text(&page, "NextMarker")?.filter(|m| !m.is_empty());
+            pages.push(page);
+            if marker.is_none() {
+                return Ok(pages);
+            }
+        }
+    }
+
+    /// Service SAS granting read access to one blob
+    fn blob_sas(
+        &self,
+        key: &hmac::Key,
+        container: &str,
+        blob: &str,
+        expiry: DateTime<Utc>,
+    ) -> String {
+        let expiry = expiry.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+        let resource = format!("/blob/{}/{container}/{blob}", self.account);
+        // Permissions, start, expiry, resource, identifier, IP, protocol,
+        // version, resource type, snapshot time, encryption scope and the five
+        // response header overrides
+        let string_to_sign = [
+            "r",
+            "",
+            &expiry,
+            &resource,
+            "",
+            "",
+            "",
+            API_VERSION,
+            "b",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+        ]
+        .join("\n");
+        let signature = STANDARD.encode(hmac::sign(key, string_to_sign.as_bytes()));
+
+        url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("sv", API_VERSION)
+            .append_pair("sr", "b")
+            .append_pair("sp", "r")
+            .append_pair("se", &expiry)
+            .append_pair("sig", &signature)
+            .finish()
+    }
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, StorageError> {
+    HeaderValue::from_str(value)
+        .map_err(|e| StorageError::Request(format!("Invalid header value {value:?}: {e}")))
+}
+
+/// The Shared Key string to sign of a request
+fn string_to_sign(account: &str, request: &Request) -> String {
+    let headers = request.headers();
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let content_length = request
+        .body()
+        .and_then(|b| b.as_bytes())
+        .map(|b| b.len())
+        .filter(|len| *len > 0)
+        .map(|len| len.to_string())
+        .unwrap_or_default();
+
+    let mut lines = vec![
+        request.method().as_str().to_string(),
+        header("content-encoding"),
+        header("content-language"),
+        content_length,
+        header("content-md5"),
+        header("content-type"),
+        header("date"),
+        header("if-modified-since"),
+        header("if-match"),
+        header("if-none-match"),
+        header("if-unmodified-since"),
+        header("range"),
+    ];
+
+    let ms_headers: BTreeMap<_, _> = headers
+        .iter()
+        .filter(|(name, _)| name.as_str().starts_with("x-ms-"))
+        .map(|(name, value)| {
+            (
+                name.as_str().to_string(),
+                value.to_str().unwrap_or_default().trim().to_string(),
+            )
+        })
+        .collect();
+    lines.extend(
+        ms_headers
+            .into_iter()
+            .map(|(name, value)| format!("{name}:{value}")),
+    );
+
+    let mut params: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (name, value) in request.url().query_pairs() {
+        params
+            .entry(name.to_lowercase())
+            .or_default()
+            .push(value.into_owned());
+    }
+    let mut resource = format!("/{account}{}", request.url().path());
+    for (name, mut values) in params {
+        values.sort();
+        resource.push_str(&format!("\n{name}:{}", values.join(",")));
+    }
+    lines.push(resource);
+
+    lines.join("\n")
+}
+
+/// Turn a non-success response into the matching error
+async fn check(response: Response, what: &str) -> Result<Response, StorageError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let code = response
+        .headers()
+        .get("x-ms-error-code")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let body = response.text().await.unwrap_or_default();
+    Err(match status {
+        StatusCode::NOT_FOUND => StorageError::NotFound(format!("{what} not found")),
+        StatusCode::FORBIDDEN if code.starts_with("Authentication") => {
+            StorageError::Authentication(format!("{what}: {code}"))
+        }
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            StorageError::PermissionDenied(format!("{what}: {code}"))
+        }
+        StatusCode::CONFLICT | StatusCode::PRECONDITION_FAILED => {
+            StorageError::Conflict(format!("{what}: {code}"))
+        }
+        StatusCode::RANGE_NOT_SATISFIABLE => {
+            StorageError::Request(format!("Requested range is outside of {what}"))
+        }
+        _ => {
+            error!("Azure request for {what} failed with {status}: {body}");
+            StorageError::Azure(format!("{what}: {status} {code}"))
+        }
+    })
+}
+
+fn parse_xml(xml: &str) -> Result<roxmltree::Document<'_>, StorageError> {
+    roxmltree::Document::parse(xml.trim_start_matches('\u{feff}'))
+        .map_err(|e| StorageError::Serialization(format!("Invalid Azure listing: {e}")))
+}
+
+fn xml_child_text(xml: &str, name: &str) -> Result<Option<String>, StorageError> {
+    let doc = parse_xml(xml)?;
+    Ok(doc
+        .root_element()
+        .children()
+        .find(|n| n.has_tag_name(name))
+        .and_then(|n| n.text())
+        .map(str::to_string))
+}
+
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|n| n.has_tag_name(name))
+        .and_then(|n| n.text())
+}
+
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|d| d.with_timezone(&Utc))
+}
+
+/// Containers of a `comp=list` page
+fn parse_containers(xml: &str) -> Result<Vec<Bucket>, StorageError> {
+    let doc = parse_xml(xml)?;
+    Ok(doc
+        .descendants()
+        .filter(|n| n.has_tag_name("Container"))
+        .filter_map(|container| {
+            let name = child_text(container, "Name")?.to_string();
+            let properties = container.children().find(|n| n.has_tag_name("Properties"));
+            let created = properties
+                .and_then(|p| child_text(p, "Last-Modified"))
+                .and_then(parse_http_date);
+            Some(Bucket {
+                name,
+                creation_date: created.map(SystemTime::from),
+                region: None,
+            })
+        })
+        .collect())
+}
+
+/// Blobs of a `restype=container&comp=list` page
+fn parse_blobs(xml: &str) -> Result<Vec<StorageObject>, StorageError> {
+    let doc = parse_xml(xml)?;
+    Ok(doc
+        .descendants()
+        .filter(|n| n.has_tag_name("Blob"))
+        .filter_map(|blob| {
+            let key = child_text(blob, "Name")?.to_string();
+            let properties = blob.children().find(|n| n.has_tag_name("Properties"));
+            let property = |name| properties.and_then(|p| child_text(p, name));
+            Some(StorageObject {
+                key,
+                size: property("Content-Length").and_then(|s| s.parse().ok()),
+                last_modified: property("Last-Modified").and_then(parse_http_date),
+                etag: property("Etag").map(str::to_string),
+                storage_class: property("AccessTier").map(str::to_string),
+            })
+        })
+        .collect())
+}
+
+/// Block IDs must all have the same length within a blob
+fn block_id(index: usize) -> String {
+    STANDARD.encode(format!("block-{index:08}"))
+}
+
+#[async_trait]
+impl StorageProvider for AzureProvider {
+    fn name(&self) -> &str {
+        "Azure Blob Storage"
+    }
+
+    async fn create_bucket(&self, bucket: &str) -> Result<(), StorageError> {
+        let url = self.url(bucket, None, &[("restype", "container")]);
+        match self
+            .send(
+                Method::PUT,
+                url,
+                HeaderMap::new(),
+                None,
+                &format!("Container {bucket}"),
+            )
+            .await
+        {
+            Ok(_) => {
+                info!("Created container {bucket}");
+                Ok(())
+            }
+            Err(StorageError::Conflict(_)) => {
+                info!("Container {bucket} already exists");
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn bucket_exists(&self, bucket: &str) -> Result<bool, StorageError> {
+        let url = self.url(bucket, None, &[("restype", "container")]);
+        match self
+            .send(
+                Method::HEAD,
+                url,
+                HeaderMap::new(),
+                None,
+                &format!("Container {bucket}"),
+            )
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(StorageError::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn list_buckets(&self) -> Result<Vec<Bucket>, StorageError> {
+        let mut buckets = Vec::new();
+        for page in self.list_all("", &[("comp", "list")], "Containers").await? {
+            buckets.extend(parse_containers(&page)?);
+        }
+        Ok(buckets)
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: Option<&str>,
+    ) -> Result<Vec<StorageObject>, StorageError> {
+        let mut query = vec![("restype", "container"), ("comp", "list")];
+        if let Some(prefix) = prefix {
+            query.push(("prefix", prefix));
+        }
+        let mut objects = Vec::new();
+        for page in self
+            .list_all(bucket, &query, &format!("Container {bucket}"))
+            .await?
+        {
+            objects.extend(parse_blobs(&page)?);
+        }
+        Ok(objects)
+    }
+
+    async fn upload_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        file_path: &Path,
+        content_type: Option<&str>,
+        metadata: Option<Metadata>,
+    ) -> Result<(), StorageError> {
+        let file = tokio::fs::File::open(file_path).await?;
+        self.upload_stream(
+            bucket,
+            key,
+            Box::pin(ReaderStream::new(file)),
+            content_type,
+            metadata,
+        )
+        .await
+    }
+
+    async fn download_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        file_path: &Path,
+    ) -> Result<(), StorageError> {
+        let mut stream = self.download_stream(bucket, key).await?;
+        if let Some(parent) = file_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut file = tokio::fs::File::create(file_path).await?;
+        while let Some(chunk) = stream.next().await {
+            file.write_all(&chunk?).await?;
+        }
+        file.flush().await?;
+        Ok(())
+    }
+
+    async fn download_stream(&self, bucket: &str, key: &str) -> Result<ByteStream, StorageError> {
+        self.download_stream_range(bucket, key, &StreamingDownloadOptions::default())
+            .await
+    }
+
+    async fn download_stream_range(
+        &self,
+        bucket: &str,
+        key: &str,
+        options: &StreamingDownloadOptions,
+    ) -> Result<ByteStream, StorageError> {
+        let response = self.get_blob(bucket, key, options.http_range()).await?;
+        Ok(Box::pin(
+            response
+                .bytes_stream()
+                .map(|r| r.map_err(std::io::Error::other)),
+        ))
+    }
+
+    /// Metadata names are decoded with [`decode_metadata_name`]
+    async fn get_object_metadata(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<ObjectMetadata, StorageError> {
+        let response = self
+            .send(
+                Method::HEAD,
+                self.url(bucket, Some(key), &[]),
+                HeaderMap::new(),
+                None,
+                &format!("Blob {bucket}/{key}"),
+            )
+            .await?;
+        let headers = response.headers();
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v: &HeaderValue| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let metadata: Metadata = headers
+            .iter()
+            .filter_map(|(name, value)| {
+                let name = name.as_str().strip_prefix(META_PREFIX)?;
+                Some((decode_metadata_name(name), value.to_str().ok()?.to_string()))
+            })
+            .collect();
+
+        Ok(ObjectMetadata {
+            key: key.to_string(),
+            size: response.content_length(),
+            last_modified: header(LAST_MODIFIED).as_deref().and_then(parse_http_date),
+            etag: header(ETAG),
+            content_type: header(CONTENT_TYPE),
+            storage_class: header(HeaderName::from_static("x-ms-access-tier")),
+            metadata: (!metadata.is_empty()).then_some(metadata),
+        })
+    }
+
+    async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), StorageError> {
+        let deleted = self
+            .send(
+                Method::DELETE,
+                self.url(bucket, Some(key), &[]),
+                HeaderMap::new(),
+                None,
+                &format!("Blob {bucket}/{key}"),
+            )
+            .await;
+        match deleted {
+            Ok(_) | Err(StorageError::NotFound(_)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn object_exists(&self, bucket: &str, key: &str) -> Result<bool, StorageError> {
+        match self.get_object_metadata(bucket, key).await {
+            Ok(_) => Ok(true),
+            Err(StorageError::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read-only service SAS URL, which needs the account key
+    async fn generate_presigned_url(
+        &self,
+        bucket: &str,
+        key: &str,
+        expires_in: Duration,
+    ) -> Result<String, StorageError> {
+        let Auth::SharedKey(account_key) = &self.auth else {
+            return Err(StorageError::Authentication(
+                "SAS URLs can only be signed with the account key".to_string(),
+            ));
+        };
+        let expires_in = ChronoDuration::from_std(expires_in)
+            .map_err(|e| StorageError::Configuration(format!("Invalid URL lifetime: {e}")))?;
+
+        let mut url = self.url(bucket, Some(key), &[]);
+        url.set_query(Some(&self.blob_sas(
+            account_key,
+            bucket,
+            key,
+            Utc::now() + expires_in,
+        )));
+        Ok(url.to_string())
+    }
+
+    /// Uploads blocks of [`BLOCK_SIZE`] and commits them as the blob
+    async fn upload_stream(
+        &self,
+        bucket: &str,
+        key: &str,
+        mut stream: ByteStream,
+        content_type: Option<&str>,
+        metadata: Option<Metadata>,
+    ) -> Result<(), StorageError> {
+        let mut buffer = BytesMut::new();
+        let mut block_ids = Vec::new();
+        let mut size = 0u64;
+        loop {
+            let chunk = stream.next().await.transpose()?;
+            let done = chunk.is_none();
+            if let Some(chunk) = chunk {
+                buffer.extend_from_slice(&chunk);
+            }
+            while buffer.len() >= BLOCK_SIZE || (done && !buffer.is_empty()) {
+                let len = buffer.len().min(BLOCK_SIZE);
+                let block = buffer.split_to(len).freeze();
+                let id = block_id(block_ids.len());
+                self.put_block(bucket, key, &id, block).await?;
+                block_ids.push(id);
+                size += len as u64;
+            }
+            if done {
+                break;
+            }
+        }
+
+        self.put_block_list(bucket, key, &block_ids, content_type, metadata)
+            .await?;
+        debug!(
+            "Uploaded {size} bytes to {bucket}/{key} in {} blocks",
+            block_ids.len()
+        );
+        Ok(())
+    }
+
+    async fn get_object(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<(Bytes, Option<String>), StorageError> {
+        let response = self.get_blob(bucket, key, None).await?;
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        Ok((response.bytes().await?, etag))
+    }
+
+    async fn put_object_if_match(
+        &self,
+        bucket: &str,
+        key: &str,
+        body: Bytes,
+        content_type: Option<&str>,
+        if_match: Option<&str>,
+    ) -> Result<Option<String>, StorageError> {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ms-blob-type", HeaderValue::from_static("BlockBlob"));
+        headers.insert(
+            CONTENT_TYPE,
+            header_value(content_type.unwrap_or("application/octet-stream"))?,
+        );
+        match if_match {
+            Some(etag) => headers.insert("if-match", header_value(etag)?),
+            None => headers.insert("if-none-match", HeaderValue::from_static("*")),
+        };
+
+        let response = self
+            .send(
+                Method::PUT,
+                self.url(bucket, Some(key), &[]),
+                headers,
+                Some(body),
+                &format!("Blob {bucket}/{key}"),
+            )
+            .await?;
+        Ok(response
+            .headers()
+            .get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string))
+    }
+}
+
+/// Name of a metadata entry as Azure stores it
+///
+/// Azure only takes C# identifiers, so every other character, such as the
+/// `-` of `content-sha256`, is written `_xHH_` with its hex code, as is a
+/// leading digit and the `_` of a literal `_x`. Identifiers like `backup_id`
+/// are kept as they are.
+fn encode_metadata_name(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    let mut chars = name.chars().peekable();
+    let mut first = true;
+    while let Some(c) = chars.next() {
+        let keep = match c {
+            'a'..='z' | 'A'..='Z' => true,
+            '0'..='9' => !first,
+            '_' => chars.peek() != Some(&'x'),
+            _ => false,
+        };
+        if keep {
+            encoded.push(c);
+        } else {
+            encoded.push_str(&format!("_x{:02x}_", c as u32));
+        }
+        first = false;
+    }
+    encoded
+}
+
+/// Metadata name written by [`encode_metadata_name`]
+fn decode_metadata_name(name: &str) -> String {
+    let mut decoded = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(start) = rest.find("_x") {
+        decoded.push_str(&rest[..start]);
+        let escape = &rest[start + 2..];
+        let code = escape
+            .split_once('_')
+            .filter(|(hex, _)| !hex.is_empty() && hex.len() <= 6)
+            .and_then(|(hex, tail)| {
+                Some((char::from_u32(u32::from_str_radix(hex, 16).ok()?)?, tail))
+            });
+        match code {
+            Some((c, tail)) => {
+                decoded.push(c);
+                rest = tail;
+            }
+            None => {
+                decoded.push_str("_x");
+                rest = escape;
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_names_round_trip() {
+        assert_eq!(encode_metadata_name("backup_id"), "backup_id");
+        assert_eq!(encode_metadata_name("content-sha256"), "content_x2d_sha256");
+        for name in [
+            "backup_id",
+            "content-sha256",
+            "warden-key_id",
+            "tax_x2d_rate",
+            "2fa",
+            "a.b",
+            "_x",
+        ] {
+            let encoded = encode_metadata_name(name);
+            assert!(
+                encoded
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    && !encoded.starts_with(|c: char| c.is_ascii_digit()),
+                "{encoded} is not an identifier"
+            );
+            assert_eq!(decode_metadata_name(&encoded), name);
+        }
+    }
+
+    #[test]
+    fn shared_key_canonicalizes_headers_and_query() {
+        let request = reqwest::Client::new()
+            .put("http://127.0.0.1:10000/devstoreaccount1/backups/a/b?comp=block&blockid=Zm9v")
+            .header("x-ms-version", API_VERSION)
+            .header("x-ms-date", "Sat, 17 Oct 2026 10:00:00 GMT")
+            .body("abc")
+            .build()
+            .unwrap();
+        assert_eq!(
+            string_to_sign("devstoreaccount1", &request),
+            "PUT\n\n\n3\n\n\n\n\n\n\n\n\n\
+             x-ms-date:Sat, 17 Oct 2026 10:00:00 GMT\n\
+             x-ms-version:2021-12-02\n\
+             /devstoreaccount1/devstoreaccount1/backups/a/b\nblockid:Zm9v\ncomp:block"
+        );
+    }
+
+    #[test]
+    fn parses_blob_listings() {
+        let xml = "\u{feff}<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+            <EnumerationResults ContainerName=\"backups\"><Blobs><Blob><Name>a/catalog.json</Name>\
+            <Properties><Last-Modified>Sat, 17 Oct 2026 10:00:00 GMT</Last-Modified>\
+            <Etag>0x8D</Etag><Content-Length>42</Content-Length><AccessTier>Hot</AccessTier>\
+            </Properties></Blob></Blobs><NextMarker>next</NextMarker></EnumerationResults>";
+        let blobs = parse_blobs(xml).unwrap();
+        assert_eq!(blobs.len(), 1);
+        assert_eq!(blobs[0].key, "a/catalog.json");
+        assert_eq!(blobs[0].size, Some(42));
+        assert_eq!(blobs[0].storage_class.as_deref(), Some("Hot"));
+        assert!(blobs[0].last_modified.is_some());
+        assert_eq!(
+            xml_child_text(xml, "NextMarker").unwrap().as_deref(),
+            Some("next")
+        );
+    }
+
+    #[test]
+    fn block_ids_have_a_fixed_length() {
+        assert_eq!(block_id(0).len(), block_id(12345).len());
+    }
+}
@@ -0,0 +1,624 @@
+//! Storage in a directory of the local filesystem, such as an NFS mount.
+//!
+//! Buckets are directories below the root and keys paths below those. The
+//! content type and metadata of an object live in a sidecar file next to
+//! it, and uploads are written to a temporary file that is renamed into
+//! place once complete. Conditional writes hold a lock file next to the
+//! object, created exclusively, so they also work between hosts sharing
+//! an NFS mount.
+
+use crate::{
+    put_if_unchanged, Bucket, ByteStream, Metadata, ObjectMetadata, StorageError, StorageObject,
+    StorageProvider, StreamingDownloadOptions,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::StreamExt;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+
+/// Suffix of the file holding the content type and metadata of an object
+pub(crate) const SIDECAR_SUFFIX: &str = ".warden-meta";
+/// Suffix of an object while it is being uploaded
+pub(crate) const PARTIAL_SUFFIX: &str = ".warden-partial";
+/// Suffix of the file held by a conditional write of an object
+pub(crate) const LOCK_SUFFIX: &str = ".warden-lock";
+/// How long a conditional write waits for the lock before giving up
+pub(crate) const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+/// Delay between attempts to take a held lock
+pub(crate) const LOCK_RETRY: Duration = Duration::from_millis(50);
+/// Age after which a lock is taken to be left by a writer that died
+pub(crate) const LOCK_STALE_AFTER: Duration = Duration::from_secs(600);
+
+/// What object stores keep alongside the content
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Sidecar {
+    pub content_type: Option<String>,
+    #[serde(default)]
+    pub metadata: Metadata,
+}
+
+impl Sidecar {
+    /// `None` when there is nothing worth writing
+    pub fn new(content_type: Option<&str>, metadata: Option<Metadata>) -> Option<Self> {
+        let sidecar = Self {
+            content_type: content_type.map(str::to_string),
+            metadata: metadata.unwrap_or_default(),
+        };
+        (sidecar.content_type.is_some() || !sidecar.metadata.is_empty()).then_some(sidecar)
+    }
+}
+
+/// Whether a file name is bookkeeping rather than an object
+pub(crate) fn is_internal(name: &str) -> bool {
+    name.ends_with(SIDECAR_SUFFIX) || name.ends_with(PARTIAL_SUFFIX) || name.ends_with(LOCK_SUFFIX)
+}
+
+/// Reject bucket names and keys that would escape the root
+pub(crate) fn check_path(path: &str, what: &str) -> Result<(), StorageError> {
+    let bad = path.is_empty()
+        || path.starts_with('/')
+        || path
+            .split('/')
+            .any(|part| part.is_empty() || part == "." || part == "..")
+        || is_internal(path);
+    if bad {
+        return Err(StorageError::Request(format!("Invalid {what} {path:?}")));
+    }
+    Ok(())
+}
+
+/// Stream the requested range of a seekable reader
+pub(crate) async fn ranged_stream<R>(
+    mut reader: R,
+    options: &StreamingDownloadOptions,
+) -> Result<ByteStream, StorageError>
+where
+    R: AsyncRead + AsyncSeek + Unpin + Send + 'static,
+{
+    let start = options.range_start.unwrap_or(0);
+    if start > 0 {
+        reader.seek(SeekFrom::Start(start)).await?;
+    }
+    match options.range_end {
+        Some(end) => {
+            let len = (end + 1).saturating_sub(start);
+            Ok(Box::pin(ReaderStream::new(reader.take(len))))
+        }
+        None => Ok(Box::pin(ReaderStream::new(reader))),
+    }
+}
+
+/// Changes whenever the file is rewritten
+pub(crate) fn file_etag(size: u64, modified: Option<SystemTime>) -> String {
+    let nanos = modified
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default()
+        .as_nanos();
+    format!("{size:x}-{nanos:x}")
+}
+
+/// Whether a lock file last touched at `modified` was left behind
+pub(crate) fn is_stale_lock(modified: Option<SystemTime>) -> bool {
+    modified
+        .and_then(|m| m.elapsed().ok())
+        .is_some_and(|age| age > LOCK_STALE_AFTER)
+}
+
+/// The error of a conditional write that could not take the lock in time
+pub(crate) fn lock_timeout(bucket: &str, key: &str, lock: &str) -> StorageError {
+    StorageError::Conflict(format!(
+        "Object {bucket}/{key} is being written by another writer, \
+         remove {lock} if that writer is gone"
+    ))
+}
+
+fn not_found(e: std::io::Error, what: &str) -> StorageError {
+    match e.kind() {
+        ErrorKind::NotFound => StorageError::NotFound(format!("{what} not found")),
+        ErrorKind::PermissionDenied => StorageError::PermissionDenied(format!("{what}: {e}")),
+        _ => StorageError::Io(e),
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+pub struct LocalProvider {
+    root: PathBuf,
+}
+
+/// The lock file of an object, removed when dropped
+struct LockFile {
+    path: PathBuf,
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Could not remove lock {}: {e}", self.path.display());
+        }
+    }
+}
+
+impl LocalProvider {
+    /// Creates a provider storing buckets below `root`, creating it if needed
+    pub async fn new(root: impl Into<PathBuf>) -> Result<Self, StorageError> {
+        let root = root.into();
+        tokio::fs::create_dir_all(&root).await?;
+        info!("Storing backups below {}", root.display());
+        Ok(Self { root })
+    }
+
+    fn bucket_dir(&self, bucket: &str) -> Result<PathBuf, StorageError> {
+        if bucket.contains('/') {
+            return Err(StorageError::Request(format!("Invalid bucket {bucket:?}")));
+        }
+        check_path(bucket, "bucket")?;
+        Ok(self.root.join(bucket))
+    }
+
+    fn object_path(&self, bucket: &str, key: &str) -> Result<PathBuf, StorageError> {
+        check_path(key, "key")?;
+        Ok(self.bucket_dir(bucket)?.join(key))
+    }
+
+    async fn read_sidecar(&self, path: &Path) -> Result<Sidecar, StorageError> {
+        match tokio::fs::read(with_suffix(path, SIDECAR_SUFFIX)).await {
+            Ok(json) => Ok(serde_json::from_slice(&json)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Sidecar::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Take the lock file of the object at `path`, waiting while another writer holds it
+    async fn lock_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        path: &Path,
+    ) -> Result<LockFile, StorageError> {
+        let lock = with_suffix(path, LOCK_SUFFIX);
+        if let Some(parent) = lock.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let deadline = Instant::now() + LOCK_TIMEOUT;
+        loop {
+            let created = tokio::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&lock)
+                .await;
+            match created {
+                Ok(_) => return Ok(LockFile { path: lock }),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            let modified = match tokio::fs::metadata(&lock).await {
+                Ok(metadata) => metadata.modified().ok(),
+                // Released in the meantime
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if is_stale_lock(modified) {
+                warn!("Removing stale lock {}", lock.display());
+                tokio::fs::remove_file(&lock).await.ok();
+                continue;
+            }
+            if Instant::now() >= deadline {
+                return Err(lock_timeout(bucket, key, &lock.display().to_string()));
+            }
+            tokio::time::sleep(LOCK_RETRY).await;
+        }
+    }
+
+    /// Remove directories left empty by a delete, up to the bucket
+    async fn prune_empty_dirs(&self, bucket_dir: &Path, path: &Path) {
+        let mut dir = path.parent();
+        while let Some(current) = dir {
+            if current == bucket_dir || tokio::fs::remove_dir(current).await.is_err() {
+                break;
+            }
+            dir = current.parent();
+        }
+    }
+}
+
+#[async_trait]
+impl StorageProvider for LocalProvider {
+    fn name(&self) -> &str {
+        "Local filesystem"
+    }
+
+    async fn create_bucket(&self, bucket: &str) -> Result<(), StorageError> {
+        tokio::fs::create_dir_all(self.bucket_dir(bucket)?).await?;
+        Ok(())
+    }
+
+    async fn bucket_exists(&self, bucket: &str) -> Result<bool, StorageError> {
+        Ok(tokio::fs::metadata(self.bucket_dir(bucket)?)
+            .await
+            .is_ok_and(|m| m.is_dir()))
+    }
+
+    async fn list_buckets(&self) -> Result<Vec<Bucket>, StorageError> {
+        let mut buckets = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.root).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_dir() {
+                buckets.push(Bucket {
+                    name: entry.file_name().to_string_lossy().into_owned(),
+                    creation_date: metadata.created().or_else(|_| metadata.modified()).ok(),
+                    region: None,
+                });
+            }
+        }
+        buckets.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(buckets)
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: Option<&str>,
+    ) -> Result<Vec<StorageObject>, StorageError> {
+        let dir = self.bucket_dir(bucket)?;
+        if !tokio::fs::try_exists(&dir).await? {
+            return Err(StorageError::NotFound(format!("Bucket {bucket} not found")));
+        }
+
+        let mut objects = Vec::new();
+        for entry in walkdir::WalkDir::new(&dir).sort_by_file_name() {
+            let entry = entry.map_err(|e| StorageError::Io(e.into()))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let key = entry
+                .path()
+                .strip_prefix(&dir)
+                .map_err(|e| StorageError::Unexpected(e.to_string()))?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if is_internal(&key) || !key.starts_with(prefix.unwrap_or_default()) {
+                continue;
+            }
+            let metadata = entry.metadata().map_err(|e| StorageError::Io(e.into()))?;
+            let modified = metadata.modified().ok();
+            objects.push(StorageObject {
+                key,
+                size: Some(metadata.len()),
+                last_modified: modified.map(Into::into),
+                etag: Some(file_etag(metadata.len(), modified)),
+                storage_class: None,
+            });
+        }
+        Ok(objects)
+    }
+
+    async fn upload_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        file_path: &Path,
+        content_type: Option<&str>,
+        metadata: Option<Metadata>,
+    ) -> Result<(), StorageError> {
+        let file = tokio::fs::File::open(file_path).await?;
+        self.upload_stream(
+            bucket,
+            key,
+            Box::pin(ReaderStream::new(file)),
+            content_type,
+            metadata,
+        )
+        .await
+    }
+
+    async fn download_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        file_path: &Path,
+    ) -> Result<(), StorageError> {
+        let path = self.object_path(bucket, key)?;
+        if let Some(parent) = file_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::copy(&path, file_path)
+            .await
+            .map_err(|e| not_found(e, &format!("Object {bucket}/{key}")))?;
+        Ok(())
+    }
+
+    async fn download_stream(&self, bucket: &str, key: &str) -> Result<ByteStream, StorageError> {
+        self.download_stream_range(bucket, key, &StreamingDownloadOptions::default())
+            .await
+    }
+
+    async fn download_stream_range(
+        &self,
+        bucket: &str,
+        key: &str,
+        options: &StreamingDownloadOptions,
+    ) -> Result<ByteStream, StorageError> {
+        let file = tokio::fs::File::open(self.object_path(bucket, key)?)
+            .await
+            .map_err(|e| not_found(e, &format!("Object {bucket}/{key}")))?;
+        ranged_stream(file, options).await
+    }
+
+    async fn get_object_metadata(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<ObjectMetadata, StorageError> {
+        let path = self.object_path(bucket, key)?;
+        let metadata = tokio::fs::metadata(&path)
+            .await
+            .map_err(|e| not_found(e, &format!("Object {bucket}/{key}")))?;
+        let sidecar = self.read_sidecar(&path).await?;
+        let modified = metadata.modified().ok();
+        Ok(ObjectMetadata {
+            key: key.to_string(),
+            size: Some(metadata.len()),
+            last_modified: modified.map(Into::into),
+            etag: Some(file_etag(metadata.len(), modified)),
+            content_type: sidecar.content_type,
+            storage_class: None,
+            metadata: (!sidecar.metadata.is_empty()).then_some(sidecar.metadata),
+        })
+    }
+
+    async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), StorageError> {
+        let path = self.object_path(bucket, key)?;
+        for file in [path.clone(), with_suffix(&path, SIDECAR_SUFFIX)] {
+            match tokio::fs::remove_file(file).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        self.prune_empty_dirs(&self.bucket_dir(bucket)?, &path)
+            .await;
+        Ok(())
+    }
+
+    async fn object_exists(&self, bucket: &str, key: &str) -> Result<bool, StorageError> {
+        Ok(tokio::fs::metadata(self.object_path(bucket, key)?)
+            .await
+            .is_ok_and(|m| m.is_file()))
+    }
+
+    async fn generate_presigned_url(
+        &self,
+        _bucket: &str,
+        _key: &str,
+        _expires_in: Duration,
+    ) -> Result<String, StorageError> {
+        Err(StorageError::Configuration(
+            "The local filesystem provider cannot generate presigned URLs".to_string(),
+        ))
+    }
+
+    /// Checks and writes while holding the lock file of the object
+    async fn put_object_if_match(
+        &self,
+        bucket: &str,
+        key: &str,
+        body: Bytes,
+        content_type: Option<&str>,
+        if_match: Option<&str>,
+    ) -> Result<Option<String>, StorageError> {
+        let path = self.object_path(bucket, key)?;
+        let _lock = self.lock_object(bucket, key, &path).await?;
+        put_if_unchanged(self, bucket, key, body, content_type, if_match).await
+    }
+
+    async fn upload_stream(
+        &self,
+        bucket: &str,
+        key: &str,
+        mut stream: ByteStream,
+        content_type: Option<&str>,
+        metadata: Option<Metadata>,
+    ) -> Result<(), StorageError> {
+        let path = self.object_path(bucket, key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let partial = with_suffix(&path, PARTIAL_SUFFIX);
+        let mut file = tokio::fs::File::create(&partial).await?;
+        let mut size = 0u64;
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    drop(file);
+                    tokio::fs::remove_file(&partial).await.ok();
+                    return Err(e.into());
+                }
+            };
+            size += chunk.len() as u64;
+            file.write_all(&chunk).await?;
+        }
+        file.sync_all().await?;
+
+        let sidecar_path = with_suffix(&path, SIDECAR_SUFFIX);
+        match Sidecar::new(content_type, metadata) {
+            Some(sidecar) => tokio::fs::write(&sidecar_path, serde_json::to_vec(&sidecar)?).await?,
+            None => match tokio::fs::remove_file(&sidecar_path).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            },
+        }
+        tokio::fs::rename(&partial, &path).await?;
+
+        debug!("Wrote {size} bytes to {}", path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::TryStreamExt;
+    use std::sync::Arc;
+
+    async fn read(stream: ByteStream) -> Vec<u8> {
+        let chunks: Vec<Bytes> = stream.try_collect().await.unwrap();
+        chunks.concat()
+    }
+
+    #[tokio::test]
+    async fn objects_round_trip_with_metadata() {
+        let root = tempfile::tempdir().unwrap();
+        let provider = LocalProvider::new(root.path()).await.unwrap();
+        provider.create_bucket("backups").await.unwrap();
+
+        let body = futures::stream::once(async { Ok(Bytes::from_static(b"0123456789")) });
+        let metadata = Metadata::from([("compression".to_string(), "zstd".to_string())]);
+        provider
+            .upload_stream(
+                "backups",
+                "b1/pg_wal/0001",
+                Box::pin(body),
+                Some("application/octet-stream"),
+                Some(metadata.clone()),
+            )
+            .await
+            .unwrap();
+
+        let objects = provider.list_objects("backups", Some("b1/")).await.unwrap();
+        assert_eq!(
+            objects.iter().map(|o| o.key.as_str()).collect::<Vec<_>>(),
+            vec!["b1/pg_wal/0001"]
+        );
+        let object = provider
+            .get_object_metadata("backups", "b1/pg_wal/0001")
+            .await
+            .unwrap();
+        assert_eq!(object.size, Some(10));
+        assert_eq!(object.metadata, Some(metadata));
+
+        let options = StreamingDownloadOptions {
+            range_start: Some(2),
+            range_end: Some(5),
+        };
+        let part = provider
+            .download_stream_range("backups", "b1/pg_wal/0001", &options)
+            .await
+            .unwrap();
+        assert_eq!(read(part).await, b"2345");
+
+        provider
+            .delete_object("backups", "b1/pg_wal/0001")
+            .await
+            .unwrap();
+        assert!(!provider
+            .object_exists("backups", "b1/pg_wal/0001")
+            .await
+            .unwrap());
+        assert!(!root.path().join("backups/b1").exists());
+        assert!(matches!(
+            provider.download_stream("backups", "b1/pg_wal/0001").await,
+            Err(StorageError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn concurrent_conditional_writes_have_one_winner() {
+        let root = tempfile::tempdir().unwrap();
+        let provider = Arc::new(LocalProvider::new(root.path()).await.unwrap());
+        provider.create_bucket("backups").await.unwrap();
+
+        let writers = (0..8).map(|i| {
+            let provider = provider.clone();
+            tokio::spawn(async move {
+                provider
+                    .put_object_if_match(
+                        "backups",
+                        "catalog.json",
+                        Bytes::from(format!("[{i}]")),
+                        None,
+                        None,
+                    )
+                    .await
+            })
+        });
+        let results = futures::future::join_all(writers).await;
+        let won = results
+            .iter()
+            .filter(|r| r.as_ref().unwrap().is_ok())
+            .count();
+        assert_eq!(won, 1);
+        assert!(results
+            .iter()
+            .all(|r| matches!(r.as_ref().unwrap(), Ok(_) | Err(StorageError::Conflict(_)))));
+        assert!(!root
+            .path()
+            .join("backups/catalog.json.warden-lock")
+            .exists());
+
+        // A lock left by a live writer makes the next one wait for it
+        let lock = root.path().join("backups/catalog.json.warden-lock");
+        std::fs::write(&lock, b"").unwrap();
+        let (_, etag) = provider
+            .get_object("backups", "catalog.json")
+            .await
+            .unwrap();
+        let waiting = {
+            let provider = provider.clone();
+            tokio::spawn(async move {
+                provider
+                    .put_object_if_match(
+                        "backups",
+                        "catalog.json",
+                        Bytes::from("[]"),
+                        None,
+                        etag.as_deref(),
+                    )
+                    .await
+            })
+        };
+        tokio::time::sleep(LOCK_RETRY * 4).await;
+        assert!(!waiting.is_finished());
+        std::fs::remove_file(&lock).unwrap();
+        waiting.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn keys_cannot_escape_the_root() {
+        let root = tempfile::tempdir().unwrap();
+        let provider = LocalProvider::new(root.path()).await.unwrap();
+        for key in [
+            "../etc/passwd",
+            "/etc/passwd",
+            "a//b",
+            "a/./b",
+            "a.warden-meta",
+            "a.warden-lock",
+        ] {
+            assert!(
+                matches!(
+                    provider.object_exists("backups", key).await,
+                    Err(StorageError::Request(_))
+                ),
+                "{key}"
+            );
+        }
+        assert!(provider.bucket_exists("..").await.is_err());
+    }
+}
@@ -0,0 +1,472 @@
+//! Storage held in memory, for tests that should not need a server.
+//!
+//! Clones of a provider share their buckets, so a test can hand one clone
+//! to the code under test and look at what it wrote through another.
+//! Writes are atomic: readers see either the old or the new content of an
+//! object, never a partial upload. Buckets support object lock, and a
+//! locked object refuses deletion until its retention expires.
+
+use crate::{
+    Bucket, BucketLockStatus, ByteStream, Metadata, ObjectMetadata, ObjectRetention, StorageError,
+    StorageObject, StorageProvider,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+/// Size of the chunks downloads are split into, so readers see several
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug)]
+struct MemoryBucket {
+    created: SystemTime,
+    objects: BTreeMap<String, MemoryObject>,
+}
+
+#[derive(Debug, Clone)]
+struct MemoryObject {
+    data: Bytes,
+    content_type: Option<String>,
+    metadata: Metadata,
+    modified: DateTime<Utc>,
+    etag: String,
+    retention: Option<ObjectRetention>,
+}
+
+impl MemoryObject {
+    fn new(data: Bytes, content_type: Option<&str>, metadata: Option<Metadata>) -> Self {
+        Self {
+            etag: format!("{:x}", md5::compute(&data)),
+            data,
+            content_type: content_type.map(str::to_string),
+            metadata: metadata.unwrap_or_default(),
+            modified: Utc::now(),
+            retention: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MemoryProvider {
+    buckets: Arc<Mutex<BTreeMap<String, MemoryBucket>>>,
+}
+
+impl MemoryProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn buckets(&self) -> MutexGuard<'_, BTreeMap<String, MemoryBucket>> {
+        // A panicking test must not take the other tests' storage with it
+        self.buckets.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Copy of an object, or `NotFound`
+    fn object(&self, bucket: &str, key: &str) -> Result<MemoryObject, StorageError> {
+        self.buckets()
+            .get(bucket)
+            .and_then(|b| b.objects.get(key))
+            .cloned()
+            .ok_or_else(|| StorageError::NotFound(format!("Object {bucket}/{key} not found")))
+    }
+
+    /// Store an object, failing if its bucket does not exist
+    fn put(&self, bucket: &str, key: &str, object: MemoryObject) -> Result<(), StorageError> {
+        check_key(key)?;
+        let mut buckets = self.buckets();
+        let objects = &mut buckets
+            .get_mut(bucket)
+            .ok_or_else(|| StorageError::NotFound(format!("Bucket {bucket} not found")))?
+            .objects;
+        objects.insert(key.to_string(), object);
+        Ok(())
+    }
+}
+
+fn check_key(key: &str) -> Result<(), StorageError> {
+    if key.is_empty() {
+        return Err(StorageError::Request("Invalid key \"\"".to_string()));
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl StorageProvider for MemoryProvider {
+    fn name(&self) -> &str {
+        "Memory"
+    }
+
+    async fn create_bucket(&self, bucket: &str) -> Result<(), StorageError> {
+        self.buckets()
+            .entry(bucket.to_string())
+            .or_insert_with(|| MemoryBucket {
+                created: SystemTime::now(),
+                objects: BTreeMap::new(),
+            });
+        Ok(())
+    }
+
+    async fn bucket_exists(&self, bucket: &str) -> Result<bool, StorageError> {
+        Ok(self.buckets().contains_key(bucket))
+    }
+
+    async fn list_buckets(&self) -> Result<Vec<Bucket>, StorageError> {
+        Ok(self
+            .buckets()
+            .iter()
+            .map(|(name, bucket)| Bucket {
+                name: name.clone(),
+                creation_date: Some(bucket.created),
+                region: None,
+            })
+            .collect())
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: Option<&str>,
+    ) -> Result<Vec<StorageObject>, StorageError> {
+        let buckets = self.buckets();
+        let objects = &buckets
+            .get(bucket)
+            .ok_or_else(|| StorageError::NotFound(format!("Bucket {bucket} not found")))?
+            .objects;
+        let prefix = prefix.unwrap_or_default();
+        Ok(objects
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, object)| StorageObject {
+                key: key.clone(),
+                size: Some(object.data.len() as u64),
+                last_modified: Some(object.modified),
+                etag: Some(object.etag.clone()),
+                storage_class: None,
+            })
+            .collect())
+    }
+
+    async fn upload_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        file_path: &Path,
+        content_type: Option<&str>,
+        metadata: Option<Metadata>,
+    ) -> Result<(), StorageError> {
+        let data = tokio::fs::read(file_path).await?;
+        self.put(
+            bucket,
+            key,
+            MemoryObject::new(data.into(), content_type, metadata),
+        )
+    }
+
+    async fn download_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        file_path: &Path,
+    ) -> Result<(), StorageError> {
+        let object = self.object(bucket, key)?;
+        if let Some(parent) = file_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(file_path, &object.data).await?;
+        Ok(())
+    }
+
+    async fn download_stream(&self, bucket: &str, key: &str) -> Result<ByteStream, StorageError> {
+        let data = self.object(bucket, key)?.data;
+        let chunks: Vec<_> = (0..data.len())
+            .step_by(CHUNK_SIZE)
+            .map(|start| Ok(data.slice(start..(start + CHUNK_SIZE).min(data.len()))))
+            .collect();
+        Ok(Box::pin(futures::stream::iter(chunks)))
+    }
+
+    async fn get_object_metadata(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<ObjectMetadata, StorageError> {
+        let object = self.object(bucket, key)?;
+        Ok(ObjectMetadata {
+            key: key.to_string(),
+            size: Some(object.data.len() as u64),
+            last_modified: Some(object.modified),
+            etag: Some(object.etag),
+            content_type: object.content_type,
+            storage_class: None,
+            metadata: (!object.metadata.is_empty()).then_some(object.metadata),
+        })
+    }
+
+    async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), StorageError> {
+        let mut buckets = self.buckets();
+        let Some(objects) = buckets.get_mut(bucket).map(|b| &mut b.objects) else {
+            return Ok(());
+        };
+        if let Some(retention) = objects
+            .get(key)
+            .and_then(|o| o.retention.as_ref())
+            .filter(|r| r.is_active(Utc::now()))
+        {
+            return Err(StorageError::Locked(format!(
+                "Object {bucket}/{key} is locked until {}",
+                retention.retain_until
+            )));
+        }
+        objects.remove(key);
+        Ok(())
+    }
+
+    async fn object_exists(&self, bucket: &str, key: &str) -> Result<bool, StorageError> {
+        Ok(self
+            .buckets()
+            .get(bucket)
+            .is_some_and(|b| b.objects.contains_key(key)))
+    }
+
+    async fn generate_presigned_url(
+        &self,
+        _bucket: &str,
+        _key: &str,
+        _expires_in: Duration,
+    ) -> Result<String, StorageError> {
+        Err(StorageError::Configuration(
+            "The memory provider cannot generate presigned URLs".to_string(),
+        ))
+    }
+
+    async fn upload_stream(
+        &self,
+        bucket: &str,
+        key: &str,
+        mut stream: ByteStream,
+        content_type: Option<&str>,
+        metadata: Option<Metadata>,
+    ) -> Result<(), StorageError> {
+        check_key(key)?;
+        let mut data = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            data.extend_from_slice(&chunk?);
+        }
+        self.put(
+            bucket,
+            key,
+            MemoryObject::new(data.into(), content_type, metadata),
+        )
+    }
+
+    /// Checks and writes under one lock, so concurrent writers cannot both win
+    async fn put_object_if_match(
+        &self,
+        bucket: &str,
+        key: &str,
+        body: Bytes,
+        content_type: Option<&str>,
+        if_match: Option<&str>,
+    ) -> Result<Option<String>, StorageError> {
+        check_key(key)?;
+        let mut buckets = self.buckets();
+        let objects = &mut buckets
+            .get_mut(bucket)
+            .ok_or_else(|| StorageError::NotFound(format!("Bucket {bucket} not found")))?
+            .objects;
+        if objects.get(key).map(|o| o.etag.as_str()) != if_match {
+            return Err(StorageError::Conflict(format!(
+                "Object {bucket}/{key} changed since it was read"
+            )));
+        }
+        let object = MemoryObject::new(body, content_type, None);
+        let etag = object.etag.clone();
+        objects.insert(key.to_string(), object);
+        Ok(Some(etag))
+    }
+
+    async fn set_object_retention(
+        &self,
+        bucket: &str,
+        key: &str,
+        retention: &ObjectRetention,
+    ) -> Result<(), StorageError> {
+        let mut buckets = self.buckets();
+        let object = buckets
+            .get_mut(bucket)
+            .and_then(|b| b.objects.get_mut(key))
+            .ok_or_else(|| StorageError::NotFound(format!("Object {bucket}/{key} not found")))?;
+        object.retention = Some(retention.clone());
+        Ok(())
+    }
+
+    async fn get_object_retention(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<Option<ObjectRetention>, StorageError> {
+        Ok(self.object(bucket, key)?.retention)
+    }
+
+    async fn bucket_lock_status(&self, _bucket: &str) -> Result<BucketLockStatus, StorageError> {
+        Ok(BucketLockStatus {
+            versioning: true,
+            object_lock: true,
+            default_retention: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        BackupType, Keyring, MasterKey, ObjectLock, ObjectLockMode, PostgresBackupStorage,
+    };
+
+    #[tokio::test]
+    async fn clones_share_objects_and_conditional_writes_conflict() {
+        let provider = MemoryProvider::new();
+        let observer = provider.clone();
+        provider.create_bucket("backups").await.unwrap();
+
+        let etag = provider
+            .put_object_if_match("backups", "catalog.json", Bytes::from("[]"), None, None)
+            .await
+            .unwrap();
+        assert!(observer
+            .object_exists("backups", "catalog.json")
+            .await
+            .unwrap());
+
+        // Creating an object that exists, or updating from a stale ETag, loses
+        assert!(matches!(
+            provider
+                .put_object_if_match("backups", "catalog.json", Bytes::from("[1]"), None, None)
+                .await,
+            Err(StorageError::Conflict(_))
+        ));
+        provider
+            .put_object_if_match(
+                "backups",
+                "catalog.json",
+                Bytes::from("[1]"),
+                None,
+                etag.as_deref(),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            provider
+                .put_object_if_match(
+                    "backups",
+                    "catalog.json",
+                    Bytes::from("[2]"),
+                    None,
+                    etag.as_deref(),
+                )
+                .await,
+            Err(StorageError::Conflict(_))
+        ));
+        let (body, _) = observer
+            .get_object("backups", "catalog.json")
+            .await
+            .unwrap();
+        assert_eq!(body, Bytes::from("[1]"));
+    }
+
+    #[tokio::test]
+    async fn delete_backup_refuses_locked_objects() {
+        let provider = MemoryProvider::new();
+        provider.create_bucket("backups").await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let dump = dir.path().join("pg_dump.dump");
+        std::fs::write(&dump, b"dump").unwrap();
+
+        let locked = PostgresBackupStorage::from_provider(
+            Box::new(provider.clone()),
+            "backups".to_string(),
+            None,
+        )
+        .with_object_lock(ObjectLock::new(ObjectLockMode::Governance, 30));
+        locked
+            .upload_logical_backup("b1", &BackupType::Full, &dump, None)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            locked.delete_backup("b1").await,
+            Err(StorageError::Locked(_))
+        ));
+        assert!(provider
+            .object_exists("backups", "b1/pg_dump.dump")
+            .await
+            .unwrap());
+
+        // Without a lock the backup goes
+        let unlocked = PostgresBackupStorage::from_provider(
+            Box::new(provider.clone()),
+            "backups".into(),
+            None,
+        );
+        unlocked
+            .upload_logical_backup("b2", &BackupType::Full, &dump, None)
+            .await
+            .unwrap();
+        unlocked.delete_backup("b2").await.unwrap();
+        assert!(!provider
+            .object_exists("backups", "b2/pg_dump.dump")
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn downloads_decrypt_by_metadata_not_content() {
+        let provider = MemoryProvider::new();
+        provider.create_bucket("backups").await.unwrap();
+        let storage = PostgresBackupStorage::from_provider(
+            Box::new(provider.clone()),
+            "backups".to_string(),
+            None,
+        )
+        .with_encryption(Keyring::new(vec![MasterKey::generate()]).unwrap());
+        let dir = tempfile::tempdir().unwrap();
+        let dump = dir.path().join("pg_dump.dump");
+        std::fs::write(&dump, b"dump").unwrap();
+        storage
+            .upload_logical_backup("b1", &BackupType::Full, &dump, None)
+            .await
+            .unwrap();
+
+        let target = dir.path().join("decrypted");
+        storage
+            .download_backup_file("b1", "pg_dump.dump", &target)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"dump");
+
+        // A plain file that happens to start like an encrypted one stays as it is
+        let plain = b"WRDNENC1 is just text here";
+        provider
+            .put_object_if_match(
+                "backups",
+                "b2/notes.txt",
+                Bytes::from_static(plain),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        let target = dir.path().join("plain");
+        storage
+            .download_backup_file("b2", "notes.txt", &target)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), plain);
+    }
+}
@@ -1,4 +1,14 @@
 pub mod aws;
+pub mod azure;
+pub mod gcs;
+pub mod local;
+pub mod memory;
+pub mod sftp;
 
 // Re-export providers for convenience
-pub use aws::{ProviderKind, S3Provider};
+pub use aws::{MultipartConfig, ProviderKind, S3Provider};
+pub use azure::AzureProvider;
+pub use gcs::GcsProvider;
+pub use local::LocalProvider;
+pub use memory::MemoryProvider;
+pub use sftp::SftpProvider;
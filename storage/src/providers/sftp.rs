@@ -0,0 +1,549 @@
+//! Storage on a remote host over SFTP.
+//!
+//! The layout matches [`super::local::LocalProvider`]: buckets are
+//! directories below a root path, with sidecar files for the metadata, and
+//! conditional writes hold a lock file opened with `O_EXCL`. The SSH
+//! connection comes from the `ssh` crate and is reopened after it drops.
+
+use super::local::{
+    check_path, file_etag, is_internal, is_stale_lock, lock_timeout, ranged_stream, Sidecar,
+    LOCK_RETRY, LOCK_SUFFIX, LOCK_TIMEOUT, PARTIAL_SUFFIX, SIDECAR_SUFFIX,
+};
+use crate::{
+    put_if_unchanged, Bucket, ByteStream, Metadata, ObjectMetadata, StorageError, StorageObject,
+    StorageProvider, StreamingDownloadOptions,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::StreamExt;
+use log::{debug, info, warn};
+use ssh::russh_sftp::client::error::Error as SftpError;
+use ssh::russh_sftp::protocol::{OpenFlags, StatusCode};
+use ssh::{HostKeyCheck, SSHTunnel, SftpClient};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+use url::Url;
+
+/// Keys tried, in order, when neither a key nor a password is given
+const DEFAULT_KEYS: [&str; 3] = [".ssh/id_ed25519", ".ssh/id_ecdsa", ".ssh/id_rsa"];
+
+pub struct SftpProvider {
+    tunnel: SSHTunnel,
+    /// Directory holding the buckets, without a trailing slash
+    root: String,
+    session: RwLock<Option<Arc<SftpClient>>>,
+}
+
+impl SftpProvider {
+    /// Creates a provider for `sftp://[user@]host[:port]/root/path`
+    ///
+    /// The root path may start with `/~/` to be relative to the home
+    /// directory. `user` overrides the one of the URL. Without a private key
+    /// or a password, the usual keys in `~/.ssh` are tried.
+    ///
+    /// The server's key must be in `~/.ssh/known_hosts`, unless the URL pins
+    /// it with `?host_key=SHA256:...` or names another file with
+    /// `?known_hosts=/path`. Servers that fail the check are refused.
+    pub async fn new(
+        endpoint: &str,
+        user: Option<String>,
+        password: Option<String>,
+        private_key_path: Option<String>,
+    ) -> Result<Self, StorageError> {
+        let url = Url::parse(endpoint)
+            .map_err(|e| StorageError::Configuration(format!("Invalid SFTP endpoint: {e}")))?;
+        if url.scheme() != "sftp" {
+            return Err(StorageError::Configuration(format!(
+                "SFTP endpoints look like sftp://user@host/path, got {endpoint}"
+            )));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| StorageError::Configuration(format!("No host in {endpoint}")))?;
+        let user = user
+            .or_else(|| (!url.username().is_empty()).then(|| url.username().to_string()))
+            .or_else(|| std::env::var("USER").ok())
+            .ok_or_else(|| StorageError::Configuration("An SFTP user is required".into()))?;
+
+        let mut host_key = HostKeyCheck::KnownHosts;
+        for (key, value) in url.query_pairs() {
+            host_key = match key.as_ref() {
+                "host_key" => HostKeyCheck::Fingerprint(value.into_owned()),
+                "known_hosts" => HostKeyCheck::KnownHostsFile(value.into_owned()),
+                _ => {
+                    return Err(StorageError::Configuration(format!(
+                        "Unknown option {key} in SFTP endpoint"
+                    )))
+                }
+            };
+        }
+
+        let mut tunnel =
+            SSHTunnel::new(host.to_string(), user, url.port()).with_host_key_check(host_key);
+        let private_key_path = private_key_path.or_else(|| {
+            if password.is_some() {
+                return None;
+            }
+            let home = std::env::var("HOME").ok()?;
+            DEFAULT_KEYS
+                .iter()
+                .map(|key| format!("{home}/{key}"))
+                .find(|path| Path::new(path).exists())
+        });
+        if let Some(path) = private_key_path {
+            tunnel = tunnel.with_private_key_path(path);
+        }
+        if let Some(password) = password {
+            tunnel = tunnel.with_password(password);
+        }
+
+        // `/~/path` is relative to the home directory, like curl has it
+        let root = match url.path().trim_end_matches('/') {
+            "" | "/~" => ".".to_string(),
+            path => match path.strip_prefix("/~/") {
+                Some(rel) => format!("./{rel}"),
+                None => path.to_string(),
+            },
+        };
+        let provider = Self {
+            tunnel,
+            root,
+            session: RwLock::new(None),
+        };
+        provider.session().await?;
+        info!("Storing backups on {}:{}", host, provider.root);
+        Ok(provider)
+    }
+
+    /// The open session, connecting first if there is none
+    async fn session(&self) -> Result<Arc<SftpClient>, StorageError> {
+        if let Some(session) = self.session.read().expect("session lock").clone() {
+            return Ok(session);
+        }
+        let session = Arc::new(self.tunnel.open_sftp().await.map_err(|e| {
+            StorageError::Authentication(format!("SFTP connection to {}: {e}", self.tunnel.host))
+        })?);
+        *self.session.write().expect("session lock") = Some(session.clone());
+        Ok(session)
+    }
+
+    /// Map an SFTP error, forgetting the session when the connection is gone
+    fn error(&self, e: SftpError, what: &str) -> StorageError {
+        match e {
+            SftpError::Status(status) => match status.status_code {
+                StatusCode::NoSuchFile => StorageError::NotFound(format!("{what} not found")),
+                StatusCode::PermissionDenied => {
+                    StorageError::PermissionDenied(format!("{what}: {}", status.error_message))
+                }
+                code => StorageError::Request(format!("{what}: {code:?} {}", status.error_message)),
+            },
+            e => {
+                warn!("SFTP session failed, reconnecting on next use: {e}");
+                self.session.write().expect("session lock").take();
+                StorageError::Request(format!("{what}: {e}"))
+            }
+        }
+    }
+
+    fn bucket_dir(&self, bucket: &str) -> Result<String, StorageError> {
+        if bucket.contains('/') {
+            return Err(StorageError::Request(format!("Invalid bucket {bucket:?}")));
+        }
+        check_path(bucket, "bucket")?;
+        Ok(format!("{}/{bucket}", self.root))
+    }
+
+    fn object_path(&self, bucket: &str, key: &str) -> Result<String, StorageError> {
+        check_path(key, "key")?;
+        Ok(format!("{}/{key}", self.bucket_dir(bucket)?))
+    }
+
+    /// Create a directory and its missing parents
+    async fn create_dirs(&self, session: &SftpClient, path: &str) -> Result<(), StorageError> {
+        let mut current = String::new();
+        for (i, part) in path.split('/').enumerate() {
+            if i > 0 {
+                current.push('/');
+            }
+            current.push_str(part);
+            if part.is_empty() || part == "." {
+                continue;
+            }
+            let exists = session
+                .try_exists(current.as_str())
+                .await
+                .map_err(|e| self.error(e, &current))?;
+            if !exists {
+                session
+                    .create_dir(current.as_str())
+                    .await
+                    .map_err(|e| self.error(e, &current))?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn remove_if_exists(&self, session: &SftpClient, path: &str) -> Result<(), StorageError> {
+        match session.remove_file(path).await {
+            Ok(()) => Ok(()),
+            Err(e) => match self.error(e, path) {
+                StorageError::NotFound(_) => Ok(()),
+                e => Err(e),
+            },
+        }
+    }
+
+    /// Take the lock file of the object at `path`, waiting while another writer holds it
+    ///
+    /// Returns the path of the lock, for the caller to remove once done.
+    async fn lock_object(
+        &self,
+        session: &SftpClient,
+        bucket: &str,
+        key: &str,
+        path: &str,
+    ) -> Result<String, StorageError> {
+        let lock = format!("{path}{LOCK_SUFFIX}");
+        if let Some((parent, _)) = path.rsplit_once('/') {
+            self.create_dirs(session, parent).await?;
+        }
+        let deadline = Instant::now() + LOCK_TIMEOUT;
+        loop {
+            let flags = OpenFlags::CREATE | OpenFlags::EXCLUDE | OpenFlags::WRITE;
+            // SFTP v3 has no distinct status for an existing file, look at the lock instead
+            let created = session.open_with_flags(lock.as_str(), flags).await;
+            let Err(create_error) = created else {
+                return Ok(lock);
+            };
+
+            let modified = match session.metadata(lock.as_str()).await {
+                Ok(attrs) => attrs.modified().ok(),
+                Err(e) => match self.error(e, &lock) {
+                    // Released in the meantime
+                    StorageError::NotFound(_) => continue,
+                    _ => return Err(self.error(create_error, &lock)),
+                },
+            };
+            if is_stale_lock(modified) {
+                warn!("Removing stale lock {}:{lock}", self.tunnel.host);
+                self.remove_if_exists(session, &lock).await?;
+                continue;
+            }
+            if Instant::now() >= deadline {
+                return Err(lock_timeout(bucket, key, &lock));
+            }
+            tokio::time::sleep(LOCK_RETRY).await;
+        }
+    }
+
+    async fn read_sidecar(
+        &self,
+        session: &SftpClient,
+        path: &str,
+    ) -> Result<Sidecar, StorageError> {
+        let sidecar = format!("{path}{SIDECAR_SUFFIX}");
+        match session.read(sidecar.as_str()).await {
+            Ok(json) => Ok(serde_json::from_slice(&json)?),
+            Err(e) => match self.error(e, &sidecar) {
+                StorageError::NotFound(_) => Ok(Sidecar::default()),
+                e => Err(e),
+            },
+        }
+    }
+
+    /// Walk a directory tree, collecting `(key, attributes)` of its files
+    async fn walk(
+        &self,
+        session: &SftpClient,
+        dir: &str,
+    ) -> Result<Vec<(String, ssh::russh_sftp::client::fs::Metadata)>, StorageError> {
+        let mut files = Vec::new();
+        let mut pending = vec![String::new()];
+        while let Some(rel) = pending.pop() {
+            let path = if rel.is_empty() {
+                dir.to_string()
+            } else {
+                format!("{dir}/{rel}")
+            };
+            let entries = session
+                .read_dir(path.as_str())
+                .await
+                .map_err(|e| self.error(e, &path))?;
+            for entry in entries {
+                let name = entry.file_name();
+                if name == "." || name == ".." {
+                    continue;
+                }
+                let key = if rel.is_empty() {
+                    name
+                } else {
+                    format!("{rel}/{name}")
+                };
+                let file_type = entry.file_type();
+                if file_type.is_dir() {
+                    pending.push(key);
+                } else if file_type.is_file() {
+                    files.push((key, entry.metadata()));
+                }
+            }
+        }
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(files)
+    }
+}
+
+#[async_trait]
+impl StorageProvider for SftpProvider {
+    fn name(&self) -> &str {
+        "SFTP"
+    }
+
+    async fn create_bucket(&self, bucket: &str) -> Result<(), StorageError> {
+        let session = self.session().await?;
+        self.create_dirs(&session, &self.bucket_dir(bucket)?).await
+    }
+
+    async fn bucket_exists(&self, bucket: &str) -> Result<bool, StorageError> {
+        let session = self.session().await?;
+        let dir = self.bucket_dir(bucket)?;
+        match session.metadata(dir.as_str()).await {
+            Ok(attrs) => Ok(attrs.is_dir()),
+            Err(e) => match self.error(e, &dir) {
+                StorageError::NotFound(_) => Ok(false),
+                e => Err(e),
+            },
+        }
+    }
+
+    async fn list_buckets(&self) -> Result<Vec<Bucket>, StorageError> {
+        let session = self.session().await?;
+        let entries = session
+            .read_dir(self.root.as_str())
+            .await
+            .map_err(|e| self.error(e, &self.root))?;
+        let mut buckets: Vec<Bucket> = entries
+            .filter(|e| e.file_type().is_dir() && e.file_name() != "." && e.file_name() != "..")
+            .map(|e| Bucket {
+                name: e.file_name(),
+                creation_date: e.metadata().modified().ok(),
+                region: None,
+            })
+            .collect();
+        buckets.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(buckets)
+    }
+
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: Option<&str>,
+    ) -> Result<Vec<StorageObject>, StorageError> {
+        let session = self.session().await?;
+        let files = self.walk(&session, &self.bucket_dir(bucket)?).await?;
+        Ok(files
+            .into_iter()
+            .filter(|(key, _)| !is_internal(key) && key.starts_with(prefix.unwrap_or_default()))
+            .map(|(key, attrs)| {
+                let modified = attrs.modified().ok();
+                StorageObject {
+                    key,
+                    size: Some(attrs.len()),
+                    last_modified: modified.map(Into::into),
+                    etag: Some(file_etag(attrs.len(), modified)),
+                    storage_class: None,
+                }
+            })
+            .collect())
+    }
+
+    async fn upload_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        file_path: &Path,
+        content_type: Option<&str>,
+        metadata: Option<Metadata>,
+    ) -> Result<(), StorageError> {
+        let file = tokio::fs::File::open(file_path).await?;
+        self.upload_stream(
+            bucket,
+            key,
+            Box::pin(ReaderStream::new(file)),
+            content_type,
+            metadata,
+        )
+        .await
+    }
+
+    async fn download_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        file_path: &Path,
+    ) -> Result<(), StorageError> {
+        let mut stream = self.download_stream(bucket, key).await?;
+        if let Some(parent) = file_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut file = tokio::fs::File::create(file_path).await?;
+        while let Some(chunk) = stream.next().await {
+            file.write_all(&chunk?).await?;
+        }
+        file.flush().await?;
+        Ok(())
+    }
+
+    async fn download_stream(&self, bucket: &str, key: &str) -> Result<ByteStream, StorageError> {
+        self.download_stream_range(bucket, key, &StreamingDownloadOptions::default())
+            .await
+    }
+
+    async fn download_stream_range(
+        &self,
+        bucket: &str,
+        key: &str,
+        options: &StreamingDownloadOptions,
+    ) -> Result<ByteStream, StorageError> {
+        let session = self.session().await?;
+        let path = self.object_path(bucket, key)?;
+        let file = session
+            .open(path.as_str())
+            .await
+            .map_err(|e| self.error(e, &format!("Object {bucket}/{key}")))?;
+        ranged_stream(file, options).await
+    }
+
+    async fn get_object_metadata(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<ObjectMetadata, StorageError> {
+        let session = self.session().await?;
+        let path = self.object_path(bucket, key)?;
+        let attrs = session
+            .metadata(path.as_str())
+            .await
+            .map_err(|e| self.error(e, &format!("Object {bucket}/{key}")))?;
+        let sidecar = self.read_sidecar(&session, &path).await?;
+        let modified = attrs.modified().ok();
+        Ok(ObjectMetadata {
+            key: key.to_string(),
+            size: Some(attrs.len()),
+            last_modified: modified.map(Into::into),
+            etag: Some(file_etag(attrs.len(), modified)),
+            content_type: sidecar.content_type,
+            storage_class: None,
+            metadata: (!sidecar.metadata.is_empty()).then_some(sidecar.metadata),
+        })
+    }
+
+    async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), StorageError> {
+        let session = self.session().await?;
+        let path = self.object_path(bucket, key)?;
+        self.remove_if_exists(&session, path.as_str()).await?;
+        self.remove_if_exists(&session, &format!("{path}{SIDECAR_SUFFIX}"))
+            .await
+    }
+
+    async fn object_exists(&self, bucket: &str, key: &str) -> Result<bool, StorageError> {
+        let session = self.session().await?;
+        let path = self.object_path(bucket, key)?;
+        session
+            .try_exists(path.as_str())
+            .await
+            .map_err(|e| self.error(e, &path))
+    }
+
+    async fn generate_presigned_url(
+        &self,
+        _bucket: &str,
+        _key: &str,
+        _expires_in: Duration,
+    ) -> Result<String, StorageError> {
+        Err(StorageError::Configuration(
+            "The SFTP provider cannot generate presigned URLs".to_string(),
+        ))
+    }
+
+    /// Checks and writes while holding the lock file of the object
+    async fn put_object_if_match(
+        &self,
+        bucket: &str,
+        key: &str,
+        body: Bytes,
+        content_type: Option<&str>,
+        if_match: Option<&str>,
+    ) -> Result<Option<String>, StorageError> {
+        let session = self.session().await?;
+        let path = self.object_path(bucket, key)?;
+        let lock = self.lock_object(&session, bucket, key, &path).await?;
+        let result = put_if_unchanged(self, bucket, key, body, content_type, if_match).await;
+        if let Err(e) = self.remove_if_exists(&session, &lock).await {
+            warn!("Could not remove lock {}:{lock}: {e}", self.tunnel.host);
+        }
+        result
+    }
+
+    /// Writes to a temporary file that replaces the object once complete
+    async fn upload_stream(
+        &self,
+        bucket: &str,
+        key: &str,
+        mut stream: ByteStream,
+        content_type: Option<&str>,
+        metadata: Option<Metadata>,
+    ) -> Result<(), StorageError> {
+        let session = self.session().await?;
+        let path = self.object_path(bucket, key)?;
+        if let Some((parent, _)) = path.rsplit_once('/') {
+            self.create_dirs(&session, parent).await?;
+        }
+
+        let partial = format!("{path}{PARTIAL_SUFFIX}");
+        let mut file = session
+            .create(partial.as_str())
+            .await
+            .map_err(|e| self.error(e, &partial))?;
+        let mut size = 0u64;
+        while let Some(chunk) = stream.next().await {
+            let written = match chunk {
+                Ok(chunk) => {
+                    size += chunk.len() as u64;
+                    file.write_all(&chunk).await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = written {
+                drop(file);
+                self.remove_if_exists(&session, &partial).await.ok();
+                return Err(e.into());
+            }
+        }
+        file.shutdown().await?;
+
+        let sidecar_path = format!("{path}{SIDECAR_SUFFIX}");
+        match Sidecar::new(content_type, metadata) {
+            Some(sidecar) => {
+                let mut file = session
+                    .create(sidecar_path.as_str())
+                    .await
+                    .map_err(|e| self.error(e, &sidecar_path))?;
+                file.write_all(&serde_json::to_vec(&sidecar)?).await?;
+                file.shutdown().await?;
+            }
+            None => self.remove_if_exists(&session, &sidecar_path).await?,
+        }
+        // Plain SFTP renames refuse to replace an existing file
+        self.remove_if_exists(&session, &path).await?;
+        session
+            .rename(partial.as_str(), path.as_str())
+            .await
+            .map_err(|e| self.error(e, &path))?;
+
+        debug!("Wrote {size} bytes to {}:{path}", self.tunnel.host);
+        Ok(())
+    }
+}
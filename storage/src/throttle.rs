@@ -0,0 +1,142 @@
+//! Bandwidth limiting shared by concurrent transfers
+
+use crate::crypto::ByteStream;
+use crate::StorageError;
+use futures::StreamExt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Token bucket letting through `rate` bytes per second on average
+///
+/// Clones share the same budget, so one limiter handed to several uploads
+/// caps their total. Bursts are limited to one second's worth of bytes.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    rate: u64,
+    state: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes that may go out right away, negative while in debt
+    available: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Limiter allowing `bytes_per_sec` bytes per second
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec.max(1);
+        Self {
+            rate,
+            state: Arc::new(Mutex::new(Bucket {
+                available: rate as f64,
+                updated: Instant::now(),
+            })),
+        }
+    }
+
+    /// Limiter from a rate such as `20M`, see [`parse_rate`]
+    pub fn parse(limit: &str) -> Result<Self, StorageError> {
+        parse_rate(limit).map(Self::new)
+    }
+
+    /// Bytes per second let through
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Take `bytes` from the budget and return how long to wait before sending them
+    fn reserve(&self, bytes: usize) -> Duration {
+        let mut bucket = self.state.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.updated).as_secs_f64() * self.rate as f64;
+        bucket.available = (bucket.available + refill).min(self.rate as f64) - bytes as f64;
+        bucket.updated = now;
+        if bucket.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.available / self.rate as f64)
+        }
+    }
+
+    /// Wait until `bytes` more bytes may be sent
+    pub async fn acquire(&self, bytes: usize) {
+        let wait = self.reserve(bytes);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Pace `stream` so it does not go faster than the limit
+    pub fn throttle(&self, stream: ByteStream) -> ByteStream {
+        let limiter = self.clone();
+        Box::pin(stream.then(move |chunk| {
+            let limiter = limiter.clone();
+            async move {
+                if let Ok(chunk) = &chunk {
+                    limiter.acquire(chunk.len()).await;
+                }
+                chunk
+            }
+        }))
+    }
+}
+
+/// Parse a rate in bytes per second, with an optional `K`, `M` or `G` suffix
+/// for KiB, MiB or GiB per second
+pub fn parse_rate(limit: &str) -> Result<u64, StorageError> {
+    let limit = limit.trim();
+    let digits = limit.trim_end_matches(|c: char| c.is_ascii_alphabetic() || c == '/');
+    let unit = limit[digits.len()..].to_ascii_uppercase();
+    let multiplier: u64 = match unit.trim_end_matches("/S").trim_end_matches('B') {
+        "" => 1,
+        "K" | "KI" => 1024,
+        "M" | "MI" => 1024 * 1024,
+        "G" | "GI" => 1024 * 1024 * 1024,
+        _ => {
+            return Err(StorageError::Configuration(format!(
+                "Invalid rate unit in {limit}, expected K, M or G"
+            )))
+        }
+    };
+    let value: f64 = digits
+        .trim()
+        .parse()
+        .map_err(|_| StorageError::Configuration(format!("Invalid rate: {limit}")))?;
+    let rate = (value * multiplier as f64) as u64;
+    if rate == 0 {
+        return Err(StorageError::Configuration(format!(
+            "Rate must be positive: {limit}"
+        )));
+    }
+    Ok(rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_parse_with_units() {
+        assert_eq!(parse_rate("512").unwrap(), 512);
+        assert_eq!(parse_rate("20M").unwrap(), 20 * 1024 * 1024);
+        assert_eq!(parse_rate("1.5 GiB/s").unwrap(), 3 * 512 * 1024 * 1024);
+        assert_eq!(parse_rate("800kb").unwrap(), 800 * 1024);
+        assert!(parse_rate("10X").is_err());
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("fast").is_err());
+    }
+
+    #[test]
+    fn bursts_up_to_the_rate_then_waits() {
+        let limiter = RateLimiter::new(1000);
+        assert_eq!(limiter.reserve(1000), Duration::ZERO);
+        let wait = limiter.reserve(500);
+        assert!(wait > Duration::from_millis(450) && wait <= Duration::from_millis(500));
+
+        // Clones draw from the same budget
+        let wait = limiter.clone().reserve(500);
+        assert!(wait > Duration::from_millis(950) && wait <= Duration::from_secs(1));
+    }
+}
@@ -1,6 +1,8 @@
+use crate::{RateLimiter, StorageError};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::str::FromStr;
 use std::time::SystemTime;
 
 /// Represents a storage bucket
@@ -62,6 +64,17 @@ pub enum BackupType {
     Snapshot,
 }
 
+impl BackupType {
+    /// Name of the type in tiering rules and in the tags of backup files
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackupType::Full => "full",
+            BackupType::Incremental => "incremental",
+            BackupType::Snapshot => "snapshot",
+        }
+    }
+}
+
 /// Information about a backup
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BackupInfo {
@@ -75,6 +88,310 @@ pub struct BackupInfo {
     pub size: u64,
     /// Parent backup ID (for incremental backups)
     pub parent_id: Option<String>,
+    /// Full record of the backup as kept by the tool that took it
+    #[serde(default)]
+    pub details: Option<serde_json::Value>,
+}
+
+/// Catalog of the backups in a bucket
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RemoteCatalog {
+    pub backups: Vec<BackupInfo>,
+}
+
+impl RemoteCatalog {
+    pub fn get(&self, id: &str) -> Option<&BackupInfo> {
+        self.backups.iter().find(|b| b.id == id)
+    }
+
+    /// Add a backup, replacing any entry with the same ID
+    pub fn upsert(&mut self, info: BackupInfo) {
+        self.remove(&info.id);
+        self.backups.push(info);
+        self.backups.sort_by_key(|b| b.timestamp);
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        self.backups.retain(|b| b.id != id);
+    }
+
+    /// Every backup descending from `ancestor_id`, oldest first
+    pub fn descendants(&self, ancestor_id: &str) -> Vec<&BackupInfo> {
+        let mut ids = vec![ancestor_id];
+        let mut found = Vec::new();
+        while let Some(id) = ids.pop() {
+            for child in self
+                .backups
+                .iter()
+                .filter(|b| b.parent_id.as_deref() == Some(id))
+            {
+                if !found.iter().any(|f: &&BackupInfo| f.id == child.id) {
+                    found.push(child);
+                    ids.push(&child.id);
+                }
+            }
+        }
+        found.sort_by_key(|b| b.timestamp);
+        found
+    }
+}
+
+/// Outcome of copying the missing backups of one storage to another
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncReport {
+    /// Backups copied
+    pub copied: Vec<String>,
+    /// Backups the target already had
+    pub up_to_date: Vec<String>,
+    /// Backups that failed to copy, with the reason
+    pub failed: Vec<(String, String)>,
+}
+
+impl SyncReport {
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// How strictly a locked object is protected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectLockMode {
+    /// Users with the bypass permission may still delete the object
+    Governance,
+    /// Nobody, not even the root account, may delete the object
+    Compliance,
+}
+
+impl ObjectLockMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectLockMode::Governance => "governance",
+            ObjectLockMode::Compliance => "compliance",
+        }
+    }
+}
+
+impl FromStr for ObjectLockMode {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "governance" => Ok(ObjectLockMode::Governance),
+            "compliance" => Ok(ObjectLockMode::Compliance),
+            _ => Err(StorageError::Configuration(format!(
+                "Unknown object lock mode {s}, expected governance or compliance"
+            ))),
+        }
+    }
+}
+
+/// Retention set on a single object
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectRetention {
+    pub mode: ObjectLockMode,
+    /// The object cannot be deleted or overwritten before this time
+    pub retain_until: DateTime<Utc>,
+}
+
+impl ObjectRetention {
+    /// Whether the object is still protected at `now`
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.retain_until > now
+    }
+}
+
+/// Retention applied to every object of a backup when it is uploaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectLock {
+    pub mode: ObjectLockMode,
+    /// How long objects stay locked after their upload
+    pub retain_for: chrono::Duration,
+}
+
+impl ObjectLock {
+    pub fn new(mode: ObjectLockMode, retain_days: u32) -> Self {
+        Self {
+            mode,
+            retain_for: chrono::Duration::days(retain_days.into()),
+        }
+    }
+
+    /// Retention of an object uploaded at `now`
+    pub fn retention_from(&self, now: DateTime<Utc>) -> ObjectRetention {
+        ObjectRetention {
+            mode: self.mode,
+            retain_until: now + self.retain_for,
+        }
+    }
+}
+
+/// Whether a bucket is set up to hold locked objects
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BucketLockStatus {
+    /// Versioning is enabled, which object lock depends on
+    pub versioning: bool,
+    /// Object lock is enabled
+    pub object_lock: bool,
+    /// Retention the bucket applies to new objects by default
+    pub default_retention: Option<String>,
+}
+
+impl BucketLockStatus {
+    pub fn is_ready(&self) -> bool {
+        self.versioning && self.object_lock
+    }
+}
+
+/// Storage tier of an object, from the most to the least expensive to keep
+///
+/// Each provider maps the tiers to its own classes, e.g. `STANDARD_IA` and
+/// `GLACIER` on S3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StorageClass {
+    Standard,
+    InfrequentAccess,
+    /// Must be rehydrated before it can be read
+    Archive,
+    /// Like `Archive`, with slower and cheaper rehydration
+    DeepArchive,
+}
+
+impl StorageClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageClass::Standard => "standard",
+            StorageClass::InfrequentAccess => "infrequent-access",
+            StorageClass::Archive => "archive",
+            StorageClass::DeepArchive => "deep-archive",
+        }
+    }
+
+    /// Whether objects of this class must be rehydrated before they are read
+    pub fn needs_rehydration(&self) -> bool {
+        matches!(self, StorageClass::Archive | StorageClass::DeepArchive)
+    }
+}
+
+impl FromStr for StorageClass {
+    type Err = StorageError;
+
+    /// Parses the tier names as well as the classes providers report
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "standard" | "hot" => Ok(StorageClass::Standard),
+            "infrequent-access" | "ia" | "standard-ia" | "cool" | "nearline" | "coldline" => {
+                Ok(StorageClass::InfrequentAccess)
+            }
+            "archive" | "glacier" => Ok(StorageClass::Archive),
+            "deep-archive" => Ok(StorageClass::DeepArchive),
+            _ => Err(StorageError::Configuration(format!(
+                "Unknown storage class {s}, expected standard, infrequent-access, archive or deep-archive"
+            ))),
+        }
+    }
+}
+
+/// Whether an object can be read right away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectAvailability {
+    Available,
+    /// In an archive tier, a restore has to be requested first
+    Archived,
+    /// A restore was requested and has not finished yet
+    Restoring,
+}
+
+/// How downloads wait for archived objects to be restored
+#[derive(Debug, Clone)]
+pub struct RehydrationConfig {
+    /// Days the restored copy stays readable
+    pub days: u32,
+    /// Delay between two checks of a pending restore
+    pub poll_interval: std::time::Duration,
+    /// Give up when the restore takes longer than this
+    pub timeout: std::time::Duration,
+}
+
+impl Default for RehydrationConfig {
+    fn default() -> Self {
+        Self {
+            days: 1,
+            poll_interval: std::time::Duration::from_secs(5 * 60),
+            // Bulk restores from deep archive can take two days
+            timeout: std::time::Duration::from_secs(48 * 3600),
+        }
+    }
+}
+
+/// Move backups of a type to a cheaper class once they are old enough
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TieringRule {
+    pub backup_type: BackupType,
+    pub after_days: u32,
+    pub class: StorageClass,
+}
+
+impl TieringRule {
+    /// Class a backup of `backup_type`, `age_days` old, belongs in, if any rule applies
+    ///
+    /// The rule with the longest delay that has passed wins.
+    pub fn target_class(
+        rules: &[TieringRule],
+        backup_type: &BackupType,
+        age_days: i64,
+    ) -> Option<StorageClass> {
+        rules
+            .iter()
+            .filter(|rule| {
+                &rule.backup_type == backup_type && i64::from(rule.after_days) <= age_days
+            })
+            .max_by_key(|rule| rule.after_days)
+            .map(|rule| rule.class)
+    }
+}
+
+impl FromStr for TieringRule {
+    type Err = StorageError;
+
+    /// Parses `type:days:class`, e.g. `full:30:infrequent-access`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            StorageError::Configuration(format!(
+                "Invalid tiering rule {s}, expected type:days:class such as full:30:archive"
+            ))
+        };
+        let mut fields = s.split(':');
+        let (Some(backup_type), Some(days), Some(class), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid());
+        };
+        let backup_type = match backup_type.to_ascii_lowercase().as_str() {
+            "full" => BackupType::Full,
+            "incremental" => BackupType::Incremental,
+            "snapshot" => BackupType::Snapshot,
+            _ => return Err(invalid()),
+        };
+        Ok(TieringRule {
+            backup_type,
+            after_days: days.parse().map_err(|_| invalid())?,
+            class: class.parse()?,
+        })
+    }
+}
+
+/// How an object is written, besides its content and metadata
+#[derive(Debug, Clone, Default)]
+pub struct UploadOptions {
+    /// Class the object is written in, the default class of the bucket when `None`
+    pub storage_class: Option<StorageClass>,
+    /// Type of the backup the object belongs to, whose tiering rules may
+    /// later move it to a cheaper class
+    pub tiering: Option<BackupType>,
+    /// Bandwidth budget the upload is paced by
+    pub rate_limiter: Option<RateLimiter>,
 }
 
 /// Storage provider configuration
@@ -90,7 +407,7 @@ pub struct StorageConfig {
     pub access_key: Option<String>,
     /// Secret access key
     pub secret_key: Option<String>,
-    /// Account ID (for Cloudflare R2)
+    /// Account ID (for Cloudflare R2), or storage account name (for Azure)
     pub account_id: Option<String>,
     /// Project ID (for Google Cloud Storage)
     pub project_id: Option<String>,
@@ -104,6 +421,18 @@ pub enum StorageProviderType {
     /// Amazon S3
     #[serde(rename = "s3")]
     S3,
+    /// Google Cloud Storage
+    #[serde(rename = "gcs")]
+    Gcs,
+    /// Azure Blob Storage
+    #[serde(rename = "azure")]
+    Azure,
+    /// A directory of the local filesystem, such as an NFS mount
+    #[serde(rename = "local")]
+    Local,
+    /// A directory on a remote host, over SFTP
+    #[serde(rename = "sftp")]
+    Sftp,
 }
 
 /// Streaming upload options
@@ -132,6 +461,109 @@ impl Default for StreamingUploadOptions {
 pub struct StreamingDownloadOptions {
     /// Range start (in bytes)
     pub range_start: Option<u64>,
-    /// Range end (in bytes)
+    /// Range end (in bytes, inclusive)
     pub range_end: Option<u64>,
 }
+
+impl StreamingDownloadOptions {
+    /// Value of the HTTP `Range` header for these options, `None` for the whole object
+    pub fn http_range(&self) -> Option<String> {
+        match (self.range_start, self.range_end) {
+            (None, None) => None,
+            (start, Some(end)) => Some(format!("bytes={}-{end}", start.unwrap_or(0))),
+            (Some(start), None) => Some(format!("bytes={start}-")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn info(id: &str, parent: Option<&str>, minute: u32) -> BackupInfo {
+        BackupInfo {
+            id: id.to_string(),
+            backup_type: if parent.is_some() {
+                BackupType::Incremental
+            } else {
+                BackupType::Full
+            },
+            timestamp: Utc.with_ymd_and_hms(2024, 1, 1, 0, minute, 0).unwrap(),
+            size: 0,
+            parent_id: parent.map(str::to_string),
+            details: None,
+        }
+    }
+
+    #[test]
+    fn descendants_follow_the_chain() {
+        let mut catalog = RemoteCatalog::default();
+        catalog.upsert(info("inc2", Some("inc1"), 2));
+        catalog.upsert(info("full", None, 0));
+        catalog.upsert(info("inc1", Some("full"), 1));
+        catalog.upsert(info("other", None, 3));
+        catalog.upsert(info("inc1", Some("full"), 1));
+
+        assert_eq!(catalog.backups.len(), 4);
+        let ids: Vec<_> = catalog
+            .descendants("full")
+            .iter()
+            .map(|b| b.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["inc1", "inc2"]);
+        assert!(catalog.descendants("other").is_empty());
+
+        catalog.remove("inc1");
+        assert!(catalog.descendants("full").is_empty());
+    }
+
+    #[test]
+    fn object_lock_retention_starts_at_upload() {
+        let lock = ObjectLock::new("Compliance".parse().unwrap(), 30);
+        let uploaded = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let retention = lock.retention_from(uploaded);
+        assert_eq!(retention.mode, ObjectLockMode::Compliance);
+        assert!(retention.is_active(uploaded + chrono::Duration::days(29)));
+        assert!(!retention.is_active(uploaded + chrono::Duration::days(30)));
+        assert!("forever".parse::<ObjectLockMode>().is_err());
+    }
+
+    #[test]
+    fn tiering_picks_the_latest_rule_that_applies() {
+        let rules: Vec<TieringRule> = [
+            "full:30:infrequent-access",
+            "full:90:GLACIER",
+            "incremental:7:ia",
+        ]
+        .iter()
+        .map(|rule| rule.parse().unwrap())
+        .collect();
+        assert_eq!(rules[1].class, StorageClass::Archive);
+
+        assert_eq!(
+            TieringRule::target_class(&rules, &BackupType::Full, 10),
+            None
+        );
+        assert_eq!(
+            TieringRule::target_class(&rules, &BackupType::Full, 45),
+            Some(StorageClass::InfrequentAccess)
+        );
+        assert_eq!(
+            TieringRule::target_class(&rules, &BackupType::Full, 400),
+            Some(StorageClass::Archive)
+        );
+        assert_eq!(
+            TieringRule::target_class(&rules, &BackupType::Incremental, 7),
+            Some(StorageClass::InfrequentAccess)
+        );
+        assert_eq!(
+            TieringRule::target_class(&rules, &BackupType::Snapshot, 400),
+            None
+        );
+
+        assert!("full:30".parse::<TieringRule>().is_err());
+        assert!("weekly:30:archive".parse::<TieringRule>().is_err());
+        assert!("full:30:tape".parse::<TieringRule>().is_err());
+    }
+}
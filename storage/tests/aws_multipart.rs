@@ -1,7 +1,7 @@
 use rand::SeedableRng;
 use std::fs::File;
 use std::io::Write;
-use storage::providers::aws::S3Provider;
+use storage::providers::aws::{MultipartConfig, S3Provider};
 use storage::StorageProvider;
 use tempfile::tempdir;
 
@@ -139,3 +139,49 @@ async fn test_single_part_upload_small_file() {
     assert_eq!(meta.size, Some(size as u64));
     provider.delete_object(&bucket, &key).await.expect("delete");
 }
+
+#[tokio::test]
+async fn test_parallel_multipart_upload_keeps_part_order() {
+    let bucket = std::env::var("AWS_TEST_BUCKET").expect("Set AWS_TEST_BUCKET env var");
+    let access_key = std::env::var("AWS_ACCESS_KEY_ID").ok();
+    let secret_key = std::env::var("AWS_SECRET_ACCESS_KEY").ok();
+    let region = std::env::var("AWS_REGION").ok();
+    let endpoint = std::env::var("AWS_ENDPOINT").ok();
+
+    let state_dir = tempdir().unwrap();
+    let provider = S3Provider::new(region, endpoint, access_key, secret_key)
+        .await
+        .expect("Failed to create S3Provider")
+        .with_multipart_config(MultipartConfig {
+            part_size: 5 * 1024 * 1024,
+            concurrency: 3,
+            state_dir: Some(state_dir.path().to_path_buf()),
+            ..Default::default()
+        });
+
+    let dir = tempdir().unwrap();
+    let file_path = dir.path().join("parallel_test_file.bin");
+    let size = 23 * 1024 * 1024;
+    use rand::RngCore;
+    let mut buf = vec![0u8; size];
+    rand::rngs::StdRng::fill_bytes(&mut rand::rngs::StdRng::seed_from_u64(7), &mut buf);
+    File::create(&file_path).unwrap().write_all(&buf).unwrap();
+
+    let key = format!("test/parallel_file_{}.bin", uuid::Uuid::new_v4());
+    provider.create_bucket(&bucket).await.ok();
+    provider
+        .upload_file(&bucket, &key, &file_path, None, None)
+        .await
+        .expect("Parallel multipart upload failed");
+
+    let downloaded = dir.path().join("downloaded.bin");
+    provider
+        .download_file(&bucket, &key, &downloaded)
+        .await
+        .expect("download");
+    assert!(std::fs::read(&downloaded).unwrap() == buf);
+    // The state of a finished upload is removed
+    assert_eq!(std::fs::read_dir(state_dir.path()).unwrap().count(), 0);
+
+    provider.delete_object(&bucket, &key).await.expect("delete");
+}
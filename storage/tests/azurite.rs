@@ -0,0 +1,192 @@
+//! Azure Blob provider against a local Azurite
+//!
+//! Start the emulator with
+//! `docker run -p 10000:10000 mcr.microsoft.com/azure-storage/azurite azurite-blob --blobHost 0.0.0.0`.
+
+use bytes::Bytes;
+use futures::TryStreamExt;
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+use storage::providers::AzureProvider;
+use storage::{StorageError, StorageProvider, StreamingDownloadOptions};
+
+/// The well-known development account of Azurite
+const AZURITE_ACCOUNT: &str = "devstoreaccount1";
+const AZURITE_KEY: &str =
+    "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw==";
+
+async fn azurite() -> (AzureProvider, String) {
+    let endpoint = env::var("AZURITE_BLOB_ENDPOINT")
+        .unwrap_or_else(|_| format!("http://127.0.0.1:10000/{AZURITE_ACCOUNT}"));
+    let container = env::var("AZURE_TEST_CONTAINER").unwrap_or_else(|_| "test-bucket".to_string());
+
+    let provider = AzureProvider::new(
+        Some(AZURITE_ACCOUNT.to_string()),
+        Some(AZURITE_KEY.to_string()),
+        None,
+        Some(endpoint),
+    )
+    .await
+    .expect("provider init");
+    provider
+        .create_bucket(&container)
+        .await
+        .expect("create container");
+    (provider, container)
+}
+
+async fn read(provider: &AzureProvider, container: &str, key: &str) -> Vec<u8> {
+    let chunks: Vec<Bytes> = provider
+        .download_stream(container, key)
+        .await
+        .expect("download_stream")
+        .try_collect()
+        .await
+        .unwrap();
+    chunks.concat()
+}
+
+#[tokio::test]
+async fn test_azure_round_trip() {
+    let (provider, container) = azurite().await;
+    assert!(provider.bucket_exists(&container).await.unwrap());
+    assert!(provider
+        .list_buckets()
+        .await
+        .unwrap()
+        .iter()
+        .any(|b| b.name == container));
+
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.txt");
+    std::fs::write(&source, b"0123456789").unwrap();
+    let key = "azure/dir/round trip.txt";
+    let metadata = HashMap::from([("encryption-key-id".to_string(), "k1".to_string())]);
+    provider
+        .upload_file(&container, key, &source, Some("text/plain"), Some(metadata))
+        .await
+        .expect("upload_file");
+
+    let object = provider.get_object_metadata(&container, key).await.unwrap();
+    assert_eq!(object.size, Some(10));
+    assert_eq!(object.content_type.as_deref(), Some("text/plain"));
+    assert_eq!(
+        object.metadata.unwrap().get("encryption-key-id"),
+        Some(&"k1".to_string())
+    );
+    assert!(provider
+        .list_objects(&container, Some("azure/dir/"))
+        .await
+        .unwrap()
+        .iter()
+        .any(|o| o.key == key));
+
+    let target = dir.path().join("target.txt");
+    provider
+        .download_file(&container, key, &target)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&target).unwrap(), b"0123456789");
+
+    let options = StreamingDownloadOptions {
+        range_start: Some(2),
+        range_end: Some(5),
+    };
+    let part: Vec<Bytes> = provider
+        .download_stream_range(&container, key, &options)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(part.concat(), b"2345");
+
+    provider.delete_object(&container, key).await.unwrap();
+    assert!(!provider.object_exists(&container, key).await.unwrap());
+    assert!(matches!(
+        provider.download_stream(&container, key).await,
+        Err(StorageError::NotFound(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_azure_block_upload_spans_blocks() {
+    let (provider, container) = azurite().await;
+    let key = "azure/large.bin";
+    // Three blocks, the last one partial
+    let body: Vec<u8> = (0..20 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let chunks: Vec<Result<Bytes, std::io::Error>> = body
+        .chunks(1024 * 1024)
+        .map(|c| Ok(Bytes::copy_from_slice(c)))
+        .collect();
+    provider
+        .upload_stream(
+            &container,
+            key,
+            Box::pin(futures::stream::iter(chunks)),
+            None,
+            None,
+        )
+        .await
+        .expect("block upload");
+
+    assert_eq!(read(&provider, &container, key).await, body);
+    provider.delete_object(&container, key).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_azure_sas_url_downloads_blob() {
+    let (provider, container) = azurite().await;
+    let key = "azure/shared.txt";
+    let stream = futures::stream::once(async { Ok(Bytes::from_static(b"shared")) });
+    provider
+        .upload_stream(&container, key, Box::pin(stream), None, None)
+        .await
+        .unwrap();
+
+    let url = provider
+        .generate_presigned_url(&container, key, Duration::from_secs(300))
+        .await
+        .expect("SAS URL");
+    let response = reqwest::get(&url).await.expect("fetch SAS URL");
+    assert!(response.status().is_success(), "{}", response.status());
+    assert_eq!(response.bytes().await.unwrap(), &b"shared"[..]);
+
+    provider.delete_object(&container, key).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_azure_conditional_writes() {
+    let (provider, container) = azurite().await;
+    let key = "azure/catalog.json";
+    provider.delete_object(&container, key).await.ok();
+
+    let etag = provider
+        .put_object_if_match(&container, key, Bytes::from_static(b"v1"), None, None)
+        .await
+        .expect("create");
+    assert!(matches!(
+        provider
+            .put_object_if_match(&container, key, Bytes::from_static(b"v1"), None, None)
+            .await,
+        Err(StorageError::Conflict(_))
+    ));
+
+    let (body, current) = provider.get_object(&container, key).await.unwrap();
+    assert_eq!(body, &b"v1"[..]);
+    assert_eq!(current, etag);
+    provider
+        .put_object_if_match(
+            &container,
+            key,
+            Bytes::from_static(b"v2"),
+            None,
+            current.as_deref(),
+        )
+        .await
+        .expect("update");
+    assert_eq!(read(&provider, &container, key).await, b"v2");
+
+    provider.delete_object(&container, key).await.unwrap();
+}
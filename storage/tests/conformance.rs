@@ -0,0 +1,356 @@
+//! Behaviour every storage provider must share
+//!
+//! The suite runs against the in-memory and local filesystem providers,
+//! and against MinIO, an SFTP server, fake-gcs-server or Azurite when
+//! `AWS_ENDPOINT`, `SFTP_TEST_ENDPOINT`, `STORAGE_EMULATOR_HOST` or
+//! `AZURITE_BLOB_ENDPOINT` point at one. Start the emulators with
+//! `docker run -p 4443:4443 fsouza/fake-gcs-server -scheme http` and
+//! `docker run -p 10000:10000 mcr.microsoft.com/azure-storage/azurite azurite-blob --blobHost 0.0.0.0`.
+
+use bytes::Bytes;
+use futures::TryStreamExt;
+use std::collections::HashMap;
+use std::env;
+use storage::providers::{
+    AzureProvider, GcsProvider, LocalProvider, MemoryProvider, ProviderKind, S3Provider,
+    SftpProvider,
+};
+use storage::{ByteStream, StorageError, StorageProvider, StreamingDownloadOptions};
+
+/// The well-known development account of Azurite
+const AZURITE_ACCOUNT: &str = "devstoreaccount1";
+const AZURITE_KEY: &str =
+    "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw==";
+
+/// Large enough for several multipart parts on S3
+const LARGE_SIZE: usize = 20 * 1024 * 1024;
+const LARGE_CHUNK: usize = 1024 * 1024;
+
+fn body(data: &'static [u8]) -> ByteStream {
+    Box::pin(futures::stream::once(async move {
+        Ok(Bytes::from_static(data))
+    }))
+}
+
+async fn read(stream: ByteStream) -> Vec<u8> {
+    let chunks: Vec<Bytes> = stream.try_collect().await.unwrap();
+    chunks.concat()
+}
+
+async fn keys(provider: &dyn StorageProvider, bucket: &str, prefix: &str) -> Vec<String> {
+    let mut keys: Vec<String> = provider
+        .list_objects(bucket, Some(prefix))
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|o| o.key)
+        .collect();
+    keys.sort();
+    keys
+}
+
+/// Byte `i` of the large object, not repeating on chunk boundaries
+fn large_byte(i: usize) -> u8 {
+    (i % 251) as u8
+}
+
+/// Run every check against `bucket`, below a prefix of its own
+async fn check_provider(provider: &dyn StorageProvider, bucket: &str) {
+    let root = format!("conformance-{}/", uuid::Uuid::new_v4());
+    let name = provider.name().to_string();
+
+    check_prefix_listing(provider, bucket, &root).await;
+    check_overwrite(provider, bucket, &root).await;
+    check_metadata_round_trip(provider, bucket, &root).await;
+    check_large_streaming_upload(provider, bucket, &root).await;
+    check_not_found(provider, bucket, &root).await;
+    check_delete_is_idempotent(provider, bucket, &root).await;
+
+    for key in keys(provider, bucket, &root).await {
+        provider.delete_object(bucket, &key).await.unwrap();
+    }
+    assert!(keys(provider, bucket, &root).await.is_empty(), "{name}");
+}
+
+async fn check_prefix_listing(provider: &dyn StorageProvider, bucket: &str, root: &str) {
+    for key in ["a/1", "a/2", "ab", "b/1"] {
+        provider
+            .upload_stream(bucket, &format!("{root}{key}"), body(b"x"), None, None)
+            .await
+            .unwrap();
+    }
+
+    let name = provider.name();
+    assert_eq!(
+        keys(provider, bucket, &format!("{root}a/")).await,
+        [format!("{root}a/1"), format!("{root}a/2")],
+        "{name}: a prefix ending in a slash lists one directory"
+    );
+    assert_eq!(
+        keys(provider, bucket, &format!("{root}a")).await.len(),
+        3,
+        "{name}: a prefix is a string prefix, not a directory"
+    );
+    assert!(keys(provider, bucket, &format!("{root}c")).await.is_empty());
+
+    let objects = provider
+        .list_objects(bucket, Some(&format!("{root}b/")))
+        .await
+        .unwrap();
+    assert_eq!(objects[0].size, Some(1), "{name}: listed size");
+}
+
+async fn check_overwrite(provider: &dyn StorageProvider, bucket: &str, root: &str) {
+    let name = provider.name();
+    let key = format!("{root}overwritten");
+    let first = HashMap::from([("version".to_string(), "1".to_string())]);
+    provider
+        .upload_stream(bucket, &key, body(b"first"), None, Some(first))
+        .await
+        .unwrap();
+    let before = provider.get_object_metadata(bucket, &key).await.unwrap();
+
+    provider
+        .upload_stream(bucket, &key, body(b"second!"), None, None)
+        .await
+        .unwrap();
+    let after = provider.get_object_metadata(bucket, &key).await.unwrap();
+    assert_eq!(after.size, Some(7), "{name}: size after overwrite");
+    assert_ne!(before.etag, after.etag, "{name}: ETag after overwrite");
+    assert!(
+        after.metadata.unwrap_or_default().is_empty(),
+        "{name}: an overwrite replaces the metadata"
+    );
+    assert_eq!(
+        read(provider.download_stream(bucket, &key).await.unwrap()).await,
+        b"second!"
+    );
+    assert_eq!(keys(provider, bucket, &key).await, [key]);
+}
+
+async fn check_metadata_round_trip(provider: &dyn StorageProvider, bucket: &str, root: &str) {
+    let name = provider.name();
+    let key = format!("{root}with-metadata.json");
+    // Lowercase names, as S3 lowercases them, with the underscore Azure has to encode
+    let metadata = HashMap::from([
+        ("backup_id".to_string(), "b1".to_string()),
+        ("compression".to_string(), "zstd".to_string()),
+    ]);
+    provider
+        .upload_stream(
+            bucket,
+            &key,
+            body(b"{}"),
+            Some("application/json"),
+            Some(metadata.clone()),
+        )
+        .await
+        .unwrap();
+
+    let object = provider.get_object_metadata(bucket, &key).await.unwrap();
+    assert_eq!(object.key, key);
+    assert_eq!(object.size, Some(2), "{name}");
+    assert_eq!(object.metadata, Some(metadata), "{name}: metadata");
+    assert_eq!(
+        object.content_type.as_deref(),
+        Some("application/json"),
+        "{name}: content type"
+    );
+}
+
+async fn check_large_streaming_upload(provider: &dyn StorageProvider, bucket: &str, root: &str) {
+    let name = provider.name();
+    let key = format!("{root}large");
+    let chunks = (0..LARGE_SIZE).step_by(LARGE_CHUNK).map(|start| {
+        let chunk: Vec<u8> = (start..start + LARGE_CHUNK).map(large_byte).collect();
+        Ok(Bytes::from(chunk))
+    });
+    provider
+        .upload_stream(
+            bucket,
+            &key,
+            Box::pin(futures::stream::iter(chunks)),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let object = provider.get_object_metadata(bucket, &key).await.unwrap();
+    assert_eq!(object.size, Some(LARGE_SIZE as u64), "{name}: large size");
+    let data = read(provider.download_stream(bucket, &key).await.unwrap()).await;
+    assert_eq!(data.len(), LARGE_SIZE, "{name}: large download");
+    assert!(
+        data.iter().enumerate().all(|(i, b)| *b == large_byte(i)),
+        "{name}: large content"
+    );
+
+    // A range across a chunk boundary
+    let start = LARGE_CHUNK - 3;
+    let options = StreamingDownloadOptions {
+        range_start: Some(start as u64),
+        range_end: Some((start + 5) as u64),
+    };
+    let range = read(
+        provider
+            .download_stream_range(bucket, &key, &options)
+            .await
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(range, &data[start..start + 6], "{name}: range");
+}
+
+async fn check_not_found(provider: &dyn StorageProvider, bucket: &str, root: &str) {
+    let name = provider.name();
+    let key = format!("{root}missing");
+    assert!(!provider.object_exists(bucket, &key).await.unwrap());
+    assert!(
+        matches!(
+            provider.get_object_metadata(bucket, &key).await,
+            Err(StorageError::NotFound(_))
+        ),
+        "{name}: metadata of a missing object"
+    );
+    assert!(
+        matches!(
+            provider.download_stream(bucket, &key).await,
+            Err(StorageError::NotFound(_))
+        ),
+        "{name}: download of a missing object"
+    );
+    let target = tempfile::tempdir().unwrap();
+    assert!(
+        matches!(
+            provider
+                .download_file(bucket, &key, &target.path().join("missing"))
+                .await,
+            Err(StorageError::NotFound(_))
+        ),
+        "{name}: download to a file of a missing object"
+    );
+}
+
+async fn check_delete_is_idempotent(provider: &dyn StorageProvider, bucket: &str, root: &str) {
+    let name = provider.name();
+    let key = format!("{root}deleted/once");
+    provider
+        .upload_stream(bucket, &key, body(b"gone"), None, None)
+        .await
+        .unwrap();
+    assert!(provider.object_exists(bucket, &key).await.unwrap());
+
+    provider.delete_object(bucket, &key).await.unwrap();
+    assert!(!provider.object_exists(bucket, &key).await.unwrap());
+    assert!(keys(provider, bucket, &format!("{root}deleted/"))
+        .await
+        .is_empty());
+    provider
+        .delete_object(bucket, &key)
+        .await
+        .unwrap_or_else(|e| panic!("{name}: deleting twice failed: {e}"));
+    provider
+        .delete_object(bucket, &format!("{root}never-existed"))
+        .await
+        .unwrap_or_else(|e| panic!("{name}: deleting a missing object failed: {e}"));
+}
+
+#[tokio::test]
+async fn test_memory_provider_conforms() {
+    let provider = MemoryProvider::new();
+    provider.create_bucket("backups").await.unwrap();
+    check_provider(&provider, "backups").await;
+}
+
+#[tokio::test]
+async fn test_local_provider_conforms() {
+    let root = tempfile::tempdir().unwrap();
+    let provider = LocalProvider::new(root.path()).await.unwrap();
+    provider.create_bucket("backups").await.unwrap();
+    check_provider(&provider, "backups").await;
+}
+
+#[tokio::test]
+async fn test_minio_conforms() {
+    let Ok(endpoint) = env::var("AWS_ENDPOINT") else {
+        println!("AWS_ENDPOINT is not set, skipping");
+        return;
+    };
+    let access_key = env::var("AWS_ACCESS_KEY_ID").unwrap_or_else(|_| "minioadmin".to_string());
+    let secret_key = env::var("AWS_SECRET_ACCESS_KEY").unwrap_or_else(|_| "minioadmin".to_string());
+    let region = env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+    let bucket = env::var("AWS_TEST_BUCKET").unwrap_or_else(|_| "test-bucket".to_string());
+
+    let provider = S3Provider::new_with_kind(
+        Some(region),
+        Some(endpoint),
+        Some(access_key),
+        Some(secret_key),
+        ProviderKind::Minio,
+    )
+    .await
+    .expect("provider init");
+    provider.create_bucket(&bucket).await.ok();
+    check_provider(&provider, &bucket).await;
+}
+
+#[tokio::test]
+async fn test_sftp_conforms() {
+    let Ok(endpoint) = env::var("SFTP_TEST_ENDPOINT") else {
+        println!("SFTP_TEST_ENDPOINT is not set, skipping");
+        return;
+    };
+    let password = env::var("SFTP_TEST_PASSWORD").unwrap_or_else(|_| "warden".to_string());
+    let bucket = env::var("SFTP_TEST_BUCKET").unwrap_or_else(|_| "test-bucket".to_string());
+
+    let provider = SftpProvider::new(&endpoint, None, Some(password), None)
+        .await
+        .expect("provider init");
+    provider
+        .create_bucket(&bucket)
+        .await
+        .expect("create bucket");
+    check_provider(&provider, &bucket).await;
+}
+
+#[tokio::test]
+async fn test_gcs_conforms() {
+    let Ok(endpoint) = env::var("STORAGE_EMULATOR_HOST") else {
+        println!("STORAGE_EMULATOR_HOST is not set, skipping");
+        return;
+    };
+    let project = env::var("GCS_TEST_PROJECT").unwrap_or_else(|_| "test-project".to_string());
+    let bucket = env::var("GCS_TEST_BUCKET").unwrap_or_else(|_| "test-bucket".to_string());
+
+    let provider = GcsProvider::new(Some(project), None, Some(endpoint))
+        .await
+        .expect("provider init");
+    provider
+        .create_bucket(&bucket)
+        .await
+        .expect("create bucket");
+    check_provider(&provider, &bucket).await;
+}
+
+#[tokio::test]
+async fn test_azure_conforms() {
+    let Ok(endpoint) = env::var("AZURITE_BLOB_ENDPOINT") else {
+        println!("AZURITE_BLOB_ENDPOINT is not set, skipping");
+        return;
+    };
+    let container = env::var("AZURE_TEST_CONTAINER").unwrap_or_else(|_| "test-bucket".to_string());
+
+    let provider = AzureProvider::new(
+        Some(AZURITE_ACCOUNT.to_string()),
+        Some(AZURITE_KEY.to_string()),
+        None,
+        Some(endpoint),
+    )
+    .await
+    .expect("provider init");
+    provider
+        .create_bucket(&container)
+        .await
+        .expect("create container");
+    check_provider(&provider, &container).await;
+}
@@ -0,0 +1,70 @@
+//! Copying backups between storages, on two local directories
+
+use chrono::Utc;
+use std::path::Path;
+use storage::{BackupInfo, BackupType, PostgresBackupStorage, StorageProviderType};
+
+async fn local_storage(root: &Path, prefix: Option<&str>) -> PostgresBackupStorage {
+    PostgresBackupStorage::new(
+        StorageProviderType::Local,
+        "backups".to_string(),
+        prefix.map(str::to_string),
+        None,
+        Some(root.to_string_lossy().into_owned()),
+        None,
+        None,
+        None,
+        None,
+        None,
+    )
+    .await
+    .expect("local storage")
+}
+
+#[tokio::test]
+async fn test_sync_copies_missing_backups() {
+    let source_root = tempfile::tempdir().unwrap();
+    let target_root = tempfile::tempdir().unwrap();
+    let source = local_storage(source_root.path(), Some("primary")).await;
+    let target = local_storage(target_root.path(), Some("offsite")).await;
+
+    let backup_dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(backup_dir.path().join("pg_wal")).unwrap();
+    std::fs::write(backup_dir.path().join("base.tar.gz"), b"base").unwrap();
+    std::fs::write(
+        backup_dir.path().join("pg_wal/000000010000000000000001"),
+        b"wal",
+    )
+    .unwrap();
+    source
+        .upload_physical_backup("b1", &BackupType::Full, backup_dir.path(), None)
+        .await
+        .unwrap();
+    source
+        .register_backup(BackupInfo {
+            id: "b1".to_string(),
+            backup_type: BackupType::Full,
+            timestamp: Utc::now(),
+            size: 7,
+            parent_id: None,
+            details: None,
+        })
+        .await
+        .unwrap();
+
+    let report = source.sync_to(&target).await.unwrap();
+    assert!(report.is_ok(), "{report:?}");
+    assert_eq!(report.copied, vec!["b1".to_string()]);
+    assert_eq!(target.get_backup_info("b1").await.unwrap().size, 7);
+
+    let restored = tempfile::tempdir().unwrap();
+    target.download_backup("b1", restored.path()).await.unwrap();
+    assert_eq!(
+        std::fs::read(restored.path().join("pg_wal/000000010000000000000001")).unwrap(),
+        b"wal"
+    );
+
+    let again = source.sync_to(&target).await.unwrap();
+    assert!(again.copied.is_empty());
+    assert_eq!(again.up_to_date, vec!["b1".to_string()]);
+}
@@ -0,0 +1,106 @@
+//! Streaming downloads, existence checks and presigned URLs against a local MinIO
+
+use bytes::Bytes;
+use futures::TryStreamExt;
+use std::env;
+use std::time::Duration;
+use storage::providers::{ProviderKind, S3Provider};
+use storage::{StorageProvider, StreamingDownloadOptions};
+
+async fn minio() -> (S3Provider, String) {
+    // Read configuration from environment to match CI settings
+    let endpoint = env::var("AWS_ENDPOINT").unwrap_or_else(|_| "http://localhost:9000".to_string());
+    let access_key = env::var("AWS_ACCESS_KEY_ID").unwrap_or_else(|_| "minioadmin".to_string());
+    let secret_key = env::var("AWS_SECRET_ACCESS_KEY").unwrap_or_else(|_| "minioadmin".to_string());
+    let region = env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+    let bucket = env::var("AWS_TEST_BUCKET").unwrap_or_else(|_| "test-bucket".to_string());
+
+    let provider = S3Provider::new_with_kind(
+        Some(region),
+        Some(endpoint),
+        Some(access_key),
+        Some(secret_key),
+        ProviderKind::Minio,
+    )
+    .await
+    .expect("provider init");
+    provider.create_bucket(&bucket).await.ok();
+    (provider, bucket)
+}
+
+async fn put(provider: &S3Provider, bucket: &str, key: &str, body: &'static [u8]) {
+    let stream = futures::stream::once(async move { Ok(Bytes::from_static(body)) });
+    provider
+        .upload_stream(bucket, key, Box::pin(stream), None, None)
+        .await
+        .expect("upload");
+}
+
+#[tokio::test]
+async fn test_download_stream_and_ranges() {
+    let (provider, bucket) = minio().await;
+    let key = "streaming/digits.txt";
+    put(&provider, &bucket, key, b"0123456789").await;
+
+    let whole: Vec<Bytes> = provider
+        .download_stream(&bucket, key)
+        .await
+        .expect("download_stream")
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(whole.concat(), b"0123456789");
+
+    for (start, end, expected) in [
+        (Some(2), Some(5), &b"2345"[..]),
+        (Some(7), None, &b"789"[..]),
+        (None, Some(0), &b"0"[..]),
+    ] {
+        let options = StreamingDownloadOptions {
+            range_start: start,
+            range_end: end,
+        };
+        let part: Vec<Bytes> = provider
+            .download_stream_range(&bucket, key, &options)
+            .await
+            .expect("download_stream_range")
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(part.concat(), expected, "range {start:?}-{end:?}");
+    }
+
+    assert!(matches!(
+        provider.download_stream(&bucket, "streaming/missing").await,
+        Err(storage::StorageError::NotFound(_))
+    ));
+    provider.delete_object(&bucket, key).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_object_exists() {
+    let (provider, bucket) = minio().await;
+    let key = "streaming/exists.txt";
+    put(&provider, &bucket, key, b"here").await;
+
+    assert!(provider.object_exists(&bucket, key).await.unwrap());
+    provider.delete_object(&bucket, key).await.unwrap();
+    assert!(!provider.object_exists(&bucket, key).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_presigned_url_downloads_object() {
+    let (provider, bucket) = minio().await;
+    let key = "streaming/shared.txt";
+    put(&provider, &bucket, key, b"shared").await;
+
+    let url = provider
+        .generate_presigned_url(&bucket, key, Duration::from_secs(300))
+        .await
+        .expect("presign");
+    let response = reqwest::get(&url).await.expect("fetch presigned URL");
+    assert!(response.status().is_success(), "{}", response.status());
+    assert_eq!(response.bytes().await.unwrap(), &b"shared"[..]);
+
+    provider.delete_object(&bucket, key).await.unwrap();
+}
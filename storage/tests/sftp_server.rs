@@ -0,0 +1,79 @@
+//! SFTP provider against a local SSH server
+//!
+//! Start one with
+//! `docker run -p 2222:22 atmoz/sftp warden:warden:::upload`, then trust its
+//! key with `ssh-keyscan -p 2222 localhost >> ~/.ssh/known_hosts`.
+
+use bytes::Bytes;
+use futures::TryStreamExt;
+use std::collections::HashMap;
+use std::env;
+use storage::providers::SftpProvider;
+use storage::{StorageError, StorageProvider, StreamingDownloadOptions};
+
+async fn sftp() -> (SftpProvider, String) {
+    let endpoint = env::var("SFTP_TEST_ENDPOINT")
+        .unwrap_or_else(|_| "sftp://warden@localhost:2222/upload".to_string());
+    let password = env::var("SFTP_TEST_PASSWORD").unwrap_or_else(|_| "warden".to_string());
+    let bucket = env::var("SFTP_TEST_BUCKET").unwrap_or_else(|_| "test-bucket".to_string());
+
+    let provider = SftpProvider::new(&endpoint, None, Some(password), None)
+        .await
+        .expect("provider init");
+    provider
+        .create_bucket(&bucket)
+        .await
+        .expect("create bucket");
+    (provider, bucket)
+}
+
+#[tokio::test]
+async fn test_sftp_round_trip() {
+    let (provider, bucket) = sftp().await;
+    assert!(provider.bucket_exists(&bucket).await.unwrap());
+
+    let key = "sftp/b1/pg_wal/000000010000000000000001";
+    let metadata = HashMap::from([("compression".to_string(), "zstd".to_string())]);
+    let body = futures::stream::once(async { Ok(Bytes::from_static(b"0123456789")) });
+    provider
+        .upload_stream(&bucket, key, Box::pin(body), None, Some(metadata.clone()))
+        .await
+        .expect("upload_stream");
+
+    let object = provider.get_object_metadata(&bucket, key).await.unwrap();
+    assert_eq!(object.size, Some(10));
+    assert_eq!(object.metadata, Some(metadata));
+    let keys: Vec<String> = provider
+        .list_objects(&bucket, Some("sftp/b1/"))
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|o| o.key)
+        .collect();
+    assert_eq!(keys, vec![key.to_string()]);
+
+    let options = StreamingDownloadOptions {
+        range_start: Some(2),
+        range_end: Some(5),
+    };
+    let part: Vec<Bytes> = provider
+        .download_stream_range(&bucket, key, &options)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(part.concat(), b"2345");
+
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("segment");
+    provider.download_file(&bucket, key, &target).await.unwrap();
+    assert_eq!(std::fs::read(&target).unwrap(), b"0123456789");
+
+    provider.delete_object(&bucket, key).await.unwrap();
+    assert!(!provider.object_exists(&bucket, key).await.unwrap());
+    assert!(matches!(
+        provider.download_stream(&bucket, key).await,
+        Err(StorageError::NotFound(_))
+    ));
+}